@@ -0,0 +1,636 @@
+//Tiled frame differencing: which parts of a frame changed since the previous one.
+use super::Frame;
+use std::time::{Duration, Instant};
+
+//Pairwise merging is cubic per step; scattered changes beyond this are first collapsed per tile row.
+const PAIRWISE_LIMIT: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    fn right(&self) -> u32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> u32 {
+        self.y + self.height
+    }
+
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: self.right().max(other.right()) - x,
+            height: self.bottom().max(other.bottom()) - y,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffConfig {
+    //Edge length of the square tiles frames are compared in.
+    pub tile_size: u32,
+    //Two regions are merged when at most this fraction of the merged rectangle is unchanged.
+    pub max_waste: f64,
+    //Beyond this many rectangles, the cheapest merges are forced; encoders pay per rectangle.
+    pub max_rects: usize,
+    //Above this fraction of changed tiles the whole frame is sent as one rectangle.
+    pub full_frame_ratio: f64,
+}
+
+impl Default for DiffConfig {
+    fn default() -> Self {
+        DiffConfig {
+            tile_size: 32,
+            max_waste: 0.3,
+            max_rects: 16,
+            full_frame_ratio: 0.6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameStats {
+    pub total_tiles: u32,
+    pub changed_tiles: u32,
+    //Pixels in changed tiles, clipped to the frame.
+    pub changed_area: u64,
+    //Pixels covered by the emitted rectangles, which merging makes a little larger.
+    pub sent_area: u64,
+    pub rects: usize,
+    pub full_frame: bool,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameDiff {
+    pub rects: Vec<Rect>,
+    pub stats: FrameStats,
+}
+
+//A rectangle and how many of its pixels actually changed.
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    rect: Rect,
+    changed: u64,
+}
+
+impl Region {
+    fn merged(&self, other: &Region) -> Region {
+        Region {
+            rect: self.rect.union(&other.rect),
+            changed: self.changed + other.changed,
+        }
+    }
+
+    fn waste(&self) -> f64 {
+        let area = self.rect.area();
+        if area == 0 {
+            return 0.0;
+        }
+        area.saturating_sub(self.changed) as f64 / area as f64
+    }
+}
+
+//Compares each frame with the previous one and keeps a copy of it for the next call.
+pub struct TileDiffer {
+    config: DiffConfig,
+    previous: Option<Frame>,
+}
+
+impl TileDiffer {
+    pub fn new(config: DiffConfig) -> Self {
+        TileDiffer {
+            config: DiffConfig {
+                tile_size: config.tile_size.max(1),
+                ..config
+            },
+            previous: None,
+        }
+    }
+
+    //Forget the previous frame, so the next one is sent whole (e.g. after a keyframe request).
+    pub fn reset(&mut self) {
+        self.previous = None;
+    }
+
+    pub fn diff(&mut self, frame: &Frame) -> FrameDiff {
+        let started = Instant::now();
+        let tile = self.config.tile_size;
+        let columns = frame.width.div_ceil(tile);
+        let rows = frame.height.div_ceil(tile);
+        let total_tiles = columns * rows;
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width: frame.width,
+            height: frame.height,
+        };
+
+        let comparable = self.previous.as_ref().is_some_and(|previous| {
+            (previous.width, previous.height, previous.format)
+                == (frame.width, frame.height, frame.format)
+        });
+        if !comparable {
+            self.previous = Some(packed_copy(frame));
+            return full_frame(full, total_tiles, started);
+        }
+        let previous = self.previous.as_mut().unwrap();
+
+        //Runs of changed tiles along each tile row.
+        let mut runs = Vec::new();
+        let mut changed_tiles = 0;
+        let mut changed_area = 0;
+        for row in 0..rows {
+            let mut run: Option<Region> = None;
+            for column in 0..columns {
+                let rect = tile_rect(column, row, tile, frame);
+                if !tile_changed(previous, frame, &rect) {
+                    if let Some(run) = run.take() {
+                        runs.push(run);
+                    }
+                    continue;
+                }
+                copy_tile(previous, frame, &rect);
+                changed_tiles += 1;
+                changed_area += rect.area();
+                let region = Region {
+                    rect,
+                    changed: rect.area(),
+                };
+                run = Some(match run {
+                    Some(run) => run.merged(&region),
+                    None => region,
+                });
+            }
+            if let Some(run) = run {
+                runs.push(run);
+            }
+        }
+        if changed_tiles as f64 > self.config.full_frame_ratio * total_tiles as f64 {
+            return full_frame(full, total_tiles, started);
+        }
+
+        let mut regions = stack(runs.clone());
+        if regions.len() > PAIRWISE_LIMIT {
+            regions = stack(row_bands(runs));
+        }
+        let regions = merge(regions, &self.config);
+        let rects: Vec<Rect> = regions.iter().map(|region| region.rect).collect();
+        FrameDiff {
+            stats: FrameStats {
+                total_tiles,
+                changed_tiles,
+                changed_area,
+                sent_area: rects.iter().map(Rect::area).sum(),
+                rects: rects.len(),
+                full_frame: false,
+                elapsed: started.elapsed(),
+            },
+            rects,
+        }
+    }
+}
+
+fn full_frame(full: Rect, total_tiles: u32, started: Instant) -> FrameDiff {
+    FrameDiff {
+        rects: vec![full],
+        stats: FrameStats {
+            total_tiles,
+            changed_tiles: total_tiles,
+            changed_area: full.area(),
+            sent_area: full.area(),
+            rects: 1,
+            full_frame: true,
+            elapsed: started.elapsed(),
+        },
+    }
+}
+
+fn tile_rect(column: u32, row: u32, tile: u32, frame: &Frame) -> Rect {
+    let x = column * tile;
+    let y = row * tile;
+    Rect {
+        x,
+        y,
+        width: tile.min(frame.width - x),
+        height: tile.min(frame.height - y),
+    }
+}
+
+fn span(frame: &Frame, rect: &Rect, y: u32) -> std::ops::Range<usize> {
+    let pixel = frame.format.bytes_per_pixel();
+    let start = y as usize * frame.stride + rect.x as usize * pixel;
+    start..start + rect.width as usize * pixel
+}
+
+fn tile_changed(previous: &Frame, frame: &Frame, rect: &Rect) -> bool {
+    (rect.y..rect.bottom())
+        .any(|y| previous.data[span(previous, rect, y)] != frame.data[span(frame, rect, y)])
+}
+
+fn copy_tile(previous: &mut Frame, frame: &Frame, rect: &Rect) {
+    for y in rect.y..rect.bottom() {
+        let target = span(previous, rect, y);
+        previous.data[target].copy_from_slice(&frame.data[span(frame, rect, y)]);
+    }
+}
+
+//The previous frame is kept without stride padding, which may hold garbage.
+fn packed_copy(frame: &Frame) -> Frame {
+    let stride = frame.width as usize * frame.format.bytes_per_pixel();
+    let mut data = Vec::with_capacity(stride * frame.height as usize);
+    for y in 0..frame.height {
+        data.extend_from_slice(frame.row(y));
+    }
+    Frame {
+        width: frame.width,
+        height: frame.height,
+        stride,
+        format: frame.format,
+        data,
+        captured_at: frame.captured_at,
+    }
+}
+
+//Stack row runs with the same horizontal extent that touch vertically into taller regions.
+fn stack(runs: Vec<Region>) -> Vec<Region> {
+    let mut regions: Vec<Region> = Vec::new();
+    for run in runs {
+        let below = regions.iter_mut().find(|region| {
+            region.rect.x == run.rect.x
+                && region.rect.width == run.rect.width
+                && region.rect.bottom() == run.rect.y
+        });
+        match below {
+            Some(region) => *region = region.merged(&run),
+            None => regions.push(run),
+        }
+    }
+    regions
+}
+
+//One region per tile row spanning all of that row's runs.
+fn row_bands(runs: Vec<Region>) -> Vec<Region> {
+    let mut bands: Vec<Region> = Vec::new();
+    for run in runs {
+        match bands.last_mut() {
+            Some(band) if band.rect.y == run.rect.y => *band = band.merged(&run),
+            _ => bands.push(run),
+        }
+    }
+    bands
+}
+
+impl Rect {
+    fn contains(&self, other: &Rect) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    fn intersects(&self, other: &Rect) -> bool {
+        self.x < other.right()
+            && other.x < self.right()
+            && self.y < other.bottom()
+            && other.y < self.bottom()
+    }
+}
+
+//`rect` widened over every region it reaches into, so merged regions never overlap the rest and
+//no pixel is encoded twice.
+fn covering(regions: &[Region], mut rect: Rect) -> Rect {
+    while let Some(region) = regions
+        .iter()
+        .find(|region| rect.intersects(&region.rect) && !rect.contains(&region.rect))
+    {
+        rect = rect.union(&region.rect);
+    }
+    rect
+}
+
+//Merge regions whose union wastes little, then force the cheapest merges until few enough remain.
+//A union absorbs every region inside it, so rings and staircases of tiles collapse into one rectangle.
+fn merge(mut regions: Vec<Region>, config: &DiffConfig) -> Vec<Region> {
+    let absorbed = |regions: &[Region], rect: Rect| Region {
+        rect,
+        changed: regions
+            .iter()
+            .filter(|region| rect.contains(&region.rect))
+            .map(|region| region.changed)
+            .sum(),
+    };
+    loop {
+        let mut best: Option<(Region, f64)> = None;
+        for i in 0..regions.len() {
+            for j in i + 1..regions.len() {
+                let union = regions[i].rect.union(&regions[j].rect);
+                let union = absorbed(&regions, covering(&regions, union));
+                let waste = union.waste();
+                if best.is_none_or(|(_, best)| waste < best) {
+                    best = Some((union, waste));
+                }
+            }
+        }
+        match best {
+            Some((union, waste))
+                if waste <= config.max_waste || regions.len() > config.max_rects =>
+            {
+                regions.retain(|region| !union.rect.contains(&region.rect));
+                regions.push(union);
+            }
+            _ => return regions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::synthetic;
+    use crate::capture::PixelFormat;
+
+    fn blank(width: u32, height: u32, stride: usize) -> Frame {
+        Frame {
+            width,
+            height,
+            stride,
+            format: PixelFormat::Bgra,
+            data: vec![0; stride * height as usize],
+            captured_at: Instant::now(),
+        }
+    }
+
+    fn paint(frame: &mut Frame, x: u32, y: u32, width: u32, height: u32, value: u8) {
+        for row in y..y + height {
+            let start = row as usize * frame.stride + x as usize * 4;
+            frame.data[start..start + width as usize * 4].fill(value);
+        }
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    //A differ that has already seen `frame`.
+    fn primed(config: DiffConfig, frame: &Frame) -> TileDiffer {
+        let mut differ = TileDiffer::new(config);
+        assert!(differ.diff(frame).stats.full_frame);
+        differ
+    }
+
+    #[test]
+    fn first_frame_and_resizes_are_sent_whole() {
+        let mut differ = TileDiffer::new(DiffConfig::default());
+        let frame = blank(100, 50, 400);
+        let diff = differ.diff(&frame);
+        assert_eq!(diff.rects, vec![rect(0, 0, 100, 50)]);
+        assert!(diff.stats.full_frame);
+        assert_eq!(diff.stats.total_tiles, 8);
+        assert!(differ.diff(&frame).rects.is_empty());
+
+        let diff = differ.diff(&blank(64, 64, 256));
+        assert_eq!(diff.rects, vec![rect(0, 0, 64, 64)]);
+
+        differ.reset();
+        assert!(differ.diff(&blank(64, 64, 256)).stats.full_frame);
+    }
+
+    #[test]
+    fn unchanged_frame_has_no_rects() {
+        let frame = blank(100, 50, 400);
+        let mut differ = primed(DiffConfig::default(), &frame);
+        let diff = differ.diff(&frame);
+        assert!(diff.rects.is_empty());
+        assert_eq!(diff.stats.changed_tiles, 0);
+        assert_eq!(diff.stats.changed_area, 0);
+    }
+
+    #[test]
+    fn stride_padding_is_ignored() {
+        let mut frame = blank(40, 40, 200);
+        let mut differ = primed(DiffConfig::default(), &frame);
+        for row in 0..40 {
+            frame.data[row * 200 + 160..row * 200 + 200].fill(0xAA);
+        }
+        assert!(differ.diff(&frame).rects.is_empty());
+    }
+
+    #[test]
+    fn change_is_reported_as_its_clipped_tile() {
+        let mut frame = blank(100, 50, 400);
+        let mut differ = primed(DiffConfig::default(), &frame);
+        paint(&mut frame, 99, 49, 1, 1, 1);
+        let diff = differ.diff(&frame);
+        assert_eq!(diff.rects, vec![rect(96, 32, 4, 18)]);
+        assert_eq!(diff.stats.changed_tiles, 1);
+        assert_eq!(diff.stats.changed_area, 4 * 18);
+        assert_eq!(diff.stats.sent_area, 4 * 18);
+        //The change is now part of the reference frame.
+        assert!(differ.diff(&frame).rects.is_empty());
+    }
+
+    #[test]
+    fn adjacent_tiles_merge_distant_ones_do_not() {
+        let mut frame = blank(320, 320, 1280);
+        let mut differ = primed(DiffConfig::default(), &frame);
+        //A 2x2 block of tiles, and a single tile far away.
+        paint(&mut frame, 40, 40, 40, 40, 1);
+        paint(&mut frame, 300, 300, 1, 1, 1);
+        let mut rects = differ.diff(&frame).rects;
+        rects.sort_by_key(|rect| (rect.y, rect.x));
+        assert_eq!(rects, vec![rect(32, 32, 64, 64), rect(288, 288, 32, 32)]);
+    }
+
+    #[test]
+    fn diagonal_neighbours_merge_within_waste_budget() {
+        let mut frame = blank(320, 320, 1280);
+        let config = DiffConfig {
+            max_waste: 0.5,
+            ..DiffConfig::default()
+        };
+        let mut differ = primed(config, &frame);
+        //Three of the four tiles of a 2x2 block; the union wastes a quarter.
+        paint(&mut frame, 0, 0, 64, 32, 1);
+        paint(&mut frame, 0, 32, 32, 32, 1);
+        let diff = differ.diff(&frame);
+        assert_eq!(diff.rects, vec![rect(0, 0, 64, 64)]);
+        assert_eq!(diff.stats.changed_area, 3 * 32 * 32);
+        assert_eq!(diff.stats.sent_area, 64 * 64);
+    }
+
+    #[test]
+    fn rect_count_is_capped() {
+        let mut frame = blank(640, 640, 2560);
+        let config = DiffConfig {
+            max_rects: 4,
+            ..DiffConfig::default()
+        };
+        let mut differ = primed(config, &frame);
+        for i in 0..10 {
+            paint(&mut frame, i * 64, i * 64, 1, 1, 1);
+        }
+        let diff = differ.diff(&frame);
+        assert_eq!(diff.stats.changed_tiles, 10);
+        assert_eq!(diff.rects.len(), 4);
+        assert!(diff.stats.sent_area >= diff.stats.changed_area);
+    }
+
+    #[test]
+    fn mostly_changed_frame_is_sent_whole() {
+        let mut frame = blank(128, 128, 512);
+        let mut differ = primed(DiffConfig::default(), &frame);
+        paint(&mut frame, 0, 0, 128, 96, 1);
+        let diff = differ.diff(&frame);
+        assert!(diff.stats.full_frame);
+        assert_eq!(diff.rects, vec![rect(0, 0, 128, 128)]);
+    }
+
+    #[test]
+    fn scattered_changes_fall_back_to_row_bands() {
+        let mut frame = blank(1024, 1024, 4096);
+        let mut differ = primed(DiffConfig::default(), &frame);
+        //Every other tile on every other row: 256 isolated tiles, a quarter of the frame.
+        for row in (0..32).step_by(2) {
+            for column in (0..32).step_by(2) {
+                paint(&mut frame, column * 32, row * 32, 1, 1, 1);
+            }
+        }
+        let diff = differ.diff(&frame);
+        assert_eq!(diff.stats.changed_tiles, 256);
+        assert!(!diff.stats.full_frame);
+        assert!(diff.rects.len() <= DiffConfig::default().max_rects);
+    }
+
+    #[test]
+    fn merged_rects_never_overlap() {
+        //Scattered changes whose unions reach partway into other regions.
+        let mut seed: u32 = 7;
+        for _ in 0..50 {
+            let before = blank(320, 320, 1280);
+            let config = DiffConfig {
+                max_waste: 0.5,
+                max_rects: 4,
+                full_frame_ratio: 1.0,
+                ..DiffConfig::default()
+            };
+            let mut differ = primed(config, &before);
+            let mut after = before.clone();
+            for _ in 0..12 {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let (column, row) = ((seed >> 8) % 10, (seed >> 16) % 10);
+                paint(&mut after, column * 32, row * 32, 32, 32, 255);
+            }
+            let diff = differ.diff(&after);
+            if diff.stats.full_frame {
+                continue;
+            }
+            for (i, a) in diff.rects.iter().enumerate() {
+                for b in &diff.rects[i + 1..] {
+                    assert!(!a.intersects(b), "{:?} overlaps {:?}", a, b);
+                }
+            }
+            assert_eq!(
+                diff.stats.sent_area,
+                diff.rects.iter().map(Rect::area).sum::<u64>()
+            );
+            assert!(diff.stats.sent_area >= diff.stats.changed_area);
+        }
+    }
+
+    #[test]
+    fn synthetic_motion_touches_only_the_square() {
+        let mut differ = TileDiffer::new(DiffConfig::default());
+        differ.diff(&synthetic::pattern(640, 480, 0));
+        let diff = differ.diff(&synthetic::pattern(640, 480, 1));
+        //The 64 pixel square moved 16 pixels diagonally: old and new positions span 80x80.
+        assert_eq!(diff.rects, vec![rect(0, 0, 96, 96)]);
+    }
+
+    //Run with `cargo test --release bench_ -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_synthetic_sequences() {
+        const FRAMES: u64 = 120;
+        let (width, height) = (1920, 1080);
+        type Scenario = Box<dyn Fn(u64) -> Frame>;
+        let scenarios: Vec<(&str, Scenario)> = vec![
+            (
+                "static",
+                Box::new(move |_| synthetic::pattern(width, height, 0)),
+            ),
+            (
+                "moving square",
+                Box::new(move |index| synthetic::pattern(width, height, index)),
+            ),
+            (
+                "typing",
+                Box::new(move |index| {
+                    let mut frame = synthetic::pattern(width, height, 0);
+                    let x = 100 + (index as u32 % 150) * 10;
+                    paint(&mut frame, x, 500, 10, 18, 0);
+                    frame
+                }),
+            ),
+            (
+                "video",
+                Box::new(move |index| {
+                    let mut frame = synthetic::pattern(width, height, 0);
+                    paint(&mut frame, 320, 180, 1280, 720, index as u8);
+                    frame
+                }),
+            ),
+            (
+                "full change",
+                Box::new(move |index| {
+                    let mut frame = synthetic::pattern(width, height, 0);
+                    paint(&mut frame, 0, 0, width, height, index as u8);
+                    frame
+                }),
+            ),
+        ];
+        for (name, scenario) in scenarios {
+            let frames: Vec<Frame> = (0..FRAMES).map(&scenario).collect();
+            let mut differ = TileDiffer::new(DiffConfig::default());
+            let mut elapsed = Duration::ZERO;
+            let mut rects = 0;
+            let mut changed = 0;
+            let mut sent = 0;
+            for frame in &frames {
+                let started = Instant::now();
+                let diff = differ.diff(frame);
+                elapsed += started.elapsed();
+                rects += diff.stats.rects;
+                changed += diff.stats.changed_area;
+                sent += diff.stats.sent_area;
+            }
+            let pixels = (width * height) as u64 * FRAMES;
+            println!(
+                "{:>14}: {:>7.3} ms/frame, {:>5.2} rects/frame, changed {:>5.1}%, sent {:>5.1}%",
+                name,
+                elapsed.as_secs_f64() * 1000.0 / FRAMES as f64,
+                rects as f64 / FRAMES as f64,
+                changed as f64 * 100.0 / pixels as f64,
+                sent as f64 * 100.0 / pixels as f64,
+            );
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//Screen capture: a `Capturer` per backend hands out raw frames of one monitor at a time.
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+pub mod diff;
+pub mod synthetic;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    //Byte order in memory; the fourth byte is alpha or padding.
+    Bgra,
+    Rgba,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        4
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Monitor {
+    //Stable for the lifetime of a capturer; backends number monitors in enumeration order.
+    pub id: u32,
+    pub name: String,
+    //Position in the virtual desktop, so monitors can be laid out as the user sees them.
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub primary: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    //Bytes from the start of one row to the next; at least width * bytes_per_pixel.
+    pub stride: usize,
+    pub format: PixelFormat,
+    pub data: Vec<u8>,
+    pub captured_at: Instant,
+}
+
+impl Frame {
+    //The visible pixels of row `y`, without stride padding.
+    pub fn row(&self, y: u32) -> &[u8] {
+        let start = y as usize * self.stride;
+        &self.data[start..start + self.width as usize * self.format.bytes_per_pixel()]
+    }
+
+    //Tightly packed RGBA with opaque alpha, as image widgets and encoders expect.
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.width as usize * self.height as usize * 4);
+        for y in 0..self.height {
+            for pixel in self.row(y).chunks_exact(4) {
+                match self.format {
+                    PixelFormat::Bgra => {
+                        rgba.extend_from_slice(&[pixel[2], pixel[1], pixel[0], 255])
+                    }
+                    PixelFormat::Rgba => {
+                        rgba.extend_from_slice(&[pixel[0], pixel[1], pixel[2], 255])
+                    }
+                }
+            }
+        }
+        rgba
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureError {
+    //The backend cannot run here, e.g. no X server or an unsupported platform.
+    Unavailable(String),
+    NoSuchMonitor(u32),
+    //The display uses a pixel layout the backend cannot convert.
+    UnsupportedFormat(String),
+    Backend(String),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Unavailable(reason) => {
+                write!(f, "screen capture unavailable: {}", reason)
+            }
+            CaptureError::NoSuchMonitor(id) => write!(f, "no monitor with id {}", id),
+            CaptureError::UnsupportedFormat(format) => {
+                write!(f, "unsupported pixel format: {}", format)
+            }
+            CaptureError::Backend(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+pub trait Capturer: Send {
+    //The monitors currently attached; may change between calls when displays are plugged in.
+    fn monitors(&mut self) -> Result<Vec<Monitor>, CaptureError>;
+    fn capture(&mut self, monitor: &Monitor) -> Result<Frame, CaptureError>;
+}
+
+//The primary monitor, or the first one if none is marked primary.
+pub fn primary(monitors: &[Monitor]) -> Option<&Monitor> {
+    monitors
+        .iter()
+        .find(|monitor| monitor.primary)
+        .or_else(|| monitors.first())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureBackend {
+    //The platform's native backend.
+    Auto,
+    X11,
+    //A moving test pattern, for demos and for running without a display.
+    Synthetic,
+}
+
+impl FromStr for CaptureBackend {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(CaptureBackend::Auto),
+            "x11" => Ok(CaptureBackend::X11),
+            "synthetic" => Ok(CaptureBackend::Synthetic),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for CaptureBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureBackend::Auto => write!(f, "auto"),
+            CaptureBackend::X11 => write!(f, "x11"),
+            CaptureBackend::Synthetic => write!(f, "synthetic"),
+        }
+    }
+}
+
+pub fn open(backend: CaptureBackend) -> Result<Box<dyn Capturer>, CaptureError> {
+    match backend {
+        CaptureBackend::Synthetic => Ok(Box::new(synthetic::SyntheticCapturer::default())),
+        #[cfg(target_os = "linux")]
+        CaptureBackend::Auto | CaptureBackend::X11 => {
+            Ok(Box::new(crate::linux::capture::X11Capturer::connect(None)?))
+        }
+        #[cfg(target_os = "windows")]
+        CaptureBackend::Auto => Err(CaptureError::Unavailable(
+            "no native capture backend on Windows yet".to_string(),
+        )),
+        #[cfg(target_os = "windows")]
+        CaptureBackend::X11 => Err(CaptureError::Unavailable(
+            "X11 capture is only available on Linux".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(format: PixelFormat, stride: usize, data: Vec<u8>) -> Frame {
+        Frame {
+            width: 2,
+            height: 2,
+            stride,
+            format,
+            data,
+            captured_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn rgba_conversion_drops_stride_padding() {
+        #[rustfmt::skip]
+        let data = vec![
+            1, 2, 3, 0,  4, 5, 6, 0,  9, 9, 9, 9,
+            7, 8, 9, 0,  10, 11, 12, 0,  9, 9, 9, 9,
+        ];
+        assert_eq!(
+            frame(PixelFormat::Bgra, 12, data.clone()).to_rgba(),
+            vec![3, 2, 1, 255, 6, 5, 4, 255, 9, 8, 7, 255, 12, 11, 10, 255]
+        );
+        assert_eq!(
+            frame(PixelFormat::Rgba, 12, data).to_rgba(),
+            vec![1, 2, 3, 255, 4, 5, 6, 255, 7, 8, 9, 255, 10, 11, 12, 255]
+        );
+    }
+
+    #[test]
+    fn primary_falls_back_to_first_monitor() {
+        let monitor = |id, primary| Monitor {
+            id,
+            name: format!("m{}", id),
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+            primary,
+        };
+        assert_eq!(
+            primary(&[monitor(0, false), monitor(1, true)]).unwrap().id,
+            1
+        );
+        assert_eq!(
+            primary(&[monitor(0, false), monitor(1, false)]).unwrap().id,
+            0
+        );
+        assert_eq!(primary(&[]), None);
+    }
+}
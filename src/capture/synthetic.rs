@@ -0,0 +1,171 @@
+use super::{CaptureError, Capturer, Frame, Monitor, PixelFormat};
+use std::time::Instant;
+
+//BGRA colour bars behind the moving square.
+const BARS: [[u8; 4]; 8] = [
+    [255, 255, 255, 255],
+    [0, 255, 255, 255],
+    [255, 255, 0, 255],
+    [0, 255, 0, 255],
+    [255, 0, 255, 255],
+    [0, 0, 255, 255],
+    [255, 0, 0, 255],
+    [0, 0, 0, 255],
+];
+const SQUARE: u32 = 64;
+const SQUARE_STEP: u32 = 16;
+
+//Colour bars with a square that moves a little every frame, so consecutive frames differ in a known area.
+pub struct SyntheticCapturer {
+    monitors: Vec<Monitor>,
+    frames: u64,
+}
+
+impl Default for SyntheticCapturer {
+    //Two side-by-side monitors of different sizes.
+    fn default() -> Self {
+        SyntheticCapturer::new(vec![
+            Monitor {
+                id: 0,
+                name: "synthetic-0".to_string(),
+                x: 0,
+                y: 0,
+                width: 1280,
+                height: 720,
+                primary: true,
+            },
+            Monitor {
+                id: 1,
+                name: "synthetic-1".to_string(),
+                x: 1280,
+                y: 0,
+                width: 1024,
+                height: 768,
+                primary: false,
+            },
+        ])
+    }
+}
+
+impl SyntheticCapturer {
+    pub fn new(monitors: Vec<Monitor>) -> Self {
+        SyntheticCapturer {
+            monitors,
+            frames: 0,
+        }
+    }
+}
+
+//Top-left corner of the moving square in frame `index`; it bounces along the diagonal.
+pub fn square_position(width: u32, height: u32, index: u64) -> (u32, u32) {
+    let travel = |size: u32| {
+        let range = size.saturating_sub(SQUARE).max(1) as u64;
+        let offset = (index * SQUARE_STEP as u64) % (2 * range);
+        (if offset < range {
+            offset
+        } else {
+            2 * range - offset
+        }) as u32
+    };
+    (travel(width), travel(height))
+}
+
+pub fn pattern(width: u32, height: u32, index: u64) -> Frame {
+    let stride = width as usize * 4;
+    let mut data = Vec::with_capacity(stride * height as usize);
+    let bar_width = (width / BARS.len() as u32).max(1);
+    let (square_x, square_y) = square_position(width, height, index);
+    for y in 0..height {
+        for x in 0..width {
+            let in_square = (square_x..square_x + SQUARE).contains(&x)
+                && (square_y..square_y + SQUARE).contains(&y);
+            if in_square {
+                data.extend_from_slice(&[32, 32, 32, 255]);
+            } else {
+                let bar = ((x / bar_width) as usize).min(BARS.len() - 1);
+                data.extend_from_slice(&BARS[bar]);
+            }
+        }
+    }
+    Frame {
+        width,
+        height,
+        stride,
+        format: PixelFormat::Bgra,
+        data,
+        captured_at: Instant::now(),
+    }
+}
+
+impl Capturer for SyntheticCapturer {
+    fn monitors(&mut self) -> Result<Vec<Monitor>, CaptureError> {
+        Ok(self.monitors.clone())
+    }
+
+    fn capture(&mut self, monitor: &Monitor) -> Result<Frame, CaptureError> {
+        let monitor = self
+            .monitors
+            .iter()
+            .find(|known| known.id == monitor.id)
+            .ok_or(CaptureError::NoSuchMonitor(monitor.id))?;
+        let frame = pattern(monitor.width, monitor.height, self.frames);
+        self.frames += 1;
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enumerates_monitors_and_captures_each() {
+        let mut capturer = SyntheticCapturer::default();
+        let monitors = capturer.monitors().unwrap();
+        assert_eq!(monitors.len(), 2);
+        for monitor in &monitors {
+            let frame = capturer.capture(monitor).unwrap();
+            assert_eq!((frame.width, frame.height), (monitor.width, monitor.height));
+            assert_eq!(frame.stride, monitor.width as usize * 4);
+            assert_eq!(frame.data.len(), frame.stride * frame.height as usize);
+            assert_eq!(frame.format, PixelFormat::Bgra);
+        }
+        let mut unplugged = monitors[0].clone();
+        unplugged.id = 7;
+        assert_eq!(
+            capturer.capture(&unplugged).unwrap_err(),
+            CaptureError::NoSuchMonitor(7)
+        );
+    }
+
+    #[test]
+    fn consecutive_frames_differ_only_around_the_square() {
+        let mut capturer = SyntheticCapturer::new(vec![Monitor {
+            id: 0,
+            name: "test".to_string(),
+            x: 0,
+            y: 0,
+            width: 256,
+            height: 128,
+            primary: true,
+        }]);
+        let monitor = capturer.monitors().unwrap().remove(0);
+        let first = capturer.capture(&monitor).unwrap();
+        let second = capturer.capture(&monitor).unwrap();
+        assert!(second.captured_at >= first.captured_at);
+        let moved = (0..monitor.height)
+            .filter(|&y| first.row(y) != second.row(y))
+            .count() as u32;
+        assert!(moved > 0 && moved <= SQUARE + SQUARE_STEP);
+    }
+
+    #[test]
+    fn square_bounces_inside_the_frame() {
+        for index in 0..200 {
+            let (x, y) = square_position(200, 100, index);
+            assert!(x + SQUARE <= 200 && y + SQUARE <= 100, "frame {}", index);
+        }
+        assert_eq!(square_position(200, 100, 0), (0, 0));
+        assert_eq!(square_position(200, 100, 1), (16, 16));
+    }
+}
@@ -0,0 +1,439 @@
+use crate::config::{self, Config};
+use crate::hub;
+use crate::install::{self, InstallOptions};
+use crate::logging::levels::{self, LevelRequest};
+use crate::service::{self, ServiceError, ServiceManager, ServiceStatus};
+use clap::{Parser, Subcommand};
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NOT_RUNNING: i32 = 3;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "deskhub",
+    version,
+    about = "Deskhub Remote Desktop Agent",
+    after_help = "Run without a command to open the setup window.\n\n\
+                  Exit codes: 0 success, 1 failure, 2 usage error, \
+                  3 service not running or not installed (status only)."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum Command {
+    /// Copy the agent to the install directory, write the config, register and start the service
+    Install {
+        /// Print the planned actions without changing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Directory the binary is copied to
+        #[arg(long, value_name = "DIR")]
+        install_dir: Option<PathBuf>,
+    },
+    /// Stop and remove the system service
+    Uninstall,
+    /// Start the system service
+    Start,
+    /// Stop the system service
+    Stop,
+    /// Show the state of the system service
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Run as the system service (started by the service manager)
+    RunService,
+    /// Run the desktop window (started by the service)
+    RunDesktop,
+    /// Enroll this agent with the hub using a one-time token
+    Enroll { token: String },
+    /// Inspect the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Change the running service's log levels for a while
+    LogLevel {
+        #[command(subcommand)]
+        command: LogLevelCommand,
+    },
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration and list every invalid field
+    Check,
+}
+
+#[derive(Debug, PartialEq, Subcommand)]
+pub enum LogLevelCommand {
+    /// Set a level until it reverts to the configured one on its own
+    Set {
+        /// off, error, warn, info, debug or trace
+        level: String,
+        /// Module to change, e.g. deskhub::hub; every module without its own level if omitted
+        #[arg(long)]
+        target: Option<String>,
+        /// Minutes until the configured level is back
+        #[arg(long, default_value_t = 30)]
+        minutes: u64,
+    },
+    /// Go back to the configured levels now
+    Reset,
+}
+
+//Services registered by older builds still start us with `-service`, and they launch `-main`.
+pub fn legacy_args<I: IntoIterator<Item = OsString>>(args: I) -> Vec<OsString> {
+    args.into_iter()
+        .map(|arg| match arg.to_str() {
+            Some("-service") => OsString::from("run-service"),
+            Some("-main") => OsString::from("run-desktop"),
+            Some("-enroll") => OsString::from("enroll"),
+            _ => arg,
+        })
+        .collect()
+}
+
+fn open_service(config: &Config) -> (Box<dyn ServiceManager>, Result<(), ServiceError>) {
+    let mut manager = service::new_service_manager(&config.service.name);
+    let opened = manager.open();
+    (manager, opened)
+}
+
+//Safe to re-run: only the steps that are not done yet are carried out.
+pub fn install(config: &Config, dry_run: bool, install_dir: Option<PathBuf>) -> i32 {
+    let Ok(source) = std::env::current_exe() else {
+        eprintln!("Failed to get the execution path.");
+        return EXIT_FAILURE;
+    };
+    let options = InstallOptions {
+        install_dir: install_dir.unwrap_or_else(install::default_install_dir),
+        config_path: config::default_path(),
+    };
+    let mut manager = service::new_service_manager(&config.service.name);
+    let actions = match install::plan(&source, config, &options, manager.as_mut()) {
+        Ok(actions) => actions,
+        Err(e) => {
+            eprintln!("Failed to inspect the current installation: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+    if actions.is_empty() {
+        println!("Service {} is installed and running.", config.service.name);
+        return EXIT_OK;
+    }
+    for action in &actions {
+        if dry_run {
+            println!("would {}", action);
+            continue;
+        }
+        println!("{}", action);
+        if let Err(e) = install::apply(action, manager.as_mut()) {
+            eprintln!("Failed to {}: {}", action, e);
+            return EXIT_FAILURE;
+        }
+    }
+    EXIT_OK
+}
+
+pub fn uninstall(config: &Config) -> i32 {
+    let (mut manager, opened) = open_service(config);
+    if let Err(e) = opened {
+        eprintln!("Failed to open service {}: {}", config.service.name, e);
+        return EXIT_FAILURE;
+    }
+    if manager.query_status() == ServiceStatus::Running {
+        if let Err(e) = manager.stop() {
+            eprintln!("Failed to stop the service: {}", e);
+            return EXIT_FAILURE;
+        }
+    }
+    match manager.unregister() {
+        Ok(()) => {
+            println!("Removed service {}.", config.service.name);
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("Service removal failed: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+pub fn start(config: &Config) -> i32 {
+    let (manager, opened) = open_service(config);
+    if let Err(e) = opened.and_then(|_| manager.start()) {
+        eprintln!("Failed to start the service: {}", e);
+        return EXIT_FAILURE;
+    }
+    println!(
+        "Service {} is {}.",
+        config.service.name,
+        manager.query_status()
+    );
+    EXIT_OK
+}
+
+pub fn stop(config: &Config) -> i32 {
+    let (manager, opened) = open_service(config);
+    if let Err(e) = opened.and_then(|_| manager.stop()) {
+        eprintln!("Failed to stop the service: {}", e);
+        return EXIT_FAILURE;
+    }
+    println!(
+        "Service {} is {}.",
+        config.service.name,
+        manager.query_status()
+    );
+    EXIT_OK
+}
+
+pub fn status_json(service_name: &str, status: &ServiceStatus, error: Option<&str>) -> String {
+    let mut value = serde_json::json!({
+        "service": service_name,
+        "status": status,
+    });
+    if let Some(error) = error {
+        value["error"] = serde_json::Value::from(error);
+    }
+    value.to_string()
+}
+
+pub fn status(config: &Config, json: bool) -> i32 {
+    let (manager, opened) = open_service(config);
+    let (status, error) = match opened {
+        Ok(()) => (manager.query_status(), None),
+        Err(ServiceError::DoesNotExist) => (ServiceStatus::DoesNotExist, None),
+        Err(e) => (ServiceStatus::Unknown, Some(e.to_string())),
+    };
+    if json {
+        println!(
+            "{}",
+            status_json(&config.service.name, &status, error.as_deref())
+        );
+    } else if let Some(error) = &error {
+        eprintln!("Failed to open service {}: {}", config.service.name, error);
+    } else {
+        println!("Service {} is {}.", config.service.name, status);
+    }
+    match status {
+        ServiceStatus::Running => EXIT_OK,
+        ServiceStatus::Stopped | ServiceStatus::DoesNotExist => EXIT_NOT_RUNNING,
+        _ => EXIT_FAILURE,
+    }
+}
+
+//Trade a one-time token for the agent identity.
+pub fn enroll(config: &Config, token: &str) -> i32 {
+    let Some(config) = config.hub_config() else {
+        eprintln!("Set hub.url in the configuration (or DESKHUB_HUB_URL) before enrolling.");
+        return EXIT_USAGE;
+    };
+    match hub::identity::Identity::load(&config.identity_path) {
+        Ok(identity) => {
+            eprintln!(
+                "Already enrolled as {}. Remove {} to enroll again.",
+                identity.agent_id,
+                config.identity_path.display()
+            );
+            return EXIT_FAILURE;
+        }
+        Err(hub::identity::IdentityError::Missing) | Err(hub::identity::IdentityError::Revoked) => {
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_FAILURE;
+        }
+    }
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to start: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+    let identity = match runtime.block_on(hub::enroll::enroll(&config.url, token)) {
+        Ok(identity) => identity,
+        Err(e) => {
+            eprintln!("Enrollment failed: {}", e);
+            return EXIT_FAILURE;
+        }
+    };
+    if let Err(e) = identity.save(&config.identity_path) {
+        eprintln!(
+            "Enrollment succeeded but the identity could not be saved: {}",
+            e
+        );
+        return EXIT_FAILURE;
+    }
+    println!("Enrolled as {}", identity.agent_id);
+    EXIT_OK
+}
+
+//Leaves the request for the service, which picks it up within a second or so.
+pub fn log_level(config: &Config, command: LogLevelCommand) -> i32 {
+    let request = match command {
+        LogLevelCommand::Set {
+            level,
+            target,
+            minutes,
+        } => LevelRequest::Set {
+            target,
+            level,
+            duration_secs: minutes.saturating_mul(60),
+        },
+        LogLevelCommand::Reset => LevelRequest::Reset,
+    };
+    if let Err(e) = request.check() {
+        eprintln!("{}", e);
+        return EXIT_USAGE;
+    }
+    let path = levels::requests_path(&config.log.directory);
+    if let Err(e) = levels::submit(&path, &request) {
+        eprintln!("Failed to write {}: {}", path.display(), e);
+        return EXIT_FAILURE;
+    }
+    println!("Requested; the service applies it if it is running.");
+    EXIT_OK
+}
+
+pub fn config_check() -> i32 {
+    match Config::load_with_source() {
+        Ok((_, Some(path))) => {
+            println!("Configuration is valid ({}).", path.display());
+            EXIT_OK
+        }
+        Ok((_, None)) => {
+            println!("No config file, using defaults.");
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        let args = std::iter::once("deskhub")
+            .chain(args.iter().copied())
+            .map(OsString::from);
+        Cli::try_parse_from(legacy_args(args))
+    }
+
+    #[test]
+    fn no_command_opens_guide() {
+        assert_eq!(parse(&[]).unwrap().command, None);
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        assert_eq!(
+            parse(&["install", "--dry-run"]).unwrap().command,
+            Some(Command::Install {
+                dry_run: true,
+                install_dir: None
+            })
+        );
+        assert_eq!(
+            parse(&["status", "--json"]).unwrap().command,
+            Some(Command::Status { json: true })
+        );
+        assert_eq!(
+            parse(&["enroll", "abc123"]).unwrap().command,
+            Some(Command::Enroll {
+                token: "abc123".to_string()
+            })
+        );
+        assert_eq!(
+            parse(&["config", "check"]).unwrap().command,
+            Some(Command::Config {
+                command: ConfigCommand::Check
+            })
+        );
+        assert_eq!(
+            parse(&[
+                "log-level",
+                "set",
+                "trace",
+                "--target",
+                "deskhub::win32::service_ctrl"
+            ])
+            .unwrap()
+            .command,
+            Some(Command::LogLevel {
+                command: LogLevelCommand::Set {
+                    level: "trace".to_string(),
+                    target: Some("deskhub::win32::service_ctrl".to_string()),
+                    minutes: 30,
+                }
+            })
+        );
+        assert_eq!(
+            parse(&["log-level", "reset"]).unwrap().command,
+            Some(Command::LogLevel {
+                command: LogLevelCommand::Reset
+            })
+        );
+    }
+
+    #[test]
+    fn accepts_legacy_flags() {
+        assert_eq!(
+            parse(&["-service"]).unwrap().command,
+            Some(Command::RunService)
+        );
+        assert_eq!(
+            parse(&["-main"]).unwrap().command,
+            Some(Command::RunDesktop)
+        );
+        assert_eq!(
+            parse(&["-enroll", "abc123"]).unwrap().command,
+            Some(Command::Enroll {
+                token: "abc123".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        let err = parse(&["frobnicate"]).unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_USAGE);
+        assert!(parse(&["enroll"]).is_err());
+    }
+
+    #[test]
+    fn status_json_shape() {
+        let value: serde_json::Value = serde_json::from_str(&status_json(
+            "DeskHubService",
+            &ServiceStatus::DoesNotExist,
+            None,
+        ))
+        .unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({"service": "DeskHubService", "status": "does_not_exist"})
+        );
+
+        let value: serde_json::Value = serde_json::from_str(&status_json(
+            "DeskHubService",
+            &ServiceStatus::Unknown,
+            Some("Access denied"),
+        ))
+        .unwrap();
+        assert_eq!(value["error"], "Access denied");
+    }
+}
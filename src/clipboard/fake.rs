@@ -0,0 +1,62 @@
+use super::{Clipboard, ClipboardContent, ClipboardError};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Default)]
+struct FakeState {
+    sequence: u64,
+    contents: Vec<ClipboardContent>,
+    reads: usize,
+    writes: usize,
+}
+
+//In-memory clipboard. Clones share state, so a test can play the user copying on the
+//host while the sync under test owns another handle.
+#[derive(Debug, Clone, Default)]
+pub struct FakeClipboard {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeClipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //Someone on the host copied `contents`.
+    pub fn copy(&self, contents: Vec<ClipboardContent>) {
+        let mut state = self.state.lock().unwrap();
+        state.sequence += 1;
+        state.contents = contents;
+    }
+
+    pub fn contents(&self) -> Vec<ClipboardContent> {
+        self.state.lock().unwrap().contents.clone()
+    }
+
+    pub fn reads(&self) -> usize {
+        self.state.lock().unwrap().reads
+    }
+
+    pub fn writes(&self) -> usize {
+        self.state.lock().unwrap().writes
+    }
+}
+
+impl Clipboard for FakeClipboard {
+    fn sequence(&mut self) -> Result<u64, ClipboardError> {
+        Ok(self.state.lock().unwrap().sequence)
+    }
+
+    fn read(&mut self) -> Result<Vec<ClipboardContent>, ClipboardError> {
+        let mut state = self.state.lock().unwrap();
+        state.reads += 1;
+        Ok(state.contents.clone())
+    }
+
+    fn write(&mut self, contents: &[ClipboardContent]) -> Result<(), ClipboardError> {
+        let mut state = self.state.lock().unwrap();
+        state.writes += 1;
+        state.sequence += 1;
+        state.contents = contents.to_vec();
+        Ok(())
+    }
+}
@@ -0,0 +1,386 @@
+//Clipboard sync between the host session and a viewer, per format and within size limits.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+#[cfg(test)]
+pub mod fake;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClipboardFormat {
+    Text,
+    Html,
+    Image,
+}
+
+impl fmt::Display for ClipboardFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardFormat::Text => write!(f, "text"),
+            ClipboardFormat::Html => write!(f, "html"),
+            ClipboardFormat::Image => write!(f, "image"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClipboardContent {
+    Text(String),
+    Html(String),
+    //PNG encoded.
+    Image(Vec<u8>),
+}
+
+impl ClipboardContent {
+    pub fn format(&self) -> ClipboardFormat {
+        match self {
+            ClipboardContent::Text(_) => ClipboardFormat::Text,
+            ClipboardContent::Html(_) => ClipboardFormat::Html,
+            ClipboardContent::Image(_) => ClipboardFormat::Image,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            ClipboardContent::Text(text) | ClipboardContent::Html(text) => text.as_bytes(),
+            ClipboardContent::Image(png) => png,
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardError {
+    //The backend cannot run here, e.g. no X server.
+    Unavailable(String),
+    Backend(String),
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClipboardError::Unavailable(reason) => write!(f, "clipboard unavailable: {}", reason),
+            ClipboardError::Backend(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+pub trait Clipboard: Send {
+    //Changes whenever something, including this backend, puts new content on the clipboard.
+    fn sequence(&mut self) -> Result<u64, ClipboardError>;
+    //Every supported format currently on the clipboard.
+    fn read(&mut self) -> Result<Vec<ClipboardContent>, ClipboardError>;
+    //Replace the clipboard with `contents`, one entry per format.
+    fn write(&mut self, contents: &[ClipboardContent]) -> Result<(), ClipboardError>;
+}
+
+pub fn open() -> Result<Box<dyn Clipboard>, ClipboardError> {
+    #[cfg(target_os = "linux")]
+    return Ok(Box::new(crate::linux::clipboard::X11Clipboard::connect(
+        None,
+    )?));
+    #[cfg(target_os = "windows")]
+    return Err(ClipboardError::Unavailable(
+        "no native clipboard backend on Windows yet".to_string(),
+    ));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Off,
+    ToViewer,
+    FromViewer,
+    Both,
+}
+
+impl Direction {
+    fn outbound(self) -> bool {
+        matches!(self, Direction::ToViewer | Direction::Both)
+    }
+
+    fn inbound(self) -> bool {
+        matches!(self, Direction::FromViewer | Direction::Both)
+    }
+}
+
+impl FromStr for Direction {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "off" => Ok(Direction::Off),
+            "to_viewer" => Ok(Direction::ToViewer),
+            "from_viewer" => Ok(Direction::FromViewer),
+            "both" => Ok(Direction::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Off => write!(f, "off"),
+            Direction::ToViewer => write!(f, "to_viewer"),
+            Direction::FromViewer => write!(f, "from_viewer"),
+            Direction::Both => write!(f, "both"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatPolicy {
+    pub direction: Direction,
+    pub max_bytes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardPolicy {
+    pub text: FormatPolicy,
+    pub html: FormatPolicy,
+    pub image: FormatPolicy,
+}
+
+impl ClipboardPolicy {
+    fn format(&self, format: ClipboardFormat) -> FormatPolicy {
+        match format {
+            ClipboardFormat::Text => self.text,
+            ClipboardFormat::Html => self.html,
+            ClipboardFormat::Image => self.image,
+        }
+    }
+}
+
+impl Default for ClipboardPolicy {
+    fn default() -> Self {
+        ClipboardPolicy {
+            text: FormatPolicy {
+                direction: Direction::Both,
+                max_bytes: 1 << 20,
+            },
+            html: FormatPolicy {
+                direction: Direction::Both,
+                max_bytes: 1 << 20,
+            },
+            image: FormatPolicy {
+                direction: Direction::Both,
+                max_bytes: 8 << 20,
+            },
+        }
+    }
+}
+
+//Keeps the host clipboard and one viewer's in step.
+pub struct ClipboardSync {
+    clipboard: Box<dyn Clipboard>,
+    policy: ClipboardPolicy,
+    sequence: Option<u64>,
+    //Fingerprint of the content per format that last crossed in either direction, so that
+    //content arriving from one side is not sent straight back to it.
+    last: HashMap<ClipboardFormat, u64>,
+}
+
+impl ClipboardSync {
+    pub fn new(clipboard: Box<dyn Clipboard>, policy: ClipboardPolicy) -> Self {
+        ClipboardSync {
+            clipboard,
+            policy,
+            sequence: None,
+            last: HashMap::new(),
+        }
+    }
+
+    //Host clipboard changes to send to the viewer, if any.
+    //The viewer connection drives this once the streaming path lands.
+    #[allow(dead_code)]
+    pub fn poll(&mut self) -> Result<Vec<ClipboardContent>, ClipboardError> {
+        let sequence = self.clipboard.sequence()?;
+        if self.sequence == Some(sequence) {
+            return Ok(Vec::new());
+        }
+        self.sequence = Some(sequence);
+        let mut changed = Vec::new();
+        for content in self.clipboard.read()? {
+            let format = content.format();
+            let policy = self.policy.format(format);
+            if !policy.direction.outbound() {
+                continue;
+            }
+            if content.bytes().len() > policy.max_bytes {
+                log::info!(
+                    "not sending {} bytes of {} to the viewer, the limit is {}",
+                    content.bytes().len(),
+                    format,
+                    policy.max_bytes
+                );
+                continue;
+            }
+            let fingerprint = content.fingerprint();
+            if self.last.insert(format, fingerprint) != Some(fingerprint) {
+                changed.push(content);
+            }
+        }
+        Ok(changed)
+    }
+
+    //Put the viewer's clipboard on the host; returns the formats that were applied.
+    #[allow(dead_code)]
+    pub fn apply(
+        &mut self,
+        incoming: Vec<ClipboardContent>,
+    ) -> Result<Vec<ClipboardFormat>, ClipboardError> {
+        let mut accepted = Vec::new();
+        for content in incoming {
+            let format = content.format();
+            let policy = self.policy.format(format);
+            if !policy.direction.inbound() {
+                log::info!("ignoring {} from the viewer, not allowed by policy", format);
+                continue;
+            }
+            if content.bytes().len() > policy.max_bytes {
+                log::warn!(
+                    "ignoring {} bytes of {} from the viewer, the limit is {}",
+                    content.bytes().len(),
+                    format,
+                    policy.max_bytes
+                );
+                continue;
+            }
+            accepted.push(content);
+        }
+        let unchanged = accepted
+            .iter()
+            .all(|content| self.last.get(&content.format()) == Some(&content.fingerprint()));
+        if accepted.is_empty() || unchanged {
+            return Ok(Vec::new());
+        }
+        self.clipboard.write(&accepted)?;
+        //The write replaced every format, so only the accepted ones count as known.
+        self.last = accepted
+            .iter()
+            .map(|content| (content.format(), content.fingerprint()))
+            .collect();
+        Ok(accepted.iter().map(ClipboardContent::format).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::FakeClipboard;
+    use super::*;
+
+    fn text(value: &str) -> ClipboardContent {
+        ClipboardContent::Text(value.to_string())
+    }
+
+    fn sync(policy: ClipboardPolicy) -> (ClipboardSync, FakeClipboard) {
+        let clipboard = FakeClipboard::new();
+        (
+            ClipboardSync::new(Box::new(clipboard.clone()), policy),
+            clipboard,
+        )
+    }
+
+    #[test]
+    fn sends_host_changes_once() {
+        let (mut sync, clipboard) = sync(ClipboardPolicy::default());
+        assert_eq!(sync.poll().unwrap(), vec![]);
+        clipboard.copy(vec![
+            text("ls -la"),
+            ClipboardContent::Html("<b>ls</b>".to_string()),
+        ]);
+        assert_eq!(
+            sync.poll().unwrap(),
+            vec![
+                text("ls -la"),
+                ClipboardContent::Html("<b>ls</b>".to_string())
+            ]
+        );
+        assert_eq!(sync.poll().unwrap(), vec![]);
+        //Copying the same text again is not news; a changed format is sent on its own.
+        clipboard.copy(vec![
+            text("ls -la"),
+            ClipboardContent::Html("<i>ls</i>".to_string()),
+        ]);
+        assert_eq!(
+            sync.poll().unwrap(),
+            vec![ClipboardContent::Html("<i>ls</i>".to_string())]
+        );
+        assert_eq!(clipboard.reads(), 3);
+    }
+
+    #[test]
+    fn applied_content_does_not_bounce_back() {
+        let (mut sync, clipboard) = sync(ClipboardPolicy::default());
+        assert_eq!(
+            sync.apply(vec![text("sudo reboot")]).unwrap(),
+            vec![ClipboardFormat::Text]
+        );
+        assert_eq!(clipboard.contents(), vec![text("sudo reboot")]);
+        assert_eq!(sync.poll().unwrap(), vec![]);
+
+        //Nor is the host's own content rewritten when the viewer echoes it.
+        clipboard.copy(vec![text("uptime")]);
+        assert_eq!(sync.poll().unwrap(), vec![text("uptime")]);
+        assert_eq!(sync.apply(vec![text("uptime")]).unwrap(), vec![]);
+        assert_eq!(clipboard.writes(), 1);
+
+        //The same content copied on the host after the viewer changed it is sent again.
+        sync.apply(vec![text("df -h")]).unwrap();
+        clipboard.copy(vec![text("uptime")]);
+        assert_eq!(sync.poll().unwrap(), vec![text("uptime")]);
+    }
+
+    #[test]
+    fn policy_limits_formats_and_directions() {
+        let policy = ClipboardPolicy {
+            html: FormatPolicy {
+                direction: Direction::ToViewer,
+                max_bytes: 1024,
+            },
+            image: FormatPolicy {
+                direction: Direction::Off,
+                max_bytes: 1024,
+            },
+            text: FormatPolicy {
+                direction: Direction::Both,
+                max_bytes: 8,
+            },
+        };
+        let (mut sync, clipboard) = sync(policy);
+        let html = ClipboardContent::Html("<p>hi</p>".to_string());
+        let image = ClipboardContent::Image(vec![0x89, b'P', b'N', b'G']);
+        clipboard.copy(vec![text("short"), html.clone(), image.clone()]);
+        assert_eq!(sync.poll().unwrap(), vec![text("short"), html.clone()]);
+        clipboard.copy(vec![text("far too long")]);
+        assert_eq!(sync.poll().unwrap(), vec![]);
+
+        assert_eq!(
+            sync.apply(vec![text("pwd"), html, image]).unwrap(),
+            vec![ClipboardFormat::Text]
+        );
+        assert_eq!(clipboard.contents(), vec![text("pwd")]);
+        assert_eq!(sync.apply(vec![text("much too long")]).unwrap(), vec![]);
+        assert_eq!(clipboard.writes(), 1);
+    }
+
+    #[test]
+    fn directions_parse() {
+        for direction in [
+            Direction::Off,
+            Direction::ToViewer,
+            Direction::FromViewer,
+            Direction::Both,
+        ] {
+            assert_eq!(direction.to_string().parse(), Ok(direction));
+        }
+        assert_eq!("sideways".parse::<Direction>(), Err(()));
+    }
+}
@@ -0,0 +1,1503 @@
+use crate::capture::CaptureBackend;
+use crate::clipboard::{ClipboardPolicy, Direction, FormatPolicy};
+use crate::consent::{ConsentPolicy, ConsentSettings};
+use crate::encode::{Codec, EncoderSettings};
+use crate::hub::{self, HubConfig};
+use crate::input::layout::{Layout, LAYOUT_NAMES};
+use crate::input::InputBackend;
+use crate::ipc;
+use crate::logging::{self, ForwardSettings, LogFormat, LogSettings, LogSink, Rotation};
+use crate::supervisor::{self, RestartPolicy};
+use crate::transfer::{self, TransferPolicy};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+#[cfg(target_os = "windows")]
+static CONFIG_PATH: &str = "C:\\ProgramData\\DeskHub\\config.toml";
+#[cfg(target_os = "linux")]
+static CONFIG_PATH: &str = "/etc/deskhub/config.toml";
+
+//Points at a config file other than the default one.
+static CONFIG_PATH_ENV: &str = "DESKHUB_CONFIG";
+//Every field can be overridden by DESKHUB_<SECTION>_<FIELD>, e.g. DESKHUB_LOG_LEVEL.
+static ENV_PREFIX: &str = "DESKHUB_";
+
+static SECTIONS: &[&str] = &[
+    "service",
+    "log",
+    "window",
+    "desktop",
+    "capture",
+    "encoder",
+    "input",
+    "clipboard",
+    "transfer",
+    "consent",
+    "hub",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub service: ServiceSection,
+    pub log: LogSection,
+    pub window: WindowSection,
+    pub desktop: DesktopSection,
+    pub capture: CaptureSection,
+    pub encoder: EncoderSection,
+    pub input: InputSection,
+    pub clipboard: ClipboardSection,
+    pub transfer: TransferSection,
+    pub consent: ConsentSection,
+    pub hub: HubSection,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceSection {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSection {
+    //Each process writes <process>.log here: service, desktop or guide.
+    pub directory: PathBuf,
+    pub level: log::LevelFilter,
+    //text or json (one object per line).
+    pub format: LogFormat,
+    //Per-module levels, e.g. "deskhub::hub=debug,deskhub::capture=trace".
+    pub modules: Vec<(String, log::LevelFilter)>,
+    //A log file is rotated once it reaches this size...
+    pub max_file_bytes: u64,
+    //...or this age; 0 rotates by size only.
+    pub max_age_hours: u64,
+    //Rotated files kept per process.
+    pub retention: u64,
+    //file, or on Linux journald or syslog, for the service; the other processes use files.
+    pub sink: LogSink,
+    //The journald or syslog socket, if not the usual one.
+    pub socket: Option<PathBuf>,
+    //Service records at or above this level are also sent to the hub, if one is configured.
+    pub forward_level: log::LevelFilter,
+    //Records waiting for the hub are kept in <directory>/spool, up to this size.
+    pub spool_max_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowSection {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesktopSection {
+    //Window station and desktop the desktop process is started on (Windows only).
+    pub name: String,
+    pub restart_initial_backoff_secs: u64,
+    pub restart_max_backoff_secs: u64,
+    //Consecutive crashes before the service stops relaunching it; 0 never gives up.
+    pub max_restarts: u64,
+    //Where the service publishes the desktop process state for the guide window.
+    pub state_path: PathBuf,
+    //Socket (named pipe on Windows) the desktop process reaches the service through.
+    pub ipc_path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureSection {
+    pub backend: CaptureBackend,
+    //Time between captured frames.
+    pub interval_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncoderSection {
+    pub bitrate_kbps: u64,
+    //WebP quality range (1-100) the rate control moves within.
+    pub max_quality: u64,
+    pub min_quality: u64,
+    //Frames between forced keyframes; 0 sends them only when a viewer asks.
+    pub keyframe_interval: u64,
+    //png or zstd, for regions that look like text.
+    pub lossless: Codec,
+    //Regions with at most this many colours are sent lossless; 0 sends everything as WebP.
+    pub text_max_colors: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputSection {
+    //How viewer input reaches the session; disabled makes the agent view-only.
+    pub backend: InputBackend,
+    //Host keyboard layout that viewer key events are translated to.
+    pub layout: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipboardSection {
+    //Which way each format may cross: off, to_viewer, from_viewer or both.
+    pub text: Direction,
+    pub html: Direction,
+    pub image: Direction,
+    //Larger content is not synced in either direction.
+    pub max_text_bytes: u64,
+    pub max_html_bytes: u64,
+    pub max_image_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferSection {
+    //Viewers may only push files into and pull files out of these; empty disables transfers.
+    pub allowed_dirs: Vec<PathBuf>,
+    pub max_file_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsentSection {
+    //always, when_present or never: when the local user must accept a session first.
+    pub policy: ConsentPolicy,
+    //Unanswered prompts are denied after this long.
+    pub timeout_secs: u64,
+    //Someone counts as present if they used the keyboard or mouse within this long.
+    pub presence_idle_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HubSection {
+    //Without a URL the agent runs unmanaged.
+    pub url: Option<String>,
+    pub identity_path: PathBuf,
+    pub heartbeat_interval_secs: u64,
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            service: ServiceSection {
+                name: "DeskHubService".to_string(),
+            },
+            log: LogSection {
+                directory: logging::default_dir(),
+                level: log::LevelFilter::Info,
+                format: LogFormat::Text,
+                modules: Vec::new(),
+                max_file_bytes: 10 * 1024 * 1024,
+                max_age_hours: 24,
+                retention: 10,
+                sink: LogSink::File,
+                socket: None,
+                forward_level: log::LevelFilter::Info,
+                spool_max_bytes: 16 * 1024 * 1024,
+            },
+            window: WindowSection {
+                width: 520,
+                height: 360,
+            },
+            desktop: DesktopSection {
+                name: "winsta0\\default".to_string(),
+                restart_initial_backoff_secs: 1,
+                restart_max_backoff_secs: 60,
+                max_restarts: 10,
+                state_path: supervisor::default_state_path(),
+                ipc_path: ipc::default_path(),
+            },
+            capture: CaptureSection {
+                backend: CaptureBackend::Auto,
+                interval_ms: 100,
+            },
+            encoder: EncoderSection {
+                bitrate_kbps: 2000,
+                max_quality: 80,
+                min_quality: 20,
+                keyframe_interval: 300,
+                lossless: Codec::Zstd,
+                text_max_colors: 64,
+            },
+            input: InputSection {
+                backend: InputBackend::Auto,
+                layout: "us".to_string(),
+            },
+            clipboard: ClipboardSection {
+                text: Direction::Both,
+                html: Direction::Both,
+                image: Direction::Both,
+                max_text_bytes: 1 << 20,
+                max_html_bytes: 1 << 20,
+                max_image_bytes: 8 << 20,
+            },
+            transfer: TransferSection {
+                allowed_dirs: vec![transfer::default_dir()],
+                max_file_bytes: 4 << 30,
+            },
+            consent: ConsentSection {
+                policy: ConsentPolicy::Always,
+                timeout_secs: 30,
+                presence_idle_secs: 300,
+            },
+            hub: HubSection {
+                url: None,
+                identity_path: hub::identity::default_path(),
+                heartbeat_interval_secs: 60,
+                initial_backoff_secs: 1,
+                max_backoff_secs: 60,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+//Everything wrong with a configuration, so it can be fixed in one go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub origin: PathBuf,
+    pub errors: Vec<FieldError>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration ({}):", self.origin.display())?;
+        for error in &self.errors {
+            write!(f, "\n  {}: {}", error.field, error.message)?;
+        }
+        Ok(())
+    }
+}
+
+//Whether a ws:// URL points at this machine, where an unencrypted hub connection is acceptable.
+fn is_loopback(url: &str) -> bool {
+    let authority = url["ws://".len()..].split('/').next().unwrap_or("");
+    let authority = authority.rsplit('@').next().unwrap_or("");
+    let host = match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(""),
+        None => authority.split(':').next().unwrap_or(""),
+    };
+    host == "localhost"
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+pub fn default_path() -> PathBuf {
+    PathBuf::from(CONFIG_PATH)
+}
+
+enum Raw {
+    File(toml::Value),
+    Env(String),
+}
+
+struct Loader<'a> {
+    table: toml::Table,
+    env: &'a HashMap<String, String>,
+    errors: Vec<FieldError>,
+}
+
+impl<'a> Loader<'a> {
+    fn error(&mut self, field: &str, message: String) {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            message,
+        });
+    }
+
+    //Remove the field from the file table (leftovers are unknown fields) and apply the env override.
+    fn take(&mut self, section: &str, key: &str) -> Option<Raw> {
+        let from_file = self
+            .table
+            .get_mut(section)
+            .and_then(|value| value.as_table_mut())
+            .and_then(|table| table.remove(key));
+        let env_name = format!("{}{}_{}", ENV_PREFIX, section, key).to_uppercase();
+        match self.env.get(&env_name) {
+            Some(value) => Some(Raw::Env(value.clone())),
+            None => from_file.map(Raw::File),
+        }
+    }
+
+    fn string(&mut self, section: &str, key: &str, default: &str) -> String {
+        let field = format!("{}.{}", section, key);
+        match self.take(section, key) {
+            None => default.to_string(),
+            Some(Raw::Env(value)) | Some(Raw::File(toml::Value::String(value))) => value,
+            Some(Raw::File(other)) => {
+                self.error(
+                    &field,
+                    format!("expected a string, found {}", other.type_str()),
+                );
+                default.to_string()
+            }
+        }
+    }
+
+    fn non_empty(&mut self, section: &str, key: &str, default: &str) -> String {
+        let value = self.string(section, key, default);
+        if value.trim().is_empty() {
+            self.error(
+                &format!("{}.{}", section, key),
+                "must not be empty".to_string(),
+            );
+            return default.to_string();
+        }
+        value
+    }
+
+    fn integer(
+        &mut self,
+        section: &str,
+        key: &str,
+        default: u64,
+        range: RangeInclusive<u64>,
+    ) -> u64 {
+        let field = format!("{}.{}", section, key);
+        let value = match self.take(section, key) {
+            None => return default,
+            Some(Raw::Env(value)) => match value.trim().parse::<u64>() {
+                Ok(value) => value,
+                Err(_) => {
+                    self.error(
+                        &field,
+                        format!("expected a whole number, found {:?}", value),
+                    );
+                    return default;
+                }
+            },
+            Some(Raw::File(toml::Value::Integer(value))) if value >= 0 => value as u64,
+            Some(Raw::File(other)) => {
+                self.error(&field, format!("expected a whole number, found {}", other));
+                return default;
+            }
+        };
+        if !range.contains(&value) {
+            self.error(
+                &field,
+                format!(
+                    "must be between {} and {}, found {}",
+                    range.start(),
+                    range.end(),
+                    value
+                ),
+            );
+            return default;
+        }
+        value
+    }
+
+    //A list of paths; an environment override separates them like PATH does.
+    fn paths(&mut self, section: &str, key: &str, default: &[PathBuf]) -> Vec<PathBuf> {
+        let field = format!("{}.{}", section, key);
+        match self.take(section, key) {
+            None => default.to_vec(),
+            Some(Raw::Env(value)) => env::split_paths(&value)
+                .filter(|path| !path.as_os_str().is_empty())
+                .collect(),
+            Some(Raw::File(toml::Value::Array(values))) => {
+                let paths: Option<Vec<PathBuf>> = values
+                    .iter()
+                    .map(|value| value.as_str().map(PathBuf::from))
+                    .collect();
+                paths.unwrap_or_else(|| {
+                    self.error(&field, "expected a list of paths".to_string());
+                    default.to_vec()
+                })
+            }
+            Some(Raw::File(other)) => {
+                self.error(
+                    &field,
+                    format!("expected a list of paths, found {}", other.type_str()),
+                );
+                default.to_vec()
+            }
+        }
+    }
+
+    fn parsed<T: FromStr>(&mut self, section: &str, key: &str, default: T, expected: &str) -> T {
+        let raw = self.string(section, key, "");
+        if raw.is_empty() {
+            return default;
+        }
+        match raw.parse::<T>() {
+            Ok(value) => value,
+            Err(_) => {
+                self.error(
+                    &format!("{}.{}", section, key),
+                    format!("expected {}, found {:?}", expected, raw),
+                );
+                default
+            }
+        }
+    }
+
+    fn report_unknown(&mut self) {
+        let table = std::mem::take(&mut self.table);
+        for (section, value) in table {
+            if !SECTIONS.contains(&section.as_str()) {
+                self.error(&section, "unknown section".to_string());
+                continue;
+            }
+            match value {
+                toml::Value::Table(fields) => {
+                    for key in fields.keys() {
+                        self.error(&format!("{}.{}", section, key), "unknown field".to_string());
+                    }
+                }
+                other => self.error(
+                    &section,
+                    format!("expected a table, found {}", other.type_str()),
+                ),
+            }
+        }
+    }
+}
+
+impl Config {
+    //Load the file named by DESKHUB_CONFIG, or the default one if it exists, then apply DESKHUB_* overrides.
+    pub fn load() -> Result<Config, ConfigError> {
+        Self::load_with_source().map(|(config, _)| config)
+    }
+
+    //As load, also returning the file that was read, or None when running on defaults.
+    pub fn load_with_source() -> Result<(Config, Option<PathBuf>), ConfigError> {
+        let env: HashMap<String, String> = env::vars()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        Self::load_with_env(&env)
+    }
+
+    pub fn load_with_env(
+        env: &HashMap<String, String>,
+    ) -> Result<(Config, Option<PathBuf>), ConfigError> {
+        let (path, explicit) = match env.get(CONFIG_PATH_ENV) {
+            Some(path) => (PathBuf::from(path), true),
+            None => (default_path(), false),
+        };
+        let (text, source) = match fs::read_to_string(&path) {
+            Ok(text) => (text, Some(path.clone())),
+            //Running without a config file is fine; asking for one that is not there is not.
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => (String::new(), None),
+            Err(e) => {
+                return Err(ConfigError {
+                    origin: path,
+                    errors: vec![FieldError {
+                        field: "file".to_string(),
+                        message: e.to_string(),
+                    }],
+                })
+            }
+        };
+        Ok((Self::parse(&text, &path, env)?, source))
+    }
+
+    pub fn parse(
+        text: &str,
+        origin: &Path,
+        env: &HashMap<String, String>,
+    ) -> Result<Config, ConfigError> {
+        let table: toml::Table = text.parse().map_err(|e: toml::de::Error| ConfigError {
+            origin: origin.to_path_buf(),
+            errors: vec![FieldError {
+                field: "file".to_string(),
+                message: e.message().to_string(),
+            }],
+        })?;
+        let defaults = Config::default();
+        let mut loader = Loader {
+            table,
+            env,
+            errors: Vec::new(),
+        };
+
+        let service_name = loader.non_empty("service", "name", &defaults.service.name);
+        if service_name.contains(|c: char| c.is_whitespace() || c == '/' || c == '\\') {
+            loader.error(
+                "service.name",
+                "must not contain whitespace or path separators".to_string(),
+            );
+        }
+
+        let log_directory = loader.non_empty(
+            "log",
+            "directory",
+            &defaults.log.directory.to_string_lossy(),
+        );
+        let log_level = loader.parsed(
+            "log",
+            "level",
+            defaults.log.level,
+            "one of off, error, warn, info, debug, trace",
+        );
+        let log_format = loader.parsed("log", "format", defaults.log.format, "text or json");
+        let log_modules = loader.string("log", "modules", "");
+        let log_modules = logging::parse_modules(&log_modules).unwrap_or_else(|e| {
+            loader.error("log.modules", e);
+            Vec::new()
+        });
+        let log_max_file_bytes = loader.integer(
+            "log",
+            "max_file_bytes",
+            defaults.log.max_file_bytes,
+            4096..=1 << 40,
+        );
+        let max_age_hours =
+            loader.integer("log", "max_age_hours", defaults.log.max_age_hours, 0..=8760);
+        let retention = loader.integer("log", "retention", defaults.log.retention, 0..=1000);
+        let log_sink = loader.parsed(
+            "log",
+            "sink",
+            defaults.log.sink,
+            "one of file, journald, syslog",
+        );
+        if cfg!(not(target_os = "linux")) && log_sink != LogSink::File {
+            loader.error(
+                "log.sink",
+                format!("{} is only available on Linux", log_sink),
+            );
+        }
+        let log_socket = loader.string("log", "socket", "");
+        let forward_level = loader.parsed(
+            "log",
+            "forward_level",
+            defaults.log.forward_level,
+            "one of off, error, warn, info, debug, trace",
+        );
+        let spool_max_bytes = loader.integer(
+            "log",
+            "spool_max_bytes",
+            defaults.log.spool_max_bytes,
+            65536..=1 << 30,
+        );
+
+        let width = loader.integer("window", "width", defaults.window.width as u64, 200..=4096);
+        let height = loader.integer(
+            "window",
+            "height",
+            defaults.window.height as u64,
+            200..=4096,
+        );
+
+        let desktop_name = loader.non_empty("desktop", "name", &defaults.desktop.name);
+        let restart_initial_backoff_secs = loader.integer(
+            "desktop",
+            "restart_initial_backoff_secs",
+            defaults.desktop.restart_initial_backoff_secs,
+            1..=3600,
+        );
+        let restart_max_backoff_secs = loader.integer(
+            "desktop",
+            "restart_max_backoff_secs",
+            defaults.desktop.restart_max_backoff_secs,
+            1..=3600,
+        );
+        if restart_max_backoff_secs < restart_initial_backoff_secs {
+            loader.error(
+                "desktop.restart_max_backoff_secs",
+                "must not be less than desktop.restart_initial_backoff_secs".to_string(),
+            );
+        }
+        let max_restarts = loader.integer(
+            "desktop",
+            "max_restarts",
+            defaults.desktop.max_restarts,
+            0..=10000,
+        );
+        let state_path = loader.non_empty(
+            "desktop",
+            "state_path",
+            &defaults.desktop.state_path.to_string_lossy(),
+        );
+        let ipc_path = loader.non_empty(
+            "desktop",
+            "ipc_path",
+            &defaults.desktop.ipc_path.to_string_lossy(),
+        );
+
+        let backend = loader.parsed(
+            "capture",
+            "backend",
+            defaults.capture.backend,
+            "one of auto, x11, synthetic",
+        );
+        let interval_ms = loader.integer(
+            "capture",
+            "interval_ms",
+            defaults.capture.interval_ms,
+            10..=10000,
+        );
+
+        let bitrate_kbps = loader.integer(
+            "encoder",
+            "bitrate_kbps",
+            defaults.encoder.bitrate_kbps,
+            10..=100_000,
+        );
+        let max_quality = loader.integer(
+            "encoder",
+            "max_quality",
+            defaults.encoder.max_quality,
+            1..=100,
+        );
+        let min_quality = loader.integer(
+            "encoder",
+            "min_quality",
+            defaults.encoder.min_quality,
+            1..=100,
+        );
+        if max_quality < min_quality {
+            loader.error(
+                "encoder.max_quality",
+                "must not be less than encoder.min_quality".to_string(),
+            );
+        }
+        let keyframe_interval = loader.integer(
+            "encoder",
+            "keyframe_interval",
+            defaults.encoder.keyframe_interval,
+            0..=100_000,
+        );
+        let lossless = loader.parsed(
+            "encoder",
+            "lossless",
+            defaults.encoder.lossless,
+            "one of png, zstd",
+        );
+        if lossless == Codec::Webp {
+            loader.error(
+                "encoder.lossless",
+                "expected one of png, zstd, found \"webp\"".to_string(),
+            );
+        }
+        let text_max_colors = loader.integer(
+            "encoder",
+            "text_max_colors",
+            defaults.encoder.text_max_colors,
+            0..=4096,
+        );
+
+        let input_backend = loader.parsed(
+            "input",
+            "backend",
+            defaults.input.backend,
+            "one of auto, x11, disabled",
+        );
+        let mut layout = loader.string("input", "layout", &defaults.input.layout);
+        if Layout::named(&layout).is_none() {
+            loader.error(
+                "input.layout",
+                format!(
+                    "expected one of {}, found {:?}",
+                    LAYOUT_NAMES.join(", "),
+                    layout
+                ),
+            );
+            layout = defaults.input.layout.clone();
+        }
+
+        let directions = "one of off, to_viewer, from_viewer, both";
+        let text = loader.parsed("clipboard", "text", defaults.clipboard.text, directions);
+        let html = loader.parsed("clipboard", "html", defaults.clipboard.html, directions);
+        let image = loader.parsed("clipboard", "image", defaults.clipboard.image, directions);
+        let max_text_bytes = loader.integer(
+            "clipboard",
+            "max_text_bytes",
+            defaults.clipboard.max_text_bytes,
+            1..=64 << 20,
+        );
+        let max_html_bytes = loader.integer(
+            "clipboard",
+            "max_html_bytes",
+            defaults.clipboard.max_html_bytes,
+            1..=64 << 20,
+        );
+        let max_image_bytes = loader.integer(
+            "clipboard",
+            "max_image_bytes",
+            defaults.clipboard.max_image_bytes,
+            1..=64 << 20,
+        );
+
+        let allowed_dirs =
+            loader.paths("transfer", "allowed_dirs", &defaults.transfer.allowed_dirs);
+        if let Some(relative) = allowed_dirs.iter().find(|dir| !dir.is_absolute()) {
+            loader.error(
+                "transfer.allowed_dirs",
+                format!("expected absolute paths, found {:?}", relative),
+            );
+        }
+        let max_file_bytes = loader.integer(
+            "transfer",
+            "max_file_bytes",
+            defaults.transfer.max_file_bytes,
+            1..=1 << 40,
+        );
+
+        let consent_policy = loader.parsed(
+            "consent",
+            "policy",
+            defaults.consent.policy,
+            "one of always, when_present, never",
+        );
+        let timeout_secs = loader.integer(
+            "consent",
+            "timeout_secs",
+            defaults.consent.timeout_secs,
+            5..=600,
+        );
+        let presence_idle_secs = loader.integer(
+            "consent",
+            "presence_idle_secs",
+            defaults.consent.presence_idle_secs,
+            10..=86400,
+        );
+
+        let url = loader.string("hub", "url", "");
+        if !url.is_empty() && !url.starts_with("ws://") && !url.starts_with("wss://") {
+            loader.error(
+                "hub.url",
+                format!("expected a ws:// or wss:// URL, found {:?}", url),
+            );
+        } else if url.starts_with("ws://") && !is_loopback(&url) {
+            loader.error(
+                "hub.url",
+                "ws:// sends the agent's credential unencrypted; use wss://".to_string(),
+            );
+        }
+        let identity_path = loader.non_empty(
+            "hub",
+            "identity_path",
+            &defaults.hub.identity_path.to_string_lossy(),
+        );
+        let heartbeat_interval_secs = loader.integer(
+            "hub",
+            "heartbeat_interval_secs",
+            defaults.hub.heartbeat_interval_secs,
+            5..=86400,
+        );
+        let initial_backoff_secs = loader.integer(
+            "hub",
+            "initial_backoff_secs",
+            defaults.hub.initial_backoff_secs,
+            1..=3600,
+        );
+        let max_backoff_secs = loader.integer(
+            "hub",
+            "max_backoff_secs",
+            defaults.hub.max_backoff_secs,
+            1..=3600,
+        );
+        if max_backoff_secs < initial_backoff_secs {
+            loader.error(
+                "hub.max_backoff_secs",
+                "must not be less than hub.initial_backoff_secs".to_string(),
+            );
+        }
+
+        loader.report_unknown();
+        if !loader.errors.is_empty() {
+            return Err(ConfigError {
+                origin: origin.to_path_buf(),
+                errors: loader.errors,
+            });
+        }
+
+        Ok(Config {
+            service: ServiceSection { name: service_name },
+            log: LogSection {
+                directory: PathBuf::from(log_directory),
+                level: log_level,
+                format: log_format,
+                modules: log_modules,
+                max_file_bytes: log_max_file_bytes,
+                max_age_hours,
+                retention,
+                sink: log_sink,
+                socket: (!log_socket.is_empty()).then(|| PathBuf::from(log_socket)),
+                forward_level,
+                spool_max_bytes,
+            },
+            window: WindowSection {
+                width: width as u32,
+                height: height as u32,
+            },
+            desktop: DesktopSection {
+                name: desktop_name,
+                restart_initial_backoff_secs,
+                restart_max_backoff_secs,
+                max_restarts,
+                state_path: PathBuf::from(state_path),
+                ipc_path: PathBuf::from(ipc_path),
+            },
+            capture: CaptureSection {
+                backend,
+                interval_ms,
+            },
+            encoder: EncoderSection {
+                bitrate_kbps,
+                max_quality,
+                min_quality,
+                keyframe_interval,
+                lossless,
+                text_max_colors,
+            },
+            input: InputSection {
+                backend: input_backend,
+                layout,
+            },
+            clipboard: ClipboardSection {
+                text,
+                html,
+                image,
+                max_text_bytes,
+                max_html_bytes,
+                max_image_bytes,
+            },
+            transfer: TransferSection {
+                allowed_dirs,
+                max_file_bytes,
+            },
+            consent: ConsentSection {
+                policy: consent_policy,
+                timeout_secs,
+                presence_idle_secs,
+            },
+            hub: HubSection {
+                url: if url.is_empty() { None } else { Some(url) },
+                identity_path: PathBuf::from(identity_path),
+                heartbeat_interval_secs,
+                initial_backoff_secs,
+                max_backoff_secs,
+            },
+        })
+    }
+
+    //Render the configuration as a file `parse` reads back unchanged.
+    pub fn to_toml(&self) -> String {
+        fn section(entries: Vec<(&str, toml::Value)>) -> toml::Value {
+            toml::Value::Table(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value))
+                    .collect(),
+            )
+        }
+        fn string(value: impl ToString) -> toml::Value {
+            toml::Value::String(value.to_string())
+        }
+        fn integer(value: u64) -> toml::Value {
+            toml::Value::Integer(value as i64)
+        }
+
+        let mut hub = vec![
+            ("identity_path", string(self.hub.identity_path.display())),
+            (
+                "heartbeat_interval_secs",
+                integer(self.hub.heartbeat_interval_secs),
+            ),
+            (
+                "initial_backoff_secs",
+                integer(self.hub.initial_backoff_secs),
+            ),
+            ("max_backoff_secs", integer(self.hub.max_backoff_secs)),
+        ];
+        if let Some(url) = &self.hub.url {
+            hub.insert(0, ("url", string(url)));
+        }
+        let mut table = toml::Table::new();
+        table.insert(
+            "service".to_string(),
+            section(vec![("name", string(&self.service.name))]),
+        );
+        let mut log = vec![
+            ("directory", string(self.log.directory.display())),
+            ("level", string(self.log.level.as_str().to_lowercase())),
+            ("format", string(self.log.format)),
+            (
+                "modules",
+                string(logging::format_modules(&self.log.modules)),
+            ),
+            ("max_file_bytes", integer(self.log.max_file_bytes)),
+            ("max_age_hours", integer(self.log.max_age_hours)),
+            ("retention", integer(self.log.retention)),
+            (
+                "forward_level",
+                string(self.log.forward_level.as_str().to_lowercase()),
+            ),
+            ("spool_max_bytes", integer(self.log.spool_max_bytes)),
+            ("sink", string(self.log.sink)),
+        ];
+        if let Some(socket) = &self.log.socket {
+            log.push(("socket", string(socket.display())));
+        }
+        table.insert("log".to_string(), section(log));
+        table.insert(
+            "window".to_string(),
+            section(vec![
+                ("width", integer(self.window.width as u64)),
+                ("height", integer(self.window.height as u64)),
+            ]),
+        );
+        table.insert(
+            "desktop".to_string(),
+            section(vec![
+                ("name", string(&self.desktop.name)),
+                (
+                    "restart_initial_backoff_secs",
+                    integer(self.desktop.restart_initial_backoff_secs),
+                ),
+                (
+                    "restart_max_backoff_secs",
+                    integer(self.desktop.restart_max_backoff_secs),
+                ),
+                ("max_restarts", integer(self.desktop.max_restarts)),
+                ("state_path", string(self.desktop.state_path.display())),
+                ("ipc_path", string(self.desktop.ipc_path.display())),
+            ]),
+        );
+        table.insert(
+            "capture".to_string(),
+            section(vec![
+                ("backend", string(self.capture.backend)),
+                ("interval_ms", integer(self.capture.interval_ms)),
+            ]),
+        );
+        table.insert(
+            "encoder".to_string(),
+            section(vec![
+                ("bitrate_kbps", integer(self.encoder.bitrate_kbps)),
+                ("max_quality", integer(self.encoder.max_quality)),
+                ("min_quality", integer(self.encoder.min_quality)),
+                ("keyframe_interval", integer(self.encoder.keyframe_interval)),
+                ("lossless", string(self.encoder.lossless)),
+                ("text_max_colors", integer(self.encoder.text_max_colors)),
+            ]),
+        );
+        table.insert(
+            "input".to_string(),
+            section(vec![
+                ("backend", string(self.input.backend)),
+                ("layout", string(&self.input.layout)),
+            ]),
+        );
+        table.insert(
+            "clipboard".to_string(),
+            section(vec![
+                ("text", string(self.clipboard.text)),
+                ("html", string(self.clipboard.html)),
+                ("image", string(self.clipboard.image)),
+                ("max_text_bytes", integer(self.clipboard.max_text_bytes)),
+                ("max_html_bytes", integer(self.clipboard.max_html_bytes)),
+                ("max_image_bytes", integer(self.clipboard.max_image_bytes)),
+            ]),
+        );
+        table.insert(
+            "transfer".to_string(),
+            section(vec![
+                (
+                    "allowed_dirs",
+                    toml::Value::Array(
+                        self.transfer
+                            .allowed_dirs
+                            .iter()
+                            .map(|dir| string(dir.display()))
+                            .collect(),
+                    ),
+                ),
+                ("max_file_bytes", integer(self.transfer.max_file_bytes)),
+            ]),
+        );
+        table.insert(
+            "consent".to_string(),
+            section(vec![
+                ("policy", string(self.consent.policy)),
+                ("timeout_secs", integer(self.consent.timeout_secs)),
+                (
+                    "presence_idle_secs",
+                    integer(self.consent.presence_idle_secs),
+                ),
+            ]),
+        );
+        table.insert("hub".to_string(), section(hub));
+        table.to_string()
+    }
+
+    pub fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy {
+            initial_backoff: Duration::from_secs(self.desktop.restart_initial_backoff_secs),
+            max_backoff: Duration::from_secs(self.desktop.restart_max_backoff_secs),
+            max_restarts: self.desktop.max_restarts as u32,
+            //A desktop that stayed up for a few minutes was not crash-looping.
+            stable_after: Duration::from_secs(300),
+            poll_interval: Duration::from_millis(500),
+        }
+    }
+
+    pub fn encoder_settings(&self) -> EncoderSettings {
+        EncoderSettings {
+            bitrate_kbps: self.encoder.bitrate_kbps as u32,
+            frame_rate: (1000 / self.capture.interval_ms).max(1) as u32,
+            max_quality: self.encoder.max_quality as u8,
+            min_quality: self.encoder.min_quality as u8,
+            keyframe_interval: self.encoder.keyframe_interval as u32,
+            lossless: self.encoder.lossless,
+            text_max_colors: self.encoder.text_max_colors as usize,
+            scale: 1.0,
+        }
+    }
+
+    pub fn log_settings(&self) -> LogSettings {
+        LogSettings {
+            directory: self.log.directory.clone(),
+            format: self.log.format,
+            level: self.log.level,
+            modules: self.log.modules.clone(),
+            rotation: Rotation {
+                max_bytes: self.log.max_file_bytes,
+                max_age: (self.log.max_age_hours > 0)
+                    .then(|| Duration::from_secs(self.log.max_age_hours * 3600)),
+                keep: self.log.retention as usize,
+            },
+            sink: self.log.sink,
+            socket: self.log.socket.clone(),
+            forward: (self.hub.url.is_some() && self.log.forward_level != log::LevelFilter::Off)
+                .then_some(ForwardSettings {
+                    level: self.log.forward_level,
+                    spool_max_bytes: self.log.spool_max_bytes,
+                }),
+        }
+    }
+
+    pub fn clipboard_policy(&self) -> ClipboardPolicy {
+        let policy = |direction, max_bytes: u64| FormatPolicy {
+            direction,
+            max_bytes: max_bytes as usize,
+        };
+        ClipboardPolicy {
+            text: policy(self.clipboard.text, self.clipboard.max_text_bytes),
+            html: policy(self.clipboard.html, self.clipboard.max_html_bytes),
+            image: policy(self.clipboard.image, self.clipboard.max_image_bytes),
+        }
+    }
+
+    pub fn transfer_policy(&self) -> TransferPolicy {
+        TransferPolicy {
+            allowed_dirs: self.transfer.allowed_dirs.clone(),
+            max_file_bytes: self.transfer.max_file_bytes,
+        }
+    }
+
+    pub fn consent_settings(&self) -> ConsentSettings {
+        ConsentSettings {
+            policy: self.consent.policy,
+            timeout: Duration::from_secs(self.consent.timeout_secs),
+            presence_idle: Duration::from_secs(self.consent.presence_idle_secs),
+        }
+    }
+
+    pub fn hub_config(&self) -> Option<HubConfig> {
+        let url = self.hub.url.as_ref()?;
+        Some(HubConfig {
+            url: url.clone(),
+            identity_path: self.hub.identity_path.clone(),
+            initial_backoff: Duration::from_secs(self.hub.initial_backoff_secs),
+            max_backoff: Duration::from_secs(self.hub.max_backoff_secs),
+            heartbeat_interval: Duration::from_secs(self.hub.heartbeat_interval_secs),
+            //Set by hub::run from the service's logger.
+            logs: None,
+            levels: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str, env: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        Config::parse(text, Path::new("test.toml"), &env)
+    }
+
+    fn fields(err: ConfigError) -> Vec<String> {
+        err.errors.into_iter().map(|error| error.field).collect()
+    }
+
+    #[test]
+    fn empty_file_uses_defaults() {
+        let config = parse("", &[]).unwrap();
+        assert_eq!(config, Config::default());
+        assert!(config.hub_config().is_none());
+        //Nothing to forward logs to without a hub.
+        assert_eq!(config.log_settings().forward, None);
+    }
+
+    #[test]
+    fn reads_every_section() {
+        let config = parse(
+            r#"
+            [service]
+            name = "DeskHubTest"
+
+            [log]
+            directory = "/tmp/deskhub"
+            level = "debug"
+            format = "json"
+            modules = "deskhub::hub=trace"
+            max_file_bytes = 65536
+            max_age_hours = 0
+            retention = 2
+            forward_level = "warn"
+            spool_max_bytes = 1048576
+
+            [window]
+            width = 800
+            height = 600
+
+            [desktop]
+            name = "winsta0\\winlogon"
+            restart_initial_backoff_secs = 5
+            restart_max_backoff_secs = 300
+            max_restarts = 0
+            ipc_path = "/tmp/deskhub/ipc.sock"
+
+            [capture]
+            backend = "synthetic"
+            interval_ms = 250
+
+            [encoder]
+            bitrate_kbps = 500
+            max_quality = 60
+            min_quality = 10
+            keyframe_interval = 0
+            lossless = "png"
+            text_max_colors = 16
+
+            [input]
+            backend = "disabled"
+            layout = "de"
+
+            [clipboard]
+            html = "to_viewer"
+            image = "off"
+            max_text_bytes = 4096
+
+            [transfer]
+            allowed_dirs = ["/srv/installers", "/var/log/deskhub"]
+            max_file_bytes = 1048576
+
+            [consent]
+            policy = "when_present"
+            timeout_secs = 20
+            presence_idle_secs = 120
+
+            [hub]
+            url = "wss://hub.example.com/agent"
+            identity_path = "/tmp/identity.json"
+            heartbeat_interval_secs = 30
+            initial_backoff_secs = 2
+            max_backoff_secs = 120
+            "#,
+            &[],
+        )
+        .unwrap();
+        assert_eq!(config.service.name, "DeskHubTest");
+        assert_eq!(config.log.directory, PathBuf::from("/tmp/deskhub"));
+        assert_eq!(config.log.level, log::LevelFilter::Debug);
+        let log = config.log_settings();
+        assert_eq!(log.format, LogFormat::Json);
+        assert_eq!(
+            log.modules,
+            vec![("deskhub::hub".to_string(), log::LevelFilter::Trace)]
+        );
+        assert_eq!(
+            log.rotation,
+            Rotation {
+                max_bytes: 65536,
+                max_age: None,
+                keep: 2,
+            }
+        );
+        assert_eq!(
+            log.forward,
+            Some(ForwardSettings {
+                level: log::LevelFilter::Warn,
+                spool_max_bytes: 1 << 20,
+            })
+        );
+        assert_eq!((config.window.width, config.window.height), (800, 600));
+        assert_eq!(config.desktop.name, "winsta0\\winlogon");
+        assert_eq!(config.desktop.ipc_path, Path::new("/tmp/deskhub/ipc.sock"));
+        let policy = config.restart_policy();
+        assert_eq!(policy.initial_backoff, Duration::from_secs(5));
+        assert_eq!(policy.max_backoff, Duration::from_secs(300));
+        assert_eq!(policy.max_restarts, 0);
+        assert_eq!(config.capture.backend, CaptureBackend::Synthetic);
+        assert_eq!(config.capture.interval_ms, 250);
+        let encoder = config.encoder_settings();
+        assert_eq!(encoder.bitrate_kbps, 500);
+        assert_eq!(encoder.frame_rate, 4);
+        assert_eq!((encoder.min_quality, encoder.max_quality), (10, 60));
+        assert_eq!(encoder.keyframe_interval, 0);
+        assert_eq!(encoder.lossless, Codec::Png);
+        assert_eq!(encoder.text_max_colors, 16);
+        assert_eq!(config.input.backend, InputBackend::Disabled);
+        assert_eq!(config.input.layout, "de");
+        let clipboard = config.clipboard_policy();
+        assert_eq!(clipboard.text.direction, Direction::Both);
+        assert_eq!(clipboard.text.max_bytes, 4096);
+        assert_eq!(clipboard.html.direction, Direction::ToViewer);
+        assert_eq!(clipboard.image.direction, Direction::Off);
+        assert_eq!(clipboard.image.max_bytes, 8 << 20);
+        let transfer = config.transfer_policy();
+        assert_eq!(
+            transfer.allowed_dirs,
+            vec![
+                PathBuf::from("/srv/installers"),
+                PathBuf::from("/var/log/deskhub")
+            ]
+        );
+        assert_eq!(transfer.max_file_bytes, 1 << 20);
+        let consent = config.consent_settings();
+        assert_eq!(consent.policy, ConsentPolicy::WhenPresent);
+        assert_eq!(consent.timeout, Duration::from_secs(20));
+        assert_eq!(consent.presence_idle, Duration::from_secs(120));
+
+        let hub = config.hub_config().unwrap();
+        assert_eq!(hub.url, "wss://hub.example.com/agent");
+        assert_eq!(hub.identity_path, PathBuf::from("/tmp/identity.json"));
+        assert_eq!(hub.heartbeat_interval, Duration::from_secs(30));
+        assert_eq!(hub.initial_backoff, Duration::from_secs(2));
+        assert_eq!(hub.max_backoff, Duration::from_secs(120));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn selects_a_system_log_sink() {
+        let config = parse(
+            r#"
+            [log]
+            sink = "syslog"
+            socket = "/run/test/log"
+            "#,
+            &[],
+        )
+        .unwrap();
+        let log = config.log_settings();
+        assert_eq!(log.sink, LogSink::Syslog);
+        assert_eq!(log.socket, Some(PathBuf::from("/run/test/log")));
+        assert_eq!(parse(&config.to_toml(), &[]).unwrap(), config);
+    }
+
+    #[test]
+    fn environment_overrides_file() {
+        let dirs = env::join_paths(["/srv/a", "/srv/b"]).unwrap();
+        let dirs = dirs.to_string_lossy();
+        let config = parse(
+            "[log]\nlevel = \"warn\"\n[hub]\nheartbeat_interval_secs = 30\n",
+            &[
+                ("DESKHUB_LOG_LEVEL", "trace"),
+                ("DESKHUB_HUB_URL", "ws://127.0.0.1:9000"),
+                ("DESKHUB_HUB_HEARTBEAT_INTERVAL_SECS", "15"),
+                ("DESKHUB_UNRELATED", "ignored"),
+                ("DESKHUB_TRANSFER_ALLOWED_DIRS", &dirs),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.log.level, log::LevelFilter::Trace);
+        assert_eq!(config.hub.url.as_deref(), Some("ws://127.0.0.1:9000"));
+        assert_eq!(config.hub.heartbeat_interval_secs, 15);
+        assert_eq!(
+            config.transfer.allowed_dirs,
+            vec![PathBuf::from("/srv/a"), PathBuf::from("/srv/b")]
+        );
+    }
+
+    #[test]
+    fn plaintext_hub_only_on_this_machine() {
+        for url in [
+            "ws://localhost/agent",
+            "ws://127.0.0.1:9000",
+            "ws://[::1]:9000/agent",
+        ] {
+            let config = parse("", &[("DESKHUB_HUB_URL", url)]).unwrap();
+            assert_eq!(config.hub.url.as_deref(), Some(url));
+        }
+        for url in [
+            "ws://hub.example.com/agent",
+            "ws://10.0.0.1",
+            "ws://localhost.evil.com",
+        ] {
+            let err = parse("", &[("DESKHUB_HUB_URL", url)]).unwrap_err();
+            assert_eq!(fields(err), vec!["hub.url"]);
+        }
+    }
+
+    #[test]
+    fn reports_every_invalid_field() {
+        let err = parse(
+            r#"
+            [service]
+            name = "Desk Hub"
+
+            [log]
+            level = "loud"
+            sink = "eventlog"
+            modules = "deskhub::hub"
+
+            [window]
+            width = 10
+            height = "tall"
+
+            [capture]
+            backend = "vnc"
+
+            [encoder]
+            lossless = "webp"
+            max_quality = 10
+            min_quality = 50
+
+            [input]
+            backend = "uinput"
+            layout = "dvorak"
+
+            [clipboard]
+            text = "sideways"
+            max_image_bytes = 0
+
+            [transfer]
+            allowed_dirs = ["relative/dir"]
+            max_file_bytes = "big"
+
+            [consent]
+            policy = "sometimes"
+            timeout_secs = 1
+
+            [hub]
+            url = "http://hub.example.com"
+            initial_backoff_secs = 30
+            max_backoff_secs = 10
+            colour = "blue"
+
+            [extras]
+            "#,
+            &[
+                ("DESKHUB_DESKTOP_NAME", ""),
+                ("DESKHUB_HUB_HEARTBEAT_INTERVAL_SECS", "soon"),
+            ],
+        )
+        .unwrap_err();
+        let mut fields = fields(err);
+        fields.sort();
+        assert_eq!(
+            fields,
+            vec![
+                "capture.backend",
+                "clipboard.max_image_bytes",
+                "clipboard.text",
+                "consent.policy",
+                "consent.timeout_secs",
+                "desktop.name",
+                "encoder.lossless",
+                "encoder.max_quality",
+                "extras",
+                "hub.colour",
+                "hub.heartbeat_interval_secs",
+                "hub.max_backoff_secs",
+                "hub.url",
+                "input.backend",
+                "input.layout",
+                "log.level",
+                "log.modules",
+                "log.sink",
+                "service.name",
+                "transfer.allowed_dirs",
+                "transfer.max_file_bytes",
+                "window.height",
+                "window.width",
+            ]
+        );
+    }
+
+    #[test]
+    fn report_lists_fields_one_per_line() {
+        let err = parse("[log]\nlevel = 3\n[window]\nwidth = -1\n", &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration (test.toml):\n  log.level: expected a string, found integer\n  window.width: expected a whole number, found -1"
+        );
+    }
+
+    #[test]
+    fn section_must_be_a_table() {
+        let err = parse("log = \"debug\"\n", &[]).unwrap_err();
+        assert_eq!(fields(err), vec!["log"]);
+    }
+
+    #[test]
+    fn syntax_error() {
+        let err = parse("[log\n", &[]).unwrap_err();
+        assert_eq!(fields(err), vec!["file"]);
+    }
+
+    #[test]
+    fn to_toml_round_trips() {
+        let mut config = Config::default();
+        assert_eq!(parse(&config.to_toml(), &[]).unwrap(), config);
+
+        config.log.level = log::LevelFilter::Debug;
+        config.desktop.name = "winsta0\\winlogon".to_string();
+        config.hub.url = Some("wss://hub.example.com/agent".to_string());
+        config.hub.heartbeat_interval_secs = 30;
+        config.capture.backend = CaptureBackend::X11;
+        config.input.backend = InputBackend::Disabled;
+        config.input.layout = "fr".to_string();
+        config.clipboard.html = Direction::FromViewer;
+        config.clipboard.max_image_bytes = 1 << 20;
+        config.transfer.allowed_dirs = vec![PathBuf::from("/srv/a"), PathBuf::from("/srv/b")];
+        assert_eq!(parse(&config.to_toml(), &[]).unwrap(), config);
+        config.transfer.allowed_dirs.clear();
+        config.consent.policy = ConsentPolicy::Never;
+        assert_eq!(parse(&config.to_toml(), &[]).unwrap(), config);
+    }
+
+    #[test]
+    fn missing_explicit_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut env = HashMap::new();
+        env.insert(
+            CONFIG_PATH_ENV.to_string(),
+            dir.path()
+                .join("missing.toml")
+                .to_string_lossy()
+                .into_owned(),
+        );
+        let err = Config::load_with_env(&env).unwrap_err();
+        assert_eq!(fields(err), vec!["file"]);
+    }
+
+    #[test]
+    fn loads_explicit_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "[window]\nwidth = 640\n").unwrap();
+        let mut env = HashMap::new();
+        env.insert(
+            CONFIG_PATH_ENV.to_string(),
+            path.to_string_lossy().into_owned(),
+        );
+        let (config, source) = Config::load_with_env(&env).unwrap();
+        assert_eq!(config.window.width, 640);
+        assert_eq!(source, Some(path));
+    }
+}
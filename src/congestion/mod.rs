@@ -0,0 +1,387 @@
+//Congestion control for streaming: turns receiver feedback into encoder bitrate, frame rate and scale.
+//Time is always passed in, so the controller behaves the same live and in trace replays.
+use crate::encode::Encoder;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[cfg(test)]
+mod sim;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CongestionConfig {
+    pub min_bitrate_kbps: u32,
+    pub max_bitrate_kbps: u32,
+    pub start_bitrate_kbps: u32,
+    pub min_frame_rate: u32,
+    pub max_frame_rate: u32,
+    //At or above this bitrate frames go out at full resolution and frame rate.
+    pub full_quality_kbps: u32,
+    //Loss above this fraction is congestion; below `loss_tolerated` it is line noise.
+    pub loss_congested: f64,
+    pub loss_tolerated: f64,
+    //Queueing delay (RTT above the lowest seen) that counts as congestion.
+    pub max_queue_delay: Duration,
+}
+
+impl Default for CongestionConfig {
+    fn default() -> Self {
+        CongestionConfig {
+            min_bitrate_kbps: 150,
+            max_bitrate_kbps: 8000,
+            start_bitrate_kbps: 1000,
+            min_frame_rate: 5,
+            max_frame_rate: 30,
+            full_quality_kbps: 2500,
+            loss_congested: 0.10,
+            loss_tolerated: 0.02,
+            max_queue_delay: Duration::from_millis(60),
+        }
+    }
+}
+
+//One receiver report, covering the packets since the previous one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Feedback {
+    //Time since the stream started.
+    pub at: Duration,
+    pub rtt: Duration,
+    pub received: u32,
+    pub lost: u32,
+    //Bytes the receiver acknowledged in this report.
+    pub acked_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Target {
+    pub bitrate_kbps: u32,
+    pub frame_rate: u32,
+    //Fraction of the captured resolution to encode at.
+    pub scale: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Increase,
+    Hold,
+    Decrease,
+}
+
+//Resolution steps and the bitrate each needs, as a fraction of `full_quality_kbps`.
+const SCALES: [(f32, f64); 4] = [(1.0, 0.6), (0.75, 0.35), (0.5, 0.15), (0.25, 0.0)];
+//A scale step is only taken once the bitrate is this far past its threshold.
+const SCALE_HYSTERESIS: f64 = 0.2;
+//Loss and throughput are judged over this much feedback, since one report may only cover a handful
+//of packets and reports bunch up when the RTT moves.
+const FEEDBACK_WINDOW: Duration = Duration::from_secs(1);
+//The lowest RTT is forgotten after this long, so a route change doesn't read as queueing forever.
+const MIN_RTT_WINDOW: Duration = Duration::from_secs(10);
+
+pub struct CongestionController {
+    config: CongestionConfig,
+    bitrate: f64,
+    phase: Phase,
+    //RTT samples within `MIN_RTT_WINDOW` as (when, rtt), keeping only those no later sample
+    //undercuts; the front is the window's minimum.
+    min_rtt: VecDeque<(Duration, Duration)>,
+    //Reports within `FEEDBACK_WINDOW`.
+    window: VecDeque<Feedback>,
+    smoothed_rtt: Option<Duration>,
+    //Bitrate when congestion was last seen; growth slows down close to it.
+    capacity_kbps: Option<f64>,
+    last_feedback: Option<Duration>,
+    hold_until: Duration,
+    scale: f32,
+}
+
+impl CongestionController {
+    pub fn new(config: CongestionConfig) -> Self {
+        let bitrate = config
+            .start_bitrate_kbps
+            .clamp(config.min_bitrate_kbps, config.max_bitrate_kbps) as f64;
+        let mut controller = CongestionController {
+            config,
+            bitrate,
+            phase: Phase::Increase,
+            min_rtt: VecDeque::new(),
+            window: VecDeque::new(),
+            smoothed_rtt: None,
+            capacity_kbps: None,
+            last_feedback: None,
+            hold_until: Duration::ZERO,
+            scale: 1.0,
+        };
+        controller.scale = controller.scale_for(bitrate, None);
+        controller
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    pub fn target(&self) -> Target {
+        let fraction = (self.bitrate / self.config.full_quality_kbps as f64).min(1.0);
+        let range = self
+            .config
+            .max_frame_rate
+            .saturating_sub(self.config.min_frame_rate) as f64;
+        Target {
+            bitrate_kbps: self.bitrate.round() as u32,
+            frame_rate: self.config.min_frame_rate + (range * fraction).round() as u32,
+            scale: self.scale,
+        }
+    }
+
+    //Push the current target into an encoder.
+    pub fn apply(&self, encoder: &mut dyn Encoder) {
+        let target = self.target();
+        encoder.set_bitrate(target.bitrate_kbps);
+        encoder.set_frame_rate(target.frame_rate);
+        encoder.set_scale(target.scale);
+    }
+
+    pub fn on_feedback(&mut self, feedback: Feedback) -> Target {
+        let elapsed = match self.last_feedback {
+            Some(last) => feedback.at.saturating_sub(last),
+            None => Duration::ZERO,
+        };
+        self.last_feedback = Some(feedback.at);
+        while self
+            .min_rtt
+            .back()
+            .is_some_and(|(_, rtt)| *rtt >= feedback.rtt)
+        {
+            self.min_rtt.pop_back();
+        }
+        self.min_rtt.push_back((feedback.at, feedback.rtt));
+        while self
+            .min_rtt
+            .front()
+            .is_some_and(|(at, _)| *at + MIN_RTT_WINDOW <= feedback.at)
+        {
+            self.min_rtt.pop_front();
+        }
+        let min_rtt = self.min_rtt[0].1;
+        let smoothed = match self.smoothed_rtt {
+            Some(smoothed) => (smoothed * 7 + feedback.rtt) / 8,
+            None => feedback.rtt,
+        };
+        self.smoothed_rtt = Some(smoothed);
+
+        self.window.push_back(feedback);
+        while self
+            .window
+            .front()
+            .is_some_and(|report| report.at + FEEDBACK_WINDOW <= feedback.at)
+        {
+            self.window.pop_front();
+        }
+        let (received, lost) = self.window.iter().fold((0, 0), |(received, lost), report| {
+            (received + report.received, lost + report.lost)
+        });
+        let loss = if received + lost == 0 {
+            0.0
+        } else {
+            lost as f64 / (received + lost) as f64
+        };
+        let queueing = feedback.rtt.saturating_sub(min_rtt);
+        //The oldest report in the window only marks where the measured span starts.
+        let span = feedback.at.saturating_sub(self.window[0].at);
+        let throughput_kbps = (span >= FEEDBACK_WINDOW / 2).then(|| {
+            let acked: u64 = self
+                .window
+                .iter()
+                .skip(1)
+                .map(|report| report.acked_bytes)
+                .sum();
+            acked as f64 * 8.0 / 1000.0 / span.as_secs_f64()
+        });
+
+        let congested = loss > self.config.loss_congested || queueing > self.config.max_queue_delay;
+        if congested && feedback.at >= self.hold_until {
+            //Back off to below what actually got through, then give the queue time to drain.
+            let delivered = throughput_kbps.unwrap_or(self.bitrate).min(self.bitrate);
+            self.capacity_kbps = Some(delivered);
+            self.bitrate = delivered * 0.85;
+            self.phase = Phase::Decrease;
+            self.hold_until = feedback.at + (smoothed * 2).max(Duration::from_millis(500));
+        } else if congested || loss > self.config.loss_tolerated || feedback.at < self.hold_until {
+            self.phase = Phase::Hold;
+        } else {
+            self.phase = Phase::Increase;
+            let seconds = elapsed.as_secs_f64().min(1.0);
+            let near_capacity = self
+                .capacity_kbps
+                .is_some_and(|capacity| self.bitrate > capacity * 0.85);
+            self.bitrate += if near_capacity {
+                //Probe gently around the last known capacity: 2% or half a packet per response time.
+                let response = (smoothed + Duration::from_millis(100)).as_secs_f64();
+                (self.bitrate * 0.02).max(4.8 / response) * seconds
+            } else {
+                self.bitrate * 0.08 * seconds
+            };
+            //Never run far ahead of what the receiver is getting.
+            if let Some(throughput) = throughput_kbps {
+                self.bitrate = self
+                    .bitrate
+                    .min(throughput.max(self.config.min_bitrate_kbps as f64) * 1.5);
+            }
+            //Capacity estimates go stale; forget one once we are well past it.
+            if self
+                .capacity_kbps
+                .is_some_and(|capacity| self.bitrate > capacity * 1.15)
+            {
+                self.capacity_kbps = None;
+            }
+        }
+        self.bitrate = self.bitrate.clamp(
+            self.config.min_bitrate_kbps as f64,
+            self.config.max_bitrate_kbps as f64,
+        );
+        self.scale = self.scale_for(self.bitrate, Some(self.scale));
+        self.target()
+    }
+
+    //Largest scale the bitrate affords; moving off `current` needs a margin either way.
+    fn scale_for(&self, bitrate: f64, current: Option<f32>) -> f32 {
+        let full = self.config.full_quality_kbps as f64;
+        let affordable = |margin: f64| {
+            SCALES
+                .iter()
+                .find(|(_, needed)| bitrate >= needed * full * margin)
+                .map_or(SCALES[SCALES.len() - 1].0, |(scale, _)| *scale)
+        };
+        let Some(current) = current else {
+            return affordable(1.0);
+        };
+        let up = affordable(1.0 + SCALE_HYSTERESIS);
+        let down = affordable(1.0 - SCALE_HYSTERESIS);
+        if up > current {
+            up
+        } else if down < current {
+            down
+        } else {
+            current
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feedback(at_ms: u64, rtt_ms: u64, lost: u32, acked_kbps: u64) -> Feedback {
+        Feedback {
+            at: Duration::from_millis(at_ms),
+            rtt: Duration::from_millis(rtt_ms),
+            received: 100,
+            lost,
+            //Reports arrive every 100ms.
+            acked_bytes: acked_kbps * 1000 / 8 / 10,
+        }
+    }
+
+    #[test]
+    fn grows_on_a_clean_link() {
+        let mut controller = CongestionController::new(CongestionConfig::default());
+        let start = controller.target().bitrate_kbps;
+        for tick in 0..100 {
+            let acked = controller.target().bitrate_kbps as u64;
+            controller.on_feedback(feedback(tick * 100, 20, 0, acked));
+        }
+        assert_eq!(controller.phase(), Phase::Increase);
+        assert!(controller.target().bitrate_kbps > start * 2);
+    }
+
+    #[test]
+    fn backs_off_below_delivered_rate_on_loss() {
+        let mut controller = CongestionController::new(CongestionConfig::default());
+        for tick in 0..6 {
+            controller.on_feedback(feedback(tick * 100, 20, 0, 600));
+        }
+        //100 of the 600 packets in the last second lost.
+        let target = controller.on_feedback(feedback(600, 20, 100, 600));
+        assert_eq!(controller.phase(), Phase::Decrease);
+        assert_eq!(target.bitrate_kbps, 510);
+        //Still congested right after: hold instead of cutting again.
+        controller.on_feedback(feedback(700, 20, 20, 500));
+        assert_eq!(controller.phase(), Phase::Hold);
+    }
+
+    #[test]
+    fn rising_rtt_is_congestion() {
+        let mut controller = CongestionController::new(CongestionConfig::default());
+        controller.on_feedback(feedback(0, 20, 0, 1000));
+        controller.on_feedback(feedback(100, 120, 0, 1000));
+        assert_eq!(controller.phase(), Phase::Decrease);
+    }
+
+    #[test]
+    fn min_rtt_is_the_lowest_within_the_window() {
+        let mut controller = CongestionController::new(CongestionConfig::default());
+        controller.on_feedback(feedback(0, 20, 0, 1000));
+        controller.on_feedback(feedback(5_000, 50, 0, 1000));
+        //The 20ms sample has expired, but the 50ms one still marks the floor.
+        controller.on_feedback(feedback(10_500, 120, 0, 1000));
+        assert_eq!(controller.phase(), Phase::Decrease);
+        //Once it expires too, a steady RTT is the new floor rather than queueing.
+        controller.on_feedback(feedback(16_000, 120, 0, 1000));
+        assert_eq!(controller.phase(), Phase::Increase);
+    }
+
+    #[test]
+    fn light_loss_holds() {
+        let mut controller = CongestionController::new(CongestionConfig::default());
+        controller.on_feedback(feedback(0, 20, 0, 1000));
+        let before = controller.target();
+        assert_eq!(controller.on_feedback(feedback(100, 20, 5, 1000)), before);
+        assert_eq!(controller.phase(), Phase::Hold);
+    }
+
+    #[test]
+    fn low_bitrate_trades_resolution_and_frame_rate() {
+        let config = CongestionConfig::default();
+        let at = |bitrate_kbps| {
+            CongestionController::new(CongestionConfig {
+                start_bitrate_kbps: bitrate_kbps,
+                ..config.clone()
+            })
+            .target()
+        };
+        let full = at(3000);
+        assert_eq!((full.scale, full.frame_rate), (1.0, 30));
+        let low = at(200);
+        assert_eq!(low.scale, 0.25);
+        assert!(low.frame_rate < 10);
+        assert!(at(1000).scale < 1.0);
+    }
+
+    #[test]
+    fn scale_has_hysteresis() {
+        let controller = CongestionController::new(CongestionConfig {
+            start_bitrate_kbps: 1400,
+            ..CongestionConfig::default()
+        });
+        assert_eq!(controller.scale, 0.75);
+        //1400 kbps is below the 1500 kbps full-scale threshold, but not by 20%.
+        assert_eq!(controller.scale_for(1400.0, Some(1.0)), 1.0);
+        assert_eq!(controller.scale_for(1100.0, Some(1.0)), 0.75);
+        assert_eq!(controller.scale_for(1700.0, Some(0.75)), 0.75);
+        assert_eq!(controller.scale_for(1900.0, Some(0.75)), 1.0);
+    }
+
+    #[test]
+    fn applies_target_to_encoder() {
+        use crate::encode::stream::StreamEncoder;
+        use crate::encode::EncoderSettings;
+        let controller = CongestionController::new(CongestionConfig::default());
+        let mut encoder = StreamEncoder::new(EncoderSettings::default());
+        controller.apply(&mut encoder);
+        assert_eq!(encoder.settings().bitrate_kbps, 1000);
+        assert_eq!(
+            encoder.settings().frame_rate,
+            controller.target().frame_rate
+        );
+        assert!(controller.target().scale < 1.0);
+        assert_eq!(encoder.settings().scale, controller.target().scale);
+    }
+}
@@ -0,0 +1,373 @@
+//Trace replay: a simulated bottleneck link driven by a recorded network trace, with the controller in the loop.
+//Everything runs on a fixed tick and a seeded generator, so a replay always produces the same run.
+use super::{CongestionConfig, CongestionController, Feedback, Phase, Target};
+use crate::encode::stream::StreamEncoder;
+use crate::encode::{Encoder, EncoderSettings};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+const TICK: Duration = Duration::from_millis(100);
+const PACKET_BYTES: f64 = 1200.0;
+//The bottleneck buffers this much sending time before it drops.
+const BUFFER: Duration = Duration::from_millis(250);
+
+//One line of a trace: link conditions from `at` until the next line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub at: Duration,
+    pub capacity_kbps: f64,
+    pub rtt: Duration,
+    pub loss: f64,
+}
+
+//Lines are `<seconds> <capacity kbps> <rtt ms> <random loss %>`; `#` starts a comment.
+//The last line only marks where the trace ends.
+pub fn parse_trace(text: &str) -> Result<Vec<Segment>, String> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<f64> = line
+            .split_whitespace()
+            .map(|field| field.parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("line {}: {}", number + 1, e))?;
+        let [at, capacity_kbps, rtt_ms, loss] = fields[..] else {
+            return Err(format!("line {}: expected 4 fields", number + 1));
+        };
+        let at = Duration::from_secs_f64(at);
+        if segments.last().is_some_and(|last| last.at >= at) {
+            return Err(format!("line {}: time goes backwards", number + 1));
+        }
+        segments.push(Segment {
+            at,
+            capacity_kbps,
+            rtt: Duration::from_secs_f64(rtt_ms / 1000.0),
+            loss: loss / 100.0,
+        });
+    }
+    if segments.len() < 2 {
+        return Err("a trace needs at least two lines".to_string());
+    }
+    Ok(segments)
+}
+
+//What the link and controller looked like at one tick.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub at: Duration,
+    pub capacity_kbps: f64,
+    pub target: Target,
+    //What the encoder was left set to.
+    pub applied: Target,
+    pub phase: Phase,
+    pub queue_delay: Duration,
+}
+
+//xorshift64, for loss that is random-looking but repeatable.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+//A report on its way back to the sender.
+struct InFlight {
+    arrives: Duration,
+    rtt: Duration,
+    received: u32,
+    lost: u32,
+    acked_bytes: u64,
+}
+
+pub fn replay(trace: &[Segment], config: CongestionConfig) -> Vec<Sample> {
+    let mut controller = CongestionController::new(config);
+    let mut encoder = StreamEncoder::new(EncoderSettings::default());
+    controller.apply(&mut encoder);
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let mut queue_bytes = 0.0;
+    let mut in_flight: VecDeque<InFlight> = VecDeque::new();
+    let mut samples = Vec::new();
+    let end = trace[trace.len() - 1].at;
+    let mut now = Duration::ZERO;
+    while now < end {
+        let link = trace
+            .iter()
+            .rev()
+            .find(|segment| segment.at <= now)
+            .unwrap_or(&trace[0]);
+        let capacity_bytes = link.capacity_kbps * 1000.0 / 8.0 * TICK.as_secs_f64();
+        let buffer_bytes = link.capacity_kbps * 1000.0 / 8.0 * BUFFER.as_secs_f64();
+
+        //Sender side: this tick's share of the target bitrate joins the bottleneck queue.
+        let target = controller.target();
+        queue_bytes += target.bitrate_kbps as f64 * 1000.0 / 8.0 * TICK.as_secs_f64();
+        let dropped = (queue_bytes - buffer_bytes).max(0.0);
+        queue_bytes -= dropped;
+        let delivered = queue_bytes.min(capacity_bytes);
+        queue_bytes -= delivered;
+
+        let mut received = 0;
+        let mut lost = (dropped / PACKET_BYTES).round() as u32;
+        for _ in 0..(delivered / PACKET_BYTES).round() as u32 {
+            if rng.next() < link.loss {
+                lost += 1;
+            } else {
+                received += 1;
+            }
+        }
+        let queue_delay = Duration::from_secs_f64(queue_bytes * 8.0 / 1000.0 / link.capacity_kbps);
+        let rtt = link.rtt + queue_delay;
+        in_flight.push_back(InFlight {
+            arrives: now + rtt,
+            rtt,
+            received,
+            lost,
+            acked_bytes: (received as f64 * PACKET_BYTES) as u64,
+        });
+
+        //Receiver reports that made it back by now reach the controller as one.
+        let mut report: Option<Feedback> = None;
+        while in_flight.front().is_some_and(|next| next.arrives <= now) {
+            let next = in_flight.pop_front().unwrap();
+            let merged = report.get_or_insert(Feedback {
+                at: now,
+                rtt: Duration::ZERO,
+                received: 0,
+                lost: 0,
+                acked_bytes: 0,
+            });
+            merged.rtt = merged.rtt.max(next.rtt);
+            merged.received += next.received;
+            merged.lost += next.lost;
+            merged.acked_bytes += next.acked_bytes;
+        }
+        if let Some(report) = report {
+            controller.on_feedback(report);
+            controller.apply(&mut encoder);
+        }
+        let settings = encoder.settings();
+        samples.push(Sample {
+            at: now,
+            capacity_kbps: link.capacity_kbps,
+            target: controller.target(),
+            applied: Target {
+                bitrate_kbps: settings.bitrate_kbps,
+                frame_rate: settings.frame_rate,
+                scale: settings.scale,
+            },
+            phase: controller.phase(),
+            queue_delay,
+        });
+        now += TICK;
+    }
+    samples
+}
+
+//How a steady stretch of a trace went, once the controller had time to settle.
+#[derive(Debug)]
+pub struct Settled {
+    pub from: Duration,
+    pub to: Duration,
+    //Mean bitrate over what the link (or the configured maximum) allows.
+    pub utilization: f64,
+    //Standard deviation of the bitrate over its mean.
+    pub variation: f64,
+    //Times the bitrate turned from rising to falling or back by more than 5%.
+    pub swings: usize,
+    pub mean_queue_delay: Duration,
+}
+
+const SETTLE: Duration = Duration::from_secs(10);
+
+//Every trace segment long enough to settle in, judged over its second half and never its first `SETTLE`.
+pub fn settled(trace: &[Segment], samples: &[Sample], config: &CongestionConfig) -> Vec<Settled> {
+    trace
+        .windows(2)
+        .filter(|pair| pair[1].at >= pair[0].at + SETTLE * 3 / 2)
+        .map(|pair| {
+            let from = (pair[0].at + SETTLE).max((pair[0].at + pair[1].at) / 2);
+            let to = pair[1].at;
+            let window: Vec<&Sample> = samples
+                .iter()
+                .filter(|sample| sample.at >= from && sample.at < to)
+                .collect();
+            let rates: Vec<f64> = window
+                .iter()
+                .map(|sample| sample.target.bitrate_kbps as f64)
+                .collect();
+            let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+            let variance =
+                rates.iter().map(|rate| (rate - mean).powi(2)).sum::<f64>() / rates.len() as f64;
+            let allowed = pair[0].capacity_kbps.min(config.max_bitrate_kbps as f64);
+            Settled {
+                from,
+                to,
+                utilization: mean / allowed,
+                variation: variance.sqrt() / mean,
+                swings: swings(&rates, 0.05),
+                mean_queue_delay: window
+                    .iter()
+                    .map(|sample| sample.queue_delay)
+                    .sum::<Duration>()
+                    / window.len() as u32,
+            }
+        })
+        .collect()
+}
+
+//Direction changes between local extremes at least `threshold` apart, relative to the lower one.
+fn swings(rates: &[f64], threshold: f64) -> usize {
+    let mut count = 0;
+    let mut rising: Option<bool> = None;
+    let mut extreme = match rates.first() {
+        Some(first) => *first,
+        None => return 0,
+    };
+    for &rate in &rates[1..] {
+        let moved = (rate - extreme) / extreme.min(rate);
+        match rising {
+            Some(true) if rate > extreme => extreme = rate,
+            Some(false) if rate < extreme => extreme = rate,
+            _ if moved.abs() >= threshold => {
+                if rising.is_some() {
+                    count += 1;
+                }
+                rising = Some(moved > 0.0);
+                extreme = rate;
+            }
+            _ => {}
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACES: [(&str, &str); 4] = [
+        ("lan", include_str!("traces/lan.trace")),
+        ("wifi", include_str!("traces/wifi.trace")),
+        ("lte", include_str!("traces/lte.trace")),
+        ("lossy", include_str!("traces/lossy.trace")),
+    ];
+
+    #[test]
+    fn parses_traces() {
+        let trace =
+            parse_trace("# comment\n0 2000 40 1.5\n\n30 500 80 0 # drop\n60 0 0 0\n").unwrap();
+        assert_eq!(trace.len(), 3);
+        assert_eq!(trace[1].at, Duration::from_secs(30));
+        assert_eq!(trace[1].rtt, Duration::from_millis(80));
+        assert_eq!(trace[0].loss, 0.015);
+        assert!(parse_trace("0 2000 40\n10 1 1 1").is_err());
+        assert!(parse_trace("10 2000 40 0\n5 2000 40 0").is_err());
+        assert!(parse_trace("0 2000 40 0").is_err());
+        for (name, text) in TRACES {
+            assert!(parse_trace(text).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn counts_swings() {
+        assert_eq!(swings(&[100.0, 110.0, 120.0, 130.0], 0.05), 0);
+        assert_eq!(swings(&[100.0, 101.0, 100.0, 102.0, 100.0], 0.05), 0);
+        assert_eq!(swings(&[100.0, 120.0, 100.0, 120.0, 100.0], 0.05), 3);
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        let trace = parse_trace(TRACES[1].1).unwrap();
+        let first = replay(&trace, CongestionConfig::default());
+        let second = replay(&trace, CongestionConfig::default());
+        assert_eq!(first.len(), second.len());
+        assert!(first
+            .iter()
+            .zip(&second)
+            .all(|(a, b)| a.target == b.target && a.queue_delay == b.queue_delay));
+    }
+
+    #[test]
+    fn converges_on_recorded_traces() {
+        let config = CongestionConfig::default();
+        for (name, text) in TRACES {
+            let trace = parse_trace(text).unwrap();
+            let samples = replay(&trace, config.clone());
+            for stretch in settled(&trace, &samples, &config) {
+                let seconds = (stretch.to - stretch.from).as_secs_f64();
+                let context = format!(
+                    "{} {:?}..{:?}: {:?}",
+                    name, stretch.from, stretch.to, stretch
+                );
+                assert!(
+                    (0.6..=1.05).contains(&stretch.utilization),
+                    "utilization {}",
+                    context
+                );
+                assert!(stretch.variation < 0.15, "variation {}", context);
+                //An occasional probe and back-off is fine; a sawtooth is not.
+                assert!(
+                    stretch.swings as f64 <= seconds / 5.0,
+                    "oscillation {}",
+                    context
+                );
+                assert!(
+                    stretch.mean_queue_delay < config.max_queue_delay,
+                    "bufferbloat {}",
+                    context
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn follows_capacity_drops_quickly() {
+        let config = CongestionConfig::default();
+        let trace = parse_trace(TRACES[2].1).unwrap();
+        let samples = replay(&trace, config.clone());
+        for pair in trace.windows(2) {
+            let Some(previous) = trace.iter().rev().find(|segment| segment.at < pair[0].at) else {
+                continue;
+            };
+            if pair[0].capacity_kbps >= previous.capacity_kbps * 0.7 {
+                continue;
+            }
+            //Within two seconds of a drop the sender is back under the new capacity.
+            let settled_at = pair[0].at + Duration::from_secs(2);
+            let sample = samples
+                .iter()
+                .find(|sample| sample.at >= settled_at)
+                .unwrap();
+            assert!(
+                (sample.target.bitrate_kbps as f64) < pair[0].capacity_kbps,
+                "{:?}: {:?}",
+                pair[0],
+                sample
+            );
+        }
+    }
+
+    #[test]
+    fn scales_down_on_slow_links() {
+        let trace = parse_trace(TRACES[2].1).unwrap();
+        let samples = replay(&trace, CongestionConfig::default());
+        let slowest = trace
+            .iter()
+            .take(trace.len() - 1)
+            .min_by(|a, b| a.capacity_kbps.total_cmp(&b.capacity_kbps))
+            .unwrap();
+        let at = slowest.at + SETTLE;
+        let sample = samples.iter().find(|sample| sample.at >= at).unwrap();
+        assert!(sample.target.scale < 1.0, "{:?}", sample);
+        assert!(samples.iter().all(|sample| sample.applied == sample.target));
+        assert!(sample.target.frame_rate < CongestionConfig::default().max_frame_rate);
+    }
+}
@@ -0,0 +1,308 @@
+//Asks the person at the machine before a viewer gets in.
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsentPolicy {
+    Always,
+    //Only when someone has used the keyboard or mouse recently; unattended machines let viewers in.
+    WhenPresent,
+    Never,
+}
+
+impl FromStr for ConsentPolicy {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "always" => Ok(ConsentPolicy::Always),
+            "when_present" => Ok(ConsentPolicy::WhenPresent),
+            "never" => Ok(ConsentPolicy::Never),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for ConsentPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsentPolicy::Always => write!(f, "always"),
+            ConsentPolicy::WhenPresent => write!(f, "when_present"),
+            ConsentPolicy::Never => write!(f, "never"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsentSettings {
+    pub policy: ConsentPolicy,
+    //Unanswered prompts count as a denial after this long.
+    pub timeout: Duration,
+    //Input more recent than this means someone is at the machine.
+    pub presence_idle: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsentRequest {
+    //Who is connecting, as the hub names them.
+    pub viewer: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Accepted,
+    Denied,
+    TimedOut,
+    //The policy let the viewer in without asking.
+    NotAsked,
+}
+
+impl Decision {
+    pub fn allowed(self) -> bool {
+        matches!(self, Decision::Accepted | Decision::NotAsked)
+    }
+}
+
+//Time since the last local keyboard or mouse input, if the OS can tell.
+pub type IdleFn = Arc<dyn Fn() -> Option<Duration> + Send + Sync>;
+
+pub fn system_idle() -> IdleFn {
+    #[cfg(target_os = "linux")]
+    return Arc::new(|| crate::linux::presence::idle_time(None));
+    #[cfg(target_os = "windows")]
+    return Arc::new(crate::win32::presence::idle_time);
+}
+
+//A request waiting its turn in the window.
+#[derive(Debug)]
+struct Queued {
+    request: ConsentRequest,
+    shown: oneshot::Sender<()>,
+    reply: oneshot::Sender<Decision>,
+}
+
+//A question in front of the local user.
+#[derive(Debug)]
+pub struct Prompt {
+    pub request: ConsentRequest,
+    //Counted from when the prompt was shown, not from when it was queued.
+    pub deadline: Instant,
+    reply: oneshot::Sender<Decision>,
+}
+
+impl Prompt {
+    pub fn answer(self, decision: Decision) {
+        //The asker may have given up already.
+        let _ = self.reply.send(decision);
+    }
+}
+
+//The prompts for the window to show, one at a time.
+pub struct Prompts {
+    queue: mpsc::Receiver<Queued>,
+    timeout: Duration,
+}
+
+impl Prompts {
+    //The next prompt, if one is waiting; its countdown starts now.
+    pub fn try_next(&mut self) -> Option<Prompt> {
+        let queued = self.queue.try_recv().ok()?;
+        Some(self.show(queued))
+    }
+
+    #[cfg(test)]
+    pub async fn next(&mut self) -> Option<Prompt> {
+        let queued = self.queue.recv().await?;
+        Some(self.show(queued))
+    }
+
+    fn show(&self, queued: Queued) -> Prompt {
+        let _ = queued.shown.send(());
+        Prompt {
+            request: queued.request,
+            deadline: Instant::now() + self.timeout,
+            reply: queued.reply,
+        }
+    }
+}
+
+//Asks for consent on behalf of whoever starts sessions; clones share the same window.
+#[derive(Clone)]
+pub struct Consent {
+    settings: ConsentSettings,
+    prompts: mpsc::Sender<Queued>,
+    idle: IdleFn,
+}
+
+//The asking end, and the prompts the window is to show.
+pub fn channel(settings: ConsentSettings, idle: IdleFn) -> (Consent, Prompts) {
+    let (tx, rx) = mpsc::channel(8);
+    let prompts = Prompts {
+        queue: rx,
+        timeout: settings.timeout,
+    };
+    (
+        Consent {
+            settings,
+            prompts: tx,
+            idle,
+        },
+        prompts,
+    )
+}
+
+impl Consent {
+    //Whether `request` may go ahead, asking the local user if the policy says to.
+    #[allow(dead_code)]
+    pub async fn ask(&self, request: ConsentRequest) -> Decision {
+        match self.settings.policy {
+            ConsentPolicy::Never => return Decision::NotAsked,
+            ConsentPolicy::WhenPresent => match (self.idle)() {
+                Some(idle) if idle >= self.settings.presence_idle => {
+                    log::info!(
+                        "letting {} in without asking, no input for {}s",
+                        request.viewer,
+                        idle.as_secs()
+                    );
+                    return Decision::NotAsked;
+                }
+                Some(_) => {}
+                //Not knowing whether anyone is there, assume someone is.
+                None => log::info!("cannot tell whether anyone is present, asking"),
+            },
+            ConsentPolicy::Always => {}
+        }
+        let viewer = request.viewer.clone();
+        let (shown_tx, shown) = oneshot::channel();
+        let (reply, rx) = oneshot::channel();
+        let queued = Queued {
+            request,
+            shown: shown_tx,
+            reply,
+        };
+        //Waits behind any prompt already showing; the window drops the queue when it closes.
+        if self.prompts.send(queued).await.is_err() || shown.await.is_err() {
+            log::warn!("no window to ask for consent in, denying {}", viewer);
+            return Decision::Denied;
+        }
+        let decision = match tokio::time::timeout(self.settings.timeout, rx).await {
+            Ok(Ok(decision)) => decision,
+            //The window closed without an answer.
+            Ok(Err(_)) => Decision::Denied,
+            Err(_) => Decision::TimedOut,
+        };
+        log::info!("session request from {}: {:?}", viewer, decision);
+        decision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> ConsentRequest {
+        ConsentRequest {
+            viewer: "alice@support".to_string(),
+            reason: "ticket 1234".to_string(),
+        }
+    }
+
+    fn consent(
+        policy: ConsentPolicy,
+        idle: Option<Duration>,
+        timeout: Duration,
+    ) -> (Consent, Prompts) {
+        let settings = ConsentSettings {
+            policy,
+            timeout,
+            presence_idle: Duration::from_secs(300),
+        };
+        channel(settings, Arc::new(move || idle))
+    }
+
+    #[tokio::test]
+    async fn policy_decides_whether_to_ask() {
+        let away = Some(Duration::from_secs(600));
+        let here = Some(Duration::from_secs(5));
+        for (policy, idle, asks) in [
+            (ConsentPolicy::Never, here, false),
+            (ConsentPolicy::WhenPresent, away, false),
+            (ConsentPolicy::WhenPresent, here, true),
+            (ConsentPolicy::WhenPresent, None, true),
+            (ConsentPolicy::Always, away, true),
+        ] {
+            let (consent, mut prompts) = consent(policy, idle, Duration::from_secs(30));
+            let asking = tokio::spawn(async move { consent.ask(request()).await });
+            let decision = if asks {
+                let prompt = prompts.next().await.unwrap();
+                assert_eq!(prompt.request, request());
+                prompt.answer(Decision::Accepted);
+                Decision::Accepted
+            } else {
+                Decision::NotAsked
+            };
+            assert_eq!(asking.await.unwrap(), decision, "{} {:?}", policy, idle);
+        }
+    }
+
+    #[tokio::test]
+    async fn unanswered_prompts_are_denied() {
+        let timeout = Duration::from_millis(50);
+        let (consent, mut prompts) = consent(ConsentPolicy::Always, None, timeout);
+        let asking = tokio::spawn({
+            let consent = consent.clone();
+            async move { consent.ask(request()).await }
+        });
+        let prompt = prompts.next().await.unwrap();
+        assert!(prompt.deadline <= Instant::now() + timeout);
+        assert_eq!(asking.await.unwrap(), Decision::TimedOut);
+
+        //A prompt dropped unanswered, or no window at all, is a denial too.
+        let asking = tokio::spawn({
+            let consent = consent.clone();
+            async move { consent.ask(request()).await }
+        });
+        drop(prompts.next().await.unwrap());
+        assert_eq!(asking.await.unwrap(), Decision::Denied);
+        drop(prompts);
+        assert_eq!(consent.ask(request()).await, Decision::Denied);
+    }
+
+    #[tokio::test]
+    async fn countdown_starts_when_shown() {
+        let timeout = Duration::from_millis(200);
+        let (consent, mut prompts) = consent(ConsentPolicy::Always, None, timeout);
+        let ask = |consent: &Consent| {
+            let consent = consent.clone();
+            tokio::spawn(async move { consent.ask(request()).await })
+        };
+        let (first, second) = (ask(&consent), ask(&consent));
+        let shown = prompts.next().await.unwrap();
+        //The second request waits its turn for longer than the timeout.
+        tokio::time::sleep(timeout * 2).await;
+        shown.answer(Decision::Denied);
+        assert_eq!(first.await.unwrap(), Decision::TimedOut);
+
+        let queued_for = Instant::now();
+        let prompt = prompts.next().await.unwrap();
+        assert!(prompt.deadline >= queued_for + timeout);
+        prompt.answer(Decision::Accepted);
+        assert_eq!(second.await.unwrap(), Decision::Accepted);
+    }
+
+    #[test]
+    fn policies_parse() {
+        for policy in [
+            ConsentPolicy::Always,
+            ConsentPolicy::WhenPresent,
+            ConsentPolicy::Never,
+        ] {
+            assert_eq!(policy.to_string().parse(), Ok(policy));
+        }
+        assert_eq!("sometimes".parse::<ConsentPolicy>(), Err(()));
+    }
+}
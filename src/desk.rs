@@ -1,19 +1,393 @@
-use iced::widget::{column, text, Space};
-use iced::{executor, Theme};
+use crate::capture::diff::FrameStats;
+use crate::capture::{self, CaptureError, Capturer, Frame, Monitor};
+use crate::clipboard::{self, ClipboardPolicy, ClipboardSync};
+use crate::config::{CaptureSection, InputSection};
+use crate::consent::{self, Consent, ConsentSettings, Decision, Prompt, Prompts};
+use crate::encode::stream::StreamEncoder;
+use crate::encode::{Decoder, Encoder, EncoderSettings};
+use crate::hub::protocol::HubMessage;
+use crate::input::layout::Layout;
+use crate::input::{self, InputSession};
+use crate::ipc::protocol::{Event, Request, Response, ServiceStatus};
+use crate::ipc::{self, IpcError};
+use crate::remote::{RemoteSessions, Viewer};
+use crate::transfer::engine::TransferService;
+use crate::transfer::{Direction, TransferHistory, TransferPolicy, TransferRecord, TransferState};
+use iced::widget::{button, column, image, row, text, Space};
+use iced::{executor, Subscription, Theme};
 use iced::{Alignment, Element, Length};
 use iced::{Application, Command};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+//Preview frames are scaled down to at most this width before they reach the renderer.
+const PREVIEW_WIDTH: u32 = 640;
 
 #[derive(Debug, Clone)]
-pub enum Message {}
-pub struct DeskWindow {}
+pub enum Message {
+    Tick,
+    MonitorSelected(u32),
+    //Checks for new consent prompts and counts down the one shown.
+    ConsentTick,
+    ConsentAnswered(bool),
+    EndSession,
+    ServiceConnected(Result<ipc::Client, IpcError>),
+    ServiceStatus(Result<Response, IpcError>),
+    ServiceReplied(Result<Response, IpcError>),
+    Encoded(Result<EncodeStats, String>),
+}
+
+pub struct DeskFlags {
+    pub capture: CaptureSection,
+    pub encoder: EncoderSettings,
+    pub input: InputSection,
+    pub clipboard: ClipboardPolicy,
+    pub transfer: TransferPolicy,
+    pub consent: ConsentSettings,
+    pub ipc_path: PathBuf,
+}
+
+//What the last encoded frame cost.
+#[derive(Debug, Clone)]
+pub struct EncodeStats {
+    diff: FrameStats,
+    bytes: usize,
+    quality: u8,
+    bitrate_kbps: u32,
+}
+
+//Encodes and decodes frames as a viewer would see them, off the UI thread.
+struct Codec {
+    encoder: Box<dyn Encoder>,
+    decoder: Decoder,
+}
+
+//Runs on a blocking thread; the codec is only locked here, never by the UI.
+fn round_trip(
+    codec: &Mutex<Codec>,
+    frame: &Frame,
+    frame_rate: Option<f64>,
+    keyframe: bool,
+) -> Result<EncodeStats, String> {
+    let mut codec = codec.lock().unwrap();
+    let Codec { encoder, decoder } = &mut *codec;
+    //Budget frames by the rate they actually arrive at, which capture cost can slow down.
+    if let Some(rate) = frame_rate {
+        encoder.set_frame_rate(rate.round() as u32);
+    }
+    if keyframe {
+        encoder.request_keyframe();
+    }
+    let encoded = encoder.encode(frame).map_err(|e| e.to_string())?;
+    decoder.apply(&encoded).map_err(|e| e.to_string())?;
+    Ok(EncodeStats {
+        bytes: encoded.size(),
+        quality: encoded.quality,
+        diff: encoded.diff,
+        bitrate_kbps: encoder.settings().bitrate_kbps,
+    })
+}
+
+pub struct DeskWindow {
+    capturer: Option<Box<dyn Capturer>>,
+    monitors: Vec<Monitor>,
+    selected: Option<u32>,
+    interval: Duration,
+    preview: Option<(u32, u32, image::Handle)>,
+    last_capture: Option<Instant>,
+    frame_rate: Option<f64>,
+    codec: Arc<Mutex<Codec>>,
+    //Set while a frame is being encoded; ticks in the meantime only refresh the preview.
+    encoding: bool,
+    //The next encoded frame starts afresh, e.g. after a monitor switch or once a viewer joins.
+    keyframe: bool,
+    stats: Option<EncodeStats>,
+    //None when the agent is view-only or the backend could not start.
+    input: Option<InputSession>,
+    //Exchanged with the viewer once frames go out over the network.
+    #[allow(dead_code)]
+    clipboard: Option<ClipboardSync>,
+    //Files viewers push and pull; the window lists the recent ones for the local user.
+    transfers: TransferService,
+    //Asks the local user before a viewer gets in; handed to the viewer connection once it lands.
+    #[allow(dead_code)]
+    consent: Consent,
+    prompts: Prompts,
+    prompt: Option<Prompt>,
+    sessions: RemoteSessions,
+    //Held until the service is connected, then passed on to its hub connection.
+    hub_outbox: mpsc::UnboundedReceiver<HubMessage>,
+    //None until connected, and again once the service goes away.
+    service: Option<ipc::Client>,
+    service_line: String,
+    error: Option<String>,
+}
+
+const CONSENT_TICK: Duration = Duration::from_millis(250);
+
+//How many finished or running transfers the window lists.
+const SHOWN_TRANSFERS: usize = 5;
+
+//One line of the transfer list, e.g. "14:02 received setup.exe (12.5 MB)".
+fn transfer_line(record: &TransferRecord) -> String {
+    let megabytes = |bytes: u64| format!("{:.1} MB", bytes as f64 / 1_000_000.0);
+    let (running, done) = match record.direction {
+        Direction::Incoming => ("receiving", "received"),
+        Direction::Outgoing => ("sending", "sent"),
+    };
+    let time = record.started_at.format("%H:%M");
+    match &record.state {
+        TransferState::Running => format!(
+            "{} {} {}: {} of {}",
+            time,
+            running,
+            record.name,
+            megabytes(record.transferred),
+            megabytes(record.size)
+        ),
+        TransferState::Completed => format!(
+            "{} {} {} ({})",
+            time,
+            done,
+            record.name,
+            megabytes(record.size)
+        ),
+        TransferState::Failed(reason) => {
+            format!("{} {} {} failed: {}", time, running, record.name, reason)
+        }
+    }
+}
+
+//e.g. "Managed by wss://hub.example.com/agent as agent-1".
+fn service_line(status: &ServiceStatus) -> String {
+    match (&status.hub_url, &status.agent_id) {
+        (Some(url), Some(agent_id)) => format!("Managed by {} as {}", url, agent_id),
+        (Some(url), None) => format!("Not yet enrolled with {}", url),
+        (None, _) => "Not managed by a hub".to_string(),
+    }
+}
+
+//"4:05" or "1:02:03".
+fn duration_text(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs / 3600 {
+        0 => format!("{}:{:02}", secs / 60, secs % 60),
+        hours => format!("{}:{:02}:{:02}", hours, secs / 60 % 60, secs % 60),
+    }
+}
+
+//"alice@support: view, control"
+fn viewer_line(viewer: &Viewer) -> String {
+    let permissions = viewer.permissions.names();
+    match permissions.is_empty() {
+        true => format!("{}: no permissions", viewer.name),
+        false => format!("{}: {}", viewer.name, permissions.join(", ")),
+    }
+}
+
+//Nearest-neighbour downscale to at most `max_width`, as packed RGBA.
+fn thumbnail(frame: &Frame, max_width: u32) -> (u32, u32, Vec<u8>) {
+    let rgba = frame.to_rgba();
+    if frame.width <= max_width {
+        return (frame.width, frame.height, rgba);
+    }
+    let width = max_width;
+    let height = ((frame.height as u64 * max_width as u64) / frame.width as u64).max(1) as u32;
+    let mut scaled = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height {
+        let source_y = (y as u64 * frame.height as u64 / height as u64) as usize;
+        for x in 0..width {
+            let source_x = (x as u64 * frame.width as u64 / width as u64) as usize;
+            let offset = (source_y * frame.width as usize + source_x) * 4;
+            scaled.extend_from_slice(&rgba[offset..offset + 4]);
+        }
+    }
+    (width, height, scaled)
+}
+
+impl DeskWindow {
+    fn refresh_monitors(&mut self) -> Result<(), CaptureError> {
+        let Some(capturer) = self.capturer.as_mut() else {
+            return Ok(());
+        };
+        self.monitors = capturer.monitors()?;
+        if let Some(input) = self.input.as_mut() {
+            input.set_monitors(self.monitors.clone());
+        }
+        let still_attached = self
+            .selected
+            .is_some_and(|id| self.monitors.iter().any(|monitor| monitor.id == id));
+        if !still_attached {
+            self.selected = capture::primary(&self.monitors).map(|monitor| monitor.id);
+        }
+        Ok(())
+    }
+
+    fn capture_preview(&mut self) -> Result<Option<Frame>, String> {
+        let Some(monitor) = self
+            .monitors
+            .iter()
+            .find(|monitor| Some(monitor.id) == self.selected)
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        let Some(capturer) = self.capturer.as_mut() else {
+            return Ok(None);
+        };
+        let frame = match capturer.capture(&monitor) {
+            //Monitors come and go; pick up the new layout and try again next tick.
+            Err(CaptureError::NoSuchMonitor(_)) => {
+                return self
+                    .refresh_monitors()
+                    .map(|_| None)
+                    .map_err(|e| e.to_string())
+            }
+            result => result.map_err(|e| e.to_string())?,
+        };
+        if let Some(last) = self.last_capture {
+            let elapsed = frame.captured_at.duration_since(last).as_secs_f64();
+            self.frame_rate = (elapsed > 0.0).then(|| 1.0 / elapsed);
+        }
+        self.last_capture = Some(frame.captured_at);
+        let (width, height, pixels) = thumbnail(&frame, PREVIEW_WIDTH);
+        self.preview = Some((
+            width,
+            height,
+            image::Handle::from_pixels(width, height, pixels),
+        ));
+        Ok(Some(frame))
+    }
+
+    //Hand the frame to the codec unless no one is watching or the last one is still going.
+    fn encode(&mut self, frame: Frame) -> Command<Message> {
+        if self.sessions.started_at().is_none() {
+            self.stats = None;
+            self.keyframe = true;
+            return Command::none();
+        }
+        if self.encoding {
+            return Command::none();
+        }
+        self.encoding = true;
+        let codec = self.codec.clone();
+        let frame_rate = self.frame_rate;
+        let keyframe = std::mem::take(&mut self.keyframe);
+        Command::perform(
+            async move {
+                tokio::task::spawn_blocking(move || {
+                    round_trip(&codec, &frame, frame_rate, keyframe)
+                })
+                .await
+                .unwrap_or_else(|e| Err(e.to_string()))
+            },
+            Message::Encoded,
+        )
+    }
+
+    //Picks up the service's events and hands it what is waiting for the hub.
+    fn talk_to_service(&mut self) -> Command<Message> {
+        let Some(client) = self.service.clone() else {
+            return Command::none();
+        };
+        while let Some(event) = client.try_event() {
+            match event {
+                Event::Stopping => {
+                    self.service = None;
+                    self.service_line = "The service is stopping".to_string();
+                    return Command::none();
+                }
+                Event::Hub { message } => log::info!("from the hub: {:?}", message),
+            }
+        }
+        let mut requests = Vec::new();
+        while let Ok(message) = self.hub_outbox.try_recv() {
+            let HubMessage::SessionEnded { viewers, reason } = message else {
+                log::warn!("not passing {:?} on to the hub", message);
+                continue;
+            };
+            let client = client.clone();
+            requests.push(Command::perform(
+                async move {
+                    client
+                        .request(Request::SessionEnded { viewers, reason })
+                        .await
+                },
+                Message::ServiceReplied,
+            ));
+        }
+        Command::batch(requests)
+    }
+}
 
 impl Application for DeskWindow {
     type Executor = executor::Default;
     type Message = Message;
     type Theme = Theme;
-    type Flags = ();
-    fn new(_: Self::Flags) -> (Self, Command<Self::Message>) {
-        (DeskWindow {}, Command::none())
+    type Flags = DeskFlags;
+    fn new(flags: Self::Flags) -> (Self, Command<Self::Message>) {
+        let (consent, prompts) = consent::channel(flags.consent, consent::system_idle());
+        let (sessions, hub_outbox) = RemoteSessions::new();
+        let mut window = DeskWindow {
+            capturer: None,
+            monitors: Vec::new(),
+            selected: None,
+            interval: Duration::from_millis(flags.capture.interval_ms),
+            preview: None,
+            last_capture: None,
+            frame_rate: None,
+            codec: Arc::new(Mutex::new(Codec {
+                encoder: Box::new(StreamEncoder::new(flags.encoder)),
+                decoder: Decoder::new(),
+            })),
+            encoding: false,
+            keyframe: true,
+            stats: None,
+            input: None,
+            clipboard: None,
+            transfers: TransferService::new(flags.transfer, TransferHistory::new()),
+            consent,
+            prompts,
+            prompt: None,
+            sessions,
+            hub_outbox,
+            service: None,
+            service_line: "Connecting to the service".to_string(),
+            error: None,
+        };
+        //The config loader only accepts known layouts.
+        let layout = Layout::named(&flags.input.layout).expect("unknown keyboard layout");
+        match input::open(flags.input.backend) {
+            Ok(injector) => window.input = Some(InputSession::new(injector, Vec::new(), layout)),
+            Err(e) => log::warn!("viewers get no input: {}", e),
+        }
+        match clipboard::open() {
+            Ok(backend) => window.clipboard = Some(ClipboardSync::new(backend, flags.clipboard)),
+            Err(e) => log::warn!("the clipboard is not shared with viewers: {}", e),
+        }
+        match capture::open(flags.capture.backend) {
+            Ok(capturer) => {
+                window.capturer = Some(capturer);
+                if let Err(e) = window.refresh_monitors() {
+                    window.error = Some(e.to_string());
+                }
+            }
+            Err(e) => {
+                log::error!(
+                    "failed to open the {} capturer: {}",
+                    flags.capture.backend,
+                    e
+                );
+                window.error = Some(e.to_string());
+            }
+        }
+        let connect = Command::perform(
+            ipc::Client::connect(flags.ipc_path),
+            Message::ServiceConnected,
+        );
+        (window, connect)
     }
 
     fn title(&self) -> String {
@@ -21,19 +395,411 @@ impl Application for DeskWindow {
     }
 
     fn view(&self) -> Element<'_, Self::Message, Self::Theme, iced::Renderer> {
-        column![
-            Space::with_height(30),
-            text("This is just a test program.").size(16)
-        ]
-        .align_items(Alignment::Center)
-        .width(Length::Fill)
-        .height(Length::Fill)
-        .padding(20)
-        .align_items(Alignment::Start)
-        .into()
-    }
-
-    fn update(&mut self, _: Self::Message) -> Command<Self::Message> {
+        //The prompt takes over the window until it is answered.
+        if let Some(prompt) = &self.prompt {
+            let remaining = prompt.deadline.saturating_duration_since(Instant::now());
+            return column![
+                text(format!(
+                    "{} wants to view and control this computer",
+                    prompt.request.viewer
+                ))
+                .size(18),
+                text(format!("Reason: {}", prompt.request.reason)).size(14),
+                text(format!(
+                    "Denied automatically in {} seconds",
+                    remaining.as_secs_f64().ceil()
+                ))
+                .size(14),
+                row![
+                    button(text("Accept")).on_press(Message::ConsentAnswered(true)),
+                    button(text("Deny"))
+                        .style(iced::theme::Button::Secondary)
+                        .on_press(Message::ConsentAnswered(false)),
+                ]
+                .spacing(8),
+            ]
+            .spacing(10)
+            .padding(20)
+            .into();
+        }
+        let monitors = self
+            .monitors
+            .iter()
+            .fold(row![].spacing(8), |row, monitor| {
+                let label = format!("{} ({}x{})", monitor.name, monitor.width, monitor.height);
+                let mut choice = button(text(label).size(14));
+                if Some(monitor.id) != self.selected {
+                    choice = choice
+                        .style(iced::theme::Button::Secondary)
+                        .on_press(Message::MonitorSelected(monitor.id));
+                }
+                row.push(choice)
+            });
+        let preview: Element<'_, Self::Message, Self::Theme, iced::Renderer> = match &self.preview {
+            Some((_, _, handle)) => image(handle.clone())
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .into(),
+            None => Space::with_height(Length::Fill).into(),
+        };
+        let status = match (&self.error, self.frame_rate, &self.stats) {
+            (Some(error), _, _) => text(error).size(14),
+            (None, Some(rate), Some(stats)) => text(format!(
+                "{:.1} frames per second, {} of {} tiles changed, {:.0} of {} kbps at quality {}",
+                rate,
+                stats.diff.changed_tiles,
+                stats.diff.total_tiles,
+                stats.bytes as f64 * 8.0 * rate / 1000.0,
+                stats.bitrate_kbps,
+                stats.quality
+            ))
+            .size(14),
+            (None, Some(rate), None) => text(format!("{:.1} frames per second", rate)).size(14),
+            _ => text("").size(14),
+        };
+        let transfers = self
+            .transfers
+            .history()
+            .records()
+            .iter()
+            .take(SHOWN_TRANSFERS)
+            .fold(column![].spacing(2), |list, record| {
+                list.push(text(transfer_line(record)).size(12))
+            });
+        //Always in view while anyone is connected, so the local user knows and can end it.
+        let viewers = self.sessions.viewers();
+        let session: Element<'_, Self::Message, Self::Theme, iced::Renderer> =
+            match self.sessions.started_at() {
+                Some(started_at) if !viewers.is_empty() => {
+                    let heading = text(format!(
+                        "Remote session active for {}",
+                        duration_text(started_at.elapsed())
+                    ))
+                    .size(16);
+                    let list = viewers.iter().fold(column![].spacing(2), |list, viewer| {
+                        list.push(text(viewer_line(viewer)).size(14))
+                    });
+                    let end = button(text("End session"))
+                        .style(iced::theme::Button::Destructive)
+                        .on_press(Message::EndSession);
+                    row![
+                        column![heading, list].spacing(4),
+                        Space::with_width(Length::Fill),
+                        end
+                    ]
+                    .align_items(Alignment::Center)
+                    .into()
+                }
+                _ => text("No one is connected").size(14).into(),
+            };
+        let service = text(&self.service_line).size(12);
+        column![session, monitors, preview, status, transfers, service]
+            .spacing(10)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(20)
+            .align_items(Alignment::Start)
+            .into()
+    }
+
+    fn update(&mut self, message: Self::Message) -> Command<Self::Message> {
+        match message {
+            Message::Tick => match self.capture_preview() {
+                Ok(Some(frame)) => {
+                    self.error = None;
+                    return self.encode(frame);
+                }
+                Ok(None) => self.error = None,
+                Err(e) => self.error = Some(e),
+            },
+            Message::Encoded(result) => {
+                self.encoding = false;
+                match result {
+                    Ok(stats) => self.stats = Some(stats),
+                    Err(e) => self.error = Some(e),
+                }
+            }
+            Message::MonitorSelected(id) => {
+                self.selected = Some(id);
+                self.preview = None;
+                self.last_capture = None;
+                self.frame_rate = None;
+                self.keyframe = true;
+                self.stats = None;
+            }
+            Message::ConsentTick => {
+                if self.prompt.is_none() {
+                    self.prompt = self.prompts.try_next();
+                }
+                if let Some(prompt) = self
+                    .prompt
+                    .take_if(|prompt| prompt.deadline <= Instant::now())
+                {
+                    prompt.answer(Decision::TimedOut);
+                }
+                return self.talk_to_service();
+            }
+            Message::EndSession => {
+                self.sessions.end("ended by the local user");
+                //Nothing the viewer was holding down may stay pressed.
+                if let Some(input) = self.input.as_mut() {
+                    input.release_all();
+                }
+            }
+            Message::ConsentAnswered(accepted) => {
+                if let Some(prompt) = self.prompt.take() {
+                    prompt.answer(match accepted {
+                        true => Decision::Accepted,
+                        false => Decision::Denied,
+                    });
+                }
+            }
+            Message::ServiceConnected(Ok(client)) => {
+                self.service = Some(client.clone());
+                return Command::perform(
+                    async move { client.request(Request::Status).await },
+                    Message::ServiceStatus,
+                );
+            }
+            Message::ServiceConnected(Err(e)) => {
+                log::warn!("the service is unreachable: {}", e);
+                self.service_line = format!("The service is unreachable: {}", e);
+            }
+            Message::ServiceStatus(Ok(Response::Status(status))) => {
+                self.service_line = service_line(&status);
+            }
+            Message::ServiceStatus(reply) | Message::ServiceReplied(reply) => match reply {
+                Ok(Response::Failed { reason }) => log::warn!("the service refused: {}", reason),
+                Ok(_) => {}
+                Err(e) => {
+                    log::warn!("lost the connection to the service: {}", e);
+                    self.service = None;
+                    self.service_line = "Lost the connection to the service".to_string();
+                }
+            },
+        }
         Command::none()
     }
+
+    fn subscription(&self) -> Subscription<Self::Message> {
+        let consent = iced::time::every(CONSENT_TICK).map(|_| Message::ConsentTick);
+        if self.capturer.is_none() {
+            return consent;
+        }
+        Subscription::batch([
+            iced::time::every(self.interval).map(|_| Message::Tick),
+            consent,
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CaptureBackend;
+    use crate::consent::{ConsentPolicy, ConsentRequest};
+    use crate::input::InputBackend;
+    use crate::remote::Permissions;
+
+    fn open_window() -> DeskWindow {
+        let (window, _) = DeskWindow::new(DeskFlags {
+            capture: CaptureSection {
+                backend: CaptureBackend::Synthetic,
+                interval_ms: 100,
+            },
+            encoder: EncoderSettings::default(),
+            input: InputSection {
+                backend: InputBackend::Disabled,
+                layout: "us".to_string(),
+            },
+            clipboard: ClipboardPolicy::default(),
+            transfer: TransferPolicy {
+                allowed_dirs: Vec::new(),
+                max_file_bytes: 0,
+            },
+            consent: ConsentSettings {
+                policy: ConsentPolicy::Always,
+                timeout: Duration::from_secs(30),
+                presence_idle: Duration::from_secs(300),
+            },
+            ipc_path: PathBuf::from("/nonexistent/ipc.sock"),
+        });
+        window
+    }
+
+    #[test]
+    fn previews_the_selected_monitor() {
+        let mut window = open_window();
+        assert_eq!(window.monitors.len(), 2);
+        assert_eq!(window.selected, Some(0));
+
+        //Nothing is encoded while no one is watching.
+        let _ = window.update(Message::Tick);
+        assert_eq!(window.error, None);
+        assert_eq!(window.frame_rate, None);
+        assert!(window.stats.is_none() && !window.encoding);
+        let (width, height, _) = window.preview.as_ref().unwrap();
+        assert_eq!((*width, *height), (640, 360));
+
+        let viewer = Viewer {
+            id: "viewer-1".to_string(),
+            name: "alice@support".to_string(),
+            permissions: Permissions::default(),
+            joined_at: Instant::now(),
+        };
+        let _stream = window.sessions.join(viewer, Decision::Accepted).unwrap();
+        let _ = window.update(Message::Tick);
+        assert!(window.encoding && !window.keyframe);
+        //Ticks while a frame is in flight skip the encoder.
+        let _ = window.update(Message::Tick);
+        assert!(window.encoding);
+
+        //Stand in for the blocking task the tick handed off.
+        for keyframe in [true, false] {
+            let frame = window.capture_preview().unwrap().unwrap();
+            let result = round_trip(&window.codec, &frame, window.frame_rate, keyframe);
+            let _ = window.update(Message::Encoded(result));
+            assert!(!window.encoding);
+            let stats = window.stats.as_ref().unwrap();
+            assert_eq!(stats.diff.full_frame, keyframe);
+            assert!(stats.bytes > 0);
+        }
+        assert!(window.stats.as_ref().unwrap().diff.changed_tiles > 0);
+
+        let _ = window.update(Message::MonitorSelected(1));
+        let _ = window.update(Message::Tick);
+        let (width, height, _) = window.preview.as_ref().unwrap();
+        assert_eq!((*width, *height), (640, 480));
+    }
+
+    //Ask through the window's consent handle and tick until the prompt shows.
+    async fn prompt(window: &mut DeskWindow) -> tokio::task::JoinHandle<Decision> {
+        let consent = window.consent.clone();
+        let asking = tokio::spawn(async move {
+            consent
+                .ask(ConsentRequest {
+                    viewer: "alice@support".to_string(),
+                    reason: "ticket 1234".to_string(),
+                })
+                .await
+        });
+        while window.prompt.is_none() {
+            tokio::task::yield_now().await;
+            let _ = window.update(Message::ConsentTick);
+        }
+        asking
+    }
+
+    #[tokio::test]
+    async fn asks_the_local_user() {
+        let mut window = open_window();
+        for (accepted, decision) in [(true, Decision::Accepted), (false, Decision::Denied)] {
+            let asking = prompt(&mut window).await;
+            assert_eq!(
+                window.prompt.as_ref().unwrap().request.viewer,
+                "alice@support"
+            );
+            let _ = window.view();
+            let _ = window.update(Message::ConsentAnswered(accepted));
+            assert!(window.prompt.is_none());
+            assert_eq!(asking.await.unwrap(), decision);
+        }
+
+        let asking = prompt(&mut window).await;
+        window.prompt.as_mut().unwrap().deadline = Instant::now();
+        let _ = window.update(Message::ConsentTick);
+        assert!(window.prompt.is_none());
+        assert_eq!(asking.await.unwrap(), Decision::TimedOut);
+    }
+
+    #[test]
+    fn ends_the_session() {
+        let mut window = open_window();
+        let viewer = Viewer {
+            id: "viewer-1".to_string(),
+            name: "alice@support".to_string(),
+            permissions: Permissions {
+                view: true,
+                control: true,
+                ..Permissions::default()
+            },
+            joined_at: Instant::now(),
+        };
+        assert_eq!(viewer_line(&viewer), "alice@support: view, control");
+        let stream = window.sessions.join(viewer, Decision::Accepted).unwrap();
+        let _ = window.view();
+
+        let _ = window.update(Message::EndSession);
+        assert!(*stream.borrow());
+        assert!(window.sessions.viewers().is_empty());
+        assert!(matches!(
+            window.hub_outbox.try_recv(),
+            Ok(HubMessage::SessionEnded { viewers, .. }) if viewers == ["viewer-1"]
+        ));
+    }
+
+    #[test]
+    fn describes_the_service() {
+        let mut status = ServiceStatus {
+            version: "1.2.3".to_string(),
+            hub_url: Some("wss://hub.example.com/agent".to_string()),
+            agent_id: Some("agent-1".to_string()),
+            desktop: Default::default(),
+        };
+        assert_eq!(
+            service_line(&status),
+            "Managed by wss://hub.example.com/agent as agent-1"
+        );
+        status.agent_id = None;
+        assert_eq!(
+            service_line(&status),
+            "Not yet enrolled with wss://hub.example.com/agent"
+        );
+        status.hub_url = None;
+        assert_eq!(service_line(&status), "Not managed by a hub");
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(duration_text(Duration::from_secs(0)), "0:00");
+        assert_eq!(duration_text(Duration::from_secs(245)), "4:05");
+        assert_eq!(duration_text(Duration::from_secs(3723)), "1:02:03");
+    }
+
+    #[test]
+    fn describes_transfers() {
+        let mut record = TransferRecord {
+            id: 1,
+            name: "setup.exe".to_string(),
+            direction: Direction::Incoming,
+            size: 12_500_000,
+            transferred: 5_000_000,
+            state: TransferState::Running,
+            started_at: chrono::Local::now(),
+        };
+        let time = record.started_at.format("%H:%M").to_string();
+        assert_eq!(
+            transfer_line(&record),
+            format!("{} receiving setup.exe: 5.0 MB of 12.5 MB", time)
+        );
+        record.state = TransferState::Completed;
+        assert_eq!(
+            transfer_line(&record),
+            format!("{} received setup.exe (12.5 MB)", time)
+        );
+        record.direction = Direction::Outgoing;
+        record.state = TransferState::Failed("checksum mismatch".to_string());
+        assert_eq!(
+            transfer_line(&record),
+            format!("{} sending setup.exe failed: checksum mismatch", time)
+        );
+    }
+
+    #[test]
+    fn thumbnail_keeps_small_frames() {
+        let frame = capture::synthetic::pattern(320, 200, 0);
+        let (width, height, pixels) = thumbnail(&frame, PREVIEW_WIDTH);
+        assert_eq!((width, height), (320, 200));
+        assert_eq!(pixels, frame.to_rgba());
+    }
 }
@@ -0,0 +1,61 @@
+//Lossless rectangles: PNG where viewers decode images natively, zstd where speed matters more.
+use super::{Codec, EncodeError};
+use crate::capture::diff::Rect;
+use std::io::Cursor;
+
+const ZSTD_LEVEL: i32 = 3;
+
+fn png_error(e: impl ToString) -> EncodeError {
+    EncodeError::Codec(Codec::Png, e.to_string())
+}
+
+fn zstd_error(e: impl ToString) -> EncodeError {
+    EncodeError::Codec(Codec::Zstd, e.to_string())
+}
+
+pub fn encode_png(rgb: &[u8], rect: &Rect) -> Result<Vec<u8>, EncodeError> {
+    let mut data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut data, rect.width, rect.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(png::Compression::Fast);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(rgb))
+        .map_err(png_error)?;
+    Ok(data)
+}
+
+pub fn decode_png(data: &[u8], rect: &Rect) -> Result<Vec<u8>, EncodeError> {
+    let mut reader = png::Decoder::new(Cursor::new(data))
+        .read_info()
+        .map_err(png_error)?;
+    let mut rgb = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut rgb).map_err(png_error)?;
+    if (info.width, info.height, info.color_type) != (rect.width, rect.height, png::ColorType::Rgb)
+    {
+        return Err(png_error(format!(
+            "image is {}x{} {:?}, expected {}x{} RGB",
+            info.width, info.height, info.color_type, rect.width, rect.height
+        )));
+    }
+    rgb.truncate(info.buffer_size());
+    Ok(rgb)
+}
+
+pub fn encode_zstd(rgb: &[u8]) -> Result<Vec<u8>, EncodeError> {
+    zstd::bulk::compress(rgb, ZSTD_LEVEL).map_err(zstd_error)
+}
+
+pub fn decode_zstd(data: &[u8], rect: &Rect) -> Result<Vec<u8>, EncodeError> {
+    let expected = rect.area() as usize * 3;
+    let rgb = zstd::bulk::decompress(data, expected).map_err(zstd_error)?;
+    if rgb.len() != expected {
+        return Err(zstd_error(format!(
+            "{} bytes, expected {}",
+            rgb.len(),
+            expected
+        )));
+    }
+    Ok(rgb)
+}
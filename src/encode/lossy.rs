@@ -0,0 +1,38 @@
+//Lossy WebP stills, encoded by libwebp. Each is a VP8 intra frame: nothing is predicted from
+//earlier frames.
+use super::{Codec, EncodeError};
+use crate::capture::diff::Rect;
+
+pub fn encode(rgb: &[u8], rect: &Rect, quality: u8) -> Result<Vec<u8>, EncodeError> {
+    webp::Encoder::from_rgb(rgb, rect.width, rect.height)
+        .encode_simple(false, quality as f32)
+        .map(|memory| memory.to_vec())
+        .map_err(|e| EncodeError::Codec(Codec::Webp, format!("{:?}", e)))
+}
+
+//Packed RGB of `rect`, which the picture must exactly cover.
+pub fn decode(data: &[u8], rect: &Rect) -> Result<Vec<u8>, EncodeError> {
+    let image = webp::Decoder::new(data)
+        .decode()
+        .ok_or_else(|| EncodeError::Codec(Codec::Webp, "undecodable picture".to_string()))?;
+    if (image.width(), image.height()) != (rect.width, rect.height) {
+        return Err(EncodeError::Codec(
+            Codec::Webp,
+            format!(
+                "picture is {}x{}, expected {}x{}",
+                image.width(),
+                image.height(),
+                rect.width,
+                rect.height
+            ),
+        ));
+    }
+    if image.is_alpha() {
+        return Ok(image
+            .chunks_exact(4)
+            .flat_map(|pixel| &pixel[..3])
+            .copied()
+            .collect());
+    }
+    Ok(image.to_vec())
+}
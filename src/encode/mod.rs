@@ -0,0 +1,348 @@
+//Turns captured frames into compact updates for viewers: only changed rectangles, each with a codec suited to it.
+use crate::capture::diff::{FrameStats, Rect};
+use crate::capture::{Frame, PixelFormat};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+pub mod lossless;
+pub mod lossy;
+pub mod stream;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    //Lossy WebP stills (VP8 intra frames, each one self-contained), for photos and video.
+    Webp,
+    //Lossless, for text and UI where blur is unacceptable.
+    Png,
+    Zstd,
+}
+
+impl FromStr for Codec {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "webp" => Ok(Codec::Webp),
+            "png" => Ok(Codec::Png),
+            "zstd" => Ok(Codec::Zstd),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::Webp => write!(f, "webp"),
+            Codec::Png => write!(f, "png"),
+            Codec::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncoderSettings {
+    pub bitrate_kbps: u32,
+    pub frame_rate: u32,
+    //Highest WebP quality (1-100); rate control lowers it to stay within the bitrate.
+    pub max_quality: u8,
+    pub min_quality: u8,
+    //Frames between forced keyframes; 0 sends them only on request.
+    pub keyframe_interval: u32,
+    //Codec for regions with few colours, such as text.
+    pub lossless: Codec,
+    //Regions with at most this many distinct colours count as text-like; 0 sends everything as WebP.
+    pub text_max_colors: usize,
+    //Fraction of the captured resolution frames are encoded at; congestion control lowers it.
+    pub scale: f32,
+}
+
+impl Default for EncoderSettings {
+    fn default() -> Self {
+        EncoderSettings {
+            bitrate_kbps: 2000,
+            frame_rate: 15,
+            max_quality: 80,
+            min_quality: 20,
+            keyframe_interval: 300,
+            lossless: Codec::Zstd,
+            text_max_colors: 64,
+            scale: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeError {
+    Codec(Codec, String),
+    //A delta frame arrived before the keyframe it builds on.
+    MissingKeyframe,
+    //An update for an empty rectangle or one reaching outside the frame.
+    OutOfBounds(Rect),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Codec(codec, message) => write!(f, "{}: {}", codec, message),
+            EncodeError::MissingKeyframe => write!(f, "delta frame without a keyframe"),
+            EncodeError::OutOfBounds(rect) => write!(
+                f,
+                "rectangle {}x{} at {},{} is empty or outside the frame",
+                rect.width, rect.height, rect.x, rect.y
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedRect {
+    pub rect: Rect,
+    pub codec: Codec,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EncodedFrame {
+    pub width: u32,
+    pub height: u32,
+    //A keyframe covers the whole frame and needs nothing before it.
+    pub keyframe: bool,
+    pub rects: Vec<EncodedRect>,
+    //WebP quality the lossy rectangles were encoded at.
+    pub quality: u8,
+    pub diff: FrameStats,
+    pub captured_at: Instant,
+}
+
+impl EncodedFrame {
+    pub fn size(&self) -> usize {
+        self.rects.iter().map(|rect| rect.data.len()).sum()
+    }
+}
+
+pub trait Encoder: Send {
+    //Encode what changed since the previous frame, or all of it for a keyframe.
+    fn encode(&mut self, frame: &Frame) -> Result<EncodedFrame, EncodeError>;
+    //Make the next frame a keyframe, e.g. when a viewer joins or lost packets.
+    fn request_keyframe(&mut self);
+    fn set_bitrate(&mut self, bitrate_kbps: u32);
+    fn set_frame_rate(&mut self, frame_rate: u32);
+    //Encode at this fraction of the captured resolution; a change starts with a keyframe.
+    fn set_scale(&mut self, scale: f32);
+    fn settings(&self) -> &EncoderSettings;
+}
+
+//Tightly packed RGB of one rectangle of `frame`, the input every codec takes.
+fn rgb(frame: &Frame, rect: &Rect) -> Vec<u8> {
+    let mut rgb = Vec::with_capacity(rect.area() as usize * 3);
+    for y in rect.y..rect.y + rect.height {
+        let start = y as usize * frame.stride + rect.x as usize * 4;
+        let row = &frame.data[start..start + rect.width as usize * 4];
+        for pixel in row.chunks_exact(4) {
+            match frame.format {
+                PixelFormat::Bgra => rgb.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]),
+                PixelFormat::Rgba => rgb.extend_from_slice(&[pixel[0], pixel[1], pixel[2]]),
+            }
+        }
+    }
+    rgb
+}
+
+//Reassembles encoded frames into an RGBA picture, as a viewer would.
+pub struct Decoder {
+    canvas: Option<Frame>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder { canvas: None }
+    }
+
+    //The picture so far; viewers render it, the agent only checks frames decode.
+    #[allow(dead_code)]
+    pub fn frame(&self) -> Option<&Frame> {
+        self.canvas.as_ref()
+    }
+
+    pub fn apply(&mut self, encoded: &EncodedFrame) -> Result<(), EncodeError> {
+        if encoded.keyframe {
+            self.canvas = Some(Frame {
+                width: encoded.width,
+                height: encoded.height,
+                stride: encoded.width as usize * 4,
+                format: PixelFormat::Rgba,
+                data: vec![0; encoded.width as usize * encoded.height as usize * 4],
+                captured_at: encoded.captured_at,
+            });
+        }
+        let canvas = match self.canvas.as_mut() {
+            Some(canvas) if (canvas.width, canvas.height) == (encoded.width, encoded.height) => {
+                canvas
+            }
+            _ => return Err(EncodeError::MissingKeyframe),
+        };
+        for update in &encoded.rects {
+            let rect = update.rect;
+            let inside = |start: u32, length: u32, limit: u32| {
+                length > 0 && start as u64 + length as u64 <= limit as u64
+            };
+            if !inside(rect.x, rect.width, canvas.width)
+                || !inside(rect.y, rect.height, canvas.height)
+            {
+                return Err(EncodeError::OutOfBounds(rect));
+            }
+            let pixels = match update.codec {
+                Codec::Webp => lossy::decode(&update.data, &rect)?,
+                Codec::Png => lossless::decode_png(&update.data, &rect)?,
+                Codec::Zstd => lossless::decode_zstd(&update.data, &rect)?,
+            };
+            for (row, source) in pixels.chunks_exact(rect.width as usize * 3).enumerate() {
+                let start = (rect.y as usize + row) * canvas.stride + rect.x as usize * 4;
+                let target = &mut canvas.data[start..start + rect.width as usize * 4];
+                for (pixel, rgb) in target.chunks_exact_mut(4).zip(source.chunks_exact(3)) {
+                    pixel.copy_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+                }
+            }
+        }
+        canvas.captured_at = encoded.captured_at;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    //Peak signal-to-noise ratio over the colour channels, in dB; infinite for identical frames.
+    pub fn psnr(original: &Frame, decoded: &Frame) -> f64 {
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width: original.width,
+            height: original.height,
+        };
+        let (a, b) = (rgb(original, &full), rgb(decoded, &full));
+        let squared: f64 = a
+            .iter()
+            .zip(&b)
+            .map(|(a, b)| (*a as f64 - *b as f64).powi(2))
+            .sum();
+        let mse = squared / a.len() as f64;
+        if mse == 0.0 {
+            return f64::INFINITY;
+        }
+        10.0 * (255.0 * 255.0 / mse).log10()
+    }
+
+    //Smooth gradients with a little noise, which compress like a photo rather than like text.
+    pub fn photo(width: u32, height: u32, seed: u32) -> Frame {
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+        let mut noise = seed.wrapping_mul(2654435761) | 1;
+        for y in 0..height {
+            for x in 0..width {
+                noise ^= noise << 13;
+                noise ^= noise >> 17;
+                noise ^= noise << 5;
+                let jitter = (noise % 9) as i32 - 4;
+                let channel = |value: u32| (value as i32 + jitter).clamp(0, 255) as u8;
+                data.extend_from_slice(&[
+                    channel((x + seed) * 255 / width.max(1) % 256),
+                    channel(y * 255 / height.max(1)),
+                    channel(((x + y) * 128 / (width + height).max(1) + 64) % 256),
+                    255,
+                ]);
+            }
+        }
+        Frame {
+            width,
+            height,
+            stride: width as usize * 4,
+            format: PixelFormat::Bgra,
+            data,
+            captured_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn rgb_reads_either_byte_order() {
+        let mut frame = photo(2, 1, 0);
+        frame.data = vec![1, 2, 3, 255, 4, 5, 6, 255];
+        let all = Rect {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 1,
+        };
+        assert_eq!(rgb(&frame, &all), vec![3, 2, 1, 6, 5, 4]);
+        frame.format = PixelFormat::Rgba;
+        assert_eq!(rgb(&frame, &all), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn delta_without_keyframe_is_rejected() {
+        let encoded = EncodedFrame {
+            width: 4,
+            height: 4,
+            keyframe: false,
+            rects: Vec::new(),
+            quality: 80,
+            diff: FrameStats::default(),
+            captured_at: Instant::now(),
+        };
+        assert_eq!(
+            Decoder::new().apply(&encoded),
+            Err(EncodeError::MissingKeyframe)
+        );
+    }
+
+    #[test]
+    fn rects_outside_the_frame_are_rejected() {
+        let pixels = lossless::encode_zstd(&[0; 2 * 2 * 3]).unwrap();
+        for rect in [
+            Rect {
+                x: 3,
+                y: 0,
+                width: 2,
+                height: 2,
+            },
+            Rect {
+                x: 0,
+                y: 3,
+                width: 2,
+                height: 2,
+            },
+            Rect {
+                x: u32::MAX,
+                y: 0,
+                width: 2,
+                height: 2,
+            },
+            Rect {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 2,
+            },
+        ] {
+            let encoded = EncodedFrame {
+                width: 4,
+                height: 4,
+                keyframe: true,
+                rects: vec![EncodedRect {
+                    rect,
+                    codec: Codec::Zstd,
+                    data: pixels.clone(),
+                }],
+                quality: 80,
+                diff: FrameStats::default(),
+                captured_at: Instant::now(),
+            };
+            assert_eq!(
+                Decoder::new().apply(&encoded),
+                Err(EncodeError::OutOfBounds(rect))
+            );
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use super::{
+    lossless, lossy, rgb, Codec, EncodeError, EncodedFrame, EncodedRect, Encoder, EncoderSettings,
+};
+use crate::capture::diff::{DiffConfig, TileDiffer};
+use crate::capture::Frame;
+use std::collections::HashSet;
+
+//Quality steps of the rate control; it backs off faster than it recovers.
+const QUALITY_DOWN: u8 = 6;
+const QUALITY_UP: u8 = 2;
+//Below this a frame is too small to be worth sending.
+const MIN_SCALE: f32 = 0.1;
+
+//Sends changed rectangles, lossless when they look like text and lossy WebP otherwise,
+//lowering WebP quality when frames run over the bitrate budget.
+pub struct StreamEncoder {
+    settings: EncoderSettings,
+    differ: TileDiffer,
+    quality: u8,
+    frames_since_keyframe: u32,
+    keyframe_requested: bool,
+}
+
+impl StreamEncoder {
+    pub fn new(settings: EncoderSettings) -> Self {
+        StreamEncoder {
+            quality: settings.max_quality,
+            settings,
+            differ: TileDiffer::new(DiffConfig::default()),
+            frames_since_keyframe: 0,
+            keyframe_requested: true,
+        }
+    }
+
+    //Bytes one frame may take at the configured bitrate and frame rate.
+    fn frame_budget(&self) -> usize {
+        self.settings.bitrate_kbps as usize * 1000 / 8 / self.settings.frame_rate.max(1) as usize
+    }
+
+    fn adjust_quality(&mut self, lossy_bytes: usize, keyframe: bool) {
+        //Keyframes are expected to be several frames' worth.
+        let budget = self.frame_budget() * if keyframe { 4 } else { 1 };
+        if lossy_bytes * 10 > budget * 11 {
+            self.quality = self
+                .quality
+                .saturating_sub(QUALITY_DOWN)
+                .max(self.settings.min_quality);
+        } else if lossy_bytes * 10 < budget * 7 {
+            self.quality = (self.quality + QUALITY_UP).min(self.settings.max_quality);
+        }
+    }
+
+    fn codec_for(&self, rgb: &[u8]) -> Codec {
+        if self.settings.text_max_colors > 0
+            && distinct_colors(rgb, self.settings.text_max_colors) <= self.settings.text_max_colors
+        {
+            self.settings.lossless
+        } else {
+            Codec::Webp
+        }
+    }
+}
+
+//Nearest-neighbour downscale to `scale` of the frame's size, keeping its pixel format.
+fn downscale(frame: &Frame, scale: f32) -> Frame {
+    let width = ((frame.width as f32 * scale).round() as u32).max(1);
+    let height = ((frame.height as f32 * scale).round() as u32).max(1);
+    let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height {
+        let row = frame.row((y as u64 * frame.height as u64 / height as u64) as u32);
+        for x in 0..width {
+            let offset = (x as u64 * frame.width as u64 / width as u64) as usize * 4;
+            data.extend_from_slice(&row[offset..offset + 4]);
+        }
+    }
+    Frame {
+        width,
+        height,
+        stride: width as usize * 4,
+        format: frame.format,
+        data,
+        captured_at: frame.captured_at,
+    }
+}
+
+//Distinct colours in packed RGB, counting no further than just past `limit`.
+fn distinct_colors(rgb: &[u8], limit: usize) -> usize {
+    let mut colors = HashSet::new();
+    for pixel in rgb.chunks_exact(3) {
+        colors.insert([pixel[0], pixel[1], pixel[2]]);
+        if colors.len() > limit {
+            break;
+        }
+    }
+    colors.len()
+}
+
+impl Encoder for StreamEncoder {
+    fn encode(&mut self, frame: &Frame) -> Result<EncodedFrame, EncodeError> {
+        //A new size no longer compares with the previous frame, so it goes out as a keyframe.
+        let scaled;
+        let frame = if self.settings.scale < 1.0 {
+            scaled = downscale(frame, self.settings.scale);
+            &scaled
+        } else {
+            frame
+        };
+        let interval = self.settings.keyframe_interval;
+        if self.keyframe_requested || (interval > 0 && self.frames_since_keyframe + 1 >= interval) {
+            self.differ.reset();
+        }
+        let diff = self.differ.diff(frame);
+        //A rectangle covering the whole frame needs nothing before it, whatever the reason.
+        let keyframe = diff.stats.full_frame;
+        if keyframe {
+            self.keyframe_requested = false;
+            self.frames_since_keyframe = 0;
+        } else {
+            self.frames_since_keyframe += 1;
+        }
+
+        let mut rects = Vec::with_capacity(diff.rects.len());
+        let mut lossy_bytes = None;
+        for rect in &diff.rects {
+            let pixels = rgb(frame, rect);
+            let codec = self.codec_for(&pixels);
+            let data = match codec {
+                Codec::Webp => lossy::encode(&pixels, rect, self.quality)?,
+                Codec::Png => lossless::encode_png(&pixels, rect)?,
+                Codec::Zstd => lossless::encode_zstd(&pixels)?,
+            };
+            if codec == Codec::Webp {
+                *lossy_bytes.get_or_insert(0) += data.len();
+            }
+            rects.push(EncodedRect {
+                rect: *rect,
+                codec,
+                data,
+            });
+        }
+        let quality = self.quality;
+        //Quality only steers WebP; lossless rectangles cost what they cost.
+        if let Some(bytes) = lossy_bytes {
+            self.adjust_quality(bytes, keyframe);
+        }
+        Ok(EncodedFrame {
+            width: frame.width,
+            height: frame.height,
+            keyframe,
+            rects,
+            quality,
+            diff: diff.stats,
+            captured_at: frame.captured_at,
+        })
+    }
+
+    fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    fn set_bitrate(&mut self, bitrate_kbps: u32) {
+        self.settings.bitrate_kbps = bitrate_kbps.max(1);
+    }
+
+    fn set_frame_rate(&mut self, frame_rate: u32) {
+        self.settings.frame_rate = frame_rate.max(1);
+    }
+
+    fn set_scale(&mut self, scale: f32) {
+        self.settings.scale = scale.clamp(MIN_SCALE, 1.0);
+    }
+
+    fn settings(&self) -> &EncoderSettings {
+        &self.settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::synthetic;
+    use crate::encode::tests::{photo, psnr};
+    use crate::encode::Decoder;
+
+    fn encoder(settings: EncoderSettings) -> StreamEncoder {
+        StreamEncoder::new(settings)
+    }
+
+    fn round_trip(encoder: &mut StreamEncoder, decoder: &mut Decoder, frame: &Frame) -> f64 {
+        let encoded = encoder.encode(frame).unwrap();
+        decoder.apply(&encoded).unwrap();
+        psnr(frame, decoder.frame().unwrap())
+    }
+
+    #[test]
+    fn photo_round_trips_through_webp() {
+        let mut encoder = encoder(EncoderSettings::default());
+        let frame = photo(320, 240, 1);
+        let encoded = encoder.encode(&frame).unwrap();
+        assert!(encoded.keyframe);
+        assert_eq!(encoded.rects.len(), 1);
+        assert_eq!(encoded.rects[0].codec, Codec::Webp);
+        assert!(encoded.size() < frame.data.len() / 10);
+
+        let mut decoder = Decoder::new();
+        decoder.apply(&encoded).unwrap();
+        let quality = psnr(&frame, decoder.frame().unwrap());
+        assert!(quality > 30.0, "PSNR {:.1} dB", quality);
+    }
+
+    #[test]
+    fn higher_quality_gives_higher_psnr() {
+        let frame = photo(256, 256, 2);
+        let at = |quality| {
+            let mut encoder = encoder(EncoderSettings {
+                max_quality: quality,
+                ..EncoderSettings::default()
+            });
+            round_trip(&mut encoder, &mut Decoder::new(), &frame)
+        };
+        let (low, high) = (at(10), at(95));
+        assert!(high > low + 3.0, "{:.1} dB vs {:.1} dB", high, low);
+    }
+
+    #[test]
+    fn text_like_regions_are_lossless() {
+        for lossless in [Codec::Png, Codec::Zstd] {
+            let mut encoder = encoder(EncoderSettings {
+                lossless,
+                ..EncoderSettings::default()
+            });
+            let mut decoder = Decoder::new();
+            for index in 0..3 {
+                let frame = synthetic::pattern(320, 240, index);
+                assert_eq!(
+                    round_trip(&mut encoder, &mut decoder, &frame),
+                    f64::INFINITY,
+                    "{} frame {}",
+                    lossless,
+                    index
+                );
+            }
+            let encoded = encoder.encode(&synthetic::pattern(320, 240, 3)).unwrap();
+            assert!(encoded.rects.iter().all(|rect| rect.codec == lossless));
+        }
+    }
+
+    #[test]
+    fn deltas_carry_only_changed_rects() {
+        let mut encoder = encoder(EncoderSettings::default());
+        let mut decoder = Decoder::new();
+        let mut frame = photo(256, 256, 3);
+        round_trip(&mut encoder, &mut decoder, &frame);
+
+        //Replace the top-left tile with a different picture.
+        let patch = photo(32, 32, 9);
+        for y in 0..32 {
+            let start = y * frame.stride;
+            frame.data[start..start + 128].copy_from_slice(patch.row(y as u32));
+        }
+        let encoded = encoder.encode(&frame).unwrap();
+        assert!(!encoded.keyframe);
+        assert_eq!(encoded.rects.len(), 1);
+        assert_eq!(encoded.rects[0].rect.area(), 32 * 32);
+        decoder.apply(&encoded).unwrap();
+        let quality = psnr(&frame, decoder.frame().unwrap());
+        assert!(quality > 30.0, "PSNR {:.1} dB", quality);
+
+        assert!(encoder.encode(&frame).unwrap().rects.is_empty());
+    }
+
+    #[test]
+    fn keyframes_on_request_and_interval() {
+        let mut encoder = encoder(EncoderSettings {
+            keyframe_interval: 3,
+            ..EncoderSettings::default()
+        });
+        let frame = synthetic::pattern(128, 128, 0);
+        let keyframes: Vec<bool> = (0..7)
+            .map(|index| {
+                if index == 4 {
+                    encoder.request_keyframe();
+                }
+                encoder.encode(&frame).unwrap().keyframe
+            })
+            .collect();
+        assert_eq!(
+            keyframes,
+            vec![true, false, false, true, true, false, false]
+        );
+    }
+
+    #[test]
+    fn rate_control_follows_the_bitrate() {
+        let mut encoder = encoder(EncoderSettings {
+            bitrate_kbps: 5,
+            frame_rate: 10,
+            ..EncoderSettings::default()
+        });
+        let quality = |encoder: &mut StreamEncoder, frames: u32| {
+            let mut quality = 0;
+            for index in 0..frames {
+                encoder.request_keyframe();
+                quality = encoder.encode(&photo(320, 240, index)).unwrap().quality;
+            }
+            quality
+        };
+        assert_eq!(quality(&mut encoder, 20), encoder.settings().min_quality);
+
+        encoder.set_bitrate(100_000);
+        assert_eq!(quality(&mut encoder, 40), encoder.settings().max_quality);
+    }
+
+    #[test]
+    fn scale_shrinks_frames() {
+        let mut encoder = encoder(EncoderSettings::default());
+        let mut decoder = Decoder::new();
+        let frame = synthetic::pattern(320, 240, 0);
+        decoder.apply(&encoder.encode(&frame).unwrap()).unwrap();
+        assert!(encoder.encode(&frame).unwrap().rects.is_empty());
+
+        encoder.set_scale(0.5);
+        let encoded = encoder.encode(&frame).unwrap();
+        assert!(encoded.keyframe);
+        assert_eq!((encoded.width, encoded.height), (160, 120));
+        decoder.apply(&encoded).unwrap();
+        let shrunk = decoder.frame().unwrap();
+        //Nearest-neighbour keeps every other pixel of every other row.
+        let original = frame.to_rgba();
+        for (x, y) in [(0, 0), (3, 7), (100, 60), (159, 119)] {
+            let offset = ((y * 2 * 320 + x * 2) * 4) as usize;
+            assert_eq!(
+                shrunk.row(y)[x as usize * 4..][..4],
+                original[offset..offset + 4]
+            );
+        }
+
+        encoder.set_scale(1.0);
+        assert_eq!(encoder.encode(&frame).unwrap().width, 320);
+    }
+}
@@ -0,0 +1,738 @@
+use crate::install;
+use crate::service::{self, ServiceError, SharedServiceManager};
+use crate::supervisor::{self, DesktopPhase, DesktopState};
+use crate::types;
+
+use iced::widget::{button, image, row, text, Column, Space};
+use iced::{color, executor, Theme};
+use iced::{theme, Application, Command};
+use iced::{Alignment, Element, Font, Length};
+use std::env;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    RegisterButtonPressed,
+    StopServiceButtonPressed,
+    StartServiceButtonPressed,
+    RemoveServiceButtonPressed,
+    Spining(bool),
+    AlertUpdated(Option<types::Alert>, bool),
+    ServiceStatusUpdated(service::ServiceStatus),
+    DesktopStateUpdated(Option<DesktopState>),
+}
+
+pub struct GuideFlags {
+    pub service: SharedServiceManager,
+    //Registering copies the program here first.
+    pub install_dir: PathBuf,
+    //Published by the service's desktop supervisor.
+    pub desktop_state_path: PathBuf,
+}
+
+pub struct GuideWindow {
+    service: SharedServiceManager,
+    install_dir: PathBuf,
+    desktop_state_path: PathBuf,
+    service_status: service::ServiceStatus,
+    desktop: Option<DesktopState>,
+    alert: Option<types::Alert>,
+    spining: bool,
+}
+
+fn commands_with_spining(
+    spining: bool,
+    message: String,
+    cmd: Command<Message>,
+) -> Command<Message> {
+    let spining_cmd = Command::perform(async move { Message::Spining(spining) }, |message| message);
+    let alert_cmd = Command::perform(
+        async {
+            Message::AlertUpdated(
+                Some(types::Alert {
+                    message,
+                    alert_type: types::AlertType::Info,
+                }),
+                false,
+            )
+        },
+        |message| message,
+    );
+    Command::batch([spining_cmd, alert_cmd, cmd])
+}
+
+fn desktop_summary(desktop: &DesktopState) -> String {
+    let mut summary = match (desktop.phase, desktop.pid) {
+        (DesktopPhase::Running, Some(pid)) => format!("Desktop window is running (pid {}).", pid),
+        (DesktopPhase::Running, None) => "Desktop window is running.".to_string(),
+        (DesktopPhase::Restarting, _) => {
+            "Desktop window exited and is being restarted.".to_string()
+        }
+        (DesktopPhase::WaitingForSession, _) => {
+            "Desktop window starts when someone logs in at the console.".to_string()
+        }
+        (DesktopPhase::GaveUp, _) => {
+            "Desktop window kept exiting and will not be restarted until the next logon."
+                .to_string()
+        }
+        (DesktopPhase::NotStarted, _) | (DesktopPhase::Stopped, _) => {
+            "Desktop window is not running.".to_string()
+        }
+    };
+    if desktop.restarts > 0 {
+        summary.push_str(&format!(" Restarted {} times", desktop.restarts));
+        match desktop.last_exit_code {
+            Some(code) => summary.push_str(&format!(", last exit code {}.", code)),
+            None => summary.push('.'),
+        }
+    }
+    summary
+}
+
+impl Application for GuideWindow {
+    type Executor = executor::Default;
+    type Message = Message;
+    type Theme = Theme;
+    type Flags = GuideFlags;
+
+    fn new(flags: Self::Flags) -> (Self, Command<Self::Message>) {
+        let GuideFlags {
+            service,
+            install_dir,
+            desktop_state_path,
+        } = flags;
+        //The program must have the permission to open and operate the Service.
+        let service_status = match service.lock().unwrap().open() {
+            Ok(()) => service::ServiceStatus::Querying,
+            Err(ServiceError::DoesNotExist) => service::ServiceStatus::DoesNotExist,
+            //Apart from the known error DoesNotExist, explain that it is not possible to operate on the Service.
+            Err(_) => service::ServiceStatus::Unknown,
+        };
+        let command: Command<Message> = if service_status == service::ServiceStatus::Querying {
+            let query_service = service.clone();
+            commands_with_spining(
+                true,
+                "Checking service status...".to_string(),
+                Command::perform(
+                    async move {
+                        let status: service::ServiceStatus =
+                            query_service.lock().unwrap().query_status();
+                        Ok::<service::ServiceStatus, std::convert::Infallible>(status)
+                    },
+                    |result| match result {
+                        Ok(status) => Message::ServiceStatusUpdated(status),
+                        Err(e) => Message::AlertUpdated(
+                            Some(types::Alert {
+                                message: e.to_string(),
+                                alert_type: types::AlertType::Error,
+                            }),
+                            true,
+                        ),
+                    },
+                ),
+            )
+        } else {
+            Command::none()
+        };
+
+        (
+            GuideWindow {
+                service,
+                install_dir,
+                desktop_state_path,
+                service_status,
+                desktop: None,
+                alert: None,
+                spining: false,
+            },
+            command,
+        )
+    }
+
+    fn title(&self) -> String {
+        String::from("DeskHub")
+    }
+
+    fn update(&mut self, message: Message) -> iced::Command<Message> {
+        match message {
+            Message::Spining(spining) => {
+                self.spining = spining;
+                Command::none()
+            }
+            Message::ServiceStatusUpdated(status) => {
+                let running = status == service::ServiceStatus::Running;
+                self.service_status = status;
+                self.spining = false;
+                self.alert = None;
+                self.desktop = None;
+                if !running {
+                    return Command::none();
+                }
+                let path = self.desktop_state_path.clone();
+                Command::perform(
+                    async move { supervisor::load_state(&path) },
+                    Message::DesktopStateUpdated,
+                )
+            }
+            Message::DesktopStateUpdated(desktop) => {
+                self.desktop = desktop;
+                Command::none()
+            }
+            Message::RegisterButtonPressed => {
+                let service = self.service.clone();
+                let install_dir = self.install_dir.clone();
+                commands_with_spining(
+                    true,
+                    "Registering service...".to_string(),
+                    Command::perform(
+                        async move {
+                            let Ok(source) = env::current_exe() else {
+                                return Err("Failed to get the execution path.".to_string());
+                            };
+                            //Register the copy in the install directory, so this one can be moved or deleted.
+                            let executable = install::install_binary(&source, &install_dir)
+                                .map_err(|e| format!("Failed to copy the program: {}", e))?;
+                            let mut service = service.lock().unwrap();
+                            if let Err(e) = service.register(
+                                service::SERVICE_DISPLAY_NAME,
+                                &service::command_line_for(&executable),
+                            ) {
+                                return Err(format!("Service registration failed: {}", e));
+                            }
+                            let status = service.query_status();
+                            Ok::<service::ServiceStatus, String>(status)
+                        },
+                        |result| match result {
+                            Ok(status) => Message::ServiceStatusUpdated(status),
+                            Err(e) => Message::AlertUpdated(
+                                Some(types::Alert {
+                                    message: e,
+                                    alert_type: types::AlertType::Error,
+                                }),
+                                true,
+                            ),
+                        },
+                    ),
+                )
+            }
+            Message::StopServiceButtonPressed => {
+                let service = self.service.clone();
+                commands_with_spining(
+                    true,
+                    "Service stopping".to_string(),
+                    Command::perform(
+                        async move {
+                            let service = service.lock().unwrap();
+                            if let Err(e) = service.stop() {
+                                return Err(format!("Failed to stop the service: {}", e));
+                            }
+                            let status = service.query_status();
+                            if status != service::ServiceStatus::Stopped {
+                                return Err("Failed to stop the service".to_string());
+                            }
+                            Ok::<service::ServiceStatus, String>(status)
+                        },
+                        |result| match result {
+                            Ok(status) => Message::ServiceStatusUpdated(status),
+                            Err(e) => Message::AlertUpdated(
+                                Some(types::Alert {
+                                    message: e,
+                                    alert_type: types::AlertType::Error,
+                                }),
+                                true,
+                            ),
+                        },
+                    ),
+                )
+            }
+            Message::StartServiceButtonPressed => {
+                let service = self.service.clone();
+                commands_with_spining(
+                    true,
+                    "The service is starting up...".to_string(),
+                    Command::perform(
+                        async move {
+                            let service = service.lock().unwrap();
+                            if let Err(e) = service.start() {
+                                return Err(format!("Failed to start the service: {}", e));
+                            }
+                            let status = service.query_status();
+                            if status != service::ServiceStatus::Running {
+                                return Err("Failed to start the service".to_string());
+                            }
+                            Ok::<service::ServiceStatus, String>(status)
+                        },
+                        |result| match result {
+                            Ok(status) => Message::ServiceStatusUpdated(status),
+                            Err(e) => Message::AlertUpdated(
+                                Some(types::Alert {
+                                    message: e,
+                                    alert_type: types::AlertType::Error,
+                                }),
+                                true,
+                            ),
+                        },
+                    ),
+                )
+            }
+            Message::RemoveServiceButtonPressed => {
+                let service = self.service.clone();
+                commands_with_spining(
+                    true,
+                    "Removing service...".to_string(),
+                    Command::perform(
+                        async move {
+                            let mut service = service.lock().unwrap();
+                            match service.unregister() {
+                                Ok(()) => Ok(service::ServiceStatus::DoesNotExist),
+                                Err(e) => Err(format!("Service removal fail: {}", e)),
+                            }
+                        },
+                        |result| match result {
+                            Ok(status) => Message::ServiceStatusUpdated(status),
+                            Err(e) => Message::AlertUpdated(
+                                Some(types::Alert {
+                                    message: e,
+                                    alert_type: types::AlertType::Error,
+                                }),
+                                true,
+                            ),
+                        },
+                    ),
+                )
+            }
+            Message::AlertUpdated(alert, hide_spining) => {
+                self.alert = alert;
+                if hide_spining {
+                    self.spining = false;
+                }
+                Command::none()
+            }
+        }
+    }
+
+    fn view(&self) -> Element<'_, Message> {
+        let mut columns = Column::new();
+        columns = columns
+            .push(image(format!("{}/assets/wave.png", env!("CARGO_MANIFEST_DIR"))).width(30))
+            .push(text("Hey!").size(24).font(Font {
+                weight: iced::font::Weight::Bold,
+                ..Default::default()
+            }))
+            .push(Space::with_height(15));
+
+        match self.service_status {
+            service::ServiceStatus::Querying => {
+                let txt = "Querying service status....";
+                columns = columns.push(text(txt).size(16));
+            }
+            service::ServiceStatus::DoesNotExist => {
+                let txt = "DeskHub needs to be registered as a service before running.\nRegistration copies the program to the install directory, so this copy can be moved or deleted afterwards.";
+                columns = columns
+                    .push(text(txt).size(16))
+                    .push(Space::with_height(15))
+                    .push({
+                        let btn = button("Register as service")
+                            .padding([12, 24])
+                            .style(theme::Button::Primary);
+                        if self.spining {
+                            btn
+                        } else {
+                            btn.on_press(Message::RegisterButtonPressed)
+                        }
+                    });
+            }
+            service::ServiceStatus::Running => {
+                let txt = "DeskHub Service has running. \nA window program displaying test information will appear normally. \nIf there are any issues, please restart the service or reinstall it after deleting the service.";
+                columns = columns.push(text(txt).size(16));
+                if let Some(desktop) = self.desktop.as_ref() {
+                    columns = columns
+                        .push(Space::with_height(8))
+                        .push(text(desktop_summary(desktop)).size(14));
+                }
+                columns = columns.push(Space::with_height(15)).push({
+                    let stop_btn = button("Stop service")
+                        .padding([8, 14])
+                        .style(theme::Button::Primary);
+                    let remove_btn = button("Remove service")
+                        .padding([8, 14])
+                        .style(theme::Button::Destructive);
+                    if self.spining {
+                        row![stop_btn, Space::with_width(15), remove_btn]
+                    } else {
+                        row![
+                            stop_btn.on_press(Message::StopServiceButtonPressed),
+                            Space::with_width(15),
+                            remove_btn.on_press(Message::RemoveServiceButtonPressed)
+                        ]
+                    }
+                });
+            }
+            service::ServiceStatus::Stopped => {
+                let txt = "Service has stopped. \nOnce the service is running normally, the main interface will automatically open.";
+                columns = columns
+                    .push(text(txt).size(16))
+                    .push(Space::with_height(15))
+                    .push({
+                        let start_btn = button("Start service")
+                            .padding([8, 14])
+                            .style(theme::Button::Primary);
+                        let remove_btn = button("Remove service")
+                            .padding([8, 14])
+                            .style(theme::Button::Destructive);
+                        if self.spining {
+                            row![start_btn, Space::with_width(15), remove_btn]
+                        } else {
+                            row![
+                                start_btn.on_press(Message::StartServiceButtonPressed),
+                                Space::with_width(15),
+                                remove_btn.on_press(Message::RemoveServiceButtonPressed)
+                            ]
+                        }
+                    });
+            }
+            service::ServiceStatus::Unknown => {
+                println!("hello2");
+            }
+        }
+
+        if let Some(alert) = self.alert.as_ref() {
+            columns =
+                columns
+                    .push(Space::with_height(15))
+                    .push(text(&alert.message).size(16).style(match alert.alert_type {
+                        types::AlertType::Error => color!(0xFF0000),
+                        types::AlertType::Info => color!(0x808080),
+                    }));
+        }
+
+        columns
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(20)
+            .align_items(Alignment::Start)
+            .into()
+    }
+
+    fn theme(&self) -> Self::Theme {
+        Self::Theme::default()
+    }
+
+    fn style(&self) -> <Self::Theme as iced::application::StyleSheet>::Style {
+        <Self::Theme as iced::application::StyleSheet>::Style::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::fake::{FakeServiceManager, Operation};
+    use crate::service::ServiceStatus;
+    use iced::futures::executor::block_on;
+    use iced_runtime::command::Action;
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    //Installing from the test binary's own directory makes the copy a no-op.
+    fn flags(fake: &FakeServiceManager) -> GuideFlags {
+        let exe = env::current_exe().unwrap();
+        flags_with_install_dir(fake, exe.parent().unwrap().to_path_buf())
+    }
+
+    fn flags_with_install_dir(fake: &FakeServiceManager, install_dir: PathBuf) -> GuideFlags {
+        GuideFlags {
+            service: Arc::new(Mutex::new(Box::new(fake.clone()))),
+            install_dir,
+            desktop_state_path: PathBuf::from("/nonexistent/desktop.json"),
+        }
+    }
+
+    //Run every future a command produced, feeding the resulting messages back into update.
+    fn settle(window: &mut GuideWindow, command: Command<Message>) {
+        let mut pending: VecDeque<_> = command.actions().into();
+        while let Some(action) = pending.pop_front() {
+            if let Action::Future(future) = action {
+                let message = block_on(future);
+                pending.extend(window.update(message).actions());
+            }
+        }
+    }
+
+    fn open_window(fake: &FakeServiceManager) -> GuideWindow {
+        let (mut window, command) = GuideWindow::new(flags(fake));
+        settle(&mut window, command);
+        window
+    }
+
+    fn press(window: &mut GuideWindow, message: Message) {
+        let command = window.update(message);
+        settle(window, command);
+    }
+
+    fn error_message(window: &GuideWindow) -> String {
+        match window.alert.as_ref() {
+            Some(types::Alert {
+                message,
+                alert_type: types::AlertType::Error,
+            }) => message.clone(),
+            other => panic!("expected an error alert, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn new_without_service_shows_registration() {
+        let fake = FakeServiceManager::new();
+        let window = open_window(&fake);
+        assert_eq!(window.service_status, ServiceStatus::DoesNotExist);
+        assert!(window.alert.is_none());
+        assert!(!window.spining);
+        assert_eq!(fake.calls(), vec![Operation::Open]);
+    }
+
+    #[test]
+    fn new_queries_status_of_existing_service() {
+        let fake = FakeServiceManager::with_status(ServiceStatus::Running);
+        let window = open_window(&fake);
+        assert_eq!(window.service_status, ServiceStatus::Running);
+        assert!(window.alert.is_none());
+        assert!(!window.spining);
+    }
+
+    #[test]
+    fn new_without_permission_is_unknown() {
+        let fake = FakeServiceManager::with_status(ServiceStatus::Running);
+        fake.fail(Operation::Open, ServiceError::AccessDenied);
+        let window = open_window(&fake);
+        assert_eq!(window.service_status, ServiceStatus::Unknown);
+        assert!(!window.spining);
+    }
+
+    #[test]
+    fn spinner_and_info_alert_shown_while_command_runs() {
+        let fake = FakeServiceManager::new();
+        let mut window = open_window(&fake);
+        let mut actions: VecDeque<_> = window
+            .update(Message::RegisterButtonPressed)
+            .actions()
+            .into();
+        for _ in 0..2 {
+            if let Some(Action::Future(future)) = actions.pop_front() {
+                let _ = window.update(block_on(future));
+            }
+        }
+        assert!(window.spining);
+        assert!(matches!(
+            window.alert,
+            Some(types::Alert {
+                alert_type: types::AlertType::Info,
+                ..
+            })
+        ));
+        assert_eq!(window.service_status, ServiceStatus::DoesNotExist);
+    }
+
+    #[test]
+    fn register_creates_stopped_service() {
+        let fake = FakeServiceManager::new();
+        let mut window = open_window(&fake);
+        press(&mut window, Message::RegisterButtonPressed);
+        assert_eq!(window.service_status, ServiceStatus::Stopped);
+        assert!(window.alert.is_none());
+        assert!(!window.spining);
+        assert!(fake.command_line().unwrap().ends_with(" run-service"));
+    }
+
+    #[test]
+    fn register_copies_program_to_install_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let fake = FakeServiceManager::new();
+        let (mut window, command) =
+            GuideWindow::new(flags_with_install_dir(&fake, dir.path().to_path_buf()));
+        settle(&mut window, command);
+        press(&mut window, Message::RegisterButtonPressed);
+        assert_eq!(window.service_status, ServiceStatus::Stopped);
+
+        let source = env::current_exe().unwrap();
+        let installed = dir.path().join(source.file_name().unwrap());
+        assert!(installed.exists());
+        assert_eq!(
+            fake.command_line(),
+            Some(service::command_line_for(&installed))
+        );
+    }
+
+    #[test]
+    fn running_service_shows_desktop_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("desktop.json");
+        let state = DesktopState {
+            phase: DesktopPhase::Running,
+            pid: Some(4812),
+            restarts: 2,
+            last_exit_code: Some(3),
+            ..Default::default()
+        };
+        std::fs::write(&path, serde_json::to_string(&state).unwrap()).unwrap();
+
+        let fake = FakeServiceManager::with_status(ServiceStatus::Running);
+        let mut flags = flags(&fake);
+        flags.desktop_state_path = path;
+        let (mut window, command) = GuideWindow::new(flags);
+        settle(&mut window, command);
+        assert_eq!(window.desktop, Some(state.clone()));
+        assert_eq!(
+            desktop_summary(&state),
+            "Desktop window is running (pid 4812). Restarted 2 times, last exit code 3."
+        );
+
+        press(&mut window, Message::StopServiceButtonPressed);
+        assert_eq!(window.desktop, None);
+    }
+
+    #[test]
+    fn register_reports_existing_service() {
+        let fake = FakeServiceManager::new();
+        let mut window = open_window(&fake);
+        fake.fail(Operation::Register, ServiceError::AlreadyExists);
+        press(&mut window, Message::RegisterButtonPressed);
+        assert_eq!(window.service_status, ServiceStatus::DoesNotExist);
+        assert!(error_message(&window).contains("already exists"));
+        assert!(!window.spining);
+    }
+
+    #[test]
+    fn register_reports_access_denied() {
+        let fake = FakeServiceManager::new();
+        let mut window = open_window(&fake);
+        fake.fail(Operation::Register, ServiceError::AccessDenied);
+        press(&mut window, Message::RegisterButtonPressed);
+        assert_eq!(window.service_status, ServiceStatus::DoesNotExist);
+        assert!(error_message(&window).contains("Access denied"));
+        assert!(!window.spining);
+        assert_eq!(fake.status(), ServiceStatus::DoesNotExist);
+
+        fake.clear_failure(Operation::Register);
+        press(&mut window, Message::RegisterButtonPressed);
+        assert_eq!(window.service_status, ServiceStatus::Stopped);
+        assert!(window.alert.is_none());
+    }
+
+    #[test]
+    fn start_runs_stopped_service() {
+        let fake = FakeServiceManager::with_status(ServiceStatus::Stopped);
+        let mut window = open_window(&fake);
+        press(&mut window, Message::StartServiceButtonPressed);
+        assert_eq!(window.service_status, ServiceStatus::Running);
+        assert!(window.alert.is_none());
+        assert!(!window.spining);
+    }
+
+    #[test]
+    fn start_reports_access_denied() {
+        let fake = FakeServiceManager::with_status(ServiceStatus::Stopped);
+        let mut window = open_window(&fake);
+        fake.fail(Operation::Start, ServiceError::AccessDenied);
+        press(&mut window, Message::StartServiceButtonPressed);
+        assert_eq!(window.service_status, ServiceStatus::Stopped);
+        assert!(error_message(&window).starts_with("Failed to start the service"));
+        assert!(!window.spining);
+    }
+
+    #[test]
+    fn stop_stops_running_service() {
+        let fake = FakeServiceManager::with_status(ServiceStatus::Running);
+        let mut window = open_window(&fake);
+        press(&mut window, Message::StopServiceButtonPressed);
+        assert_eq!(window.service_status, ServiceStatus::Stopped);
+        assert!(window.alert.is_none());
+        assert!(!window.spining);
+    }
+
+    #[test]
+    fn stop_reports_timeout() {
+        let fake = FakeServiceManager::with_status(ServiceStatus::Running);
+        let mut window = open_window(&fake);
+        fake.fail(Operation::Stop, ServiceError::Timeout);
+        press(&mut window, Message::StopServiceButtonPressed);
+        assert_eq!(window.service_status, ServiceStatus::Running);
+        assert!(error_message(&window).contains("Timed out"));
+        assert!(!window.spining);
+        assert_eq!(fake.status(), ServiceStatus::Running);
+    }
+
+    #[test]
+    fn remove_deletes_service() {
+        let fake = FakeServiceManager::with_status(ServiceStatus::Stopped);
+        let mut window = open_window(&fake);
+        press(&mut window, Message::RemoveServiceButtonPressed);
+        assert_eq!(window.service_status, ServiceStatus::DoesNotExist);
+        assert!(window.alert.is_none());
+        assert!(!window.spining);
+        assert_eq!(fake.status(), ServiceStatus::DoesNotExist);
+    }
+
+    #[test]
+    fn remove_reports_access_denied() {
+        let fake = FakeServiceManager::with_status(ServiceStatus::Running);
+        let mut window = open_window(&fake);
+        fake.fail(Operation::Unregister, ServiceError::AccessDenied);
+        press(&mut window, Message::RemoveServiceButtonPressed);
+        assert_eq!(window.service_status, ServiceStatus::Running);
+        assert!(error_message(&window).starts_with("Service removal fail"));
+        assert!(!window.spining);
+    }
+
+    #[test]
+    fn spining_message_toggles_spinner() {
+        let fake = FakeServiceManager::new();
+        let mut window = open_window(&fake);
+        press(&mut window, Message::Spining(true));
+        assert!(window.spining);
+        press(&mut window, Message::Spining(false));
+        assert!(!window.spining);
+    }
+
+    #[test]
+    fn alert_updated_only_hides_spinner_when_asked() {
+        let fake = FakeServiceManager::new();
+        let mut window = open_window(&fake);
+        let alert = types::Alert {
+            message: "working".to_string(),
+            alert_type: types::AlertType::Info,
+        };
+        press(&mut window, Message::Spining(true));
+        press(
+            &mut window,
+            Message::AlertUpdated(Some(alert.clone()), false),
+        );
+        assert!(window.spining);
+        assert_eq!(window.alert.as_ref().unwrap().message, "working");
+        press(&mut window, Message::AlertUpdated(None, true));
+        assert!(!window.spining);
+        assert!(window.alert.is_none());
+    }
+
+    #[test]
+    fn status_update_clears_alert_and_spinner() {
+        let fake = FakeServiceManager::new();
+        let mut window = open_window(&fake);
+        press(&mut window, Message::Spining(true));
+        press(
+            &mut window,
+            Message::AlertUpdated(
+                Some(types::Alert {
+                    message: "boom".to_string(),
+                    alert_type: types::AlertType::Error,
+                }),
+                false,
+            ),
+        );
+        press(
+            &mut window,
+            Message::ServiceStatusUpdated(ServiceStatus::Running),
+        );
+        assert_eq!(window.service_status, ServiceStatus::Running);
+        assert!(window.alert.is_none());
+        assert!(!window.spining);
+    }
+}
@@ -0,0 +1,116 @@
+use super::identity::Identity;
+use super::protocol::{Envelope, HubMessage};
+use futures_util::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+static ENROLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+//Trade a one-time enrollment token for a durable agent identity.
+pub async fn enroll(url: &str, token: &str) -> Result<Identity, String> {
+    let (mut stream, _) = tokio_tungstenite::connect_async(url)
+        .await
+        .map_err(|e| format!("failed to connect to hub: {}", e))?;
+    let request = Envelope::new(
+        1,
+        HubMessage::Enroll {
+            token: token.to_string(),
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+        },
+    );
+    stream
+        .send(WsMessage::Text(request.encode()))
+        .await
+        .map_err(|e| format!("failed to send enrollment request: {}", e))?;
+
+    let reply = tokio::time::timeout(ENROLL_TIMEOUT, async {
+        loop {
+            match stream.next().await {
+                Some(Ok(WsMessage::Text(text))) => match Envelope::decode(&text) {
+                    Ok(envelope) => match envelope.message {
+                        HubMessage::Enrolled {
+                            agent_id,
+                            credential,
+                        } => return Ok(Identity::new(&agent_id, &credential)),
+                        HubMessage::EnrollmentRejected { reason } => {
+                            return Err(format!("enrollment rejected: {}", reason))
+                        }
+                        _ => {}
+                    },
+                    Err(e) => log::warn!("ignoring hub message: {}", e),
+                },
+                Some(Ok(WsMessage::Close(_))) | None => {
+                    return Err("the hub closed the connection".to_string())
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.to_string()),
+            }
+        }
+    })
+    .await
+    .unwrap_or_else(|_| Err("timed out waiting for the hub".to_string()));
+
+    let _ = stream.close(None).await;
+    reply
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock::MockHub;
+    use super::*;
+
+    #[tokio::test]
+    async fn enrolls_with_token() {
+        let mut hub = MockHub::start().await;
+        let url = hub.url.clone();
+        let agent = tokio::spawn(async move { enroll(&url, "one-time").await });
+
+        let mut connection = hub.next_connection().await;
+        match connection.recv().await.message {
+            HubMessage::Enroll { token, .. } => assert_eq!(token, "one-time"),
+            other => panic!("expected an enrollment request, got {:?}", other),
+        }
+        connection
+            .send(HubMessage::Enrolled {
+                agent_id: "agent-1".to_string(),
+                credential: "secret".to_string(),
+            })
+            .await;
+
+        let identity = agent.await.unwrap().unwrap();
+        assert_eq!(identity.agent_id, "agent-1");
+        assert_eq!(identity.credential, "secret");
+        assert!(!identity.revoked);
+    }
+
+    #[tokio::test]
+    async fn reports_rejection() {
+        let mut hub = MockHub::start().await;
+        let url = hub.url.clone();
+        let agent = tokio::spawn(async move { enroll(&url, "used-twice").await });
+
+        let mut connection = hub.next_connection().await;
+        connection.recv().await;
+        connection
+            .send(HubMessage::EnrollmentRejected {
+                reason: "token already used".to_string(),
+            })
+            .await;
+
+        let err = agent.await.unwrap().unwrap_err();
+        assert!(err.contains("token already used"));
+    }
+
+    #[tokio::test]
+    async fn fails_when_hub_hangs_up() {
+        let mut hub = MockHub::start().await;
+        let url = hub.url.clone();
+        let agent = tokio::spawn(async move { enroll(&url, "one-time").await });
+
+        let mut connection = hub.next_connection().await;
+        connection.recv().await;
+        connection.close().await;
+
+        assert!(agent.await.unwrap().is_err());
+    }
+}
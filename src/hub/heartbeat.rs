@@ -0,0 +1,184 @@
+use crate::supervisor::{DesktopState, DesktopStateFn};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use sysinfo::{Disks, System};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hardware {
+    pub cpu_model: String,
+    pub cpu_arch: String,
+    pub cpu_cores: usize,
+    pub memory_total_bytes: u64,
+    pub disk_total_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub agent_version: String,
+    pub os: String,
+    pub hostname: String,
+    pub system_uptime_secs: u64,
+    pub agent_uptime_secs: u64,
+    pub desktop: DesktopState,
+    pub session_user: Option<String>,
+    pub hardware: Hardware,
+}
+
+//Gathers heartbeat payloads; hardware is read once since it does not change while we run.
+pub struct HeartbeatCollector {
+    started: Instant,
+    desktop_state: DesktopStateFn,
+    hardware: Hardware,
+}
+
+fn collect_hardware() -> Hardware {
+    let mut system = System::new();
+    system.refresh_cpu();
+    system.refresh_memory();
+    let disks = Disks::new_with_refreshed_list();
+    Hardware {
+        cpu_model: system
+            .cpus()
+            .first()
+            .map(|cpu| cpu.brand().trim().to_string())
+            .unwrap_or_default(),
+        cpu_arch: System::cpu_arch().unwrap_or_default(),
+        cpu_cores: system
+            .physical_core_count()
+            .unwrap_or_else(|| system.cpus().len()),
+        memory_total_bytes: system.total_memory(),
+        disk_total_bytes: disks.list().iter().map(|disk| disk.total_space()).sum(),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn session_user() -> Option<String> {
+    crate::win32::session::active_session_user()
+}
+
+#[cfg(target_os = "linux")]
+fn session_user() -> Option<String> {
+    crate::linux::session::active_session_user()
+}
+
+impl HeartbeatCollector {
+    pub fn new(desktop_state: DesktopStateFn) -> Self {
+        HeartbeatCollector {
+            started: Instant::now(),
+            desktop_state,
+            hardware: collect_hardware(),
+        }
+    }
+
+    pub fn collect(&self) -> Heartbeat {
+        Heartbeat {
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            os: System::long_os_version().unwrap_or_else(|| std::env::consts::OS.to_string()),
+            hostname: System::host_name().unwrap_or_default(),
+            system_uptime_secs: System::uptime(),
+            agent_uptime_secs: self.started.elapsed().as_secs(),
+            desktop: (self.desktop_state)(),
+            session_user: session_user(),
+            hardware: self.hardware.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::protocol::{Envelope, HubMessage};
+    use super::*;
+    use crate::supervisor::DesktopPhase;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn sample() -> Heartbeat {
+        Heartbeat {
+            agent_version: "0.1.0".to_string(),
+            os: "Windows 11 Pro".to_string(),
+            hostname: "front-desk-01".to_string(),
+            system_uptime_secs: 86400,
+            agent_uptime_secs: 120,
+            desktop: DesktopState {
+                phase: DesktopPhase::Running,
+                pid: Some(4812),
+                session: Some("1".to_string()),
+                locked: false,
+                restarts: 2,
+                last_exit_code: Some(-1073741819),
+                last_exit_at: Some("2024-03-01T08:15:00+00:00".to_string()),
+            },
+            session_user: Some("alice".to_string()),
+            hardware: Hardware {
+                cpu_model: "Intel(R) Core(TM) i5-8500".to_string(),
+                cpu_arch: "x86_64".to_string(),
+                cpu_cores: 6,
+                memory_total_bytes: 17179869184,
+                disk_total_bytes: 512110190592,
+            },
+        }
+    }
+
+    #[test]
+    fn heartbeat_json_schema() {
+        let envelope = Envelope::new(9, HubMessage::Heartbeat(sample()));
+        let value: serde_json::Value = serde_json::from_str(&envelope.encode()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "version": 1,
+                "id": 9,
+                "type": "heartbeat",
+                "payload": {
+                    "agent_version": "0.1.0",
+                    "os": "Windows 11 Pro",
+                    "hostname": "front-desk-01",
+                    "system_uptime_secs": 86400,
+                    "agent_uptime_secs": 120,
+                    "desktop": {
+                        "phase": "running",
+                        "pid": 4812,
+                        "session": "1",
+                        "locked": false,
+                        "restarts": 2,
+                        "last_exit_code": -1073741819,
+                        "last_exit_at": "2024-03-01T08:15:00+00:00"
+                    },
+                    "session_user": "alice",
+                    "hardware": {
+                        "cpu_model": "Intel(R) Core(TM) i5-8500",
+                        "cpu_arch": "x86_64",
+                        "cpu_cores": 6,
+                        "memory_total_bytes": 17179869184u64,
+                        "disk_total_bytes": 512110190592u64
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn heartbeat_round_trip() {
+        let mut heartbeat = sample();
+        heartbeat.session_user = None;
+        heartbeat.desktop = DesktopState::default();
+        let envelope = Envelope::new(1, HubMessage::Heartbeat(heartbeat));
+        let text = envelope.encode();
+        assert!(text.contains(r#""session_user":null"#));
+        assert!(text.contains(r#""phase":"not_started""#));
+        assert_eq!(Envelope::decode(&text).unwrap(), envelope);
+    }
+
+    #[test]
+    fn collects_live_values() {
+        let collector = HeartbeatCollector::new(Arc::new(|| DesktopState {
+            phase: DesktopPhase::Stopped,
+            ..Default::default()
+        }));
+        let heartbeat = collector.collect();
+        assert_eq!(heartbeat.agent_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(heartbeat.desktop.phase, DesktopPhase::Stopped);
+        assert!(!heartbeat.os.is_empty());
+        assert!(heartbeat.hardware.memory_total_bytes > 0);
+    }
+}
@@ -0,0 +1,211 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(target_os = "windows")]
+static IDENTITY_PATH: &str = "C:\\ProgramData\\DeskHub\\identity.json";
+#[cfg(target_os = "linux")]
+static IDENTITY_PATH: &str = "/var/lib/deskhub/identity.json";
+
+//What the hub handed out at enrollment. The credential is a secret, so the file is
+//readable only by the account the service runs as.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    pub agent_id: String,
+    pub credential: String,
+    pub enrolled_at: String,
+    #[serde(default)]
+    pub revoked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdentityError {
+    Missing,
+    Revoked,
+    Invalid(String),
+    Io(String),
+}
+
+impl fmt::Display for IdentityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdentityError::Missing => write!(f, "the agent is not enrolled"),
+            IdentityError::Revoked => write!(f, "the agent's enrollment has been revoked"),
+            IdentityError::Invalid(e) => write!(f, "the identity file is invalid: {}", e),
+            IdentityError::Io(e) => write!(f, "failed to access the identity file: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for IdentityError {
+    fn from(err: io::Error) -> Self {
+        IdentityError::Io(err.to_string())
+    }
+}
+
+pub fn default_path() -> PathBuf {
+    PathBuf::from(IDENTITY_PATH)
+}
+
+impl Identity {
+    pub fn new(agent_id: &str, credential: &str) -> Self {
+        Identity {
+            agent_id: agent_id.to_string(),
+            credential: credential.to_string(),
+            enrolled_at: chrono::Utc::now().to_rfc3339(),
+            revoked: false,
+        }
+    }
+
+    //Load an identity the hub connection may use; revoked identities are refused.
+    pub fn load(path: &Path) -> Result<Self, IdentityError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(IdentityError::Missing),
+            Err(e) => return Err(e.into()),
+        };
+        let identity: Identity =
+            serde_json::from_str(&text).map_err(|e| IdentityError::Invalid(e.to_string()))?;
+        if identity.revoked {
+            return Err(IdentityError::Revoked);
+        }
+        Ok(identity)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), IdentityError> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        //Write next to the target and rename, so a crash never leaves a half-written identity.
+        let tmp_path = path.with_extension("tmp");
+        let text = serde_json::to_string_pretty(self).expect("identity always serializes");
+        let written =
+            write_protected(&tmp_path, text.as_bytes()).and_then(|()| fs::rename(&tmp_path, path));
+        if let Err(e) = written {
+            //The credential must not linger anywhere but the identity file.
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    //Keep the file so the agent ID stays visible, but never connect with it again.
+    pub fn mark_revoked(path: &Path) -> Result<(), IdentityError> {
+        let text = fs::read_to_string(path)?;
+        let mut identity: Identity =
+            serde_json::from_str(&text).map_err(|e| IdentityError::Invalid(e.to_string()))?;
+        identity.revoked = true;
+        identity.save(path)
+    }
+}
+
+#[cfg(unix)]
+fn write_protected(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    //mode() only applies when the file is created.
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+//The file is created for SYSTEM and Administrators only, so the credential is never readable by
+//the users ProgramData would otherwise let in, not even for a moment.
+#[cfg(target_os = "windows")]
+fn write_protected(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use crate::win32::security::{SecurityAttributes, SYSTEM_AND_ADMINS};
+    use std::io::Write;
+    use std::os::windows::io::FromRawHandle;
+    use windows_sys::Win32::Foundation::{GENERIC_WRITE, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{CreateFileW, CREATE_NEW, FILE_ATTRIBUTE_NORMAL};
+
+    //An existing file would keep its own security descriptor.
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut security = SecurityAttributes::from_sddl(SYSTEM_AND_ADMINS)?;
+    let wide = widestring::U16CString::from_os_str(path.as_os_str())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let handle = unsafe {
+        CreateFileW(
+            wide.as_ptr(),
+            GENERIC_WRITE,
+            0,
+            security.as_mut_ptr(),
+            CREATE_NEW,
+            FILE_ATTRIBUTE_NORMAL,
+            0,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        return Err(io::Error::last_os_error());
+    }
+    let mut file = unsafe { fs::File::from_raw_handle(handle as _) };
+    file.write_all(contents)?;
+    file.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("identity.json");
+        let identity = Identity::new("agent-1", "secret");
+        identity.save(&path).unwrap();
+        assert_eq!(Identity::load(&path).unwrap(), identity);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.json");
+        Identity::new("agent-1", "secret").save(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            Identity::load(&dir.path().join("identity.json")),
+            Err(IdentityError::Missing)
+        );
+    }
+
+    #[test]
+    fn revoked_identity_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.json");
+        Identity::new("agent-1", "secret").save(&path).unwrap();
+        Identity::mark_revoked(&path).unwrap();
+        assert_eq!(Identity::load(&path), Err(IdentityError::Revoked));
+    }
+
+    #[test]
+    fn invalid_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("identity.json");
+        fs::write(&path, "{}").unwrap();
+        assert!(matches!(
+            Identity::load(&path),
+            Err(IdentityError::Invalid(_))
+        ));
+    }
+}
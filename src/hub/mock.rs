@@ -0,0 +1,105 @@
+//A local stand-in for the hub, used by tests to drive the client over real sockets.
+use super::heartbeat::Heartbeat;
+use super::protocol::{Envelope, HubMessage};
+use futures_util::{SinkExt, StreamExt};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::WebSocketStream;
+
+static WAIT: Duration = Duration::from_secs(5);
+
+pub struct MockHub {
+    pub url: String,
+    connections: mpsc::Receiver<MockConnection>,
+}
+
+pub struct MockConnection {
+    stream: WebSocketStream<TcpStream>,
+    next_id: u64,
+}
+
+impl MockHub {
+    pub async fn start() -> Self {
+        Self::bind("127.0.0.1:0".parse().unwrap()).await
+    }
+
+    pub async fn bind(addr: SocketAddr) -> Self {
+        let listener = TcpListener::bind(addr).await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel(8);
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                if let Ok(stream) = tokio_tungstenite::accept_async(tcp).await {
+                    let connection = MockConnection { stream, next_id: 0 };
+                    if tx.send(connection).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        MockHub {
+            url,
+            connections: rx,
+        }
+    }
+
+    pub async fn next_connection(&mut self) -> MockConnection {
+        tokio::time::timeout(WAIT, self.connections.recv())
+            .await
+            .expect("no agent connected to the mock hub")
+            .unwrap()
+    }
+}
+
+impl MockConnection {
+    //Next envelope from the agent, skipping control frames and periodic heartbeats.
+    pub async fn recv(&mut self) -> Envelope {
+        loop {
+            let envelope = self.recv_any().await;
+            if !matches!(envelope.message, HubMessage::Heartbeat(_)) {
+                return envelope;
+            }
+        }
+    }
+
+    pub async fn recv_heartbeat(&mut self) -> Heartbeat {
+        loop {
+            if let HubMessage::Heartbeat(heartbeat) = self.recv_any().await.message {
+                return heartbeat;
+            }
+        }
+    }
+
+    async fn recv_any(&mut self) -> Envelope {
+        loop {
+            let frame = tokio::time::timeout(WAIT, self.stream.next())
+                .await
+                .expect("agent sent nothing")
+                .expect("agent closed the connection")
+                .unwrap();
+            if let WsMessage::Text(text) = frame {
+                return Envelope::decode(&text).unwrap();
+            }
+        }
+    }
+
+    pub async fn send(&mut self, message: HubMessage) {
+        self.next_id += 1;
+        let text = Envelope::new(self.next_id, message).encode();
+        self.send_raw(&text).await;
+    }
+
+    pub async fn send_raw(&mut self, text: &str) {
+        self.stream
+            .send(WsMessage::Text(text.to_string()))
+            .await
+            .unwrap();
+    }
+
+    pub async fn close(mut self) {
+        let _ = self.stream.close(None).await;
+    }
+}
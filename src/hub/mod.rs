@@ -0,0 +1,672 @@
+pub mod enroll;
+pub mod heartbeat;
+pub mod identity;
+#[cfg(test)]
+pub mod mock;
+pub mod protocol;
+
+use crate::backoff::Backoff;
+use crate::logging::forward::LogSpool;
+use crate::logging::levels::{LevelControl, LevelRequest};
+use crate::supervisor::DesktopStateFn;
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, StreamExt};
+use heartbeat::HeartbeatCollector;
+use identity::Identity;
+use protocol::{Envelope, HubMessage};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+#[derive(Debug, Clone)]
+pub struct HubConfig {
+    pub url: String,
+    pub identity_path: PathBuf,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub heartbeat_interval: Duration,
+    //Log records to send while connected.
+    pub logs: Option<LogSpool>,
+    //Changed by the hub's LogLevel commands.
+    pub levels: Option<LevelControl>,
+}
+
+//How often pending log records are sealed and sent, and how many batches go out each time so a
+//long replay does not hold up the rest of the connection.
+const LOG_INTERVAL: Duration = Duration::from_secs(2);
+const LOG_BATCHES_PER_TICK: usize = 16;
+
+//Connects the hub to the desktop process, by way of the service's IPC server.
+pub struct DesktopLink {
+    //Messages the desktop process sends to the hub.
+    pub outgoing: mpsc::Receiver<HubMessage>,
+    //Hub messages the service does not act on itself.
+    pub incoming: broadcast::Sender<HubMessage>,
+}
+
+type HubSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, WsMessage>;
+
+//Owned by whoever started the client; dropping it also stops the client.
+pub struct HubHandle {
+    outgoing: mpsc::Sender<HubMessage>,
+    shutdown: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl HubHandle {
+    //Queue a message for the hub. It is sent once a connection is up. Never waits: false if the
+    //queue is full, e.g. while the hub is unreachable.
+    pub fn send(&self, message: HubMessage) -> bool {
+        self.outgoing.try_send(message).is_ok()
+    }
+
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(true);
+        let _ = self.task.await;
+    }
+}
+
+enum SessionEnd {
+    Shutdown,
+    Disconnected(String),
+    Revoked(String),
+}
+
+struct HubClient {
+    config: HubConfig,
+    identity: Identity,
+    heartbeat: HeartbeatCollector,
+    outgoing: mpsc::Receiver<HubMessage>,
+    incoming: mpsc::Sender<HubMessage>,
+    shutdown: watch::Receiver<bool>,
+    next_id: u64,
+}
+
+//Spawn the client on the current tokio runtime. Messages from the hub arrive on the returned receiver.
+pub fn start(
+    config: HubConfig,
+    identity: Identity,
+    desktop_state: DesktopStateFn,
+) -> (HubHandle, mpsc::Receiver<HubMessage>) {
+    let (outgoing_tx, outgoing_rx) = mpsc::channel(64);
+    let (incoming_tx, incoming_rx) = mpsc::channel(64);
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let client = HubClient {
+        config,
+        identity,
+        heartbeat: HeartbeatCollector::new(desktop_state),
+        outgoing: outgoing_rx,
+        incoming: incoming_tx,
+        shutdown: shutdown_rx,
+        next_id: 0,
+    };
+    let task = tokio::spawn(client.run());
+    (
+        HubHandle {
+            outgoing: outgoing_tx,
+            shutdown: shutdown_tx,
+            task,
+        },
+        incoming_rx,
+    )
+}
+
+//Run the client for the lifetime of the service process, until `stop` changes.
+//An agent that was never enrolled, or whose enrollment was revoked, does not connect at all.
+pub async fn run(
+    mut config: HubConfig,
+    desktop_state: DesktopStateFn,
+    mut desktop: DesktopLink,
+    mut stop: watch::Receiver<bool>,
+) {
+    config.logs = config.logs.or_else(LogSpool::installed);
+    let logs = config.logs.clone();
+    let levels = config.levels.take().or_else(LevelControl::installed);
+    let identity = match Identity::load(&config.identity_path) {
+        Ok(identity) => identity,
+        Err(e) => {
+            log::error!("not connecting to hub: {}", e);
+            return;
+        }
+    };
+    let (handle, mut incoming) = start(config, identity, desktop_state);
+    let mut dropped: u64 = 0;
+    loop {
+        tokio::select! {
+            message = incoming.recv() => match message {
+                Some(HubMessage::LogLevel(request)) => change_levels(levels.as_ref(), &request),
+                Some(message) => {
+                    log::info!("hub message: {:?}", message);
+                    //Nobody listening is fine; the desktop process may not be running.
+                    let _ = desktop.incoming.send(message);
+                }
+                None => break,
+            },
+            Some(message) = desktop.outgoing.recv() => {
+                //Waiting for room would hold up hub messages and stopping for as long as the hub
+                //is down.
+                if !handle.send(message) {
+                    dropped += 1;
+                    log::warn!("hub queue full, dropped {} desktop message(s)", dropped);
+                }
+            }
+            _ = stop.changed() => break,
+        }
+    }
+    handle.shutdown().await;
+    //Records not sent yet go out after the next start.
+    if let Some(logs) = logs {
+        logs.seal();
+    }
+}
+
+fn change_levels(levels: Option<&LevelControl>, request: &LevelRequest) {
+    let Some(levels) = levels else {
+        log::warn!("ignoring log level change, the logger cannot be changed");
+        return;
+    };
+    match request.check() {
+        Ok(change) => {
+            let applied = levels.apply(change, std::time::Instant::now());
+            log::info!("hub: {}", applied);
+        }
+        Err(e) => log::warn!("ignoring log level change from hub: {}", e),
+    }
+}
+
+impl HubClient {
+    async fn run(mut self) {
+        let mut backoff = Backoff::new(self.config.initial_backoff, self.config.max_backoff);
+        loop {
+            log::info!("connecting to hub {}", self.config.url);
+            let connected = tokio::select! {
+                result = tokio_tungstenite::connect_async(self.config.url.as_str()) => result,
+                _ = self.shutdown.changed() => return,
+            };
+            match connected {
+                Ok((stream, _)) => {
+                    log::info!("connected to hub");
+                    backoff.reset();
+                    match self.session(stream).await {
+                        SessionEnd::Shutdown => return,
+                        SessionEnd::Disconnected(reason) => {
+                            log::warn!("hub connection lost: {}", reason)
+                        }
+                        SessionEnd::Revoked(reason) => {
+                            log::error!("agent enrollment revoked by hub: {}", reason);
+                            if let Err(e) = Identity::mark_revoked(&self.config.identity_path) {
+                                log::error!("failed to record revocation: {}", e);
+                            }
+                            return;
+                        }
+                    }
+                }
+                Err(e) => log::warn!("failed to connect to hub: {}", e),
+            }
+
+            let delay = backoff.next_delay();
+            log::info!("reconnecting to hub in {:?}", delay);
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = self.shutdown.changed() => return,
+            }
+        }
+    }
+
+    fn frame(&mut self, message: HubMessage) -> WsMessage {
+        self.next_id += 1;
+        WsMessage::Text(Envelope::new(self.next_id, message).encode())
+    }
+
+    async fn session(&mut self, stream: WebSocketStream<MaybeTlsStream<TcpStream>>) -> SessionEnd {
+        let (mut sink, mut source) = stream.split();
+        let hello = self.frame(HubMessage::Hello {
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            agent_id: self.identity.agent_id.clone(),
+            credential: self.identity.credential.clone(),
+        });
+        if let Err(e) = sink.send(hello).await {
+            return SessionEnd::Disconnected(e.to_string());
+        }
+
+        //The first tick fires immediately, so the hub gets the inventory right after Hello.
+        let mut heartbeat = tokio::time::interval(self.config.heartbeat_interval);
+        heartbeat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        //Also fires right away, replaying whatever was spooled while disconnected.
+        let mut logs = tokio::time::interval(LOG_INTERVAL);
+        logs.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = logs.tick(), if self.config.logs.is_some() => {
+                    if let Err(e) = self.send_logs(&mut sink).await {
+                        return SessionEnd::Disconnected(e);
+                    }
+                }
+                _ = heartbeat.tick() => {
+                    let frame = self.frame(HubMessage::Heartbeat(self.heartbeat.collect()));
+                    if let Err(e) = sink.send(frame).await {
+                        return SessionEnd::Disconnected(e.to_string());
+                    }
+                }
+                _ = self.shutdown.changed() => {
+                    let _ = sink.close().await;
+                    return SessionEnd::Shutdown;
+                }
+                message = self.outgoing.recv() => {
+                    let Some(message) = message else {
+                        let _ = sink.close().await;
+                        return SessionEnd::Shutdown;
+                    };
+                    let frame = self.frame(message);
+                    if let Err(e) = sink.send(frame).await {
+                        return SessionEnd::Disconnected(e.to_string());
+                    }
+                }
+                frame = source.next() => match frame {
+                    Some(Ok(WsMessage::Text(text))) => match Envelope::decode(&text) {
+                        Ok(envelope) => match envelope.message {
+                            HubMessage::Ping => {
+                                let pong = self.frame(HubMessage::Pong);
+                                if let Err(e) = sink.send(pong).await {
+                                    return SessionEnd::Disconnected(e.to_string());
+                                }
+                            }
+                            HubMessage::Revoked { reason } => {
+                                let _ = sink.close().await;
+                                return SessionEnd::Revoked(reason);
+                            }
+                            message => {
+                                let _ = self.incoming.send(message).await;
+                            }
+                        },
+                        Err(e) => log::warn!("ignoring hub message: {}", e),
+                    },
+                    Some(Ok(WsMessage::Close(_))) | None => {
+                        return SessionEnd::Disconnected("closed by hub".to_string());
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return SessionEnd::Disconnected(e.to_string()),
+                },
+            }
+        }
+    }
+
+    //A batch leaves the spool only once it is sent; one cut off by a disconnect goes again.
+    async fn send_logs(&mut self, sink: &mut HubSink) -> Result<(), String> {
+        let Some(spool) = self.config.logs.clone() else {
+            return Ok(());
+        };
+        spool.seal();
+        for _ in 0..LOG_BATCHES_PER_TICK {
+            let Some(batch) = spool.oldest() else {
+                break;
+            };
+            let frame = self.frame(HubMessage::Logs {
+                entries: batch.entries.clone(),
+                dropped: batch.dropped,
+            });
+            sink.send(frame).await.map_err(|e| e.to_string())?;
+            spool.delivered(&batch);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockHub;
+    use super::*;
+    use crate::logging::forward::LogEntry;
+    use crate::logging::levels::Levels;
+    use crate::supervisor::{DesktopPhase, DesktopState};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn test_config(url: &str) -> HubConfig {
+        HubConfig {
+            url: url.to_string(),
+            identity_path: PathBuf::from("/nonexistent/identity.json"),
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(50),
+            heartbeat_interval: Duration::from_millis(50),
+            logs: None,
+            levels: None,
+        }
+    }
+
+    fn desktop_running() -> DesktopStateFn {
+        Arc::new(|| DesktopState {
+            phase: DesktopPhase::Running,
+            ..Default::default()
+        })
+    }
+
+    fn no_desktop() -> DesktopLink {
+        DesktopLink {
+            outgoing: mpsc::channel(1).1,
+            incoming: broadcast::channel(1).0,
+        }
+    }
+
+    fn test_identity() -> Identity {
+        Identity::new("agent-1", "secret")
+    }
+
+    fn enrolled_config(url: &str, dir: &Path) -> HubConfig {
+        let mut config = test_config(url);
+        config.identity_path = dir.join("identity.json");
+        test_identity().save(&config.identity_path).unwrap();
+        config
+    }
+
+    fn is_hello(envelope: &Envelope) -> bool {
+        matches!(envelope.message, HubMessage::Hello { .. })
+    }
+
+    #[tokio::test]
+    async fn sends_hello_on_connect() {
+        let mut hub = MockHub::start().await;
+        let (handle, _incoming) = start(test_config(&hub.url), test_identity(), desktop_running());
+        let mut connection = hub.next_connection().await;
+        let hello = connection.recv().await;
+        assert_eq!(hello.version, protocol::PROTOCOL_VERSION);
+        assert_eq!(
+            hello.message,
+            HubMessage::Hello {
+                agent_version: env!("CARGO_PKG_VERSION").to_string(),
+                agent_id: "agent-1".to_string(),
+                credential: "secret".to_string(),
+            }
+        );
+        handle.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn exchanges_messages() {
+        let mut hub = MockHub::start().await;
+        let (handle, mut incoming) =
+            start(test_config(&hub.url), test_identity(), desktop_running());
+        let mut connection = hub.next_connection().await;
+        assert!(is_hello(&connection.recv().await));
+
+        connection.send(HubMessage::Ping).await;
+        assert_eq!(connection.recv().await.message, HubMessage::Pong);
+
+        connection.send(HubMessage::Pong).await;
+        assert_eq!(incoming.recv().await, Some(HubMessage::Pong));
+
+        assert!(handle.send(HubMessage::Ping));
+        let sent = connection.recv().await;
+        assert_eq!(sent.message, HubMessage::Ping);
+        assert!(sent.id > 1);
+        handle.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn ignores_bad_frames() {
+        let mut hub = MockHub::start().await;
+        let (handle, mut incoming) =
+            start(test_config(&hub.url), test_identity(), desktop_running());
+        let mut connection = hub.next_connection().await;
+        assert!(is_hello(&connection.recv().await));
+
+        connection.send_raw("not json").await;
+        connection
+            .send_raw(r#"{"version":99,"id":1,"type":"ping"}"#)
+            .await;
+        connection.send(HubMessage::Pong).await;
+        assert_eq!(incoming.recv().await, Some(HubMessage::Pong));
+        handle.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_hub_closes() {
+        let mut hub = MockHub::start().await;
+        let (handle, _incoming) = start(test_config(&hub.url), test_identity(), desktop_running());
+        let mut first = hub.next_connection().await;
+        assert!(is_hello(&first.recv().await));
+        first.close().await;
+
+        let mut second = hub.next_connection().await;
+        assert!(is_hello(&second.recv().await));
+        handle.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn retries_until_hub_is_up() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (handle, _incoming) = start(
+            test_config(&format!("ws://{}", addr)),
+            test_identity(),
+            desktop_running(),
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut hub = MockHub::bind(addr).await;
+        let mut connection = hub.next_connection().await;
+        assert!(is_hello(&connection.recv().await));
+        handle.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn shutdown_while_disconnected() {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (handle, _incoming) = start(
+            test_config(&format!("ws://{}", addr)),
+            test_identity(),
+            desktop_running(),
+        );
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        tokio::time::timeout(Duration::from_secs(1), handle.shutdown())
+            .await
+            .expect("client did not stop");
+    }
+
+    #[tokio::test]
+    async fn revocation_stops_client_and_is_persisted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut hub = MockHub::start().await;
+        let config = enrolled_config(&hub.url, dir.path());
+        let identity_path = config.identity_path.clone();
+        let (_stop_tx, stop_rx) = watch::channel(false);
+        let client = tokio::spawn(run(config, desktop_running(), no_desktop(), stop_rx));
+
+        let mut connection = hub.next_connection().await;
+        assert!(is_hello(&connection.recv().await));
+        connection
+            .send(HubMessage::Revoked {
+                reason: "decommissioned".to_string(),
+            })
+            .await;
+
+        tokio::time::timeout(Duration::from_secs(5), client)
+            .await
+            .expect("client kept running after revocation")
+            .unwrap();
+        assert_eq!(
+            Identity::load(&identity_path),
+            Err(identity::IdentityError::Revoked)
+        );
+    }
+
+    #[tokio::test]
+    async fn changes_log_levels_on_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut hub = MockHub::start().await;
+        let mut config = enrolled_config(&hub.url, dir.path());
+        let levels = LevelControl::new(Levels {
+            global: log::LevelFilter::Info,
+            modules: Vec::new(),
+        });
+        config.levels = Some(levels.clone());
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let client = tokio::spawn(run(config, desktop_running(), no_desktop(), stop_rx));
+
+        let mut connection = hub.next_connection().await;
+        assert!(is_hello(&connection.recv().await));
+        connection
+            .send(HubMessage::LogLevel(LevelRequest::Set {
+                target: Some("deskhub::hub".to_string()),
+                level: "trace".to_string(),
+                duration_secs: 60,
+            }))
+            .await;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while levels.levels().level_for("deskhub::hub") != log::LevelFilter::Trace {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the level did not change");
+        let _ = stop_tx.send(true);
+        client.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn relays_messages_for_the_desktop() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut hub = MockHub::start().await;
+        let config = enrolled_config(&hub.url, dir.path());
+        let (to_hub, outgoing) = mpsc::channel(8);
+        let (incoming, mut from_hub) = broadcast::channel(8);
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let desktop = DesktopLink { outgoing, incoming };
+        let client = tokio::spawn(run(config, desktop_running(), desktop, stop_rx));
+
+        let mut connection = hub.next_connection().await;
+        assert!(is_hello(&connection.recv().await));
+        let ended = HubMessage::SessionEnded {
+            viewers: vec!["viewer-1".to_string()],
+            reason: "ended by the local user".to_string(),
+        };
+        to_hub.send(ended.clone()).await.unwrap();
+        assert_eq!(connection.recv().await.message, ended);
+        connection.send(HubMessage::Pong).await;
+        assert_eq!(from_hub.recv().await.unwrap(), HubMessage::Pong);
+        let _ = stop_tx.send(true);
+        client.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stops_while_the_hub_is_unreachable() {
+        let dir = tempfile::tempdir().unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let config = enrolled_config(&format!("ws://127.0.0.1:{}/agent", port), dir.path());
+        let (to_hub, outgoing) = mpsc::channel(8);
+        let (incoming, _from_hub) = broadcast::channel(8);
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let desktop = DesktopLink { outgoing, incoming };
+        tokio::time::timeout(Duration::from_secs(5), async move {
+            let client = tokio::spawn(run(config, desktop_running(), desktop, stop_rx));
+            //Far more than the client queues while it cannot connect.
+            for _ in 0..200 {
+                to_hub.send(HubMessage::Ping).await.unwrap();
+            }
+            let _ = stop_tx.send(true);
+            client.await.unwrap();
+        })
+        .await
+        .expect("the client should stop with its queue full");
+    }
+
+    #[tokio::test]
+    async fn refuses_to_connect_without_identity() {
+        let hub = MockHub::start().await;
+        let (_stop_tx, stop_rx) = watch::channel(false);
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            run(
+                test_config(&hub.url),
+                desktop_running(),
+                no_desktop(),
+                stop_rx,
+            ),
+        )
+        .await
+        .expect("client should give up immediately");
+    }
+
+    #[tokio::test]
+    async fn refuses_to_connect_when_revoked() {
+        let dir = tempfile::tempdir().unwrap();
+        let hub = MockHub::start().await;
+        let config = enrolled_config(&hub.url, dir.path());
+        Identity::mark_revoked(&config.identity_path).unwrap();
+        let (_stop_tx, stop_rx) = watch::channel(false);
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            run(config, desktop_running(), no_desktop(), stop_rx),
+        )
+        .await
+        .expect("client should give up immediately");
+    }
+
+    #[tokio::test]
+    async fn replays_spooled_logs_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = LogSpool::open(dir.path(), 1 << 20).unwrap();
+        let entry = |message: &str| LogEntry {
+            time: "2024-01-02T03:04:05.000+00:00".to_string(),
+            level: "INFO".to_string(),
+            target: "deskhub::hub".to_string(),
+            process: "service".to_string(),
+            message: message.to_string(),
+        };
+        //Two batches from while the hub was unreachable, and one not sealed yet.
+        for message in ["first", "second", "third"] {
+            spool.seal();
+            spool.push(entry(message));
+        }
+        let mut hub = MockHub::start().await;
+        let mut config = test_config(&hub.url);
+        config.logs = Some(spool.clone());
+        let (handle, _incoming) = start(config, test_identity(), desktop_running());
+        let mut connection = hub.next_connection().await;
+        assert!(is_hello(&connection.recv().await));
+        for message in ["first", "second", "third"] {
+            assert_eq!(
+                connection.recv().await.message,
+                HubMessage::Logs {
+                    entries: vec![entry(message)],
+                    dropped: 0,
+                }
+            );
+        }
+        assert_eq!(spool.oldest(), None);
+        handle.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn sends_heartbeats_while_connected() {
+        let mut hub = MockHub::start().await;
+        let (handle, _incoming) = start(
+            test_config(&hub.url),
+            test_identity(),
+            Arc::new(|| DesktopState {
+                phase: DesktopPhase::Stopped,
+                ..Default::default()
+            }),
+        );
+        let mut connection = hub.next_connection().await;
+        let first = connection.recv_heartbeat().await;
+        assert_eq!(first.agent_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(first.desktop.phase, DesktopPhase::Stopped);
+        let second = connection.recv_heartbeat().await;
+        assert!(second.agent_uptime_secs >= first.agent_uptime_secs);
+        handle.shutdown().await;
+    }
+}
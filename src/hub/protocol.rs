@@ -0,0 +1,220 @@
+use super::heartbeat::Heartbeat;
+use crate::logging::forward::LogEntry;
+use crate::logging::levels::LevelRequest;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+//Bumped whenever the envelope or an existing message changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+//Every frame on the hub connection is one JSON envelope:
+//{"version":1,"id":7,"type":"hello","payload":{...}}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub version: u32,
+    pub id: u64,
+    #[serde(flatten)]
+    pub message: HubMessage,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum HubMessage {
+    //First message on every authenticated connection.
+    Hello {
+        agent_version: String,
+        agent_id: String,
+        credential: String,
+    },
+    Ping,
+    Pong,
+    //Sent on a fresh connection instead of Hello to trade a one-time token for an identity.
+    Enroll {
+        token: String,
+        agent_version: String,
+    },
+    Enrolled {
+        agent_id: String,
+        credential: String,
+    },
+    EnrollmentRejected {
+        reason: String,
+    },
+    //Sent periodically while connected.
+    Heartbeat(Heartbeat),
+    //The hub no longer accepts this agent's credential.
+    Revoked {
+        reason: String,
+    },
+    //The agent disconnected these viewers, e.g. because the local user ended the session.
+    SessionEnded {
+        viewers: Vec<String>,
+        reason: String,
+    },
+    //Agent log records, oldest first. `dropped` counts records lost before these because the
+    //agent's spool was full.
+    Logs {
+        entries: Vec<LogEntry>,
+        dropped: u64,
+    },
+    //From the hub: change the agent's log levels for a while.
+    LogLevel(LevelRequest),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    Malformed(String),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Malformed(e) => write!(f, "malformed message: {}", e),
+            ProtocolError::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version {}", version)
+            }
+        }
+    }
+}
+
+impl Envelope {
+    pub fn new(id: u64, message: HubMessage) -> Self {
+        Envelope {
+            version: PROTOCOL_VERSION,
+            id,
+            message,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("hub envelopes always serialize")
+    }
+
+    pub fn decode(text: &str) -> Result<Self, ProtocolError> {
+        //Check the version first so a newer hub's unknown message types are reported as such.
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+        let versioned: Versioned =
+            serde_json::from_str(text).map_err(|e| ProtocolError::Malformed(e.to_string()))?;
+        if versioned.version != PROTOCOL_VERSION {
+            return Err(ProtocolError::UnsupportedVersion(versioned.version));
+        }
+        serde_json::from_str(text).map_err(|e| ProtocolError::Malformed(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn envelope_json_shape() {
+        let envelope = Envelope::new(
+            3,
+            HubMessage::Hello {
+                agent_version: "0.1.0".to_string(),
+                agent_id: "agent-1".to_string(),
+                credential: "secret".to_string(),
+            },
+        );
+        let value: serde_json::Value = serde_json::from_str(&envelope.encode()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "version": PROTOCOL_VERSION,
+                "id": 3,
+                "type": "hello",
+                "payload": {
+                    "agent_version": "0.1.0",
+                    "agent_id": "agent-1",
+                    "credential": "secret"
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn unit_messages_have_no_payload() {
+        let value: serde_json::Value =
+            serde_json::from_str(&Envelope::new(1, HubMessage::Ping).encode()).unwrap();
+        assert_eq!(
+            value,
+            json!({"version": PROTOCOL_VERSION, "id": 1, "type": "ping"})
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for message in [
+            HubMessage::Hello {
+                agent_version: "1.2.3".to_string(),
+                agent_id: "agent-1".to_string(),
+                credential: "secret".to_string(),
+            },
+            HubMessage::Ping,
+            HubMessage::Pong,
+            HubMessage::Enroll {
+                token: "one-time".to_string(),
+                agent_version: "1.2.3".to_string(),
+            },
+            HubMessage::Enrolled {
+                agent_id: "agent-1".to_string(),
+                credential: "secret".to_string(),
+            },
+            HubMessage::EnrollmentRejected {
+                reason: "token expired".to_string(),
+            },
+            HubMessage::Revoked {
+                reason: "decommissioned".to_string(),
+            },
+            HubMessage::SessionEnded {
+                viewers: vec!["viewer-1".to_string()],
+                reason: "ended by the local user".to_string(),
+            },
+            HubMessage::Logs {
+                entries: vec![LogEntry {
+                    time: "2024-01-02T03:04:05.678+01:00".to_string(),
+                    level: "WARN".to_string(),
+                    target: "deskhub::supervisor".to_string(),
+                    process: "service".to_string(),
+                    message: "desktop exited".to_string(),
+                }],
+                dropped: 3,
+            },
+            HubMessage::LogLevel(LevelRequest::Set {
+                target: Some("deskhub::win32::service_ctrl".to_string()),
+                level: "trace".to_string(),
+                duration_secs: 900,
+            }),
+            HubMessage::LogLevel(LevelRequest::Reset),
+        ] {
+            let envelope = Envelope::new(42, message);
+            assert_eq!(Envelope::decode(&envelope.encode()).unwrap(), envelope);
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let text = r#"{"version":99,"id":1,"type":"something_new","payload":{}}"#;
+        assert_eq!(
+            Envelope::decode(text),
+            Err(ProtocolError::UnsupportedVersion(99))
+        );
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(matches!(
+            Envelope::decode("not json"),
+            Err(ProtocolError::Malformed(_))
+        ));
+        assert!(matches!(
+            Envelope::decode(r#"{"version":1,"id":1,"type":"no_such_message"}"#),
+            Err(ProtocolError::Malformed(_))
+        ));
+    }
+}
@@ -0,0 +1,74 @@
+use super::{Button, Injector, InputError, Key};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Injected {
+    Key(Key, bool),
+    Pointer(i32, i32),
+    Button(Button, bool),
+    Wheel(i32, i32),
+    Text(String),
+}
+
+#[derive(Debug, Default)]
+struct FakeState {
+    injected: Vec<Injected>,
+    failure: Option<InputError>,
+}
+
+//Records what would have been injected. Clones share state, so a test can keep one
+//handle to inspect while the session under test owns another.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingInjector {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl RecordingInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn injected(&self) -> Vec<Injected> {
+        self.state.lock().unwrap().injected.clone()
+    }
+
+    //Make every later call fail with `error`, recording nothing, until cleared.
+    pub fn fail(&self, error: InputError) {
+        self.state.lock().unwrap().failure = Some(error);
+    }
+
+    pub fn clear_failure(&self) {
+        self.state.lock().unwrap().failure = None;
+    }
+
+    fn record(&self, injected: Injected) -> Result<(), InputError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(error) = &state.failure {
+            return Err(error.clone());
+        }
+        state.injected.push(injected);
+        Ok(())
+    }
+}
+
+impl Injector for RecordingInjector {
+    fn key(&mut self, key: Key, down: bool) -> Result<(), InputError> {
+        self.record(Injected::Key(key, down))
+    }
+
+    fn pointer(&mut self, x: i32, y: i32) -> Result<(), InputError> {
+        self.record(Injected::Pointer(x, y))
+    }
+
+    fn button(&mut self, button: Button, down: bool) -> Result<(), InputError> {
+        self.record(Injected::Button(button, down))
+    }
+
+    fn wheel(&mut self, dx: i32, dy: i32) -> Result<(), InputError> {
+        self.record(Injected::Wheel(dx, dy))
+    }
+
+    fn text(&mut self, text: &str) -> Result<(), InputError> {
+        self.record(Injected::Text(text.to_string()))
+    }
+}
@@ -0,0 +1,918 @@
+//Translates browser key events (`KeyboardEvent.code` and `.key`) into keys on the host's layout.
+//Characters are typed by finding them on the host layout, through a dead key if need be, and
+//anything the layout cannot produce is injected as text.
+use super::{InputEvent, Key};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+//Physical key positions (`KeyboardEvent.code`) as evdev codes.
+const SCANCODES: &[(&str, u16)] = &[
+    ("Escape", 1),
+    ("Digit1", 2),
+    ("Digit2", 3),
+    ("Digit3", 4),
+    ("Digit4", 5),
+    ("Digit5", 6),
+    ("Digit6", 7),
+    ("Digit7", 8),
+    ("Digit8", 9),
+    ("Digit9", 10),
+    ("Digit0", 11),
+    ("Minus", 12),
+    ("Equal", 13),
+    ("Backspace", 14),
+    ("Tab", 15),
+    ("KeyQ", 16),
+    ("KeyW", 17),
+    ("KeyE", 18),
+    ("KeyR", 19),
+    ("KeyT", 20),
+    ("KeyY", 21),
+    ("KeyU", 22),
+    ("KeyI", 23),
+    ("KeyO", 24),
+    ("KeyP", 25),
+    ("BracketLeft", 26),
+    ("BracketRight", 27),
+    ("Enter", 28),
+    ("ControlLeft", 29),
+    ("KeyA", 30),
+    ("KeyS", 31),
+    ("KeyD", 32),
+    ("KeyF", 33),
+    ("KeyG", 34),
+    ("KeyH", 35),
+    ("KeyJ", 36),
+    ("KeyK", 37),
+    ("KeyL", 38),
+    ("Semicolon", 39),
+    ("Quote", 40),
+    ("Backquote", 41),
+    ("ShiftLeft", 42),
+    ("Backslash", 43),
+    ("KeyZ", 44),
+    ("KeyX", 45),
+    ("KeyC", 46),
+    ("KeyV", 47),
+    ("KeyB", 48),
+    ("KeyN", 49),
+    ("KeyM", 50),
+    ("Comma", 51),
+    ("Period", 52),
+    ("Slash", 53),
+    ("ShiftRight", 54),
+    ("NumpadMultiply", 55),
+    ("AltLeft", 56),
+    ("Space", 57),
+    ("CapsLock", 58),
+    ("F1", 59),
+    ("F2", 60),
+    ("F3", 61),
+    ("F4", 62),
+    ("F5", 63),
+    ("F6", 64),
+    ("F7", 65),
+    ("F8", 66),
+    ("F9", 67),
+    ("F10", 68),
+    ("NumLock", 69),
+    ("ScrollLock", 70),
+    ("Numpad7", 71),
+    ("Numpad8", 72),
+    ("Numpad9", 73),
+    ("NumpadSubtract", 74),
+    ("Numpad4", 75),
+    ("Numpad5", 76),
+    ("Numpad6", 77),
+    ("NumpadAdd", 78),
+    ("Numpad1", 79),
+    ("Numpad2", 80),
+    ("Numpad3", 81),
+    ("Numpad0", 82),
+    ("NumpadDecimal", 83),
+    ("IntlBackslash", 86),
+    ("F11", 87),
+    ("F12", 88),
+    ("NumpadEnter", 96),
+    ("ControlRight", 97),
+    ("NumpadDivide", 98),
+    ("PrintScreen", 99),
+    ("AltRight", 100),
+    ("Home", 102),
+    ("ArrowUp", 103),
+    ("PageUp", 104),
+    ("ArrowLeft", 105),
+    ("ArrowRight", 106),
+    ("End", 107),
+    ("ArrowDown", 108),
+    ("PageDown", 109),
+    ("Insert", 110),
+    ("Delete", 111),
+    ("Pause", 119),
+    ("MetaLeft", 125),
+    ("MetaRight", 126),
+    ("ContextMenu", 127),
+];
+
+//Non-character `KeyboardEvent.key` values as keysyms.
+const NAMED: &[(&str, u32)] = &[
+    ("Enter", 0xff0d),
+    ("Tab", 0xff09),
+    ("Backspace", 0xff08),
+    ("Escape", 0xff1b),
+    ("Delete", 0xffff),
+    ("Insert", 0xff63),
+    ("Home", 0xff50),
+    ("End", 0xff57),
+    ("PageUp", 0xff55),
+    ("PageDown", 0xff56),
+    ("ArrowLeft", 0xff51),
+    ("ArrowUp", 0xff52),
+    ("ArrowRight", 0xff53),
+    ("ArrowDown", 0xff54),
+    ("F1", 0xffbe),
+    ("F2", 0xffbf),
+    ("F3", 0xffc0),
+    ("F4", 0xffc1),
+    ("F5", 0xffc2),
+    ("F6", 0xffc3),
+    ("F7", 0xffc4),
+    ("F8", 0xffc5),
+    ("F9", 0xffc6),
+    ("F10", 0xffc7),
+    ("F11", 0xffc8),
+    ("F12", 0xffc9),
+    ("NumLock", 0xff7f),
+    ("ScrollLock", 0xff14),
+    ("Pause", 0xff13),
+    ("PrintScreen", 0xff61),
+    ("ContextMenu", 0xff67),
+    (" ", 0x20),
+];
+
+//Modifiers by position, since `key` does not tell left from right.
+const MODIFIERS: &[(&str, u32)] = &[
+    ("ShiftLeft", 0xffe1),
+    ("ShiftRight", 0xffe2),
+    ("ControlLeft", 0xffe3),
+    ("ControlRight", 0xffe4),
+    ("AltLeft", 0xffe9),
+    ("AltRight", 0xffea),
+    ("MetaLeft", 0xffeb),
+    ("MetaRight", 0xffec),
+];
+
+const ISO_LEVEL3_SHIFT: u32 = 0xfe03;
+const SHIFT: Key = Key {
+    scancode: 42,
+    keysym: 0xffe1,
+};
+const ALTGR: Key = Key {
+    scancode: 100,
+    keysym: ISO_LEVEL3_SHIFT,
+};
+
+//Dead keys by the accent they add, with the keysym X11 gives them.
+const DEAD_KEYSYMS: &[(char, u32)] = &[
+    ('`', 0xfe50),
+    ('´', 0xfe51),
+    ('^', 0xfe52),
+    ('~', 0xfe53),
+    ('¨', 0xfe57),
+    ('¸', 0xfe5b),
+];
+
+//Accent, then pairs of base letter and the letter it composes to. A space gives the accent itself.
+const COMPOSE: &[(char, &str)] = &[
+    ('´', " ´aáeéiíoóuúyýAÁEÉIÍOÓUÚYÝcćnńsśzźCĆNŃSŚZŹ"),
+    ('`', " `aàeèiìoòuùAÀEÈIÌOÒUÙ"),
+    ('^', " ^aâeêiîoôuûAÂEÊIÎOÔUÛ"),
+    ('~', " ~aãnñoõAÃNÑOÕ"),
+    ('¨', " ¨aäeëiïoöuüyÿAÄEËIÏOÖUÜ"),
+    ('¸', " ¸cçCÇ"),
+];
+
+//Printable keys of each layout: code, then the base, Shift and AltGr levels.
+//`-` is an empty level and `dead:x` a dead key adding accent x.
+const US: &str = "
+Backquote ` ~
+Digit1 1 !
+Digit2 2 @
+Digit3 3 #
+Digit4 4 $
+Digit5 5 %
+Digit6 6 ^
+Digit7 7 &
+Digit8 8 *
+Digit9 9 (
+Digit0 0 )
+Minus - _
+Equal = +
+KeyQ q Q
+KeyW w W
+KeyE e E
+KeyR r R
+KeyT t T
+KeyY y Y
+KeyU u U
+KeyI i I
+KeyO o O
+KeyP p P
+BracketLeft [ {
+BracketRight ] }
+Backslash \\ |
+KeyA a A
+KeyS s S
+KeyD d D
+KeyF f F
+KeyG g G
+KeyH h H
+KeyJ j J
+KeyK k K
+KeyL l L
+Semicolon ; :
+Quote ' \"
+KeyZ z Z
+KeyX x X
+KeyC c C
+KeyV v V
+KeyB b B
+KeyN n N
+KeyM m M
+Comma , <
+Period . >
+Slash / ?
+";
+
+const GB: &str = "
+Backquote ` ¬ ¦
+Digit1 1 !
+Digit2 2 \"
+Digit3 3 £
+Digit4 4 $ €
+Digit5 5 %
+Digit6 6 ^
+Digit7 7 &
+Digit8 8 *
+Digit9 9 (
+Digit0 0 )
+Minus - _
+Equal = +
+KeyQ q Q
+KeyW w W
+KeyE e E é
+KeyR r R
+KeyT t T
+KeyY y Y
+KeyU u U ú
+KeyI i I í
+KeyO o O ó
+KeyP p P
+BracketLeft [ {
+BracketRight ] }
+Backslash # ~
+KeyA a A á
+KeyS s S
+KeyD d D
+KeyF f F
+KeyG g G
+KeyH h H
+KeyJ j J
+KeyK k K
+KeyL l L
+Semicolon ; :
+Quote ' @
+KeyZ z Z
+KeyX x X
+KeyC c C
+KeyV v V
+KeyB b B
+KeyN n N
+KeyM m M
+Comma , <
+Period . >
+Slash / ?
+IntlBackslash \\ |
+";
+
+const DE: &str = "
+Backquote dead:^ ° ′
+Digit1 1 ! ¹
+Digit2 2 \" ²
+Digit3 3 § ³
+Digit4 4 $ ¼
+Digit5 5 % ½
+Digit6 6 & ¬
+Digit7 7 / {
+Digit8 8 ( [
+Digit9 9 ) ]
+Digit0 0 = }
+Minus ß ? \\
+Equal dead:´ dead:` dead:¸
+KeyQ q Q @
+KeyW w W ł
+KeyE e E €
+KeyR r R ¶
+KeyT t T ŧ
+KeyY z Z ←
+KeyU u U ↓
+KeyI i I →
+KeyO o O ø
+KeyP p P þ
+BracketLeft ü Ü dead:¨
+BracketRight + * dead:~
+Backslash # ' ’
+KeyA a A æ
+KeyS s S ſ
+KeyD d D ð
+KeyF f F đ
+KeyG g G ŋ
+KeyH h H ħ
+KeyJ j J
+KeyK k K ĸ
+KeyL l L ł
+Semicolon ö Ö
+Quote ä Ä
+KeyZ y Y »
+KeyX x X «
+KeyC c C ¢
+KeyV v V „
+KeyB b B “
+KeyN n N ”
+KeyM m M µ
+Comma , ; ·
+Period . : …
+Slash - _ –
+IntlBackslash < > |
+";
+
+const FR: &str = "
+Backquote ²
+Digit1 & 1
+Digit2 é 2 dead:~
+Digit3 \" 3 #
+Digit4 ' 4 {
+Digit5 ( 5 [
+Digit6 - 6 |
+Digit7 è 7 dead:`
+Digit8 _ 8 \\
+Digit9 ç 9 ^
+Digit0 à 0 @
+Minus ) ° ]
+Equal = + }
+KeyQ a A
+KeyW z Z
+KeyE e E €
+KeyR r R
+KeyT t T
+KeyY y Y
+KeyU u U
+KeyI i I
+KeyO o O
+KeyP p P
+BracketLeft dead:^ dead:¨
+BracketRight $ £ ¤
+Backslash * µ
+KeyA q Q
+KeyS s S
+KeyD d D
+KeyF f F
+KeyG g G
+KeyH h H
+KeyJ j J
+KeyK k K
+KeyL l L
+Semicolon m M
+Quote ù %
+KeyZ w W
+KeyX x X
+KeyC c C
+KeyV v V
+KeyB b B
+KeyN n N
+KeyM , ?
+Comma ; .
+Period : /
+Slash ! §
+IntlBackslash < >
+";
+
+pub static LAYOUT_NAMES: &[&str] = &["us", "gb", "de", "fr"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Sym {
+    Char(char),
+    Dead(char),
+}
+
+//Where a symbol is on a layout: the key, and the level (0 base, 1 Shift, 2 AltGr).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Position {
+    code: &'static str,
+    level: usize,
+}
+
+#[derive(Debug)]
+pub struct Layout {
+    pub name: &'static str,
+    symbols: HashMap<Sym, Position>,
+}
+
+impl Layout {
+    fn parse(name: &'static str, table: &'static str) -> Layout {
+        let mut symbols = HashMap::new();
+        symbols.insert(
+            Sym::Char(' '),
+            Position {
+                code: "Space",
+                level: 0,
+            },
+        );
+        for line in table.lines() {
+            let mut fields = line.split_whitespace();
+            let Some(code) = fields.next() else {
+                continue;
+            };
+            for (level, field) in fields.enumerate() {
+                let sym = match field.strip_prefix("dead:") {
+                    Some(accent) => Sym::Dead(accent.chars().next().unwrap()),
+                    None if field == "-" => continue,
+                    None => Sym::Char(field.chars().next().unwrap()),
+                };
+                //The lowest level wins when a symbol appears twice.
+                symbols.entry(sym).or_insert(Position { code, level });
+            }
+        }
+        Layout { name, symbols }
+    }
+
+    pub fn named(name: &str) -> Option<&'static Layout> {
+        static LAYOUTS: OnceLock<Vec<Layout>> = OnceLock::new();
+        LAYOUTS
+            .get_or_init(|| {
+                vec![
+                    Layout::parse("us", US),
+                    Layout::parse("gb", GB),
+                    Layout::parse("de", DE),
+                    Layout::parse("fr", FR),
+                ]
+            })
+            .iter()
+            .find(|layout| layout.name == name)
+    }
+
+    fn position(&self, sym: Sym) -> Option<Position> {
+        self.symbols.get(&sym).copied()
+    }
+
+    //A dead key and base letter on this layout that compose to `c`.
+    fn compose(&self, c: char) -> Option<(char, Position, char, Position)> {
+        COMPOSE.iter().find_map(|(accent, pairs)| {
+            let pairs: Vec<char> = pairs.chars().collect();
+            let base = pairs.chunks(2).find(|pair| pair[1] == c)?[0];
+            let dead = self.position(Sym::Dead(*accent))?;
+            let plain = self.position(Sym::Char(base))?;
+            Some((*accent, dead, base, plain))
+        })
+    }
+}
+
+pub fn scancode(code: &str) -> Option<u16> {
+    SCANCODES
+        .iter()
+        .find(|(name, _)| *name == code)
+        .map(|(_, scancode)| *scancode)
+}
+
+//X11 keysym of a character: Latin-1 maps directly, everything else to the Unicode range.
+pub fn char_keysym(c: char) -> u32 {
+    match c as u32 {
+        code @ (0x20..=0x7e | 0xa0..=0xff) => code,
+        code => 0x0100_0000 | code,
+    }
+}
+
+//Turns one viewer's browser key events into host key presses for `layout`.
+pub struct KeyTranslator {
+    layout: &'static Layout,
+    //What each held viewer key pressed on the host, released together on key up.
+    pressed: HashMap<String, Vec<Key>>,
+}
+
+impl KeyTranslator {
+    pub fn new(layout: &'static Layout) -> Self {
+        KeyTranslator {
+            layout,
+            pressed: HashMap::new(),
+        }
+    }
+
+    fn held(&self, matches: impl Fn(&Key) -> bool) -> Vec<Key> {
+        let mut held: Vec<Key> = self
+            .pressed
+            .values()
+            .flatten()
+            .copied()
+            .filter(matches)
+            .collect();
+        held.sort_by_key(|key| key.scancode);
+        held
+    }
+
+    //Shortcuts keep their modifiers; only Shift and AltGr pick a level.
+    fn shortcut_held(&self) -> bool {
+        !self
+            .held(|key| (0xffe3..=0xffec).contains(&key.keysym) && key.keysym != 0xffe5)
+            .is_empty()
+    }
+
+    //Events that put Shift and AltGr into the state `level` needs, and the events that undo them.
+    fn set_level(&self, level: usize) -> (Vec<InputEvent>, Vec<InputEvent>) {
+        let (mut set, mut undo) = (Vec::new(), Vec::new());
+        let wanted = [(SHIFT, level == 1), (ALTGR, level == 2)];
+        for (modifier, wanted) in wanted {
+            let held = self.held(|key| {
+                key.keysym == modifier.keysym
+                    || (modifier.keysym == SHIFT.keysym && key.keysym == 0xffe2)
+            });
+            if wanted && held.is_empty() {
+                set.push(InputEvent::KeyDown(modifier));
+                undo.push(InputEvent::KeyUp(modifier));
+            }
+            if !wanted {
+                for key in held {
+                    set.push(InputEvent::KeyUp(key));
+                    undo.push(InputEvent::KeyDown(key));
+                }
+            }
+        }
+        undo.reverse();
+        (set, undo)
+    }
+
+    fn press(&mut self, code: &str, key: Key) -> Vec<InputEvent> {
+        self.pressed.entry(code.to_string()).or_default().push(key);
+        vec![InputEvent::KeyDown(key)]
+    }
+
+    fn type_char(&mut self, code: &str, c: char) -> Vec<InputEvent> {
+        let layout = self.layout;
+        if let Some(position) = layout.position(Sym::Char(c)) {
+            let key = Key {
+                scancode: scancode(position.code).unwrap_or(0),
+                keysym: char_keysym(c),
+            };
+            let (mut events, undo) = self.set_level(position.level);
+            events.extend(self.press(code, key));
+            events.extend(undo);
+            return events;
+        }
+        if let Some((accent, dead, base, plain)) = layout.compose(c) {
+            let dead_key = Key {
+                scancode: scancode(dead.code).unwrap_or(0),
+                keysym: DEAD_KEYSYMS
+                    .iter()
+                    .find(|(candidate, _)| *candidate == accent)
+                    .map_or(0, |(_, keysym)| *keysym),
+            };
+            let (mut events, undo) = self.set_level(dead.level);
+            events.push(InputEvent::KeyDown(dead_key));
+            events.push(InputEvent::KeyUp(dead_key));
+            events.extend(undo);
+            let (set, undo) = self.set_level(plain.level);
+            events.extend(set);
+            events.extend(self.press(
+                code,
+                Key {
+                    scancode: scancode(plain.code).unwrap_or(0),
+                    keysym: char_keysym(base),
+                },
+            ));
+            events.extend(undo);
+            return events;
+        }
+        vec![InputEvent::Text(c.to_string())]
+    }
+
+    pub fn key_down(&mut self, code: &str, key: &str) -> Vec<InputEvent> {
+        //A repeat of a held key presses the same host keys again.
+        if let Some(keys) = self.pressed.get(code) {
+            return keys.iter().map(|key| InputEvent::KeyDown(*key)).collect();
+        }
+        let physical = scancode(code).unwrap_or(0);
+        if key == "AltGraph" {
+            return self.press(
+                code,
+                Key {
+                    scancode: physical,
+                    keysym: ISO_LEVEL3_SHIFT,
+                },
+            );
+        }
+        if let Some((_, keysym)) = MODIFIERS.iter().find(|(name, _)| *name == code) {
+            return self.press(
+                code,
+                Key {
+                    scancode: physical,
+                    keysym: *keysym,
+                },
+            );
+        }
+        //Characters arrive already composed, and Caps Lock is already applied to them.
+        if key == "Dead" || key == "CapsLock" {
+            return Vec::new();
+        }
+        if let Some((_, keysym)) = NAMED.iter().find(|(name, _)| *name == key) {
+            return self.press(
+                code,
+                Key {
+                    scancode: physical,
+                    keysym: *keysym,
+                },
+            );
+        }
+        //Other key names, such as media keys, go by position alone.
+        let named = key.len() > 1 && key.chars().all(|c| c.is_ascii_alphanumeric());
+        if key.is_empty() || named {
+            if physical == 0 {
+                return Vec::new();
+            }
+            return self.press(
+                code,
+                Key {
+                    scancode: physical,
+                    keysym: 0,
+                },
+            );
+        }
+        let mut chars = key.chars();
+        match (chars.next(), chars.next()) {
+            //With Ctrl, Alt or Meta held this is a shortcut, so press the key as it is.
+            (Some(c), None) if self.shortcut_held() => {
+                let lower = c.to_lowercase().next().unwrap_or(c);
+                let position = self.layout.position(Sym::Char(lower));
+                let scancode = position
+                    .and_then(|position| scancode(position.code))
+                    .unwrap_or(physical);
+                self.press(
+                    code,
+                    Key {
+                        scancode,
+                        keysym: char_keysym(lower),
+                    },
+                )
+            }
+            (Some(c), None) => self.type_char(code, c),
+            //Several characters at once, e.g. from an input method.
+            _ => vec![InputEvent::Text(key.to_string())],
+        }
+    }
+
+    pub fn key_up(&mut self, code: &str) -> Vec<InputEvent> {
+        self.pressed
+            .remove(code)
+            .unwrap_or_default()
+            .into_iter()
+            .rev()
+            .map(InputEvent::KeyUp)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use InputEvent::{KeyDown, KeyUp, Text};
+
+    fn key(scancode: u16, keysym: u32) -> Key {
+        Key { scancode, keysym }
+    }
+
+    const SHIFT_RIGHT: Key = Key {
+        scancode: 54,
+        keysym: 0xffe2,
+    };
+    const CONTROL: Key = Key {
+        scancode: 29,
+        keysym: 0xffe3,
+    };
+
+    #[test]
+    fn layouts_parse() {
+        for name in LAYOUT_NAMES {
+            let layout = Layout::named(name).unwrap();
+            assert!(layout.symbols.len() > 40, "{}", name);
+            for position in layout.symbols.values() {
+                assert!(
+                    scancode(position.code).is_some(),
+                    "{} {}",
+                    name,
+                    position.code
+                );
+            }
+        }
+        assert!(Layout::named("xx").is_none());
+    }
+
+    #[test]
+    fn translates_key_events() {
+        //(host layout, viewer events as (code, key) downs or code ups, expected host events)
+        type Case = (
+            &'static str,
+            Vec<(&'static str, Option<&'static str>)>,
+            Vec<InputEvent>,
+        );
+        let cases: Vec<Case> = vec![
+            //Same layout on both ends.
+            (
+                "us",
+                vec![("KeyA", Some("a")), ("KeyA", None)],
+                vec![KeyDown(key(30, 0x61)), KeyUp(key(30, 0x61))],
+            ),
+            //A German viewer's z sits on the host's KeyZ.
+            (
+                "us",
+                vec![("KeyY", Some("z"))],
+                vec![KeyDown(key(44, 0x7a))],
+            ),
+            //Shift is added for the duration of the press...
+            (
+                "us",
+                vec![("Digit1", Some("!"))],
+                vec![KeyDown(SHIFT), KeyDown(key(2, 0x21)), KeyUp(SHIFT)],
+            ),
+            //...or taken away, when the host has the character on the base level.
+            (
+                "de",
+                vec![("ShiftRight", Some("Shift")), ("Equal", Some("+"))],
+                vec![
+                    KeyDown(SHIFT_RIGHT),
+                    KeyUp(SHIFT_RIGHT),
+                    KeyDown(key(27, 0x2b)),
+                    KeyDown(SHIFT_RIGHT),
+                ],
+            ),
+            //Held Shift is kept when the host needs it too.
+            (
+                "us",
+                vec![
+                    ("ShiftLeft", Some("Shift")),
+                    ("KeyA", Some("A")),
+                    ("KeyA", None),
+                    ("ShiftLeft", None),
+                ],
+                vec![
+                    KeyDown(SHIFT),
+                    KeyDown(key(30, 0x41)),
+                    KeyUp(key(30, 0x41)),
+                    KeyUp(SHIFT),
+                ],
+            ),
+            //AltGr on the host for a US viewer's @.
+            (
+                "de",
+                vec![("Digit2", Some("@"))],
+                vec![KeyDown(ALTGR), KeyDown(key(16, 0x40)), KeyUp(ALTGR)],
+            ),
+            //A viewer's AltGr goes through, and the host level follows it.
+            (
+                "de",
+                vec![("AltRight", Some("AltGraph")), ("KeyE", Some("€"))],
+                vec![KeyDown(ALTGR), KeyDown(key(18, 0x10020ac))],
+            ),
+            //The viewer's dead key waits for the composed character.
+            (
+                "us",
+                vec![("Quote", Some("Dead")), ("KeyE", Some("é"))],
+                vec![Text("é".to_string())],
+            ),
+            //A host with the character as a key types it directly.
+            (
+                "fr",
+                vec![("Quote", Some("Dead")), ("KeyE", Some("é"))],
+                vec![KeyDown(key(3, 0xe9))],
+            ),
+            //A host with a dead key composes it.
+            (
+                "de",
+                vec![("KeyE", Some("é")), ("KeyE", None)],
+                vec![
+                    KeyDown(key(13, 0xfe51)),
+                    KeyUp(key(13, 0xfe51)),
+                    KeyDown(key(18, 0x65)),
+                    KeyUp(key(18, 0x65)),
+                ],
+            ),
+            (
+                "de",
+                vec![("Backquote", Some("^"))],
+                vec![
+                    KeyDown(key(41, 0xfe52)),
+                    KeyUp(key(41, 0xfe52)),
+                    KeyDown(key(57, 0x20)),
+                ],
+            ),
+            (
+                "fr",
+                vec![("KeyO", Some("ö"))],
+                vec![
+                    KeyDown(SHIFT),
+                    KeyDown(key(26, 0xfe57)),
+                    KeyUp(key(26, 0xfe57)),
+                    KeyUp(SHIFT),
+                    KeyDown(key(24, 0x6f)),
+                ],
+            ),
+            //Unicode fallback for what the host layout cannot type.
+            (
+                "us",
+                vec![("KeyQ", Some("ж")), ("KeyQ", None)],
+                vec![Text("ж".to_string())],
+            ),
+            (
+                "us",
+                vec![("", Some("日本"))],
+                vec![Text("日本".to_string())],
+            ),
+            //Shortcuts press the letter's key with the modifiers as held.
+            (
+                "fr",
+                vec![("ControlLeft", Some("Control")), ("KeyA", Some("q"))],
+                vec![KeyDown(CONTROL), KeyDown(key(30, 0x71))],
+            ),
+            (
+                "us",
+                vec![
+                    ("ControlLeft", Some("Control")),
+                    ("ShiftLeft", Some("Shift")),
+                    ("KeyT", Some("T")),
+                ],
+                vec![KeyDown(CONTROL), KeyDown(SHIFT), KeyDown(key(20, 0x74))],
+            ),
+            //Named keys keep their position and get their keysym.
+            (
+                "de",
+                vec![
+                    ("Enter", Some("Enter")),
+                    ("ArrowLeft", Some("ArrowLeft")),
+                    ("Space", Some(" ")),
+                ],
+                vec![
+                    KeyDown(key(28, 0xff0d)),
+                    KeyDown(key(105, 0xff51)),
+                    KeyDown(key(57, 0x20)),
+                ],
+            ),
+            //Repeats press again, and the release matches what was pressed.
+            (
+                "de",
+                vec![("KeyZ", Some("y")), ("KeyZ", Some("y")), ("KeyZ", None)],
+                vec![
+                    KeyDown(key(44, 0x79)),
+                    KeyDown(key(44, 0x79)),
+                    KeyUp(key(44, 0x79)),
+                ],
+            ),
+            //Caps Lock is already in the characters; unknown keys without a code are dropped.
+            (
+                "us",
+                vec![
+                    ("CapsLock", Some("CapsLock")),
+                    ("KeyB", None),
+                    ("Lang1", Some("Unidentified")),
+                ],
+                vec![],
+            ),
+            (
+                "us",
+                vec![("F13", Some("F13")), ("PrintScreen", Some("Unidentified"))],
+                vec![KeyDown(key(99, 0))],
+            ),
+        ];
+        for (layout, events, expected) in cases {
+            let mut translator = KeyTranslator::new(Layout::named(layout).unwrap());
+            let mut translated = Vec::new();
+            for (code, key) in &events {
+                translated.extend(match key {
+                    Some(key) => translator.key_down(code, key),
+                    None => translator.key_up(code),
+                });
+            }
+            assert_eq!(translated, expected, "{} {:?}", layout, events);
+        }
+    }
+
+    #[test]
+    fn keysyms_for_characters() {
+        let cases = [
+            ('a', 0x61),
+            ('~', 0x7e),
+            ('é', 0xe9),
+            ('€', 0x10020ac),
+            ('ж', 0x1000436),
+        ];
+        for (c, keysym) in cases {
+            assert_eq!(char_keysym(c), keysym, "{}", c);
+        }
+    }
+}
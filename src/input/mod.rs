@@ -0,0 +1,456 @@
+//Remote input: normalized events from viewers, injected into the desktop session by an `Injector` backend.
+use crate::capture::Monitor;
+use layout::{KeyTranslator, Layout};
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(test)]
+pub mod fake;
+pub mod layout;
+
+//Bounds on what one viewer event may ask for. Each notch is a click and each character a keymap
+//change with a pause on X11, so larger requests would stall the desktop process.
+const MAX_WHEEL_NOTCHES: i32 = 10;
+const MAX_TEXT_CHARS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    //Linux evdev code (KEY_A is 30), which matches the PC set 1 scancode for most keys.
+    //Identifies the physical key, for layout-independent input such as games.
+    pub scancode: u16,
+    //X11 keysym of the character or function the viewer meant; 0 if unknown.
+    pub keysym: u32,
+}
+
+//Variants are built from viewer messages once the streaming path lands.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    Left,
+    Middle,
+    Right,
+    Back,
+    Forward,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    KeyDown(Key),
+    KeyUp(Key),
+    //Position in pixels within one monitor, as the viewer sees it.
+    PointerMove { monitor: u32, x: u32, y: u32 },
+    ButtonDown(Button),
+    ButtonUp(Button),
+    //Wheel notches; positive scrolls right and down.
+    Wheel { dx: i32, dy: i32 },
+    //Characters to type whatever the keyboard layout, for those no key produces.
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    //The backend cannot run here, e.g. no X server or no XTest extension.
+    Unavailable(String),
+    NoSuchMonitor(u32),
+    //Neither the keysym nor the scancode maps to a key on the host keyboard.
+    UnmappedKey(Key),
+    Backend(String),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::Unavailable(reason) => write!(f, "input injection unavailable: {}", reason),
+            InputError::NoSuchMonitor(id) => write!(f, "no monitor with id {}", id),
+            InputError::UnmappedKey(key) => write!(
+                f,
+                "no key for keysym {:#x} or scancode {}",
+                key.keysym, key.scancode
+            ),
+            InputError::Backend(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+//Low-level injection into the session; `InputSession` does the bookkeeping around it.
+pub trait Injector: Send {
+    fn key(&mut self, key: Key, down: bool) -> Result<(), InputError>;
+    //Absolute position in the virtual desktop that monitors are laid out in.
+    fn pointer(&mut self, x: i32, y: i32) -> Result<(), InputError>;
+    fn button(&mut self, button: Button, down: bool) -> Result<(), InputError>;
+    fn wheel(&mut self, dx: i32, dy: i32) -> Result<(), InputError>;
+    fn text(&mut self, text: &str) -> Result<(), InputError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputBackend {
+    //The platform's native backend.
+    Auto,
+    X11,
+    //Ignore remote input, for view-only agents.
+    Disabled,
+}
+
+impl FromStr for InputBackend {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(InputBackend::Auto),
+            "x11" => Ok(InputBackend::X11),
+            "disabled" => Ok(InputBackend::Disabled),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for InputBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputBackend::Auto => write!(f, "auto"),
+            InputBackend::X11 => write!(f, "x11"),
+            InputBackend::Disabled => write!(f, "disabled"),
+        }
+    }
+}
+
+pub fn open(backend: InputBackend) -> Result<Box<dyn Injector>, InputError> {
+    match backend {
+        InputBackend::Disabled => Err(InputError::Unavailable(
+            "remote input is disabled".to_string(),
+        )),
+        #[cfg(target_os = "linux")]
+        InputBackend::Auto | InputBackend::X11 => {
+            Ok(Box::new(crate::linux::input::XTestInjector::connect(None)?))
+        }
+        #[cfg(target_os = "windows")]
+        InputBackend::Auto => Err(InputError::Unavailable(
+            "no native input backend on Windows yet".to_string(),
+        )),
+        #[cfg(target_os = "windows")]
+        InputBackend::X11 => Err(InputError::Unavailable(
+            "X11 input is only available on Linux".to_string(),
+        )),
+    }
+}
+
+//One viewer's input into the session. Tracks what is held down, so nothing stays stuck
+//when the viewer goes away mid-press.
+pub struct InputSession {
+    injector: Box<dyn Injector>,
+    monitors: Vec<Monitor>,
+    translator: KeyTranslator,
+    keys: Vec<Key>,
+    buttons: Vec<Button>,
+}
+
+impl InputSession {
+    //`layout` is the host keyboard layout that browser key events are translated to.
+    pub fn new(
+        injector: Box<dyn Injector>,
+        monitors: Vec<Monitor>,
+        layout: &'static Layout,
+    ) -> Self {
+        InputSession {
+            injector,
+            monitors,
+            translator: KeyTranslator::new(layout),
+            keys: Vec::new(),
+            buttons: Vec::new(),
+        }
+    }
+
+    //Pointer positions are relative to these from now on, e.g. after a display change.
+    pub fn set_monitors(&mut self, monitors: Vec<Monitor>) {
+        self.monitors = monitors;
+    }
+
+    //Fed by viewers once input arrives over the streaming path.
+    #[allow(dead_code)]
+    pub fn handle(&mut self, event: &InputEvent) -> Result<(), InputError> {
+        match *event {
+            InputEvent::Text(ref text) => match text.char_indices().nth(MAX_TEXT_CHARS) {
+                Some((end, _)) => {
+                    log::warn!(
+                        "typing only the first {} of {} characters",
+                        MAX_TEXT_CHARS,
+                        text.chars().count()
+                    );
+                    self.injector.text(&text[..end])
+                }
+                None => self.injector.text(text),
+            },
+            //A repeated down is auto-repeat and goes through; it is still one held key.
+            InputEvent::KeyDown(key) => {
+                self.injector.key(key, true)?;
+                if !self.keys.contains(&key) {
+                    self.keys.push(key);
+                }
+                Ok(())
+            }
+            //Releases of keys pressed before the viewer connected are not ours to send.
+            InputEvent::KeyUp(key) => match self.keys.iter().position(|held| *held == key) {
+                Some(index) => {
+                    self.keys.remove(index);
+                    self.injector.key(key, false)
+                }
+                None => Ok(()),
+            },
+            InputEvent::PointerMove { monitor, x, y } => {
+                let (x, y) = self.to_desktop(monitor, x, y)?;
+                self.injector.pointer(x, y)
+            }
+            InputEvent::ButtonDown(button) => {
+                if self.buttons.contains(&button) {
+                    return Ok(());
+                }
+                self.injector.button(button, true)?;
+                self.buttons.push(button);
+                Ok(())
+            }
+            InputEvent::ButtonUp(button) => {
+                match self.buttons.iter().position(|held| *held == button) {
+                    Some(index) => {
+                        self.buttons.remove(index);
+                        self.injector.button(button, false)
+                    }
+                    None => Ok(()),
+                }
+            }
+            InputEvent::Wheel { dx: 0, dy: 0 } => Ok(()),
+            InputEvent::Wheel { dx, dy } => self.injector.wheel(
+                dx.clamp(-MAX_WHEEL_NOTCHES, MAX_WHEEL_NOTCHES),
+                dy.clamp(-MAX_WHEEL_NOTCHES, MAX_WHEEL_NOTCHES),
+            ),
+        }
+    }
+
+    //A browser key event (`KeyboardEvent.code` and `.key`); `key` is ignored on release.
+    #[allow(dead_code)]
+    pub fn handle_browser_key(
+        &mut self,
+        code: &str,
+        key: &str,
+        down: bool,
+    ) -> Result<(), InputError> {
+        let events = if down {
+            self.translator.key_down(code, key)
+        } else {
+            self.translator.key_up(code)
+        };
+        for event in &events {
+            self.handle(event)?;
+        }
+        Ok(())
+    }
+
+    //Let go of every key and button still held, newest first.
+    pub fn release_all(&mut self) {
+        for key in std::mem::take(&mut self.keys).into_iter().rev() {
+            if let Err(e) = self.injector.key(key, false) {
+                log::warn!("failed to release key {:?}: {}", key, e);
+            }
+        }
+        for button in std::mem::take(&mut self.buttons).into_iter().rev() {
+            if let Err(e) = self.injector.button(button, false) {
+                log::warn!("failed to release button {:?}: {}", button, e);
+            }
+        }
+    }
+
+    //Monitor-relative pixels to the virtual desktop, clamped to the monitor's edge.
+    fn to_desktop(&self, monitor: u32, x: u32, y: u32) -> Result<(i32, i32), InputError> {
+        let monitor = self
+            .monitors
+            .iter()
+            .find(|candidate| candidate.id == monitor)
+            .ok_or(InputError::NoSuchMonitor(monitor))?;
+        let x = x.min(monitor.width.saturating_sub(1)) as i32;
+        let y = y.min(monitor.height.saturating_sub(1)) as i32;
+        Ok((monitor.x + x, monitor.y + y))
+    }
+}
+
+impl Drop for InputSession {
+    fn drop(&mut self) {
+        self.release_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::{Injected, RecordingInjector};
+    use super::*;
+    use crate::capture::synthetic::SyntheticCapturer;
+    use crate::capture::Capturer;
+
+    const A: Key = Key {
+        scancode: 30,
+        keysym: 0x61,
+    };
+    const SHIFT: Key = Key {
+        scancode: 42,
+        keysym: 0xffe1,
+    };
+
+    fn session() -> (InputSession, RecordingInjector) {
+        let injector = RecordingInjector::new();
+        let monitors = SyntheticCapturer::default().monitors().unwrap();
+        (
+            InputSession::new(
+                Box::new(injector.clone()),
+                monitors,
+                Layout::named("us").unwrap(),
+            ),
+            injector,
+        )
+    }
+
+    #[test]
+    fn maps_pointer_to_the_monitor() {
+        let (mut session, injector) = session();
+        //(monitor, x, y, expected desktop position)
+        let cases = [
+            (0, 10, 20, (10, 20)),
+            (1, 10, 20, (1290, 20)),
+            (0, 5000, 5000, (1279, 719)),
+            (1, 1023, 767, (2303, 767)),
+        ];
+        for (monitor, x, y, expected) in cases {
+            session
+                .handle(&InputEvent::PointerMove { monitor, x, y })
+                .unwrap();
+            assert_eq!(
+                injector.injected().last(),
+                Some(&Injected::Pointer(expected.0, expected.1))
+            );
+        }
+        assert_eq!(
+            session.handle(&InputEvent::PointerMove {
+                monitor: 7,
+                x: 0,
+                y: 0
+            }),
+            Err(InputError::NoSuchMonitor(7))
+        );
+    }
+
+    #[test]
+    fn forwards_keys_buttons_and_wheel() {
+        let (mut session, injector) = session();
+        for event in [
+            InputEvent::KeyDown(SHIFT),
+            InputEvent::KeyDown(A),
+            InputEvent::KeyDown(A),
+            InputEvent::KeyUp(A),
+            InputEvent::KeyUp(SHIFT),
+            InputEvent::ButtonDown(Button::Left),
+            InputEvent::ButtonUp(Button::Left),
+            InputEvent::Wheel { dx: 0, dy: -2 },
+            InputEvent::Wheel { dx: 0, dy: 0 },
+        ] {
+            session.handle(&event).unwrap();
+        }
+        assert_eq!(
+            injector.injected(),
+            vec![
+                Injected::Key(SHIFT, true),
+                Injected::Key(A, true),
+                Injected::Key(A, true),
+                Injected::Key(A, false),
+                Injected::Key(SHIFT, false),
+                Injected::Button(Button::Left, true),
+                Injected::Button(Button::Left, false),
+                Injected::Wheel(0, -2),
+            ]
+        );
+    }
+
+    #[test]
+    fn bounds_wheel_and_text() {
+        let (mut session, injector) = session();
+        session
+            .handle(&InputEvent::Wheel {
+                dx: i32::MIN,
+                dy: i32::MAX,
+            })
+            .unwrap();
+        session
+            .handle(&InputEvent::Text("ж".repeat(10_000)))
+            .unwrap();
+        session.handle(&InputEvent::Text("hi".to_string())).unwrap();
+        assert_eq!(
+            injector.injected(),
+            vec![
+                Injected::Wheel(-MAX_WHEEL_NOTCHES, MAX_WHEEL_NOTCHES),
+                Injected::Text("ж".repeat(MAX_TEXT_CHARS)),
+                Injected::Text("hi".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn translates_browser_keys() {
+        let (mut session, injector) = session();
+        session.handle_browser_key("Digit1", "!", true).unwrap();
+        session.handle_browser_key("Digit1", "", false).unwrap();
+        session.handle_browser_key("KeyQ", "ж", true).unwrap();
+        let one = Key {
+            scancode: 2,
+            keysym: 0x21,
+        };
+        assert_eq!(
+            injector.injected(),
+            vec![
+                Injected::Key(SHIFT, true),
+                Injected::Key(one, true),
+                Injected::Key(SHIFT, false),
+                Injected::Key(one, false),
+                Injected::Text("ж".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_releases_it_did_not_press() {
+        let (mut session, injector) = session();
+        session.handle(&InputEvent::KeyUp(A)).unwrap();
+        session
+            .handle(&InputEvent::ButtonUp(Button::Right))
+            .unwrap();
+        assert_eq!(injector.injected(), vec![]);
+    }
+
+    #[test]
+    fn releases_held_input_when_dropped() {
+        let (mut session, injector) = session();
+        session.handle(&InputEvent::KeyDown(SHIFT)).unwrap();
+        session.handle(&InputEvent::KeyDown(A)).unwrap();
+        session
+            .handle(&InputEvent::ButtonDown(Button::Left))
+            .unwrap();
+        drop(session);
+        assert_eq!(
+            injector.injected()[3..],
+            [
+                Injected::Key(A, false),
+                Injected::Key(SHIFT, false),
+                Injected::Button(Button::Left, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_press_is_not_held() {
+        let (mut session, injector) = session();
+        injector.fail(InputError::UnmappedKey(A));
+        assert_eq!(
+            session.handle(&InputEvent::KeyDown(A)),
+            Err(InputError::UnmappedKey(A))
+        );
+        injector.clear_failure();
+        drop(session);
+        assert_eq!(injector.injected(), vec![]);
+    }
+}
@@ -0,0 +1,364 @@
+//Unattended installation: copy the binary to a stable directory, write the config, register and
+//start the service. Steps that are already done are left out of the plan, so it can be re-run.
+use crate::config::Config;
+use crate::service::{self, ServiceError, ServiceManager, ServiceStatus};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(target_os = "windows")]
+static INSTALL_DIR: &str = "C:\\Program Files\\DeskHub";
+#[cfg(target_os = "linux")]
+static INSTALL_DIR: &str = "/opt/deskhub";
+
+pub fn default_install_dir() -> PathBuf {
+    PathBuf::from(INSTALL_DIR)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstallOptions {
+    pub install_dir: PathBuf,
+    pub config_path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    CreateDir(PathBuf),
+    //The running service holds the old binary and config; it is started again at the end.
+    StopService,
+    CopyBinary { from: PathBuf, to: PathBuf },
+    WriteConfig { path: PathBuf, contents: String },
+    RegisterService { command_line: String },
+    UpdateService { command_line: String },
+    StartService,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::CreateDir(path) => write!(f, "create directory {}", path.display()),
+            Action::StopService => write!(f, "stop the service"),
+            Action::CopyBinary { from, to } => {
+                write!(f, "copy {} to {}", from.display(), to.display())
+            }
+            Action::WriteConfig { path, .. } => write!(f, "write configuration {}", path.display()),
+            Action::RegisterService { command_line } => {
+                write!(f, "register the service to run {}", command_line)
+            }
+            Action::UpdateService { command_line } => {
+                write!(f, "point the service at {}", command_line)
+            }
+            Action::StartService => write!(f, "start the service"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InstallError {
+    Io(PathBuf, io::Error),
+    Service(ServiceError),
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            InstallError::Service(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<ServiceError> for InstallError {
+    fn from(e: ServiceError) -> Self {
+        InstallError::Service(e)
+    }
+}
+
+fn same_contents(a: &Path, b: &Path) -> bool {
+    match (fs::read(a), fs::read(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+pub fn installed_binary(source: &Path, install_dir: &Path) -> PathBuf {
+    install_dir.join(source.file_name().unwrap_or_else(|| "deskhub".as_ref()))
+}
+
+//Copy `source` into `install_dir` unless an identical binary is already there; returns the installed path.
+pub fn install_binary(source: &Path, install_dir: &Path) -> Result<PathBuf, InstallError> {
+    let target = installed_binary(source, install_dir);
+    if source == target || same_contents(source, &target) {
+        return Ok(target);
+    }
+    fs::create_dir_all(install_dir).map_err(|e| InstallError::Io(install_dir.to_path_buf(), e))?;
+    //Copy next to the target and rename, so an interrupted copy never leaves a truncated binary.
+    let partial = target.with_extension("partial");
+    fs::copy(source, &partial).map_err(|e| InstallError::Io(partial.clone(), e))?;
+    fs::rename(&partial, &target).map_err(|e| InstallError::Io(target.clone(), e))?;
+    Ok(target)
+}
+
+//Work out what is left to do. `manager` is opened to learn whether the service exists.
+pub fn plan(
+    source: &Path,
+    config: &Config,
+    options: &InstallOptions,
+    manager: &mut dyn ServiceManager,
+) -> Result<Vec<Action>, InstallError> {
+    let target = installed_binary(source, &options.install_dir);
+    let command_line = service::command_line_for(&target);
+    let copy_binary = source != target && !same_contents(source, &target);
+    let contents = config.to_toml();
+    let write_config = fs::read_to_string(&options.config_path).ok().as_deref() != Some(&contents);
+
+    let registered = match manager.open() {
+        Ok(()) => Some(manager.command_line()?),
+        Err(ServiceError::DoesNotExist) => None,
+        Err(e) => return Err(e.into()),
+    };
+    let running = registered.is_some() && manager.query_status() == ServiceStatus::Running;
+    let update_service = registered
+        .as_ref()
+        .is_some_and(|registered| *registered != command_line);
+    let restart = running && (copy_binary || write_config || update_service);
+
+    let mut actions = Vec::new();
+    if restart {
+        actions.push(Action::StopService);
+    }
+    if copy_binary {
+        if !options.install_dir.exists() {
+            actions.push(Action::CreateDir(options.install_dir.clone()));
+        }
+        actions.push(Action::CopyBinary {
+            from: source.to_path_buf(),
+            to: target,
+        });
+    }
+    if write_config {
+        if let Some(parent) = options.config_path.parent() {
+            if !parent.as_os_str().is_empty() && !parent.exists() {
+                actions.push(Action::CreateDir(parent.to_path_buf()));
+            }
+        }
+        actions.push(Action::WriteConfig {
+            path: options.config_path.clone(),
+            contents,
+        });
+    }
+    if registered.is_none() {
+        actions.push(Action::RegisterService {
+            command_line: command_line.clone(),
+        });
+    } else if update_service {
+        actions.push(Action::UpdateService {
+            command_line: command_line.clone(),
+        });
+    }
+    if !running || restart {
+        actions.push(Action::StartService);
+    }
+    Ok(actions)
+}
+
+pub fn apply(action: &Action, manager: &mut dyn ServiceManager) -> Result<(), InstallError> {
+    match action {
+        Action::CreateDir(path) => {
+            fs::create_dir_all(path).map_err(|e| InstallError::Io(path.clone(), e))
+        }
+        Action::StopService => Ok(manager.stop()?),
+        Action::CopyBinary { from, to } => {
+            let install_dir = to.parent().unwrap_or_else(|| Path::new("."));
+            install_binary(from, install_dir).map(|_| ())
+        }
+        Action::WriteConfig { path, contents } => {
+            fs::write(path, contents).map_err(|e| InstallError::Io(path.clone(), e))
+        }
+        Action::RegisterService { command_line } => {
+            Ok(manager.register(service::SERVICE_DISPLAY_NAME, command_line)?)
+        }
+        Action::UpdateService { command_line } => Ok(manager.set_command_line(command_line)?),
+        Action::StartService => Ok(manager.start()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::fake::{FakeServiceManager, Operation};
+
+    struct Fixture {
+        _dir: tempfile::TempDir,
+        source: PathBuf,
+        options: InstallOptions,
+    }
+
+    fn fixture() -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("downloads").join("deskhub");
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::write(&source, b"binary v1").unwrap();
+        let options = InstallOptions {
+            install_dir: dir.path().join("opt").join("deskhub"),
+            config_path: dir.path().join("etc").join("config.toml"),
+        };
+        Fixture {
+            _dir: dir,
+            source,
+            options,
+        }
+    }
+
+    fn install(fixture: &Fixture, config: &Config, fake: &FakeServiceManager) -> Vec<Action> {
+        let mut manager = fake.clone();
+        let actions = plan(&fixture.source, config, &fixture.options, &mut manager).unwrap();
+        for action in &actions {
+            apply(action, &mut manager).unwrap();
+        }
+        actions
+    }
+
+    #[test]
+    fn fresh_install_runs_every_step() {
+        let fixture = fixture();
+        let fake = FakeServiceManager::new();
+        let config = Config::default();
+        let actions = install(&fixture, &config, &fake);
+
+        let target = fixture.options.install_dir.join("deskhub");
+        assert_eq!(
+            actions,
+            vec![
+                Action::CreateDir(fixture.options.install_dir.clone()),
+                Action::CopyBinary {
+                    from: fixture.source.clone(),
+                    to: target.clone(),
+                },
+                Action::CreateDir(fixture.options.config_path.parent().unwrap().to_path_buf()),
+                Action::WriteConfig {
+                    path: fixture.options.config_path.clone(),
+                    contents: config.to_toml(),
+                },
+                Action::RegisterService {
+                    command_line: service::command_line_for(&target),
+                },
+                Action::StartService,
+            ]
+        );
+        assert_eq!(fs::read(&target).unwrap(), b"binary v1");
+        assert_eq!(
+            fs::read_to_string(&fixture.options.config_path).unwrap(),
+            config.to_toml()
+        );
+        assert_eq!(fake.status(), ServiceStatus::Running);
+        assert_eq!(
+            fake.command_line(),
+            Some(service::command_line_for(&target))
+        );
+    }
+
+    #[test]
+    fn second_run_does_nothing() {
+        let fixture = fixture();
+        let fake = FakeServiceManager::new();
+        install(&fixture, &Config::default(), &fake);
+        assert_eq!(install(&fixture, &Config::default(), &fake), vec![]);
+    }
+
+    #[test]
+    fn stopped_service_is_started() {
+        let fixture = fixture();
+        let fake = FakeServiceManager::new();
+        install(&fixture, &Config::default(), &fake);
+        fake.clone().stop().unwrap();
+        assert_eq!(
+            install(&fixture, &Config::default(), &fake),
+            vec![Action::StartService]
+        );
+    }
+
+    #[test]
+    fn changes_restart_the_running_service() {
+        let fixture = fixture();
+        let fake = FakeServiceManager::new();
+        install(&fixture, &Config::default(), &fake);
+
+        fs::write(&fixture.source, b"binary v2").unwrap();
+        let mut config = Config::default();
+        config.log.level = log::LevelFilter::Debug;
+        let actions = install(&fixture, &config, &fake);
+        assert_eq!(actions.first(), Some(&Action::StopService));
+        assert_eq!(actions.last(), Some(&Action::StartService));
+        assert_eq!(actions.len(), 4);
+        assert_eq!(
+            fs::read(fixture.options.install_dir.join("deskhub")).unwrap(),
+            b"binary v2"
+        );
+        assert_eq!(fake.status(), ServiceStatus::Running);
+    }
+
+    #[test]
+    fn service_registered_elsewhere_is_repointed() {
+        let fixture = fixture();
+        let fake = FakeServiceManager::new();
+        fake.clone()
+            .register(
+                service::SERVICE_DISPLAY_NAME,
+                "\"/home/me/deskhub\" run-service",
+            )
+            .unwrap();
+        let actions = install(&fixture, &Config::default(), &fake);
+        let target = fixture.options.install_dir.join("deskhub");
+        assert!(actions.contains(&Action::UpdateService {
+            command_line: service::command_line_for(&target),
+        }));
+        let registrations = fake
+            .calls()
+            .iter()
+            .filter(|call| **call == Operation::Register)
+            .count();
+        assert_eq!(registrations, 1);
+        assert_eq!(
+            fake.command_line(),
+            Some(service::command_line_for(&target))
+        );
+    }
+
+    #[test]
+    fn planning_does_not_touch_anything() {
+        let fixture = fixture();
+        let fake = FakeServiceManager::new();
+        let mut manager = fake.clone();
+        plan(
+            &fixture.source,
+            &Config::default(),
+            &fixture.options,
+            &mut manager,
+        )
+        .unwrap();
+        assert!(!fixture.options.install_dir.exists());
+        assert!(!fixture.options.config_path.exists());
+        assert_eq!(fake.calls(), vec![Operation::Open]);
+    }
+
+    #[test]
+    fn service_manager_errors_stop_planning() {
+        let fixture = fixture();
+        let fake = FakeServiceManager::new();
+        fake.fail(Operation::Open, ServiceError::AccessDenied);
+        let mut manager = fake.clone();
+        let err = plan(
+            &fixture.source,
+            &Config::default(),
+            &fixture.options,
+            &mut manager,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            InstallError::Service(ServiceError::AccessDenied)
+        ));
+    }
+}
@@ -0,0 +1,535 @@
+//Local channel between the service and the desktop process it launches: a Unix domain socket on
+//Linux, a named pipe on Windows. The desktop process asks and the service answers; the service
+//also sends events unasked. Every connection is vetted by the OS-reported peer before anything
+//but the handshake is read from it.
+pub mod protocol;
+
+use crate::config::Config;
+use crate::hub::identity::Identity;
+use crate::hub::protocol::HubMessage;
+use crate::hub::DesktopLink;
+use crate::supervisor::DesktopStateFn;
+use protocol::{read_frame, write_frame, Event, Frame, Request, Response, ServiceStatus};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+
+#[cfg(target_os = "linux")]
+use crate::linux::ipc as platform;
+#[cfg(target_os = "windows")]
+use crate::win32::ipc as platform;
+
+#[cfg(target_os = "windows")]
+static IPC_PATH: &str = "\\\\.\\pipe\\deskhub";
+#[cfg(target_os = "linux")]
+static IPC_PATH: &str = "/run/deskhub/ipc.sock";
+
+//Hub messages queued in either direction before the sender waits or, for events, drops them.
+const HUB_QUEUE: usize = 64;
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+pub fn default_path() -> PathBuf {
+    PathBuf::from(IPC_PATH)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpcError {
+    Io(String),
+    //The other end broke the protocol.
+    Protocol(String),
+    //The service would not let this process in.
+    Rejected(String),
+    Closed,
+}
+
+impl fmt::Display for IpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpcError::Io(e) => write!(f, "{}", e),
+            IpcError::Protocol(e) => write!(f, "protocol error: {}", e),
+            IpcError::Rejected(reason) => write!(f, "rejected by the service: {}", reason),
+            IpcError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl From<io::Error> for IpcError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset => IpcError::Closed,
+            _ => IpcError::Io(e.to_string()),
+        }
+    }
+}
+
+//The process at the other end of a connection, as the OS reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peer {
+    pub pid: u32,
+}
+
+//Decides whether a peer may talk to the service; the error is sent back as the reason.
+pub type Authorize = Arc<dyn Fn(&Peer) -> Result<(), String> + Send + Sync>;
+
+pub type StatusFn = Arc<dyn Fn() -> ServiceStatus + Send + Sync>;
+
+//Only the desktop process the supervisor launched may connect. Anything else running in the user's
+//session could reach the socket too, but must not speak for the desktop.
+pub fn desktop_only(desktop_state: DesktopStateFn) -> Authorize {
+    Arc::new(move |peer| match desktop_state().pid {
+        Some(pid) if pid == peer.pid => Ok(()),
+        Some(_) => Err(format!("process {} is not the desktop process", peer.pid)),
+        None => Err("the desktop process is not running".to_string()),
+    })
+}
+
+//The identity is read on every request because enrolling does not restart the service.
+pub fn service_status(config: &Config, desktop_state: DesktopStateFn) -> StatusFn {
+    let hub_url = config.hub.url.clone();
+    let identity_path = config.hub.identity_path.clone();
+    Arc::new(move || ServiceStatus {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        hub_url: hub_url.clone(),
+        agent_id: Identity::load(&identity_path)
+            .ok()
+            .map(|identity| identity.agent_id),
+        desktop: desktop_state(),
+    })
+}
+
+pub fn bind(path: &Path) -> io::Result<platform::Listener> {
+    platform::bind(path)
+}
+
+//Frames from the other end as they arrive. Reading in a task of its own keeps select! from
+//dropping a half-read frame; dropping this stops the task, which closes the stream.
+struct Incoming {
+    frames: mpsc::Receiver<Result<Frame, IpcError>>,
+    task: JoinHandle<()>,
+}
+
+impl Incoming {
+    fn spawn<R: AsyncRead + Send + Unpin + 'static>(mut reader: R) -> Self {
+        let (tx, frames) = mpsc::channel(16);
+        let task = tokio::spawn(async move {
+            loop {
+                let frame = match read_frame(&mut reader).await {
+                    Ok(Some(frame)) => Ok(frame),
+                    Ok(None) => return,
+                    Err(e) => Err(e),
+                };
+                let failed = frame.is_err();
+                if tx.send(frame).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Incoming { frames, task }
+    }
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[derive(Clone)]
+pub struct Server {
+    authorize: Authorize,
+    status: StatusFn,
+    to_hub: mpsc::Sender<HubMessage>,
+    from_hub: broadcast::Sender<HubMessage>,
+}
+
+//The server and the other end of its hub traffic, for hub::run.
+pub fn server(authorize: Authorize, status: StatusFn) -> (Server, DesktopLink) {
+    let (to_hub, outgoing) = mpsc::channel(HUB_QUEUE);
+    let (incoming, _) = broadcast::channel(HUB_QUEUE);
+    let server = Server {
+        authorize,
+        status,
+        to_hub,
+        from_hub: incoming.clone(),
+    };
+    (server, DesktopLink { outgoing, incoming })
+}
+
+impl Server {
+    //Accept connections until `stop` changes, each served by a task of its own.
+    pub async fn run(self, mut listener: platform::Listener, mut stop: watch::Receiver<bool>) {
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        tokio::spawn(self.clone().serve(stream, peer, stop.clone()));
+                    }
+                    Err(e) => {
+                        log::warn!("failed to accept an IPC connection: {}", e);
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    }
+                },
+                _ = stop.changed() => return,
+            }
+        }
+    }
+
+    async fn serve<S>(self, stream: S, peer: Peer, stop: watch::Receiver<bool>)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        match self.connection(stream, peer, stop).await {
+            Ok(()) => log::info!("process {} disconnected", peer.pid),
+            Err(IpcError::Rejected(reason)) => {
+                log::warn!("turned away process {}: {}", peer.pid, reason)
+            }
+            Err(e) => log::warn!("dropped process {}: {}", peer.pid, e),
+        }
+    }
+
+    async fn connection<S>(
+        &self,
+        stream: S,
+        peer: Peer,
+        mut stop: watch::Receiver<bool>,
+    ) -> Result<(), IpcError>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let (id, version) = match read_frame(&mut reader).await? {
+            Some(Frame::Request {
+                id,
+                request: Request::Hello { version },
+            }) => (id, version),
+            Some(frame) => {
+                return Err(IpcError::Protocol(format!(
+                    "expected hello, got {:?}",
+                    frame
+                )))
+            }
+            None => return Err(IpcError::Closed),
+        };
+        let verdict = (self.authorize)(&peer).and_then(|()| match version {
+            protocol::PROTOCOL_VERSION => Ok(()),
+            _ => Err(format!("unsupported protocol version {}", version)),
+        });
+        let response = match &verdict {
+            Ok(()) => Response::Welcome {
+                version: protocol::PROTOCOL_VERSION,
+            },
+            Err(reason) => Response::Rejected {
+                reason: reason.clone(),
+            },
+        };
+        write_frame(&mut writer, &Frame::Response { id, response }).await?;
+        verdict.map_err(IpcError::Rejected)?;
+        log::info!("process {} connected", peer.pid);
+
+        let mut incoming = Incoming::spawn(reader);
+        let mut from_hub = self.from_hub.subscribe();
+        loop {
+            let frame = tokio::select! {
+                frame = incoming.frames.recv() => match frame {
+                    Some(Ok(Frame::Request { id, request })) => Frame::Response {
+                        id,
+                        response: self.answer(request).await,
+                    },
+                    Some(Ok(frame)) => {
+                        return Err(IpcError::Protocol(format!("unexpected {:?}", frame)))
+                    }
+                    Some(Err(e)) => return Err(e),
+                    None => return Ok(()),
+                },
+                message = from_hub.recv() => match message {
+                    Ok(message) => Frame::Event {
+                        event: Event::Hub {
+                            message: Box::new(message),
+                        },
+                    },
+                    Err(RecvError::Lagged(missed)) => {
+                        log::warn!("process {} missed {} hub messages", peer.pid, missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                _ = stop.changed() => {
+                    let stopping = Frame::Event {
+                        event: Event::Stopping,
+                    };
+                    return write_frame(&mut writer, &stopping).await;
+                }
+            };
+            write_frame(&mut writer, &frame).await?;
+        }
+    }
+
+    async fn answer(&self, request: Request) -> Response {
+        match request {
+            Request::Hello { .. } => Response::Failed {
+                reason: "already connected".to_string(),
+            },
+            Request::Status => Response::Status((self.status)()),
+            Request::SessionEnded { viewers, reason } => match self
+                .to_hub
+                .send(HubMessage::SessionEnded { viewers, reason })
+                .await
+            {
+                Ok(()) => Response::Done,
+                //hub::run is not running, or gave up for want of an identity.
+                Err(_) => Response::Failed {
+                    reason: "the agent is not managed by a hub".to_string(),
+                },
+            },
+        }
+    }
+}
+
+type Pending = (Request, oneshot::Sender<Response>);
+
+//The desktop process's end. Clones share one connection.
+#[derive(Debug, Clone)]
+pub struct Client {
+    requests: mpsc::Sender<Pending>,
+    events: Arc<Mutex<mpsc::UnboundedReceiver<Event>>>,
+}
+
+impl Client {
+    pub async fn connect(path: PathBuf) -> Result<Client, IpcError> {
+        let stream = platform::connect(&path).await?;
+        Client::start(stream).await
+    }
+
+    async fn start<S>(stream: S) -> Result<Client, IpcError>
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let hello = Frame::Request {
+            id: 0,
+            request: Request::Hello {
+                version: protocol::PROTOCOL_VERSION,
+            },
+        };
+        write_frame(&mut writer, &hello).await?;
+        let mut incoming = Incoming::spawn(reader);
+        match incoming.frames.recv().await {
+            Some(Ok(Frame::Response {
+                response: Response::Welcome { .. },
+                ..
+            })) => {}
+            Some(Ok(Frame::Response {
+                response: Response::Rejected { reason },
+                ..
+            })) => return Err(IpcError::Rejected(reason)),
+            Some(Ok(frame)) => {
+                return Err(IpcError::Protocol(format!(
+                    "expected welcome, got {:?}",
+                    frame
+                )))
+            }
+            Some(Err(e)) => return Err(e),
+            None => return Err(IpcError::Closed),
+        }
+        let (requests_tx, requests) = mpsc::channel(16);
+        let (events_tx, events) = mpsc::unbounded_channel();
+        tokio::spawn(run_client(incoming, writer, requests, events_tx));
+        Ok(Client {
+            requests: requests_tx,
+            events: Arc::new(Mutex::new(events)),
+        })
+    }
+
+    pub async fn request(&self, request: Request) -> Result<Response, IpcError> {
+        let (reply, response) = oneshot::channel();
+        self.requests
+            .send((request, reply))
+            .await
+            .map_err(|_| IpcError::Closed)?;
+        response.await.map_err(|_| IpcError::Closed)
+    }
+
+    //The next event from the service, if one has arrived.
+    pub fn try_event(&self) -> Option<Event> {
+        self.events.lock().unwrap().try_recv().ok()
+    }
+}
+
+//Writes requests and matches responses to them until the service or every Client goes away.
+async fn run_client<W: AsyncWrite + Unpin>(
+    mut incoming: Incoming,
+    mut writer: W,
+    mut requests: mpsc::Receiver<Pending>,
+    events: mpsc::UnboundedSender<Event>,
+) {
+    let mut pending: HashMap<u64, oneshot::Sender<Response>> = HashMap::new();
+    let mut next_id = 1;
+    loop {
+        tokio::select! {
+            request = requests.recv() => {
+                let Some((request, reply)) = request else {
+                    return;
+                };
+                let id = next_id;
+                next_id += 1;
+                if let Err(e) = write_frame(&mut writer, &Frame::Request { id, request }).await {
+                    log::warn!("lost the connection to the service: {}", e);
+                    return;
+                }
+                pending.insert(id, reply);
+            }
+            frame = incoming.frames.recv() => match frame {
+                Some(Ok(Frame::Response { id, response })) => {
+                    if let Some(reply) = pending.remove(&id) {
+                        let _ = reply.send(response);
+                    }
+                }
+                Some(Ok(Frame::Event { event })) => {
+                    let _ = events.send(event);
+                }
+                Some(Ok(frame)) => log::warn!("ignoring {:?} from the service", frame),
+                Some(Err(e)) => {
+                    log::warn!("lost the connection to the service: {}", e);
+                    return;
+                }
+                None => return,
+            },
+        }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::supervisor::{DesktopPhase, DesktopState};
+
+    fn desktop(pid: Option<u32>) -> DesktopStateFn {
+        Arc::new(move || DesktopState {
+            phase: DesktopPhase::Running,
+            pid,
+            ..Default::default()
+        })
+    }
+
+    fn status() -> StatusFn {
+        Arc::new(|| ServiceStatus {
+            version: "1.2.3".to_string(),
+            hub_url: None,
+            agent_id: Some("agent-1".to_string()),
+            desktop: DesktopState::default(),
+        })
+    }
+
+    struct Running {
+        path: PathBuf,
+        link: DesktopLink,
+        stop: watch::Sender<bool>,
+        task: JoinHandle<()>,
+        _dir: tempfile::TempDir,
+    }
+
+    fn start_server(authorize: Authorize) -> Running {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ipc.sock");
+        let (server, link) = server(authorize, status());
+        let listener = bind(&path).unwrap();
+        let (stop, stop_rx) = watch::channel(false);
+        let task = tokio::spawn(server.run(listener, stop_rx));
+        Running {
+            path,
+            link,
+            stop,
+            task,
+            _dir: dir,
+        }
+    }
+
+    async fn next_event(client: &Client) -> Event {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(event) = client.try_event() {
+                    return event;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("no event arrived")
+    }
+
+    #[tokio::test]
+    async fn serves_the_desktop_process() {
+        let mut running = start_server(desktop_only(desktop(Some(std::process::id()))));
+        let client = Client::connect(running.path.clone()).await.unwrap();
+
+        assert_eq!(
+            client.request(Request::Status).await.unwrap(),
+            Response::Status((status())())
+        );
+        let ended = HubMessage::SessionEnded {
+            viewers: vec!["viewer-1".to_string()],
+            reason: "ended by the local user".to_string(),
+        };
+        let request = Request::SessionEnded {
+            viewers: vec!["viewer-1".to_string()],
+            reason: "ended by the local user".to_string(),
+        };
+        assert_eq!(client.request(request).await.unwrap(), Response::Done);
+        assert_eq!(running.link.outgoing.recv().await, Some(ended));
+
+        running.link.incoming.send(HubMessage::Pong).unwrap();
+        assert_eq!(
+            next_event(&client).await,
+            Event::Hub {
+                message: Box::new(HubMessage::Pong)
+            }
+        );
+
+        running.stop.send(true).unwrap();
+        assert_eq!(next_event(&client).await, Event::Stopping);
+        assert_eq!(client.request(Request::Status).await, Err(IpcError::Closed));
+        running.task.await.unwrap();
+        assert!(!running.path.exists());
+    }
+
+    #[tokio::test]
+    async fn turns_away_other_processes() {
+        let running = start_server(desktop_only(desktop(Some(std::process::id() + 1))));
+        let err = Client::connect(running.path.clone()).await.unwrap_err();
+        assert_eq!(
+            err,
+            IpcError::Rejected(format!(
+                "process {} is not the desktop process",
+                std::process::id()
+            ))
+        );
+
+        let running = start_server(desktop_only(desktop(None)));
+        let err = Client::connect(running.path.clone()).await.unwrap_err();
+        assert!(matches!(err, IpcError::Rejected(_)));
+    }
+
+    #[tokio::test]
+    async fn reports_a_missing_hub() {
+        let running = start_server(desktop_only(desktop(Some(std::process::id()))));
+        drop(running.link);
+        let client = Client::connect(running.path.clone()).await.unwrap();
+        let request = Request::SessionEnded {
+            viewers: vec!["viewer-1".to_string()],
+            reason: "ended by the local user".to_string(),
+        };
+        assert!(matches!(
+            client.request(request).await.unwrap(),
+            Response::Failed { .. }
+        ));
+    }
+}
@@ -0,0 +1,176 @@
+use super::IpcError;
+use crate::hub::protocol::HubMessage;
+use crate::supervisor::DesktopState;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+//Bumped whenever a message changes incompatibly. Both ends come from the same install, so a
+//mismatch means one of them was replaced under the other.
+pub const PROTOCOL_VERSION: u32 = 1;
+const MAX_FRAME: usize = 1024 * 1024;
+
+//From the desktop process to the service, each answered by one Response with the same id.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    //First request on every connection; nothing else is answered before it.
+    Hello {
+        version: u32,
+    },
+    Status,
+    //The local user disconnected these viewers; passed on to the hub. The desktop process may
+    //only tell the hub what has a request of its own here.
+    SessionEnded {
+        viewers: Vec<String>,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Welcome { version: u32 },
+    Status(ServiceStatus),
+    Done,
+    Failed { reason: String },
+    //The service will not talk to this process and closes the connection.
+    Rejected { reason: String },
+}
+
+//From the service, whenever something happens.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    //The service is shutting down and closes the connection.
+    Stopping,
+    //A hub message the service does not act on itself.
+    Hub { message: Box<HubMessage> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub version: String,
+    //None when no hub is configured.
+    pub hub_url: Option<String>,
+    //None until the agent is enrolled.
+    pub agent_id: Option<String>,
+    pub desktop: DesktopState,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "frame", rename_all = "snake_case")]
+pub enum Frame {
+    Request { id: u64, request: Request },
+    Response { id: u64, response: Response },
+    Event { event: Event },
+}
+
+//Each frame is a JSON object preceded by its length as a big-endian u32.
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    frame: &Frame,
+) -> Result<(), IpcError> {
+    let json = serde_json::to_vec(frame).expect("IPC frames always serialize");
+    let mut bytes = Vec::with_capacity(4 + json.len());
+    bytes.extend_from_slice(&(json.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(&json);
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+//The next frame, or None if the stream ended cleanly between frames.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Frame>, IpcError> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME {
+        return Err(IpcError::Protocol(format!(
+            "frame of {} bytes exceeds {}",
+            length, MAX_FRAME
+        )));
+    }
+    let mut json = vec![0; length];
+    reader.read_exact(&mut json).await?;
+    serde_json::from_slice(&json)
+        .map(Some)
+        .map_err(|e| IpcError::Protocol(format!("bad frame: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn frame_json_shape() {
+        let frame = Frame::Request {
+            id: 2,
+            request: Request::SessionEnded {
+                viewers: vec!["viewer-1".to_string()],
+                reason: "ended by the local user".to_string(),
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&frame).unwrap(),
+            json!({
+                "frame": "request",
+                "id": 2,
+                "request": {
+                    "type": "session_ended",
+                    "viewers": ["viewer-1"],
+                    "reason": "ended by the local user"
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn frames_round_trip() {
+        let (mut a, mut b) = tokio::io::duplex(4096);
+        let frames = [
+            Frame::Request {
+                id: 1,
+                request: Request::Hello {
+                    version: PROTOCOL_VERSION,
+                },
+            },
+            Frame::Response {
+                id: 1,
+                response: Response::Status(ServiceStatus {
+                    version: "1.2.3".to_string(),
+                    hub_url: Some("wss://hub.example.com/agent".to_string()),
+                    agent_id: None,
+                    desktop: DesktopState::default(),
+                }),
+            },
+            Frame::Event {
+                event: Event::Stopping,
+            },
+        ];
+        for frame in &frames {
+            write_frame(&mut a, frame).await.unwrap();
+        }
+        drop(a);
+        for frame in frames {
+            assert_eq!(read_frame(&mut b).await.unwrap(), Some(frame));
+        }
+        assert_eq!(read_frame(&mut b).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_frames() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+        a.write_all(&(MAX_FRAME as u32 + 1).to_be_bytes())
+            .await
+            .unwrap();
+        assert!(matches!(
+            read_frame(&mut b).await,
+            Err(IpcError::Protocol(_))
+        ));
+    }
+}
@@ -0,0 +1,290 @@
+use crate::capture::{CaptureError, Capturer, Frame, Monitor, PixelFormat};
+use std::fmt::Display;
+use std::io;
+use std::ptr;
+use std::time::Instant;
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::randr::ConnectionExt as _;
+use x11rb::protocol::shm::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{ConnectionExt as _, ImageFormat, ImageOrder, Window};
+use x11rb::rust_connection::RustConnection;
+
+fn backend_error(e: impl Display) -> CaptureError {
+    CaptureError::Backend(e.to_string())
+}
+
+//A SysV shared memory segment the X server copies the screen into (MIT-SHM).
+struct SharedSegment {
+    seg: shm::Seg,
+    address: *mut u8,
+    size: usize,
+}
+
+//The mapping is private to the capturer that owns it.
+unsafe impl Send for SharedSegment {}
+
+//Captures the root window of an X11 display, through MIT-SHM when the server is local.
+pub struct X11Capturer {
+    connection: RustConnection,
+    root: Window,
+    screen_width: u32,
+    screen_height: u32,
+    segment: Option<SharedSegment>,
+    //Cleared after the first failed attach, e.g. on a remote display; core GetImage is used instead.
+    use_shm: bool,
+}
+
+impl X11Capturer {
+    //`display` is an X display name like ":0"; None uses $DISPLAY.
+    pub fn connect(display: Option<&str>) -> Result<Self, CaptureError> {
+        let (connection, screen_num) =
+            x11rb::connect(display).map_err(|e| CaptureError::Unavailable(e.to_string()))?;
+        let setup = connection.setup();
+        let screen = &setup.roots[screen_num];
+        let bits_per_pixel = setup
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == screen.root_depth)
+            .map(|format| format.bits_per_pixel);
+        //32-bit little-endian pixels are BGRX in memory, which is all the pipeline handles.
+        if bits_per_pixel != Some(32) || setup.image_byte_order != ImageOrder::LSB_FIRST {
+            return Err(CaptureError::UnsupportedFormat(format!(
+                "depth {} with {:?} bits per pixel, {:?}",
+                screen.root_depth, bits_per_pixel, setup.image_byte_order
+            )));
+        }
+        let root = screen.root;
+        let (screen_width, screen_height) = (
+            screen.width_in_pixels as u32,
+            screen.height_in_pixels as u32,
+        );
+        let use_shm = connection
+            .extension_information(shm::X11_EXTENSION_NAME)
+            .map_err(backend_error)?
+            .is_some();
+        Ok(X11Capturer {
+            connection,
+            root,
+            screen_width,
+            screen_height,
+            segment: None,
+            use_shm,
+        })
+    }
+
+    fn attach_segment(&mut self, size: usize) -> Result<SharedSegment, CaptureError> {
+        let shmid = unsafe { libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600) };
+        if shmid < 0 {
+            return Err(backend_error(io::Error::last_os_error()));
+        }
+        let address = unsafe { libc::shmat(shmid, ptr::null(), 0) };
+        //Marked for removal right away; the kernel frees it once we and the server detach.
+        unsafe { libc::shmctl(shmid, libc::IPC_RMID, ptr::null_mut()) };
+        if address as isize == -1 {
+            return Err(backend_error(io::Error::last_os_error()));
+        }
+        let attached = self
+            .connection
+            .generate_id()
+            .map_err(backend_error)
+            .and_then(|seg| {
+                self.connection
+                    .shm_attach(seg, shmid as u32, false)
+                    .map_err(backend_error)?
+                    .check()
+                    .map_err(backend_error)?;
+                Ok(seg)
+            });
+        match attached {
+            Ok(seg) => Ok(SharedSegment {
+                seg,
+                address: address as *mut u8,
+                size,
+            }),
+            Err(e) => {
+                unsafe { libc::shmdt(address) };
+                Err(e)
+            }
+        }
+    }
+
+    fn detach_segment(&mut self) {
+        if let Some(segment) = self.segment.take() {
+            let _ = self.connection.shm_detach(segment.seg);
+            let _ = self.connection.flush();
+            unsafe { libc::shmdt(segment.address as *const libc::c_void) };
+        }
+    }
+
+    fn capture_shm(&mut self, monitor: &Monitor, size: usize) -> Result<Vec<u8>, CaptureError> {
+        if self
+            .segment
+            .as_ref()
+            .is_none_or(|segment| segment.size < size)
+        {
+            self.detach_segment();
+            self.segment = Some(self.attach_segment(size)?);
+        }
+        let segment = self.segment.as_ref().unwrap();
+        let reply = self
+            .connection
+            .shm_get_image(
+                self.root,
+                monitor.x as i16,
+                monitor.y as i16,
+                monitor.width as u16,
+                monitor.height as u16,
+                !0,
+                ImageFormat::Z_PIXMAP.into(),
+                segment.seg,
+                0,
+            )
+            .map_err(backend_error)?
+            .reply()
+            .map_err(backend_error)?;
+        if (reply.size as usize) < size {
+            return Err(backend_error(format!(
+                "short image from the X server: {} of {} bytes",
+                reply.size, size
+            )));
+        }
+        Ok(unsafe { std::slice::from_raw_parts(segment.address, size) }.to_vec())
+    }
+
+    fn capture_core(&self, monitor: &Monitor) -> Result<Vec<u8>, CaptureError> {
+        let reply = self
+            .connection
+            .get_image(
+                ImageFormat::Z_PIXMAP,
+                self.root,
+                monitor.x as i16,
+                monitor.y as i16,
+                monitor.width as u16,
+                monitor.height as u16,
+                !0,
+            )
+            .map_err(backend_error)?
+            .reply()
+            .map_err(backend_error)?;
+        Ok(reply.data)
+    }
+
+    fn screen_monitor(&self) -> Monitor {
+        Monitor {
+            id: 0,
+            name: "screen".to_string(),
+            x: 0,
+            y: 0,
+            width: self.screen_width,
+            height: self.screen_height,
+            primary: true,
+        }
+    }
+}
+
+impl Capturer for X11Capturer {
+    //RandR monitors when the server has them, otherwise the whole screen as one monitor.
+    fn monitors(&mut self) -> Result<Vec<Monitor>, CaptureError> {
+        let reply = match self
+            .connection
+            .randr_get_monitors(self.root, true)
+            .map(|cookie| cookie.reply())
+        {
+            Ok(Ok(reply)) if !reply.monitors.is_empty() => reply,
+            _ => return Ok(vec![self.screen_monitor()]),
+        };
+        let mut monitors = Vec::with_capacity(reply.monitors.len());
+        for (id, info) in reply.monitors.iter().enumerate() {
+            let name = match self.connection.get_atom_name(info.name) {
+                Ok(cookie) => cookie
+                    .reply()
+                    .map(|reply| String::from_utf8_lossy(&reply.name).into_owned())
+                    .unwrap_or_default(),
+                Err(_) => String::new(),
+            };
+            monitors.push(Monitor {
+                id: id as u32,
+                name,
+                x: info.x as i32,
+                y: info.y as i32,
+                width: info.width as u32,
+                height: info.height as u32,
+                primary: info.primary,
+            });
+        }
+        Ok(monitors)
+    }
+
+    fn capture(&mut self, monitor: &Monitor) -> Result<Frame, CaptureError> {
+        let inside = monitor.x >= 0
+            && monitor.y >= 0
+            && monitor.x as u32 + monitor.width <= self.screen_width
+            && monitor.y as u32 + monitor.height <= self.screen_height;
+        if !inside || monitor.width == 0 || monitor.height == 0 {
+            return Err(CaptureError::NoSuchMonitor(monitor.id));
+        }
+        let stride = monitor.width as usize * 4;
+        let size = stride * monitor.height as usize;
+        let mut data = None;
+        if self.use_shm {
+            match self.capture_shm(monitor, size) {
+                Ok(pixels) => data = Some(pixels),
+                Err(e) => {
+                    log::warn!("MIT-SHM capture failed, using GetImage: {}", e);
+                    self.detach_segment();
+                    self.use_shm = false;
+                }
+            }
+        }
+        let data = match data {
+            Some(data) => data,
+            None => self.capture_core(monitor)?,
+        };
+        Ok(Frame {
+            width: monitor.width,
+            height: monitor.height,
+            stride,
+            format: PixelFormat::Bgra,
+            data,
+            captured_at: Instant::now(),
+        })
+    }
+}
+
+impl Drop for X11Capturer {
+    fn drop(&mut self) {
+        self.detach_segment();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_display_is_unavailable() {
+        assert!(matches!(
+            X11Capturer::connect(Some(":4242")),
+            Err(CaptureError::Unavailable(_))
+        ));
+    }
+
+    #[test]
+    #[ignore = "needs an X server, e.g. xvfb-run cargo test -- --ignored"]
+    fn captures_every_monitor() {
+        let mut capturer = X11Capturer::connect(None).unwrap();
+        let monitors = capturer.monitors().unwrap();
+        assert!(!monitors.is_empty());
+        for monitor in &monitors {
+            let frame = capturer.capture(monitor).unwrap();
+            assert_eq!((frame.width, frame.height), (monitor.width, monitor.height));
+            assert_eq!(frame.data.len(), frame.stride * frame.height as usize);
+        }
+        //The core protocol path returns the same pixels as MIT-SHM.
+        let primary = crate::capture::primary(&monitors).unwrap().clone();
+        let shm = capturer.capture(&primary).unwrap();
+        capturer.use_shm = false;
+        let core = capturer.capture(&primary).unwrap();
+        assert_eq!(shm.data.len(), core.data.len());
+    }
+}
@@ -0,0 +1,613 @@
+use crate::clipboard::{Clipboard, ClipboardContent, ClipboardError};
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::xfixes::{self, ConnectionExt as _, SelectionEventMask};
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux, EventMask,
+    PropMode, Property, SelectionNotifyEvent, SelectionRequestEvent, Window, WindowClass,
+    SELECTION_NOTIFY_EVENT,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+use x11rb::NONE;
+
+fn backend_error(e: impl Display) -> ClipboardError {
+    ClipboardError::Backend(e.to_string())
+}
+
+//How long the clipboard owner gets to answer a conversion request.
+const CONVERT_TIMEOUT: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+//An incremental transfer whose requestor stops reading is dropped after this long.
+const INCR_TIMEOUT: Duration = Duration::from_secs(10);
+//Largest content taken in incrementally, the highest clipboard limit the config allows.
+const MAX_INCR_BYTES: usize = 64 << 20;
+
+#[derive(Debug, Clone, Copy)]
+struct Atoms {
+    clipboard: Atom,
+    targets: Atom,
+    utf8_string: Atom,
+    html: Atom,
+    png: Atom,
+    incr: Atom,
+    //Where conversions for us land.
+    property: Atom,
+}
+
+impl Atoms {
+    fn intern(connection: &RustConnection) -> Result<Self, ClipboardError> {
+        let names: [&[u8]; 7] = [
+            b"CLIPBOARD",
+            b"TARGETS",
+            b"UTF8_STRING",
+            b"text/html",
+            b"image/png",
+            b"INCR",
+            b"DESKHUB_CLIPBOARD",
+        ];
+        let cookies = names
+            .iter()
+            .map(|name| connection.intern_atom(false, name))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(backend_error)?;
+        let mut atoms = Vec::with_capacity(cookies.len());
+        for cookie in cookies {
+            atoms.push(cookie.reply().map_err(backend_error)?.atom);
+        }
+        Ok(Atoms {
+            clipboard: atoms[0],
+            targets: atoms[1],
+            utf8_string: atoms[2],
+            html: atoms[3],
+            png: atoms[4],
+            incr: atoms[5],
+            property: atoms[6],
+        })
+    }
+
+    fn target(&self, content: &ClipboardContent) -> Atom {
+        match content {
+            ClipboardContent::Text(_) => self.utf8_string,
+            ClipboardContent::Html(_) => self.html,
+            ClipboardContent::Image(_) => self.png,
+        }
+    }
+}
+
+//Browsers put text/html on the clipboard as UTF-16 with a byte order mark.
+fn decode_html(data: &[u8]) -> String {
+    let utf16 = |bytes: &[u8], little: bool| {
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|pair| match little {
+                true => u16::from_le_bytes([pair[0], pair[1]]),
+                false => u16::from_be_bytes([pair[0], pair[1]]),
+            })
+            .collect();
+        String::from_utf16_lossy(&units)
+    };
+    match data {
+        [0xff, 0xfe, rest @ ..] => utf16(rest, true),
+        [0xfe, 0xff, rest @ ..] => utf16(rest, false),
+        _ => String::from_utf8_lossy(data).into_owned(),
+    }
+}
+
+fn create_window(connection: &RustConnection, screen_num: usize) -> Result<Window, ClipboardError> {
+    let screen = &connection.setup().roots[screen_num];
+    let window = connection.generate_id().map_err(backend_error)?;
+    connection
+        .create_window(
+            0,
+            window,
+            screen.root,
+            0,
+            0,
+            1,
+            1,
+            0,
+            WindowClass::INPUT_ONLY,
+            0,
+            //Property changes pace incremental transfers.
+            &CreateWindowAux::new()
+                .event_mask(EventMask::STRUCTURE_NOTIFY | EventMask::PROPERTY_CHANGE),
+        )
+        .map_err(backend_error)?;
+    Ok(window)
+}
+
+//Owns the CLIPBOARD selection on behalf of the viewer and answers other clients' requests
+//for it, on a connection and thread of its own so that it never waits on the agent.
+struct Owner {
+    connection: Arc<RustConnection>,
+    window: Window,
+    contents: Arc<Mutex<Vec<ClipboardContent>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Owner {
+    fn start(display: Option<&str>) -> Result<Self, ClipboardError> {
+        let (connection, screen_num) = x11rb::connect(display).map_err(backend_error)?;
+        let atoms = Atoms::intern(&connection)?;
+        let window = create_window(&connection, screen_num)?;
+        connection.flush().map_err(backend_error)?;
+        let connection = Arc::new(connection);
+        let contents = Arc::new(Mutex::new(Vec::new()));
+        let thread = {
+            let (connection, contents) = (connection.clone(), contents.clone());
+            thread::Builder::new()
+                .name("clipboard".to_string())
+                .spawn(move || serve(&connection, window, atoms, &contents))
+                .map_err(backend_error)?
+        };
+        Ok(Owner {
+            connection,
+            window,
+            contents,
+            thread: Some(thread),
+        })
+    }
+
+    fn take(&self, contents: &[ClipboardContent], clipboard: Atom) -> Result<(), ClipboardError> {
+        *self.contents.lock().unwrap() = contents.to_vec();
+        self.connection
+            .set_selection_owner(self.window, clipboard, x11rb::CURRENT_TIME)
+            .map_err(backend_error)?;
+        self.connection.flush().map_err(backend_error)
+    }
+}
+
+impl Drop for Owner {
+    //Destroying the window also gives up the selection and ends the thread.
+    fn drop(&mut self) {
+        let _ = self.connection.destroy_window(self.window);
+        let _ = self.connection.flush();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+//Content too large for one request, sent a chunk at a time (ICCCM INCR): the requestor deletes
+//the property whenever it has read a chunk, and an empty chunk ends the transfer.
+struct Transfer {
+    requestor: Window,
+    property: Atom,
+    target: Atom,
+    data: Vec<u8>,
+    sent: usize,
+    updated: Instant,
+}
+
+//Bytes per property change: what one request can carry, less the request header.
+fn chunk_size(connection: &RustConnection) -> usize {
+    connection.maximum_request_bytes() - 32
+}
+
+fn serve(
+    connection: &RustConnection,
+    window: Window,
+    atoms: Atoms,
+    contents: &Mutex<Vec<ClipboardContent>>,
+) {
+    let mut transfers: Vec<Transfer> = Vec::new();
+    loop {
+        let event = match connection.wait_for_event() {
+            Ok(event) => event,
+            Err(e) => {
+                log::warn!("clipboard owner lost its X connection: {}", e);
+                return;
+            }
+        };
+        transfers.retain(|transfer| transfer.updated.elapsed() < INCR_TIMEOUT);
+        match event {
+            Event::SelectionRequest(request) => {
+                let contents = contents.lock().unwrap().clone();
+                if let Err(e) = answer(connection, &request, atoms, &contents, &mut transfers) {
+                    log::warn!("failed to answer a clipboard request: {}", e);
+                }
+            }
+            Event::PropertyNotify(notify) if notify.state == Property::DELETE => {
+                if let Err(e) = send_chunk(connection, &mut transfers, notify.window, notify.atom) {
+                    log::warn!("failed to send clipboard content: {}", e);
+                }
+            }
+            Event::SelectionClear(_) => contents.lock().unwrap().clear(),
+            Event::DestroyNotify(destroyed) if destroyed.window == window => return,
+            _ => {}
+        }
+    }
+}
+
+fn answer(
+    connection: &RustConnection,
+    request: &SelectionRequestEvent,
+    atoms: Atoms,
+    contents: &[ClipboardContent],
+    transfers: &mut Vec<Transfer>,
+) -> Result<(), ClipboardError> {
+    //Clients from before ICCCM 2 leave the property out and mean the target.
+    let property = match request.property {
+        NONE => request.target,
+        property => property,
+    };
+    let mut reply = property;
+    if request.target == atoms.targets {
+        let mut targets = vec![atoms.targets];
+        targets.extend(contents.iter().map(|content| atoms.target(content)));
+        connection
+            .change_property32(
+                PropMode::REPLACE,
+                request.requestor,
+                property,
+                AtomEnum::ATOM,
+                &targets,
+            )
+            .map_err(backend_error)?;
+    } else if let Some(content) = contents
+        .iter()
+        .find(|content| atoms.target(content) == request.target)
+    {
+        let data = content.bytes();
+        if data.len() <= chunk_size(connection) {
+            connection
+                .change_property8(
+                    PropMode::REPLACE,
+                    request.requestor,
+                    property,
+                    request.target,
+                    data,
+                )
+                .map_err(backend_error)?;
+        } else {
+            //Property deletions on the requestor's window ask for each following chunk.
+            connection
+                .change_window_attributes(
+                    request.requestor,
+                    &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+                )
+                .map_err(backend_error)?;
+            connection
+                .change_property32(
+                    PropMode::REPLACE,
+                    request.requestor,
+                    property,
+                    atoms.incr,
+                    &[data.len().min(u32::MAX as usize) as u32],
+                )
+                .map_err(backend_error)?;
+            transfers.retain(|transfer| {
+                (transfer.requestor, transfer.property) != (request.requestor, property)
+            });
+            transfers.push(Transfer {
+                requestor: request.requestor,
+                property,
+                target: request.target,
+                data: data.to_vec(),
+                sent: 0,
+                updated: Instant::now(),
+            });
+        }
+    } else {
+        reply = NONE;
+    }
+    let notify = SelectionNotifyEvent {
+        response_type: SELECTION_NOTIFY_EVENT,
+        sequence: 0,
+        time: request.time,
+        requestor: request.requestor,
+        selection: request.selection,
+        target: request.target,
+        property: reply,
+    };
+    connection
+        .send_event(false, request.requestor, EventMask::NO_EVENT, notify)
+        .map_err(backend_error)?;
+    connection.flush().map_err(backend_error)
+}
+
+//The next chunk of the transfer to `requestor`, if it just deleted the property one goes to.
+fn send_chunk(
+    connection: &RustConnection,
+    transfers: &mut Vec<Transfer>,
+    requestor: Window,
+    property: Atom,
+) -> Result<(), ClipboardError> {
+    let Some(index) = transfers
+        .iter()
+        .position(|transfer| (transfer.requestor, transfer.property) == (requestor, property))
+    else {
+        return Ok(());
+    };
+    let transfer = &mut transfers[index];
+    let end = (transfer.sent + chunk_size(connection)).min(transfer.data.len());
+    connection
+        .change_property8(
+            PropMode::REPLACE,
+            requestor,
+            property,
+            transfer.target,
+            &transfer.data[transfer.sent..end],
+        )
+        .map_err(backend_error)?;
+    if transfer.sent == end {
+        transfers.remove(index);
+        connection
+            .change_window_attributes(
+                requestor,
+                &ChangeWindowAttributesAux::new().event_mask(EventMask::NO_EVENT),
+            )
+            .map_err(backend_error)?;
+    } else {
+        transfer.sent = end;
+        transfer.updated = Instant::now();
+    }
+    connection.flush().map_err(backend_error)
+}
+
+//Reads and writes the X11 CLIPBOARD selection.
+pub struct X11Clipboard {
+    connection: RustConnection,
+    window: Window,
+    atoms: Atoms,
+    //Counts selection owner changes; without XFixes every call counts as one.
+    xfixes: bool,
+    sequence: u64,
+    owner: Owner,
+}
+
+impl X11Clipboard {
+    //`display` is an X display name like ":0"; None uses $DISPLAY.
+    pub fn connect(display: Option<&str>) -> Result<Self, ClipboardError> {
+        let (connection, screen_num) =
+            x11rb::connect(display).map_err(|e| ClipboardError::Unavailable(e.to_string()))?;
+        let atoms = Atoms::intern(&connection)?;
+        let window = create_window(&connection, screen_num)?;
+        let xfixes = connection
+            .extension_information(xfixes::X11_EXTENSION_NAME)
+            .map_err(backend_error)?
+            .is_some();
+        if xfixes {
+            connection
+                .xfixes_query_version(5, 0)
+                .map_err(backend_error)?
+                .reply()
+                .map_err(backend_error)?;
+            connection
+                .xfixes_select_selection_input(
+                    window,
+                    atoms.clipboard,
+                    SelectionEventMask::SET_SELECTION_OWNER
+                        | SelectionEventMask::SELECTION_WINDOW_DESTROY
+                        | SelectionEventMask::SELECTION_CLIENT_CLOSE,
+                )
+                .map_err(backend_error)?;
+        } else {
+            log::info!("the X server has no XFIXES extension, polling the clipboard");
+        }
+        connection.flush().map_err(backend_error)?;
+        let owner = Owner::start(display)?;
+        Ok(X11Clipboard {
+            connection,
+            window,
+            atoms,
+            xfixes,
+            sequence: 0,
+            owner,
+        })
+    }
+
+    fn handle(&mut self, event: &Event) {
+        if let Event::XfixesSelectionNotify(_) = event {
+            self.sequence += 1;
+        }
+    }
+
+    //The selection converted to `target`, or None if the owner cannot provide it.
+    fn convert(&mut self, target: Atom) -> Result<Option<(Atom, Vec<u8>)>, ClipboardError> {
+        self.connection
+            .convert_selection(
+                self.window,
+                self.atoms.clipboard,
+                target,
+                self.atoms.property,
+                x11rb::CURRENT_TIME,
+            )
+            .map_err(backend_error)?;
+        self.connection.flush().map_err(backend_error)?;
+        let notify = self.wait_for(|event| match event {
+            Event::SelectionNotify(notify) if notify.target == target => Some(*notify),
+            _ => None,
+        })?;
+        if notify.property == NONE {
+            return Ok(None);
+        }
+        let (type_, value) = self.take_property()?;
+        if type_ != self.atoms.incr {
+            return Ok(Some((type_, value)));
+        }
+        //Taking the INCR property asked for the first chunk; an empty one ends the transfer.
+        let (window, property) = (self.window, self.atoms.property);
+        let mut data = Vec::new();
+        loop {
+            self.wait_for(|event| match event {
+                Event::PropertyNotify(notify)
+                    if (notify.window, notify.atom, notify.state)
+                        == (window, property, Property::NEW_VALUE) =>
+                {
+                    Some(())
+                }
+                _ => None,
+            })?;
+            let (type_, chunk) = self.take_property()?;
+            if chunk.is_empty() {
+                return Ok(Some((type_, data)));
+            }
+            if data.len() + chunk.len() > MAX_INCR_BYTES {
+                return Err(ClipboardError::Backend(format!(
+                    "clipboard content exceeds {} bytes",
+                    MAX_INCR_BYTES
+                )));
+            }
+            data.extend_from_slice(&chunk);
+        }
+    }
+
+    //What `wanted` makes of the first event it accepts, handling those before it.
+    fn wait_for<T>(&mut self, wanted: impl Fn(&Event) -> Option<T>) -> Result<T, ClipboardError> {
+        let deadline = Instant::now() + CONVERT_TIMEOUT;
+        loop {
+            match self.connection.poll_for_event().map_err(backend_error)? {
+                Some(event) => match wanted(&event) {
+                    Some(value) => return Ok(value),
+                    None => self.handle(&event),
+                },
+                None if Instant::now() >= deadline => {
+                    return Err(ClipboardError::Backend(
+                        "the clipboard owner did not answer".to_string(),
+                    ))
+                }
+                None => thread::sleep(POLL_INTERVAL),
+            }
+        }
+    }
+
+    //Type and value of our conversion property, deleting it.
+    fn take_property(&mut self) -> Result<(Atom, Vec<u8>), ClipboardError> {
+        let reply = self
+            .connection
+            .get_property(
+                true,
+                self.window,
+                self.atoms.property,
+                AtomEnum::ANY,
+                0,
+                u32::MAX / 4,
+            )
+            .map_err(backend_error)?
+            .reply()
+            .map_err(backend_error)?;
+        Ok((reply.type_, reply.value))
+    }
+}
+
+impl Clipboard for X11Clipboard {
+    fn sequence(&mut self) -> Result<u64, ClipboardError> {
+        if !self.xfixes {
+            self.sequence += 1;
+            return Ok(self.sequence);
+        }
+        while let Some(event) = self.connection.poll_for_event().map_err(backend_error)? {
+            self.handle(&event);
+        }
+        Ok(self.sequence)
+    }
+
+    fn read(&mut self) -> Result<Vec<ClipboardContent>, ClipboardError> {
+        let owner = self
+            .connection
+            .get_selection_owner(self.atoms.clipboard)
+            .map_err(backend_error)?
+            .reply()
+            .map_err(backend_error)?
+            .owner;
+        if owner == NONE {
+            return Ok(Vec::new());
+        }
+        let Some((type_, data)) = self.convert(self.atoms.targets)? else {
+            return Ok(Vec::new());
+        };
+        if type_ != u32::from(AtomEnum::ATOM) {
+            return Err(ClipboardError::Backend(
+                "the clipboard owner sent malformed targets".to_string(),
+            ));
+        }
+        let targets: Vec<Atom> = data
+            .chunks_exact(4)
+            .map(|atom| u32::from_ne_bytes([atom[0], atom[1], atom[2], atom[3]]))
+            .collect();
+        let mut contents = Vec::new();
+        for target in [self.atoms.utf8_string, self.atoms.html, self.atoms.png] {
+            if !targets.contains(&target) {
+                continue;
+            }
+            let Some((_, data)) = self.convert(target)? else {
+                continue;
+            };
+            contents.push(match target {
+                t if t == self.atoms.utf8_string => {
+                    ClipboardContent::Text(String::from_utf8_lossy(&data).into_owned())
+                }
+                t if t == self.atoms.html => ClipboardContent::Html(decode_html(&data)),
+                _ => ClipboardContent::Image(data),
+            });
+        }
+        Ok(contents)
+    }
+
+    fn write(&mut self, contents: &[ClipboardContent]) -> Result<(), ClipboardError> {
+        self.owner.take(contents, self.atoms.clipboard)
+    }
+}
+
+impl Drop for X11Clipboard {
+    fn drop(&mut self) {
+        let _ = self.connection.destroy_window(self.window);
+        let _ = self.connection.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_display_is_unavailable() {
+        assert!(matches!(
+            X11Clipboard::connect(Some(":4242")),
+            Err(ClipboardError::Unavailable(_))
+        ));
+    }
+
+    #[test]
+    fn decodes_html_in_either_encoding() {
+        assert_eq!(decode_html(b"<b>hi</b>"), "<b>hi</b>");
+        assert_eq!(decode_html(&[0xff, 0xfe, b'<', 0, b'p', 0, b'>', 0]), "<p>");
+        assert_eq!(decode_html(&[0xfe, 0xff, 0, b'<', 0, b'p', 0, b'>']), "<p>");
+    }
+
+    #[test]
+    #[ignore = "needs an X server, e.g. xvfb-run cargo test -- --ignored"]
+    fn round_trips_through_the_display() {
+        let mut writer = X11Clipboard::connect(None).unwrap();
+        let mut reader = X11Clipboard::connect(None).unwrap();
+        let before = reader.sequence().unwrap();
+        let contents = vec![
+            ClipboardContent::Text("echo héllo".to_string()),
+            ClipboardContent::Html("<b>echo</b>".to_string()),
+            ClipboardContent::Image(vec![0x89, b'P', b'N', b'G']),
+        ];
+        writer.write(&contents).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_ne!(reader.sequence().unwrap(), before);
+        assert_eq!(reader.read().unwrap(), contents);
+    }
+
+    #[test]
+    #[ignore = "needs an X server, e.g. xvfb-run cargo test -- --ignored"]
+    fn large_content_goes_incrementally() {
+        let mut writer = X11Clipboard::connect(None).unwrap();
+        let mut reader = X11Clipboard::connect(None).unwrap();
+        let image: Vec<u8> = (0..8 << 20).map(|i| (i % 251) as u8).collect();
+        assert!(image.len() > chunk_size(&writer.connection));
+        let contents = vec![ClipboardContent::Image(image)];
+        writer.write(&contents).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(reader.read().unwrap(), contents);
+    }
+}
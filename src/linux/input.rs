@@ -0,0 +1,324 @@
+use crate::input::layout::char_keysym;
+use crate::input::{Button, Injector, InputError, Key};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::thread;
+use std::time::Duration;
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::xproto::{
+    ConnectionExt as _, Keycode, Keysym, Window, BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT,
+    KEY_PRESS_EVENT, KEY_RELEASE_EVENT, MOTION_NOTIFY_EVENT,
+};
+use x11rb::protocol::xtest::{self, ConnectionExt as _};
+use x11rb::rust_connection::RustConnection;
+
+fn backend_error(e: impl Display) -> InputError {
+    InputError::Backend(e.to_string())
+}
+
+//evdev codes sit this far below X keycodes under the evdev and libinput drivers.
+const EVDEV_OFFSET: u16 = 8;
+//Clients refetch the keymap when told it changed; give them time before and after each use.
+const REMAP_DELAY: Duration = Duration::from_millis(10);
+
+//Keysym to the keycode that produces it at the lowest shift level, from a GetKeyboardMapping reply.
+fn keysym_table(
+    min_keycode: Keycode,
+    keysyms_per_keycode: u8,
+    keysyms: &[Keysym],
+) -> HashMap<Keysym, Keycode> {
+    let mut table = HashMap::new();
+    if keysyms_per_keycode == 0 {
+        return table;
+    }
+    for level in 0..keysyms_per_keycode as usize {
+        for (offset, row) in keysyms.chunks(keysyms_per_keycode as usize).enumerate() {
+            let Some(&keysym) = row.get(level) else {
+                continue;
+            };
+            let keycode = min_keycode as usize + offset;
+            if keysym != 0 && keycode <= Keycode::MAX as usize {
+                table.entry(keysym).or_insert(keycode as Keycode);
+            }
+        }
+    }
+    table
+}
+
+//The highest keycode without any keysyms, free to borrow for typing text.
+fn spare_keycode(
+    min_keycode: Keycode,
+    keysyms_per_keycode: u8,
+    keysyms: &[Keysym],
+) -> Option<Keycode> {
+    if keysyms_per_keycode == 0 {
+        return None;
+    }
+    keysyms
+        .chunks(keysyms_per_keycode as usize)
+        .enumerate()
+        .rev()
+        .find(|(_, row)| row.iter().all(|keysym| *keysym == 0))
+        .and_then(|(offset, _)| Keycode::try_from(min_keycode as usize + offset).ok())
+}
+
+fn button_number(button: Button) -> u8 {
+    match button {
+        Button::Left => 1,
+        Button::Middle => 2,
+        Button::Right => 3,
+        Button::Back => 8,
+        Button::Forward => 9,
+    }
+}
+
+//X11 scrolls by clicking buttons 4-7, one click per notch.
+fn wheel_clicks(dx: i32, dy: i32) -> impl Iterator<Item = u8> {
+    let vertical = if dy < 0 { 4 } else { 5 };
+    let horizontal = if dx < 0 { 6 } else { 7 };
+    std::iter::repeat_n(vertical, dy.unsigned_abs() as usize)
+        .chain(std::iter::repeat_n(horizontal, dx.unsigned_abs() as usize))
+}
+
+//Injects input into an X11 display through the XTEST extension.
+pub struct XTestInjector {
+    connection: RustConnection,
+    root: Window,
+    min_keycode: Keycode,
+    max_keycode: Keycode,
+    keysyms: HashMap<Keysym, Keycode>,
+    spare: Option<Keycode>,
+    //Set while the spare keycode has a keysym of ours, to be cleared on drop.
+    remapped: bool,
+    //The keycode each held key went down on, so it comes up on the same one.
+    pressed: HashMap<Key, Keycode>,
+}
+
+impl XTestInjector {
+    //`display` is an X display name like ":0"; None uses $DISPLAY.
+    pub fn connect(display: Option<&str>) -> Result<Self, InputError> {
+        let (connection, screen_num) =
+            x11rb::connect(display).map_err(|e| InputError::Unavailable(e.to_string()))?;
+        if connection
+            .extension_information(xtest::X11_EXTENSION_NAME)
+            .map_err(backend_error)?
+            .is_none()
+        {
+            return Err(InputError::Unavailable(
+                "the X server has no XTEST extension".to_string(),
+            ));
+        }
+        let setup = connection.setup();
+        let root = setup.roots[screen_num].root;
+        let (min_keycode, max_keycode) = (setup.min_keycode, setup.max_keycode);
+        let mut injector = XTestInjector {
+            connection,
+            root,
+            min_keycode,
+            max_keycode,
+            keysyms: HashMap::new(),
+            spare: None,
+            remapped: false,
+            pressed: HashMap::new(),
+        };
+        injector.load_keyboard_mapping()?;
+        Ok(injector)
+    }
+
+    fn load_keyboard_mapping(&mut self) -> Result<(), InputError> {
+        let count = self.max_keycode - self.min_keycode + 1;
+        let reply = self
+            .connection
+            .get_keyboard_mapping(self.min_keycode, count)
+            .map_err(backend_error)?
+            .reply()
+            .map_err(backend_error)?;
+        self.keysyms = keysym_table(self.min_keycode, reply.keysyms_per_keycode, &reply.keysyms);
+        //Our own remapping makes the spare look taken; keep using it.
+        if !self.remapped {
+            self.spare = spare_keycode(self.min_keycode, reply.keysyms_per_keycode, &reply.keysyms);
+        }
+        Ok(())
+    }
+
+    //Prefer the keysym, so the host types what the viewer meant; fall back to the physical key.
+    fn keycode(&mut self, key: Key) -> Result<Keycode, InputError> {
+        if key.keysym != 0 {
+            if let Some(keycode) = self.keysyms.get(&key.keysym) {
+                return Ok(*keycode);
+            }
+            //The layout may have changed since we last looked.
+            self.load_keyboard_mapping()?;
+            if let Some(keycode) = self.keysyms.get(&key.keysym) {
+                return Ok(*keycode);
+            }
+        }
+        let keycode = key.scancode + EVDEV_OFFSET;
+        if key.scancode != 0
+            && (self.min_keycode as u16..=self.max_keycode as u16).contains(&keycode)
+        {
+            return Ok(keycode as Keycode);
+        }
+        Err(InputError::UnmappedKey(key))
+    }
+
+    fn fake_input(&self, type_: u8, detail: u8, x: i16, y: i16) -> Result<(), InputError> {
+        self.connection
+            .xtest_fake_input(type_, detail, x11rb::CURRENT_TIME, self.root, x, y, 0)
+            .map_err(backend_error)?;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), InputError> {
+        self.connection.flush().map_err(backend_error)
+    }
+
+    fn remap_spare(&mut self, keycode: Keycode, keysym: Keysym) -> Result<(), InputError> {
+        self.connection
+            .change_keyboard_mapping(1, keycode, 2, &[keysym, keysym])
+            .map_err(backend_error)?;
+        self.remapped = keysym != 0;
+        self.flush()
+    }
+}
+
+impl Injector for XTestInjector {
+    fn key(&mut self, key: Key, down: bool) -> Result<(), InputError> {
+        let keycode = match (down, self.pressed.get(&key)) {
+            (false, Some(keycode)) => *keycode,
+            _ => self.keycode(key)?,
+        };
+        if down {
+            self.pressed.insert(key, keycode);
+            self.fake_input(KEY_PRESS_EVENT, keycode, 0, 0)?;
+        } else {
+            self.pressed.remove(&key);
+            self.fake_input(KEY_RELEASE_EVENT, keycode, 0, 0)?;
+        }
+        self.flush()
+    }
+
+    fn pointer(&mut self, x: i32, y: i32) -> Result<(), InputError> {
+        let clamp = |value: i32| value.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        //Detail 0 makes the motion absolute, relative to the root window.
+        self.fake_input(MOTION_NOTIFY_EVENT, 0, clamp(x), clamp(y))?;
+        self.flush()
+    }
+
+    fn button(&mut self, button: Button, down: bool) -> Result<(), InputError> {
+        let type_ = if down {
+            BUTTON_PRESS_EVENT
+        } else {
+            BUTTON_RELEASE_EVENT
+        };
+        self.fake_input(type_, button_number(button), 0, 0)?;
+        self.flush()
+    }
+
+    fn wheel(&mut self, dx: i32, dy: i32) -> Result<(), InputError> {
+        for button in wheel_clicks(dx, dy) {
+            self.fake_input(BUTTON_PRESS_EVENT, button, 0, 0)?;
+            self.fake_input(BUTTON_RELEASE_EVENT, button, 0, 0)?;
+        }
+        self.flush()
+    }
+
+    //Each character goes through the spare keycode, mapped to its keysym on both levels.
+    fn text(&mut self, text: &str) -> Result<(), InputError> {
+        let spare = self
+            .spare
+            .ok_or_else(|| InputError::Backend("no free keycode to type text with".to_string()))?;
+        for c in text.chars() {
+            self.remap_spare(spare, char_keysym(c))?;
+            thread::sleep(REMAP_DELAY);
+            self.fake_input(KEY_PRESS_EVENT, spare, 0, 0)?;
+            self.fake_input(KEY_RELEASE_EVENT, spare, 0, 0)?;
+            self.flush()?;
+            thread::sleep(REMAP_DELAY);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for XTestInjector {
+    fn drop(&mut self) {
+        if let (true, Some(spare)) = (self.remapped, self.spare) {
+            let _ = self.remap_spare(spare, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_display_is_unavailable() {
+        assert!(matches!(
+            XTestInjector::connect(Some(":4242")),
+            Err(InputError::Unavailable(_))
+        ));
+    }
+
+    #[test]
+    fn keysyms_map_to_the_lowest_level() {
+        //Keycodes 10-12 with two levels: a/A, b/B, and A again on its own key.
+        let keysyms = [0x61, 0x41, 0x62, 0x42, 0x41, 0];
+        let table = keysym_table(10, 2, &keysyms);
+        assert_eq!(table.get(&0x61), Some(&10));
+        assert_eq!(table.get(&0x62), Some(&11));
+        assert_eq!(table.get(&0x41), Some(&12));
+        assert_eq!(table.get(&0x42), Some(&11));
+        assert_eq!(table.get(&0), None);
+    }
+
+    #[test]
+    fn finds_the_last_unmapped_keycode() {
+        let keysyms = [0x61, 0x41, 0, 0, 0x62, 0, 0, 0, 0x63, 0x43];
+        assert_eq!(spare_keycode(8, 2, &keysyms), Some(11));
+        assert_eq!(spare_keycode(8, 2, &[0x61, 0x41]), None);
+    }
+
+    #[test]
+    fn wheel_notches_become_button_clicks() {
+        let clicks = |dx, dy| wheel_clicks(dx, dy).collect::<Vec<u8>>();
+        assert_eq!(clicks(0, 2), vec![5, 5]);
+        assert_eq!(clicks(0, -1), vec![4]);
+        assert_eq!(clicks(-1, 1), vec![5, 6]);
+        assert_eq!(clicks(0, 0), Vec::<u8>::new());
+    }
+
+    #[test]
+    #[ignore = "needs an X server, e.g. xvfb-run cargo test -- --ignored"]
+    fn injects_into_the_display() {
+        let mut injector = XTestInjector::connect(None).unwrap();
+        injector.pointer(12, 34).unwrap();
+        let pointer = injector
+            .connection
+            .query_pointer(injector.root)
+            .unwrap()
+            .reply()
+            .unwrap();
+        assert_eq!((pointer.root_x, pointer.root_y), (12, 34));
+
+        let key = Key {
+            scancode: 30,
+            keysym: 0x61,
+        };
+        let keycode = injector.keycode(key).unwrap();
+        let held = |injector: &XTestInjector| {
+            let keys = injector
+                .connection
+                .query_keymap()
+                .unwrap()
+                .reply()
+                .unwrap()
+                .keys;
+            keys[keycode as usize / 8] & (1 << (keycode % 8)) != 0
+        };
+        injector.key(key, true).unwrap();
+        assert!(held(&injector));
+        injector.key(key, false).unwrap();
+        assert!(!held(&injector));
+    }
+}
@@ -0,0 +1,73 @@
+use crate::ipc::Peer;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use tokio::net::{UnixListener, UnixStream};
+
+//The socket is open to everyone so the desktop process, running as the console user, can reach it;
+//each connection is then vetted by the SO_PEERCRED credentials the kernel reports for it.
+pub struct Listener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+pub fn bind(path: &Path) -> io::Result<Listener> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    //Left behind when the service last stopped without cleaning up.
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+    Ok(Listener {
+        listener,
+        path: path.to_path_buf(),
+    })
+}
+
+impl Listener {
+    pub async fn accept(&mut self) -> io::Result<(UnixStream, Peer)> {
+        let (stream, _) = self.listener.accept().await?;
+        let pid = stream
+            .peer_cred()?
+            .pid()
+            .ok_or_else(|| io::Error::other("the kernel did not report the peer's process"))?;
+        Ok((stream, Peer { pid: pid as u32 }))
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub async fn connect(path: &Path) -> io::Result<UnixStream> {
+    UnixStream::connect(path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_the_connecting_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run/ipc.sock");
+        fs::create_dir(dir.path().join("run")).unwrap();
+        fs::write(&path, "stale").unwrap();
+        let mut listener = bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o666);
+
+        let (_client, accepted) = tokio::join!(connect(&path), listener.accept());
+        let (_stream, peer) = accepted.unwrap();
+        assert_eq!(peer.pid, std::process::id());
+        drop(listener);
+        assert!(!path.exists());
+    }
+}
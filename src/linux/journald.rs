@@ -0,0 +1,163 @@
+//Writes records to systemd-journald over its native protocol, so they keep their fields.
+use super::syslog::severity;
+use crate::logging::{self, LogContext, Process};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+
+pub const SOCKET: &str = "/run/systemd/journal/socket";
+
+pub struct Journald {
+    socket: UnixDatagram,
+    path: PathBuf,
+    process: Process,
+}
+
+impl Journald {
+    pub fn new(path: PathBuf, process: Process) -> io::Result<Self> {
+        Ok(Journald {
+            //Unconnected, so records still arrive after journald restarts.
+            socket: UnixDatagram::unbound()?,
+            path,
+            process,
+        })
+    }
+
+    pub fn output(self) -> fern::Output {
+        fern::Output::call(move |record| {
+            let entry = entry(self.process, &logging::context(), record);
+            if let Err(e) = self.socket.send_to(&entry, &self.path) {
+                eprintln!("failed to log to {}: {}", self.path.display(), e);
+            }
+        })
+    }
+}
+
+pub fn entry(process: Process, context: &LogContext, record: &log::Record) -> Vec<u8> {
+    let mut entry = Vec::new();
+    field(&mut entry, "MESSAGE", &record.args().to_string());
+    field(
+        &mut entry,
+        "PRIORITY",
+        &severity(record.level()).to_string(),
+    );
+    field(&mut entry, "SYSLOG_IDENTIFIER", "deskhub");
+    field(&mut entry, "CODE_MODULE", record.target());
+    if let Some(file) = record.file() {
+        field(&mut entry, "CODE_FILE", file);
+    }
+    if let Some(line) = record.line() {
+        field(&mut entry, "CODE_LINE", &line.to_string());
+    }
+    field(&mut entry, "DESKHUB_PROCESS", process.name());
+    if let Some(agent_id) = &context.agent_id {
+        field(&mut entry, "DESKHUB_AGENT_ID", agent_id);
+    }
+    if let Some(session_id) = &context.session_id {
+        field(&mut entry, "DESKHUB_SESSION_ID", session_id);
+    }
+    entry
+}
+
+//NAME=value, or for a value spanning lines NAME, its length as a little-endian u64, and the value.
+fn field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    //Reads an entry back the way journald does.
+    fn parse(mut entry: &[u8]) -> Vec<(String, String)> {
+        let mut fields = Vec::new();
+        while !entry.is_empty() {
+            let end = entry.iter().position(|&b| b == b'=' || b == b'\n').unwrap();
+            let name = String::from_utf8(entry[..end].to_vec()).unwrap();
+            let value;
+            if entry[end] == b'=' {
+                let line = entry[end..].iter().position(|&b| b == b'\n').unwrap() + end;
+                value = entry[end + 1..line].to_vec();
+                entry = &entry[line + 1..];
+            } else {
+                let length = u64::from_le_bytes(entry[end + 1..end + 9].try_into().unwrap());
+                let start = end + 9;
+                value = entry[start..start + length as usize].to_vec();
+                assert_eq!(entry[start + length as usize], b'\n');
+                entry = &entry[start + length as usize + 1..];
+            }
+            fields.push((name, String::from_utf8(value).unwrap()));
+        }
+        fields
+    }
+
+    fn value<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn carries_structured_fields() {
+        let context = LogContext {
+            agent_id: Some("agent-1".to_string()),
+            session_id: Some("2".to_string()),
+        };
+        let entry = entry(
+            Process::Service,
+            &context,
+            &log::Record::builder()
+                .args(format_args!("desktop exited\nwith code 3"))
+                .level(Level::Warn)
+                .target("deskhub::supervisor")
+                .file(Some("src/supervisor.rs"))
+                .line(Some(42))
+                .build(),
+        );
+        let fields = parse(&entry);
+        assert_eq!(
+            value(&fields, "MESSAGE"),
+            Some("desktop exited\nwith code 3")
+        );
+        assert_eq!(value(&fields, "PRIORITY"), Some("4"));
+        assert_eq!(value(&fields, "CODE_MODULE"), Some("deskhub::supervisor"));
+        assert_eq!(value(&fields, "CODE_FILE"), Some("src/supervisor.rs"));
+        assert_eq!(value(&fields, "CODE_LINE"), Some("42"));
+        assert_eq!(value(&fields, "DESKHUB_PROCESS"), Some("service"));
+        assert_eq!(value(&fields, "DESKHUB_AGENT_ID"), Some("agent-1"));
+        assert_eq!(value(&fields, "DESKHUB_SESSION_ID"), Some("2"));
+    }
+
+    #[test]
+    fn sends_to_the_journal_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal.socket");
+        let journal = UnixDatagram::bind(&path).unwrap();
+        let (_, logger) = fern::Dispatch::new()
+            .chain(Journald::new(path, Process::Service).unwrap().output())
+            .into_log();
+        logger.log(
+            &log::Record::builder()
+                .args(format_args!("service running"))
+                .level(Level::Info)
+                .target("deskhub::linux::service_ctrl")
+                .build(),
+        );
+        let mut datagram = vec![0; 4096];
+        let length = journal.recv(&mut datagram).unwrap();
+        let fields = parse(&datagram[..length]);
+        assert_eq!(value(&fields, "MESSAGE"), Some("service running"));
+        assert_eq!(value(&fields, "PRIORITY"), Some("6"));
+        assert_eq!(value(&fields, "SYSLOG_IDENTIFIER"), Some("deskhub"));
+    }
+}
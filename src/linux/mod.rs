@@ -0,0 +1,10 @@
+pub mod capture;
+pub mod clipboard;
+pub mod input;
+pub mod ipc;
+pub mod journald;
+pub mod presence;
+pub mod service;
+pub mod service_ctrl;
+pub mod session;
+pub mod syslog;
@@ -0,0 +1,28 @@
+use std::time::Duration;
+use x11rb::connection::Connection;
+use x11rb::protocol::screensaver::ConnectionExt as _;
+
+//Time since the last keyboard or mouse input on the X display, from the MIT-SCREEN-SAVER
+//extension. `display` is an X display name like ":0"; None uses $DISPLAY.
+pub fn idle_time(display: Option<&str>) -> Option<Duration> {
+    let (connection, screen_num) = x11rb::connect(display).ok()?;
+    let root = connection.setup().roots[screen_num].root;
+    let info = connection.screensaver_query_info(root).ok()?.reply().ok()?;
+    Some(Duration::from_millis(info.ms_since_user_input as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_display_is_unknown() {
+        assert_eq!(idle_time(Some(":4242")), None);
+    }
+
+    #[test]
+    #[ignore = "needs an X server, e.g. xvfb-run cargo test -- --ignored"]
+    fn reads_idle_time_from_the_display() {
+        assert!(idle_time(None).is_some());
+    }
+}
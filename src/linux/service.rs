@@ -0,0 +1,217 @@
+use crate::service::{ServiceError, ServiceManager, ServiceStatus};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+
+static SYSTEMD_UNIT_DIR: &str = "/etc/systemd/system";
+
+//A system service managed through a unit file and `systemctl`.
+#[derive(Debug, Clone)]
+pub struct SystemdService {
+    pub unit_name: String,
+    unit_dir: PathBuf,
+}
+
+//systemd expands `%` specifiers and backslash escapes in ExecStart, even inside quotes.
+fn exec_start(command_line: &str) -> String {
+    command_line.replace('\\', "\\\\").replace('%', "%%")
+}
+
+//The command line an ExecStart value was written from.
+fn exec_start_command_line(value: &str) -> String {
+    let mut command_line = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' | '\\' => command_line.push(chars.next().unwrap_or(c)),
+            c => command_line.push(c),
+        }
+    }
+    command_line
+}
+
+fn unit_file_contents(display_name: &str, command_line: &str) -> String {
+    format!(
+        "[Unit]\n\
+         Description={}\n\
+         After=network-online.target\n\
+         Wants=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=simple\n\
+         ExecStart={}\n\
+         Restart=on-failure\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        display_name,
+        exec_start(command_line)
+    )
+}
+
+fn io_service_error(err: io::Error) -> ServiceError {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => ServiceError::AccessDenied,
+        io::ErrorKind::AlreadyExists => ServiceError::AlreadyExists,
+        io::ErrorKind::NotFound => ServiceError::DoesNotExist,
+        _ => ServiceError::Os(err.raw_os_error().unwrap_or(-1) as i64, err.to_string()),
+    }
+}
+
+//systemctl reports failures only through its exit code and stderr text.
+fn systemctl_service_error(code: Option<i32>, stderr: &str) -> ServiceError {
+    let lower = stderr.to_lowercase();
+    if lower.contains("access denied") || lower.contains("authentication required") {
+        ServiceError::AccessDenied
+    } else if lower.contains("not found") || lower.contains("not loaded") {
+        ServiceError::DoesNotExist
+    } else if lower.contains("timed out") || lower.contains("timeout") {
+        ServiceError::Timeout
+    } else {
+        ServiceError::Os(code.unwrap_or(-1) as i64, stderr.trim().to_string())
+    }
+}
+
+fn systemctl(args: &[&str]) -> Result<String, ServiceError> {
+    let output = Command::new("systemctl")
+        .args(args)
+        .output()
+        .map_err(io_service_error)?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(systemctl_service_error(
+            output.status.code(),
+            &String::from_utf8_lossy(&output.stderr),
+        ))
+    }
+}
+
+impl SystemdService {
+    pub fn new(service_name: &str) -> Self {
+        SystemdService {
+            unit_name: format!("{}.service", service_name),
+            unit_dir: PathBuf::from(SYSTEMD_UNIT_DIR),
+        }
+    }
+
+    fn unit_path(&self) -> PathBuf {
+        self.unit_dir.join(&self.unit_name)
+    }
+}
+
+impl ServiceManager for SystemdService {
+    fn open(&mut self) -> Result<(), ServiceError> {
+        if self.unit_path().exists() {
+            Ok(())
+        } else {
+            Err(ServiceError::DoesNotExist)
+        }
+    }
+
+    fn register(&mut self, display_name: &str, command_line: &str) -> Result<(), ServiceError> {
+        if self.unit_path().exists() {
+            return Err(ServiceError::AlreadyExists);
+        }
+        fs::write(
+            self.unit_path(),
+            unit_file_contents(display_name, command_line),
+        )
+        .map_err(io_service_error)?;
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", &self.unit_name])?;
+        Ok(())
+    }
+
+    fn unregister(&mut self) -> Result<(), ServiceError> {
+        if !self.unit_path().exists() {
+            return Err(ServiceError::DoesNotExist);
+        }
+        systemctl(&["disable", &self.unit_name])?;
+        fs::remove_file(self.unit_path()).map_err(io_service_error)?;
+        systemctl(&["daemon-reload"])?;
+        Ok(())
+    }
+
+    fn command_line(&self) -> Result<String, ServiceError> {
+        let contents = fs::read_to_string(self.unit_path()).map_err(io_service_error)?;
+        contents
+            .lines()
+            .find_map(|line| line.strip_prefix("ExecStart="))
+            .map(exec_start_command_line)
+            .ok_or_else(|| {
+                ServiceError::Os(-1, format!("{} has no ExecStart line", self.unit_name))
+            })
+    }
+
+    fn set_command_line(&mut self, command_line: &str) -> Result<(), ServiceError> {
+        let contents = fs::read_to_string(self.unit_path()).map_err(io_service_error)?;
+        let contents: String = contents
+            .lines()
+            .map(|line| {
+                if line.starts_with("ExecStart=") {
+                    format!("ExecStart={}\n", exec_start(command_line))
+                } else {
+                    format!("{}\n", line)
+                }
+            })
+            .collect();
+        fs::write(self.unit_path(), contents).map_err(io_service_error)?;
+        systemctl(&["daemon-reload"])?;
+        Ok(())
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        systemctl(&["start", &self.unit_name]).map(|_| ())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        systemctl(&["stop", &self.unit_name]).map(|_| ())
+    }
+
+    fn query_status(&self) -> ServiceStatus {
+        let output = match systemctl(&["show", "--property=LoadState,ActiveState", &self.unit_name])
+        {
+            Ok(output) => output,
+            Err(_) => return ServiceStatus::Unknown,
+        };
+        let mut load_state = "";
+        let mut active_state = "";
+        for line in output.lines() {
+            if let Some(value) = line.strip_prefix("LoadState=") {
+                load_state = value;
+            } else if let Some(value) = line.strip_prefix("ActiveState=") {
+                active_state = value;
+            }
+        }
+        if load_state == "not-found" {
+            return ServiceStatus::DoesNotExist;
+        }
+        match active_state {
+            "active" | "activating" | "reloading" => ServiceStatus::Running,
+            "inactive" | "failed" | "deactivating" => ServiceStatus::Stopped,
+            _ => ServiceStatus::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::command_line_for;
+    use std::path::Path;
+
+    #[test]
+    fn escapes_exec_start() {
+        let command_line = command_line_for(Path::new("/opt/desk hub/100%/deskhub"));
+        let unit = unit_file_contents("DeskHubService", &command_line);
+        assert!(unit.contains("\nExecStart=\"/opt/desk hub/100%%/deskhub\" run-service\n"));
+        let value = unit
+            .lines()
+            .find_map(|line| line.strip_prefix("ExecStart="))
+            .unwrap();
+        assert_eq!(exec_start_command_line(value), command_line);
+        assert_eq!(exec_start_command_line(&exec_start("a\\b%c")), "a\\b%c");
+    }
+}
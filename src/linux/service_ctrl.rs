@@ -0,0 +1,217 @@
+use crate::config::Config;
+use crate::hub::{self, identity::Identity};
+use crate::ipc;
+use crate::linux::session;
+use crate::logging::{
+    self,
+    levels::{self, LevelControl},
+};
+use crate::supervisor::{
+    self, DesktopState, DesktopStateFn, LaunchError, Launched, Launcher, Supervisor,
+};
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+//logind has no change notification short of D-Bus, so sessions are compared this often.
+const SESSION_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+//systemd runs the service in the foreground and stops it with SIGTERM.
+//Mirrors StartServiceCtrlDispatcherW: returns non-zero when the service ran and exited normally.
+pub fn service_dispatch(config: Config) -> i32 {
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::error!("failed to create service runtime: {}", e);
+            return 0;
+        }
+    };
+    runtime.block_on(async {
+        let mut sigterm = match signal(SignalKind::terminate()) {
+            Ok(sigterm) => sigterm,
+            Err(e) => {
+                log::error!("failed to install SIGTERM handler: {}", e);
+                return 0;
+            }
+        };
+        let executable = match std::env::current_exe() {
+            Ok(executable) => executable,
+            Err(e) => {
+                log::error!("failed to get the execution path: {}", e);
+                return 0;
+            }
+        };
+        let (stop_tx, stop_rx) = watch::channel(false);
+        let (supervisor, desktop_state) = Supervisor::new(
+            Box::new(SessionLauncher { executable }),
+            config.restart_policy(),
+        );
+        tokio::spawn(supervisor::persist_state(
+            desktop_state.clone(),
+            config.desktop.state_path.clone(),
+        ));
+        tokio::spawn(follow_session(desktop_state.clone()));
+        if let Some(control) = LevelControl::installed() {
+            tokio::spawn(control.run(
+                levels::requests_path(&config.log.directory),
+                stop_rx.clone(),
+            ));
+        }
+        if let Ok(identity) = Identity::load(&config.hub.identity_path) {
+            logging::set_agent_id(&identity.agent_id);
+        }
+        let state: DesktopStateFn = Arc::new(move || desktop_state.borrow().clone());
+        let (ipc_server, desktop_link) = ipc::server(
+            ipc::desktop_only(state.clone()),
+            ipc::service_status(&config, state.clone()),
+        );
+        //Listening before the desktop process starts, so it finds the socket.
+        match ipc::bind(&config.desktop.ipc_path) {
+            Ok(listener) => {
+                tokio::spawn(ipc_server.run(listener, stop_rx.clone()));
+            }
+            Err(e) => log::error!(
+                "the desktop process cannot reach the service, failed to listen on {}: {}",
+                config.desktop.ipc_path.display(),
+                e
+            ),
+        }
+        let sessions = session::watch_sessions(SESSION_POLL_INTERVAL);
+        let supervisor_task = tokio::spawn(supervisor.run(stop_rx.clone(), sessions));
+        let hub_task = match config.hub_config() {
+            Some(config) => Some(tokio::spawn(hub::run(config, state, desktop_link, stop_rx))),
+            None => {
+                log::info!("no hub configured, running unmanaged");
+                drop(desktop_link);
+                None
+            }
+        };
+        log::info!("service running");
+        tokio::select! {
+            _ = sigterm.recv() => log::info!("received SIGTERM, stopping service"),
+            _ = tokio::signal::ctrl_c() => log::info!("received SIGINT, stopping service"),
+        }
+        let _ = stop_tx.send(true);
+        let _ = supervisor_task.await;
+        if let Some(hub_task) = hub_task {
+            let _ = hub_task.await;
+        }
+        1
+    })
+}
+
+//Keeps the session in journald and syslog records up to date.
+async fn follow_session(mut state: watch::Receiver<DesktopState>) {
+    loop {
+        let session = state.borrow_and_update().session.clone();
+        logging::set_session_id(session);
+        if state.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+//What the desktop process finds in PATH; nothing else of the service's environment is passed on
+//but its own DESKHUB_* overrides and the locale.
+const SESSION_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+//The groups `user` belongs to, as login would set them up. Looked up before forking, since the
+//child may only make async-signal-safe calls.
+fn supplementary_groups(user: &CStr, gid: libc::gid_t) -> Vec<libc::gid_t> {
+    let mut count: libc::c_int = 32;
+    while count <= 65536 {
+        let mut groups = vec![0; count as usize];
+        let asked = count;
+        if unsafe { libc::getgrouplist(user.as_ptr(), gid, groups.as_mut_ptr(), &mut count) } >= 0 {
+            groups.truncate(count as usize);
+            return groups;
+        }
+        //glibc reports how many it needs; others leave the count alone.
+        count = count.max(asked * 2);
+    }
+    vec![gid]
+}
+
+//Starts the desktop process as the user of the active console session, on that session's display.
+//The process is spawned directly with the user's ids so killing it reaches the desktop itself.
+struct SessionLauncher {
+    executable: PathBuf,
+}
+
+impl Launcher for SessionLauncher {
+    fn launch(&mut self) -> Result<Launched, LaunchError> {
+        let session = session::active_session()
+            .ok_or_else(|| LaunchError::NoSession("nobody is logged in on seat0".to_string()))?;
+        let display = session.display.ok_or_else(|| {
+            LaunchError::NoSession(format!("session {} has no X11 display", session.id))
+        })?;
+        let (gid, home) = session::passwd_entry(&session.user).ok_or_else(|| {
+            LaunchError::NoSession(format!("user {} is not in /etc/passwd", session.user))
+        })?;
+        let user = CString::new(session.user.clone())
+            .map_err(|_| LaunchError::NoSession(format!("bad user name {:?}", session.user)))?;
+        let groups = supplementary_groups(&user, gid);
+        let uid = session.uid;
+        let mut command = Command::new(&self.executable);
+        command
+            .arg("run-desktop")
+            .current_dir(&home)
+            .env_clear()
+            .envs(std::env::vars_os().filter(|(key, _)| {
+                key.to_str()
+                    .is_some_and(|key| key.starts_with("DESKHUB_") || key == "LANG")
+            }))
+            .env("PATH", SESSION_PATH)
+            .env("HOME", &home)
+            .env("USER", &session.user)
+            .env("LOGNAME", &session.user)
+            .env("DISPLAY", display)
+            .env("XDG_RUNTIME_DIR", format!("/run/user/{}", session.uid));
+        match session::xauthority(session.uid, Path::new(&home)) {
+            Some(xauthority) => {
+                command.env("XAUTHORITY", xauthority);
+            }
+            None => log::warn!(
+                "no X authority cookie found for {}; the display may refuse the desktop process",
+                session.user
+            ),
+        }
+        //Command::uid would drop the supplementary groups (video, input and the like).
+        unsafe {
+            command.pre_exec(move || {
+                if libc::setgroups(groups.len(), groups.as_ptr()) != 0
+                    || libc::setgid(gid) != 0
+                    || libc::setuid(uid) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        Ok(Launched {
+            process: Box::new(child),
+            session: session.id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_supplementary_groups() {
+        let groups = supplementary_groups(&CString::new("root").unwrap(), 0);
+        assert!(groups.contains(&0));
+        //Anyone unknown still keeps the primary group.
+        let nobody = CString::new("no-such-user-deskhub").unwrap();
+        assert_eq!(supplementary_groups(&nobody, 4321), vec![4321]);
+    }
+}
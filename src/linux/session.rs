@@ -0,0 +1,358 @@
+use crate::session::{self, SessionChange, SessionEvent, SessionEvents};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+fn loginctl_output(args: &[&str]) -> Option<String> {
+    let output = Command::new("loginctl").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn loginctl(args: &[&str]) -> Option<String> {
+    loginctl_output(args).filter(|value| !value.is_empty())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub id: String,
+    pub user: String,
+    pub uid: u32,
+    //X11 display of a graphical session, e.g. ":0".
+    pub display: Option<String>,
+}
+
+fn parse_session(id: &str, properties: &str) -> Option<Session> {
+    let mut user = None;
+    let mut uid = None;
+    let mut display = None;
+    for line in properties.lines() {
+        match line.split_once('=') {
+            Some(("Name", value)) if !value.is_empty() => user = Some(value.to_string()),
+            Some(("User", value)) => uid = value.parse().ok(),
+            Some(("Display", value)) if !value.is_empty() => display = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    Some(Session {
+        id: id.to_string(),
+        user: user?,
+        uid: uid?,
+        display,
+    })
+}
+
+//The active session on seat0, if anyone is logged in at the console.
+pub fn active_session() -> Option<Session> {
+    let id = loginctl(&["show-seat", "seat0", "--property=ActiveSession", "--value"])?;
+    let properties = loginctl(&["show-session", &id, "--property=Name,User,Display"])?;
+    parse_session(&id, &properties)
+}
+
+pub fn active_session_user() -> Option<String> {
+    active_session().map(|session| session.user)
+}
+
+//The X authority cookie of the user's session. GDM keeps it in the user's runtime directory,
+//SDDM and Xwayland next to it under a random name, startx in the home directory.
+pub fn xauthority(uid: u32, home: &Path) -> Option<PathBuf> {
+    find_xauthority(&PathBuf::from(format!("/run/user/{}", uid)), home)
+}
+
+fn find_xauthority(runtime_dir: &Path, home: &Path) -> Option<PathBuf> {
+    let gdm = runtime_dir.join("gdm/Xauthority");
+    if gdm.is_file() {
+        return Some(gdm);
+    }
+    let newest = fs::read_dir(runtime_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with("xauth_") || name.starts_with(".mutter-Xwaylandauth.")
+        })
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .max();
+    if let Some((_, path)) = newest {
+        return Some(path);
+    }
+    Some(home.join(".Xauthority")).filter(|path| path.is_file())
+}
+
+//Primary group and home directory of `user` from /etc/passwd.
+pub fn passwd_entry(user: &str) -> Option<(u32, String)> {
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    passwd.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() < 6 || fields[0] != user {
+            return None;
+        }
+        Some((fields[3].parse().ok()?, fields[5].to_string()))
+    })
+}
+
+//What the logind source tracks of each user session on seat0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SeatSession {
+    id: String,
+    active: bool,
+    locked: bool,
+}
+
+//`loginctl show-session` prints one block of properties per session, separated by blank lines.
+fn parse_seat_sessions(output: &str) -> Vec<SeatSession> {
+    output
+        .split("\n\n")
+        .filter_map(|block| {
+            let mut id = None;
+            let mut active = false;
+            let mut locked = false;
+            let mut seat0 = false;
+            let mut user_class = false;
+            let mut closing = false;
+            for line in block.lines() {
+                match line.split_once('=') {
+                    Some(("Id", value)) => id = Some(value.to_string()),
+                    Some(("Active", value)) => active = value == "yes",
+                    Some(("LockedHint", value)) => locked = value == "yes",
+                    Some(("Seat", value)) => seat0 = value == "seat0",
+                    Some(("Class", value)) => user_class = value == "user",
+                    Some(("State", value)) => closing = value == "closing",
+                    _ => {}
+                }
+            }
+            if !seat0 || !user_class || closing {
+                return None;
+            }
+            Some(SeatSession {
+                id: id?,
+                active,
+                locked,
+            })
+        })
+        .collect()
+}
+
+fn seat_sessions() -> Option<Vec<SeatSession>> {
+    let list = loginctl_output(&["list-sessions", "--no-legend"])?;
+    let ids: Vec<&str> = list
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+    if ids.is_empty() {
+        return Some(Vec::new());
+    }
+    let mut args = vec!["show-session"];
+    args.extend(ids);
+    args.push("--property=Id,Active,LockedHint,Seat,Class,State");
+    loginctl_output(&args).map(|output| parse_seat_sessions(&output))
+}
+
+fn diff(before: &[SeatSession], after: &[SeatSession]) -> Vec<SessionEvent> {
+    let mut events = Vec::new();
+    for old in before {
+        if !after.iter().any(|new| new.id == old.id) {
+            if old.active {
+                events.push(SessionEvent::new(&old.id, SessionChange::ConsoleDisconnect));
+            }
+            events.push(SessionEvent::new(&old.id, SessionChange::Logoff));
+        }
+    }
+    for new in after {
+        let old = before.iter().find(|old| old.id == new.id);
+        if old.is_none() {
+            events.push(SessionEvent::new(&new.id, SessionChange::Logon));
+        }
+        let was_active = old.is_some_and(|old| old.active);
+        let was_locked = old.is_some_and(|old| old.locked);
+        //Deactivate before activate, so a switch reads as disconnect then connect.
+        if was_active && !new.active {
+            events.insert(
+                0,
+                SessionEvent::new(&new.id, SessionChange::ConsoleDisconnect),
+            );
+        }
+        if !was_active && new.active {
+            events.push(SessionEvent::new(&new.id, SessionChange::ConsoleConnect));
+        }
+        if was_locked != new.locked {
+            let change = if new.locked {
+                SessionChange::Lock
+            } else {
+                SessionChange::Unlock
+            };
+            events.push(SessionEvent::new(&new.id, change));
+        }
+    }
+    events
+}
+
+//logind session changes, found by polling loginctl every `interval` and comparing snapshots.
+//Stops when the receiver is dropped.
+pub fn watch_sessions(interval: Duration) -> SessionEvents {
+    let (events, receiver) = session::channel();
+    tokio::spawn(async move {
+        let mut known: Option<Vec<SeatSession>> = None;
+        while !events.is_closed() {
+            if let Ok(Some(current)) = tokio::task::spawn_blocking(seat_sessions).await {
+                if let Some(known) = &known {
+                    for event in diff(known, &current) {
+                        let _ = events.send(event);
+                    }
+                }
+                known = Some(current);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_show_session_output() {
+        assert_eq!(
+            parse_session("2", "Name=alice\nUser=1000\nDisplay=:0\n"),
+            Some(Session {
+                id: "2".to_string(),
+                user: "alice".to_string(),
+                uid: 1000,
+                display: Some(":0".to_string()),
+            })
+        );
+        assert_eq!(
+            parse_session("c1", "Name=gdm\nUser=120\nDisplay=\n")
+                .unwrap()
+                .display,
+            None
+        );
+        assert_eq!(parse_session("3", "Name=\nUser=1000\n"), None);
+    }
+
+    #[test]
+    fn finds_the_session_cookie() {
+        let runtime = tempfile::tempdir().unwrap();
+        let home = tempfile::tempdir().unwrap();
+        assert_eq!(find_xauthority(runtime.path(), home.path()), None);
+        fs::write(home.path().join(".Xauthority"), "").unwrap();
+        assert_eq!(
+            find_xauthority(runtime.path(), home.path()),
+            Some(home.path().join(".Xauthority"))
+        );
+        fs::write(runtime.path().join("xauth_AbCdEf"), "").unwrap();
+        assert_eq!(
+            find_xauthority(runtime.path(), home.path()),
+            Some(runtime.path().join("xauth_AbCdEf"))
+        );
+        fs::create_dir(runtime.path().join("gdm")).unwrap();
+        fs::write(runtime.path().join("gdm/Xauthority"), "").unwrap();
+        assert_eq!(
+            find_xauthority(runtime.path(), home.path()),
+            Some(runtime.path().join("gdm/Xauthority"))
+        );
+    }
+
+    #[test]
+    fn keeps_only_user_sessions_on_seat0() {
+        let output = "Id=2\nActive=yes\nLockedHint=no\nSeat=seat0\nClass=user\nState=active\n\n\
+                      Id=c1\nActive=no\nLockedHint=no\nSeat=seat0\nClass=greeter\nState=online\n\n\
+                      Id=5\nActive=yes\nLockedHint=no\nSeat=\nClass=user\nState=active\n\n\
+                      Id=3\nActive=no\nLockedHint=yes\nSeat=seat0\nClass=user\nState=closing";
+        assert_eq!(
+            parse_seat_sessions(output),
+            vec![SeatSession {
+                id: "2".to_string(),
+                active: true,
+                locked: false,
+            }]
+        );
+    }
+
+    fn seat(id: &str, active: bool, locked: bool) -> SeatSession {
+        SeatSession {
+            id: id.to_string(),
+            active,
+            locked,
+        }
+    }
+
+    fn changes(events: Vec<SessionEvent>) -> Vec<(String, SessionChange)> {
+        events
+            .into_iter()
+            .map(|event| (event.session, event.change))
+            .collect()
+    }
+
+    #[test]
+    fn diff_reports_session_changes() {
+        use SessionChange::*;
+        //(before, after, expected events)
+        type Case = (
+            Vec<SeatSession>,
+            Vec<SeatSession>,
+            Vec<(&'static str, SessionChange)>,
+        );
+        let cases: Vec<Case> = vec![
+            (vec![], vec![], vec![]),
+            (
+                vec![],
+                vec![seat("2", true, false)],
+                vec![("2", Logon), ("2", ConsoleConnect)],
+            ),
+            (
+                vec![seat("2", true, false)],
+                vec![],
+                vec![("2", ConsoleDisconnect), ("2", Logoff)],
+            ),
+            (
+                vec![seat("2", true, false)],
+                vec![seat("2", true, true)],
+                vec![("2", Lock)],
+            ),
+            (
+                vec![seat("2", true, true)],
+                vec![seat("2", true, false)],
+                vec![("2", Unlock)],
+            ),
+            (
+                vec![seat("2", true, false)],
+                vec![seat("2", false, false), seat("3", true, false)],
+                vec![
+                    ("2", ConsoleDisconnect),
+                    ("3", Logon),
+                    ("3", ConsoleConnect),
+                ],
+            ),
+            (
+                vec![seat("2", false, false), seat("3", true, false)],
+                vec![seat("2", true, false)],
+                vec![
+                    ("3", ConsoleDisconnect),
+                    ("3", Logoff),
+                    ("2", ConsoleConnect),
+                ],
+            ),
+        ];
+        for (before, after, expected) in cases {
+            let expected: Vec<(String, SessionChange)> = expected
+                .into_iter()
+                .map(|(id, change)| (id.to_string(), change))
+                .collect();
+            assert_eq!(
+                changes(diff(&before, &after)),
+                expected,
+                "{:?} -> {:?}",
+                before,
+                after
+            );
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//Writes records as RFC 5424 syslog messages to a local datagram socket.
+use crate::logging::{self, LogContext, Process};
+use chrono::{DateTime, Local, SecondsFormat};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use sysinfo::System;
+
+pub const SOCKET: &str = "/dev/log";
+
+//LOG_DAEMON.
+const FACILITY: u8 = 3;
+//Structured data needs a private enterprise number; this is the one RFC 5612 sets aside for examples.
+const SD_ID: &str = "deskhub@32473";
+
+//syslog severities, which journald's PRIORITY uses too.
+pub fn severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+pub struct Syslog {
+    socket: UnixDatagram,
+    path: PathBuf,
+    process: Process,
+    hostname: String,
+}
+
+impl Syslog {
+    pub fn new(path: PathBuf, process: Process) -> io::Result<Self> {
+        Ok(Syslog {
+            socket: UnixDatagram::unbound()?,
+            path,
+            process,
+            hostname: System::host_name().unwrap_or_default(),
+        })
+    }
+
+    pub fn output(self) -> fern::Output {
+        fern::Output::call(move |record| {
+            let message = message(
+                self.process,
+                &logging::context(),
+                &self.hostname,
+                Local::now(),
+                record,
+            );
+            if let Err(e) = self.socket.send_to(message.as_bytes(), &self.path) {
+                eprintln!("failed to log to {}: {}", self.path.display(), e);
+            }
+        })
+    }
+}
+
+//<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID [STRUCTURED-DATA] MSG
+pub fn message(
+    process: Process,
+    context: &LogContext,
+    hostname: &str,
+    time: DateTime<Local>,
+    record: &log::Record,
+) -> String {
+    let mut params = vec![("module", record.target())];
+    if let Some(agent_id) = &context.agent_id {
+        params.push(("agent_id", agent_id));
+    }
+    if let Some(session_id) = &context.session_id {
+        params.push(("session_id", session_id));
+    }
+    let params: Vec<String> = params
+        .into_iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    format!(
+        "<{}>1 {} {} deskhub {} {} [{} {}] {}",
+        FACILITY * 8 + severity(record.level()),
+        time.to_rfc3339_opts(SecondsFormat::Micros, false),
+        header_field(hostname),
+        std::process::id(),
+        process.name(),
+        SD_ID,
+        params.join(" "),
+        record.args()
+    )
+}
+
+//Header fields are printable ASCII without spaces, and "-" when unknown.
+fn header_field(value: &str) -> String {
+    let value: String = value.chars().filter(|c| c.is_ascii_graphic()).collect();
+    if value.is_empty() {
+        return "-".to_string();
+    }
+    value
+}
+
+//Parameter values escape '"', '\' and ']'.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use log::Level;
+
+    fn record<'a>(args: std::fmt::Arguments<'a>, level: Level) -> log::Record<'a> {
+        log::Record::builder()
+            .args(args)
+            .level(level)
+            .target("deskhub::hub")
+            .build()
+    }
+
+    #[test]
+    fn formats_rfc5424() {
+        let context = LogContext {
+            agent_id: Some("agent \"1\"]".to_string()),
+            session_id: None,
+        };
+        let time = Local.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        let line = message(
+            Process::Service,
+            &context,
+            "front desk 01",
+            time,
+            &record(format_args!("connection lost"), Level::Error),
+        );
+        assert_eq!(
+            line,
+            format!(
+                "<27>1 {} frontdesk01 deskhub {} service [deskhub@32473 module=\"deskhub::hub\" agent_id=\"agent \\\"1\\\"\\]\"] connection lost",
+                time.to_rfc3339_opts(SecondsFormat::Micros, false),
+                std::process::id()
+            )
+        );
+        let line = message(
+            Process::Service,
+            &LogContext::default(),
+            "",
+            time,
+            &record(format_args!("x"), Level::Debug),
+        );
+        assert!(line.starts_with("<31>1 "));
+        assert!(line.contains(" - deskhub "));
+    }
+
+    #[test]
+    fn sends_to_the_syslog_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let syslog = UnixDatagram::bind(&path).unwrap();
+        let (_, logger) = fern::Dispatch::new()
+            .chain(Syslog::new(path, Process::Service).unwrap().output())
+            .into_log();
+        logger.log(&record(format_args!("heartbeat sent"), Level::Info));
+        let mut datagram = vec![0; 4096];
+        let length = syslog.recv(&mut datagram).unwrap();
+        let message = std::str::from_utf8(&datagram[..length]).unwrap();
+        assert!(message.starts_with("<30>1 "), "{}", message);
+        assert!(message.ends_with("] heartbeat sent"), "{}", message);
+    }
+}
@@ -0,0 +1,338 @@
+//Log records on their way to the hub. They are spooled to disk in batches first, so records
+//written while the hub is unreachable go out in order once it is back.
+use super::Process;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+//Records per batch; a full batch is sealed to disk right away.
+const BATCH_LEN: usize = 200;
+
+//The spool of this process's logger, once setup has installed one.
+static INSTALLED: OnceLock<LogSpool> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub time: String,
+    pub level: String,
+    pub target: String,
+    pub process: String,
+    pub message: String,
+}
+
+impl LogEntry {
+    pub fn new(process: Process, record: &log::Record) -> Self {
+        LogEntry {
+            time: Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            level: record.level().as_str().to_string(),
+            target: record.target().to_string(),
+            process: process.name().to_string(),
+            message: record.args().to_string(),
+        }
+    }
+}
+
+//The oldest batch on disk, and the records dropped before it that the hub has not heard of.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    pub seq: u64,
+    pub entries: Vec<LogEntry>,
+    pub dropped: u64,
+}
+
+struct Segment {
+    seq: u64,
+    bytes: u64,
+    entries: u64,
+}
+
+struct SpoolState {
+    directory: PathBuf,
+    max_bytes: u64,
+    //Records not yet sealed into a batch.
+    pending: Vec<LogEntry>,
+    //Sealed batches, oldest first.
+    segments: VecDeque<Segment>,
+    bytes: u64,
+    next_seq: u64,
+    dropped: u64,
+}
+
+//A bounded queue of batches in a directory, one file each. Once the files add up to more than
+//`max_bytes` the oldest are deleted and counted. Clones share the same queue.
+//
+//This is written to from inside the logger, so it reports its own trouble on stderr; logging
+//it would come straight back here.
+#[derive(Clone)]
+pub struct LogSpool {
+    state: Arc<Mutex<SpoolState>>,
+}
+
+impl fmt::Debug for LogSpool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("LogSpool")
+            .field("directory", &state.directory)
+            .field("batches", &state.segments.len())
+            .finish()
+    }
+}
+
+impl LogSpool {
+    //Opens the spool in `directory`, picking up batches an earlier run left behind.
+    pub fn open(directory: &Path, max_bytes: u64) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            let seq = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|seq| seq.parse::<u64>().ok());
+            let Some(seq) = seq else {
+                //Left over from a write that never finished.
+                if path.extension().is_some_and(|ext| ext == "tmp") {
+                    let _ = fs::remove_file(&path);
+                }
+                continue;
+            };
+            match read_batch(&path) {
+                Ok(entries) => segments.push(Segment {
+                    seq,
+                    bytes: fs::metadata(&path)?.len(),
+                    entries: entries.len() as u64,
+                }),
+                Err(e) => {
+                    eprintln!("discarding spooled logs {}: {}", path.display(), e);
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+        segments.sort_by_key(|segment| segment.seq);
+        let state = SpoolState {
+            directory: directory.to_path_buf(),
+            max_bytes,
+            pending: Vec::new(),
+            bytes: segments.iter().map(|segment| segment.bytes).sum(),
+            next_seq: segments.last().map_or(0, |segment| segment.seq + 1),
+            segments: segments.into(),
+            dropped: 0,
+        };
+        Ok(LogSpool {
+            state: Arc::new(Mutex::new(state)),
+        })
+    }
+
+    pub fn push(&self, entry: LogEntry) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.push(entry);
+        if state.pending.len() >= BATCH_LEN {
+            state.seal();
+        }
+    }
+
+    //Writes out whatever records are pending as a batch of their own.
+    pub fn seal(&self) {
+        self.state.lock().unwrap().seal();
+    }
+
+    pub fn oldest(&self) -> Option<Batch> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let segment = state.segments.front()?;
+            let seq = segment.seq;
+            match read_batch(&state.path(seq)) {
+                Ok(entries) => {
+                    return Some(Batch {
+                        seq,
+                        entries,
+                        dropped: state.dropped,
+                    })
+                }
+                Err(e) => {
+                    eprintln!(
+                        "discarding spooled logs {}: {}",
+                        state.path(seq).display(),
+                        e
+                    );
+                    state.drop_oldest();
+                }
+            }
+        }
+    }
+
+    //The hub has `batch`; forget it, and the drops it reported.
+    pub fn delivered(&self, batch: &Batch) {
+        let mut state = self.state.lock().unwrap();
+        state.dropped = state.dropped.saturating_sub(batch.dropped);
+        //The batch may have been dropped meanwhile to make room.
+        if let Some(index) = state
+            .segments
+            .iter()
+            .position(|segment| segment.seq == batch.seq)
+        {
+            let segment = state.segments.remove(index).unwrap();
+            state.bytes -= segment.bytes;
+            if let Err(e) = fs::remove_file(state.path(segment.seq)) {
+                eprintln!("failed to remove delivered logs: {}", e);
+            }
+        }
+    }
+
+    //A fern output feeding this spool.
+    pub fn output(&self, process: Process) -> fern::Output {
+        let spool = self.clone();
+        fern::Output::call(move |record| spool.push(LogEntry::new(process, record)))
+    }
+
+    //Makes this the spool the hub connection drains.
+    pub fn install(&self) {
+        let _ = INSTALLED.set(self.clone());
+    }
+
+    pub fn installed() -> Option<LogSpool> {
+        INSTALLED.get().cloned()
+    }
+}
+
+impl SpoolState {
+    fn path(&self, seq: u64) -> PathBuf {
+        self.directory.join(format!("{:020}.json", seq))
+    }
+
+    fn seal(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let entries = std::mem::take(&mut self.pending);
+        let seq = self.next_seq;
+        let path = self.path(seq);
+        let json = serde_json::to_vec(&entries).expect("log entries always serialize");
+        let tmp = path.with_extension("tmp");
+        if let Err(e) = fs::write(&tmp, &json).and_then(|_| fs::rename(&tmp, &path)) {
+            eprintln!("failed to spool {} log records: {}", entries.len(), e);
+            self.dropped += entries.len() as u64;
+            return;
+        }
+        self.next_seq += 1;
+        self.bytes += json.len() as u64;
+        self.segments.push_back(Segment {
+            seq,
+            bytes: json.len() as u64,
+            entries: entries.len() as u64,
+        });
+        //The newest batch stays even if it alone is over the limit.
+        while self.bytes > self.max_bytes && self.segments.len() > 1 {
+            self.drop_oldest();
+        }
+    }
+
+    fn drop_oldest(&mut self) {
+        if let Some(segment) = self.segments.pop_front() {
+            self.bytes -= segment.bytes;
+            self.dropped += segment.entries;
+            let _ = fs::remove_file(self.path(segment.seq));
+        }
+    }
+}
+
+fn read_batch(path: &Path) -> io::Result<Vec<LogEntry>> {
+    let json = fs::read(path)?;
+    serde_json::from_slice(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(message: &str) -> LogEntry {
+        LogEntry {
+            time: "2024-01-02T03:04:05.000+00:00".to_string(),
+            level: "INFO".to_string(),
+            target: "deskhub::service".to_string(),
+            process: "service".to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn messages(batch: &Batch) -> Vec<&str> {
+        batch
+            .entries
+            .iter()
+            .map(|entry| entry.message.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn batches_in_order_and_survives_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = LogSpool::open(dir.path(), 1 << 20).unwrap();
+        for n in 0..BATCH_LEN + 1 {
+            spool.push(entry(&n.to_string()));
+        }
+        //Only the full batch is on disk so far.
+        let first = spool.oldest().unwrap();
+        assert_eq!(first.entries.len(), BATCH_LEN);
+        spool.seal();
+        drop(spool);
+
+        let spool = LogSpool::open(dir.path(), 1 << 20).unwrap();
+        let first = spool.oldest().unwrap();
+        assert_eq!(messages(&first)[..2], ["0", "1"]);
+        spool.delivered(&first);
+        let second = spool.oldest().unwrap();
+        assert_eq!(messages(&second), [BATCH_LEN.to_string()]);
+        spool.delivered(&second);
+        assert_eq!(spool.oldest(), None);
+
+        //Numbering carries on after what is still there, so nothing is overwritten.
+        spool.push(entry("later"));
+        spool.seal();
+        assert!(spool.oldest().unwrap().seq > second.seq);
+    }
+
+    #[test]
+    fn drops_the_oldest_batches_and_counts_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let batch_bytes = serde_json::to_vec(&vec![entry("0")]).unwrap().len() as u64;
+        let spool = LogSpool::open(dir.path(), batch_bytes * 3).unwrap();
+        for n in 0..5 {
+            spool.push(entry(&n.to_string()));
+            spool.seal();
+        }
+        let oldest = spool.oldest().unwrap();
+        assert_eq!(messages(&oldest), ["2"]);
+        assert_eq!(oldest.dropped, 2);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+
+        //The count is reported once, with the batch that went out next.
+        spool.delivered(&oldest);
+        let next = spool.oldest().unwrap();
+        assert_eq!((messages(&next), next.dropped), (vec!["3"], 0));
+    }
+
+    #[test]
+    fn skips_unreadable_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = LogSpool::open(dir.path(), 1 << 20).unwrap();
+        spool.push(entry("lost"));
+        spool.seal();
+        spool.push(entry("kept"));
+        spool.seal();
+        fs::write(dir.path().join(format!("{:020}.json", 0)), "{truncated").unwrap();
+        fs::write(dir.path().join("00000000000000000009.tmp"), "[").unwrap();
+
+        let batch = spool.oldest().unwrap();
+        assert_eq!((messages(&batch), batch.dropped), (vec!["kept"], 1));
+        drop(spool);
+        let reopened = LogSpool::open(dir.path(), 1 << 20).unwrap();
+        assert_eq!(messages(&reopened.oldest().unwrap()), ["kept"]);
+        assert!(!dir.path().join("00000000000000000009.tmp").exists());
+    }
+}
@@ -0,0 +1,468 @@
+//Log levels that can be changed while the service runs. Every change lapses on its own, back to
+//the configured levels, so verbose logging is not left on by accident.
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+//The longest a change may last.
+pub const MAX_DURATION: Duration = Duration::from_secs(24 * 3600);
+//How often changes are checked for expiry and the CLI's requests are picked up.
+const TICK: Duration = Duration::from_secs(1);
+
+static INSTALLED: OnceLock<LevelControl> = OnceLock::new();
+
+//What the CLI and the hub ask for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum LevelRequest {
+    Set {
+        //A module such as deskhub::hub; None for every module without a level of its own.
+        target: Option<String>,
+        level: String,
+        duration_secs: u64,
+    },
+    //Back to the configured levels now.
+    Reset,
+}
+
+//A request that makes sense, ready to apply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Set {
+        target: Option<String>,
+        level: LevelFilter,
+        duration: Duration,
+    },
+    Reset,
+}
+
+impl LevelRequest {
+    pub fn check(&self) -> Result<Change, String> {
+        match self {
+            LevelRequest::Set {
+                target,
+                level,
+                duration_secs,
+            } => {
+                if let Some(target) = target {
+                    if target.is_empty() || target.contains(char::is_whitespace) {
+                        return Err(format!("invalid module name {:?}", target));
+                    }
+                }
+                let level = level.parse().map_err(|_| {
+                    format!(
+                        "expected one of off, error, warn, info, debug, trace, found {:?}",
+                        level
+                    )
+                })?;
+                let duration = Duration::from_secs(*duration_secs);
+                if duration.is_zero() || duration > MAX_DURATION {
+                    return Err(format!(
+                        "the duration must be between 1 second and {} hours",
+                        MAX_DURATION.as_secs() / 3600
+                    ));
+                }
+                Ok(Change::Set {
+                    target: target.clone(),
+                    level,
+                    duration,
+                })
+            }
+            LevelRequest::Reset => Ok(Change::Reset),
+        }
+    }
+}
+
+//A global level and per-module ones; the longest module matching a target wins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Levels {
+    pub global: LevelFilter,
+    pub modules: Vec<(String, LevelFilter)>,
+}
+
+impl Levels {
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(module, _)| {
+                target
+                    .strip_prefix(module.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map_or(self.global, |(_, level)| *level)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.global, Ord::max)
+    }
+
+    fn with(&self, target: &Option<String>, level: LevelFilter) -> Levels {
+        let mut levels = self.clone();
+        match target {
+            None => levels.global = level,
+            Some(target) => {
+                levels.modules.retain(|(module, _)| module != target);
+                levels.modules.push((target.clone(), level));
+            }
+        }
+        levels
+    }
+}
+
+#[derive(Debug)]
+struct Override {
+    target: Option<String>,
+    level: LevelFilter,
+    until: Instant,
+}
+
+#[derive(Debug)]
+struct State {
+    configured: Levels,
+    overrides: Vec<Override>,
+    effective: Levels,
+}
+
+impl State {
+    fn recompute(&mut self) {
+        self.effective = self
+            .overrides
+            .iter()
+            .fold(self.configured.clone(), |levels, change| {
+                levels.with(&change.target, change.level)
+            });
+        log::set_max_level(self.effective.max());
+    }
+}
+
+//The levels the logger filters by. Clones share the same levels.
+#[derive(Debug, Clone)]
+pub struct LevelControl {
+    state: Arc<RwLock<State>>,
+}
+
+impl LevelControl {
+    pub fn new(configured: Levels) -> Self {
+        LevelControl {
+            state: Arc::new(RwLock::new(State {
+                effective: configured.clone(),
+                configured,
+                overrides: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level()
+            <= self
+                .state
+                .read()
+                .unwrap()
+                .effective
+                .level_for(metadata.target())
+    }
+
+    //log's global maximum, which saves formatting records nothing would let through.
+    pub fn set_max_level(&self) {
+        log::set_max_level(self.state.read().unwrap().effective.max());
+    }
+
+    pub fn levels(&self) -> Levels {
+        self.state.read().unwrap().effective.clone()
+    }
+
+    //Applies `change`, returning what it did for the log.
+    pub fn apply(&self, change: Change, now: Instant) -> String {
+        let mut state = self.state.write().unwrap();
+        let description = match change {
+            Change::Set {
+                target,
+                level,
+                duration,
+            } => {
+                let description = format!(
+                    "log level of {} set to {} for {}s",
+                    target.as_deref().unwrap_or("all modules"),
+                    level.as_str().to_lowercase(),
+                    duration.as_secs()
+                );
+                state.overrides.retain(|change| change.target != target);
+                state.overrides.push(Override {
+                    target,
+                    level,
+                    until: now + duration,
+                });
+                description
+            }
+            Change::Reset => {
+                state.overrides.clear();
+                "log levels reset to the configured ones".to_string()
+            }
+        };
+        state.recompute();
+        description
+    }
+
+    //Drops the changes that have run their course, returning the targets they were for.
+    pub fn expire(&self, now: Instant) -> Vec<Option<String>> {
+        let mut state = self.state.write().unwrap();
+        let (expired, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut state.overrides)
+            .into_iter()
+            .partition(|change| change.until <= now);
+        state.overrides = kept;
+        if !expired.is_empty() {
+            state.recompute();
+        }
+        expired.into_iter().map(|change| change.target).collect()
+    }
+
+    //Reverts expired changes and applies the CLI's requests from `requests`, until `stop` changes.
+    pub async fn run(self, requests: PathBuf, mut stop: watch::Receiver<bool>) {
+        //Whatever was asked for while the service was not running is stale by now.
+        let _ = take_requests(&requests);
+        let mut tick = tokio::time::interval(TICK);
+        loop {
+            tokio::select! {
+                _ = tick.tick() => {}
+                _ = stop.changed() => return,
+            }
+            match take_requests(&requests) {
+                Ok(taken) => {
+                    for request in taken {
+                        match request.check() {
+                            Ok(change) => {
+                                let applied = self.apply(change, Instant::now());
+                                log::info!("{}", applied);
+                            }
+                            Err(e) => log::warn!("ignoring log level request: {}", e),
+                        }
+                    }
+                }
+                Err(e) => log::warn!("failed to read {}: {}", requests.display(), e),
+            }
+            for target in self.expire(Instant::now()) {
+                log::info!(
+                    "log level of {} is back to {}",
+                    target.as_deref().unwrap_or("all modules"),
+                    self.levels()
+                        .level_for(target.as_deref().unwrap_or(""))
+                        .as_str()
+                        .to_lowercase()
+                );
+            }
+        }
+    }
+
+    //Makes this the control the hub connection and the service's request file change.
+    pub fn install(&self) {
+        let _ = INSTALLED.set(self.clone());
+    }
+
+    pub fn installed() -> Option<LevelControl> {
+        INSTALLED.get().cloned()
+    }
+}
+
+//The file the CLI leaves requests in for the service, one JSON object per line.
+pub fn requests_path(log_directory: &Path) -> PathBuf {
+    log_directory.join("level-requests.jsonl")
+}
+
+pub fn submit(path: &Path, request: &LevelRequest) -> io::Result<()> {
+    let mut line = serde_json::to_string(request).expect("level requests always serialize");
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+//Whether `file` was written by root or the service's own account.
+#[cfg(unix)]
+fn trusted(file: &File) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let owner = file.metadata()?.uid();
+    Ok(owner == 0 || owner == unsafe { libc::geteuid() })
+}
+
+//Whether `file` was written by SYSTEM or an elevated administrator.
+#[cfg(target_os = "windows")]
+fn trusted(file: &File) -> io::Result<bool> {
+    crate::win32::security::owned_by_admins(file)
+}
+
+//The requests so far, in order, leaving the file to later ones. Requests from a file anyone else
+//wrote are dropped unread, since verbose logging records what viewers do.
+fn take_requests(path: &Path) -> io::Result<Vec<LevelRequest>> {
+    let taken = path.with_extension("taken");
+    match fs::rename(path, &taken) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    }
+    let text = File::open(&taken).and_then(|mut file| {
+        if !trusted(&file)? {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "written by an untrusted account",
+            ));
+        }
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        Ok(text)
+    });
+    fs::remove_file(&taken)?;
+    Ok(text?
+        .lines()
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(request) => Some(request),
+            Err(e) => {
+                log::warn!("ignoring log level request {:?}: {}", line, e);
+                None
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configured() -> Levels {
+        Levels {
+            global: LevelFilter::Info,
+            modules: vec![("deskhub::capture".to_string(), LevelFilter::Warn)],
+        }
+    }
+
+    fn set(target: Option<&str>, level: LevelFilter, secs: u64) -> Change {
+        Change::Set {
+            target: target.map(str::to_string),
+            level,
+            duration: Duration::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn longest_module_wins() {
+        let levels = Levels {
+            global: LevelFilter::Info,
+            modules: vec![
+                ("deskhub".to_string(), LevelFilter::Warn),
+                ("deskhub::hub".to_string(), LevelFilter::Trace),
+            ],
+        };
+        assert_eq!(
+            levels.level_for("deskhub::hub::heartbeat"),
+            LevelFilter::Trace
+        );
+        assert_eq!(levels.level_for("deskhub::hub"), LevelFilter::Trace);
+        assert_eq!(levels.level_for("deskhub::hubris"), LevelFilter::Warn);
+        assert_eq!(levels.level_for("tokio"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn changes_lapse_back_to_the_configuration() {
+        let control = LevelControl::new(configured());
+        let now = Instant::now();
+        control.apply(
+            set(Some("deskhub::win32::service_ctrl"), LevelFilter::Trace, 60),
+            now,
+        );
+        control.apply(set(None, LevelFilter::Debug, 600), now);
+        let levels = control.levels();
+        assert_eq!(
+            levels.level_for("deskhub::win32::service_ctrl"),
+            LevelFilter::Trace
+        );
+        assert_eq!(levels.level_for("deskhub::hub"), LevelFilter::Debug);
+        assert_eq!(levels.level_for("deskhub::capture"), LevelFilter::Warn);
+
+        assert!(control.expire(now + Duration::from_secs(59)).is_empty());
+        assert_eq!(
+            control.expire(now + Duration::from_secs(60)),
+            vec![Some("deskhub::win32::service_ctrl".to_string())]
+        );
+        assert_eq!(
+            control.levels().level_for("deskhub::win32::service_ctrl"),
+            LevelFilter::Debug
+        );
+        //A new change to the same target replaces the old one, timer and all.
+        control.apply(set(None, LevelFilter::Trace, 5), now);
+        assert_eq!(control.expire(now + Duration::from_secs(5)), vec![None]);
+        assert_eq!(control.levels(), configured());
+
+        control.apply(set(Some("deskhub::hub"), LevelFilter::Off, 60), now);
+        control.apply(Change::Reset, now);
+        assert_eq!(control.levels(), configured());
+    }
+
+    #[test]
+    fn checks_requests() {
+        let request = |target: Option<&str>, level: &str, duration_secs| LevelRequest::Set {
+            target: target.map(str::to_string),
+            level: level.to_string(),
+            duration_secs,
+        };
+        assert_eq!(
+            request(Some("deskhub::hub"), "DEBUG", 60).check(),
+            Ok(set(Some("deskhub::hub"), LevelFilter::Debug, 60))
+        );
+        assert_eq!(LevelRequest::Reset.check(), Ok(Change::Reset));
+        for invalid in [
+            request(None, "loud", 60),
+            request(Some(""), "debug", 60),
+            request(Some("deskhub hub"), "debug", 60),
+            request(None, "debug", 0),
+            request(None, "debug", MAX_DURATION.as_secs() + 1),
+        ] {
+            assert!(invalid.check().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn passes_requests_through_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = requests_path(dir.path());
+        assert_eq!(take_requests(&path).unwrap(), vec![]);
+        let requests = vec![
+            LevelRequest::Set {
+                target: None,
+                level: "debug".to_string(),
+                duration_secs: 60,
+            },
+            LevelRequest::Reset,
+        ];
+        for request in &requests {
+            submit(&path, request).unwrap();
+        }
+        assert_eq!(take_requests(&path).unwrap(), requests);
+        assert_eq!(take_requests(&path).unwrap(), vec![]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn ignores_requests_from_other_users() {
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = requests_path(dir.path());
+        submit(&path, &LevelRequest::Reset).unwrap();
+        std::os::unix::fs::chown(&path, Some(65534), None).unwrap();
+        let error = take_requests(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!path.exists() && !path.with_extension("taken").exists());
+    }
+}
@@ -0,0 +1,526 @@
+//Where each process writes its log, and in what shape.
+use chrono::{DateTime, Local};
+use fern::Dispatch;
+use std::ffi::OsString;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+pub mod forward;
+pub mod levels;
+pub mod rotate;
+
+use forward::LogSpool;
+use levels::{LevelControl, Levels};
+pub use rotate::Rotation;
+
+#[cfg(target_os = "windows")]
+static LOG_DIR: &str = "C:\\ProgramData\\DeskHub\\logs";
+#[cfg(target_os = "linux")]
+static LOG_DIR: &str = "/var/log/deskhub";
+
+pub fn default_dir() -> PathBuf {
+    PathBuf::from(LOG_DIR)
+}
+
+//Where the desktop and guide processes log. They run as the logged-on user, who cannot write the
+//service's directory.
+pub fn user_dir() -> Option<PathBuf> {
+    user_dir_from(|name| std::env::var_os(name))
+}
+
+fn user_dir_from(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let absolute = |name| {
+        var(name)
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+    };
+    if cfg!(target_os = "windows") {
+        return absolute("LOCALAPPDATA").map(|dir| dir.join("DeskHub").join("logs"));
+    }
+    absolute("XDG_STATE_HOME")
+        .or_else(|| absolute("HOME").map(|home| home.join(".local").join("state")))
+        .map(|dir| dir.join("deskhub"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    //One JSON object per line, for log shippers.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+//Where the formatted records go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogSink {
+    //The rotating file of the process, and stdout.
+    File,
+    //systemd-journald's native protocol, with structured fields.
+    Journald,
+    //RFC 5424 syslog messages.
+    Syslog,
+}
+
+impl FromStr for LogSink {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "file" => Ok(LogSink::File),
+            "journald" => Ok(LogSink::Journald),
+            "syslog" => Ok(LogSink::Syslog),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for LogSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogSink::File => write!(f, "file"),
+            LogSink::Journald => write!(f, "journald"),
+            LogSink::Syslog => write!(f, "syslog"),
+        }
+    }
+}
+
+//Each process logs to its own file, named after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Process {
+    Service,
+    Desktop,
+    Guide,
+}
+
+impl Process {
+    pub fn name(self) -> &'static str {
+        match self {
+            Process::Service => "service",
+            Process::Desktop => "desktop",
+            Process::Guide => "guide",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSettings {
+    pub directory: PathBuf,
+    pub format: LogFormat,
+    pub level: log::LevelFilter,
+    //Levels for particular modules, overriding `level`.
+    pub modules: Vec<(String, log::LevelFilter)>,
+    pub rotation: Rotation,
+    //Only the service uses a sink other than its file.
+    pub sink: LogSink,
+    //Overrides the sink's default socket.
+    pub socket: Option<PathBuf>,
+    //Only the service talks to the hub, so only it forwards.
+    pub forward: Option<ForwardSettings>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForwardSettings {
+    //Records at or above this level go to the hub as well.
+    pub level: log::LevelFilter,
+    //Records waiting for the hub take at most this much disk; the oldest are dropped first.
+    pub spool_max_bytes: u64,
+}
+
+impl LogSettings {
+    pub fn levels(&self) -> Levels {
+        Levels {
+            global: self.level,
+            modules: self.modules.clone(),
+        }
+    }
+}
+
+//Who and where this process is, as far as it knows yet, for sinks with structured fields.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogContext {
+    pub agent_id: Option<String>,
+    //The session the desktop process runs in.
+    pub session_id: Option<String>,
+}
+
+static CONTEXT: Mutex<LogContext> = Mutex::new(LogContext {
+    agent_id: None,
+    session_id: None,
+});
+
+pub fn context() -> LogContext {
+    CONTEXT.lock().unwrap().clone()
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_agent_id(agent_id: &str) {
+    CONTEXT.lock().unwrap().agent_id = Some(agent_id.to_string());
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_session_id(session_id: Option<String>) {
+    CONTEXT.lock().unwrap().session_id = session_id;
+}
+
+//Parses "deskhub::hub=debug,deskhub::capture=trace".
+pub fn parse_modules(value: &str) -> Result<Vec<(String, log::LevelFilter)>, String> {
+    let mut modules = Vec::new();
+    for directive in value.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let Some((module, level)) = directive.split_once('=') else {
+            return Err(format!("expected module=level, found {:?}", directive));
+        };
+        let module = module.trim();
+        if module.is_empty() || module.contains(char::is_whitespace) {
+            return Err(format!("invalid module name {:?}", module));
+        }
+        let level = level
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid level {:?} for {}", level.trim(), module))?;
+        modules.push((module.to_string(), level));
+    }
+    Ok(modules)
+}
+
+pub fn format_modules(modules: &[(String, log::LevelFilter)]) -> String {
+    modules
+        .iter()
+        .map(|(module, level)| format!("{}={}", module, level.as_str().to_lowercase()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+//One log line, without the newline.
+pub fn format_line(
+    format: LogFormat,
+    process: Process,
+    time: DateTime<Local>,
+    record: &log::Record,
+) -> String {
+    match format {
+        LogFormat::Text => format!(
+            "{}[{}][{}] {}",
+            time.format("[%Y-%m-%d][%H:%M:%S]"),
+            record.target(),
+            record.level(),
+            record.args()
+        ),
+        LogFormat::Json => serde_json::json!({
+            "time": time.to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "process": process.name(),
+            "message": record.args().to_string(),
+        })
+        .to_string(),
+    }
+}
+
+//The level filters every output shares; they can change while the logger runs.
+fn filtered(control: &LevelControl) -> Dispatch {
+    let control = control.clone();
+    Dispatch::new()
+        .level(log::LevelFilter::Trace)
+        .filter(move |metadata| control.enabled(metadata))
+}
+
+//Records as lines in the configured format.
+fn lines(format: LogFormat, process: Process) -> Dispatch {
+    //fern's message is the record's own arguments.
+    Dispatch::new().format(move |out, _, record| {
+        out.finish(format_args!(
+            "{}",
+            format_line(format, process, Local::now(), record)
+        ))
+    })
+}
+
+//Formatted lines in the rotating file of `process`.
+fn formatted(settings: &LogSettings, process: Process) -> io::Result<Dispatch> {
+    let file = rotate::RotatingFile::open(
+        &settings.directory,
+        process.name(),
+        settings.rotation.clone(),
+    )?;
+    Ok(lines(settings.format, process).chain(Box::new(file) as Box<dyn Write + Send>))
+}
+
+//Where the records of `process` go, per the configured sink.
+fn output(settings: &LogSettings, process: Process) -> io::Result<Dispatch> {
+    #[cfg(target_os = "linux")]
+    use crate::linux::{journald, syslog};
+    #[cfg(target_os = "linux")]
+    let socket = |default: &str| {
+        settings
+            .socket
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(default))
+    };
+    Ok(match settings.sink {
+        #[cfg(target_os = "linux")]
+        LogSink::Journald => Dispatch::new()
+            .chain(journald::Journald::new(socket(journald::SOCKET), process)?.output()),
+        #[cfg(target_os = "linux")]
+        LogSink::Syslog => {
+            Dispatch::new().chain(syslog::Syslog::new(socket(syslog::SOCKET), process)?.output())
+        }
+        //The config only allows the system sinks on Linux.
+        _ => formatted(settings, process)?.chain(io::stdout()),
+    })
+}
+
+//The configured output, or stdout alone if it cannot be opened, rather than no logging at all.
+fn output_or_stdout(settings: &LogSettings, process: Process) -> Dispatch {
+    output(settings, process).unwrap_or_else(|e| {
+        //The logger is not up yet to say so.
+        eprintln!(
+            "logging to stdout only, cannot log to {}: {}",
+            settings.directory.display(),
+            e
+        );
+        lines(settings.format, process).chain(io::stdout())
+    })
+}
+
+pub fn setup(settings: &LogSettings, process: Process) -> Result<(), fern::InitError> {
+    let control = LevelControl::new(settings.levels());
+    let mut dispatch = filtered(&control).chain(output_or_stdout(settings, process));
+    if let Some(forward) = &settings.forward {
+        let directory = settings.directory.join("spool");
+        match LogSpool::open(&directory, forward.spool_max_bytes) {
+            Ok(spool) => {
+                dispatch = dispatch.chain(
+                    Dispatch::new()
+                        .level(forward.level)
+                        .chain(spool.output(process)),
+                );
+                spool.install();
+            }
+            //Still log locally; the logger is not up yet to say so.
+            Err(e) => eprintln!(
+                "not forwarding logs, cannot open {}: {}",
+                directory.display(),
+                e
+            ),
+        }
+    }
+    dispatch.apply()?;
+    //fern lets everything through its filter, so it lifts log's maximum all the way.
+    control.set_max_level();
+    control.install();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::{Level, LevelFilter};
+    use std::fs;
+
+    fn settings(directory: PathBuf, format: LogFormat) -> LogSettings {
+        LogSettings {
+            directory,
+            format,
+            level: LevelFilter::Info,
+            modules: vec![("deskhub::hub".to_string(), LevelFilter::Trace)],
+            rotation: Rotation {
+                max_bytes: 1 << 20,
+                max_age: None,
+                keep: 3,
+            },
+            sink: LogSink::File,
+            socket: None,
+            forward: None,
+        }
+    }
+
+    fn log(logger: &dyn log::Log, target: &str, level: Level, message: &str) {
+        logger.log(
+            &log::Record::builder()
+                .args(format_args!("{}", message))
+                .level(level)
+                .target(target)
+                .build(),
+        );
+    }
+
+    #[test]
+    fn writes_json_lines_with_module_levels() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = settings(dir.path().join("logs"), LogFormat::Json);
+        let (_, logger) = filtered(&LevelControl::new(settings.levels()))
+            .chain(formatted(&settings, Process::Desktop).unwrap())
+            .into_log();
+        log(&*logger, "deskhub::hub", Level::Debug, "heartbeat \"ok\"");
+        log(&*logger, "deskhub::capture", Level::Debug, "dropped");
+        log(&*logger, "deskhub::capture", Level::Warn, "slow frame");
+        logger.flush();
+
+        let text = fs::read_to_string(dir.path().join("logs").join("desktop.log")).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["message"], "heartbeat \"ok\"");
+        assert_eq!(lines[0]["level"], "DEBUG");
+        assert_eq!(lines[0]["target"], "deskhub::hub");
+        assert_eq!(lines[0]["process"], "desktop");
+        assert_eq!(lines[1]["message"], "slow frame");
+        assert!(DateTime::parse_from_rfc3339(lines[1]["time"].as_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn writes_text_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = settings(dir.path().to_path_buf(), LogFormat::Text);
+        let (_, logger) = filtered(&LevelControl::new(settings.levels()))
+            .chain(formatted(&settings, Process::Service).unwrap())
+            .into_log();
+        log(&*logger, "deskhub::service", Level::Info, "started");
+        let text = fs::read_to_string(dir.path().join("service.log")).unwrap();
+        assert!(
+            text.ends_with("[deskhub::service][INFO] started\n"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn falls_back_to_stdout_when_the_directory_is_unusable() {
+        let dir = tempfile::tempdir().unwrap();
+        //A file where the directory should be: not writable, even for root.
+        let blocked = dir.path().join("logs");
+        fs::write(&blocked, "").unwrap();
+        let settings = settings(blocked.clone(), LogFormat::Text);
+        assert!(output(&settings, Process::Desktop).is_err());
+        let (_, logger) = filtered(&LevelControl::new(settings.levels()))
+            .chain(output_or_stdout(&settings, Process::Desktop))
+            .into_log();
+        assert!(logger.enabled(&log::Metadata::builder().level(Level::Info).build()));
+        log(&*logger, "deskhub::desk", Level::Info, "still logging");
+        assert!(blocked.is_file());
+    }
+
+    #[test]
+    fn user_processes_log_under_their_own_state_directory() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| OsString::from(value))
+            }
+        };
+        if cfg!(target_os = "windows") {
+            assert_eq!(
+                user_dir_from(env(&[("LOCALAPPDATA", "C:\\Users\\ann\\AppData\\Local")])),
+                Some(PathBuf::from(
+                    "C:\\Users\\ann\\AppData\\Local\\DeskHub\\logs"
+                ))
+            );
+            return;
+        }
+        assert_eq!(
+            user_dir_from(env(&[
+                ("XDG_STATE_HOME", "/home/ann/state"),
+                ("HOME", "/home/ann")
+            ])),
+            Some(PathBuf::from("/home/ann/state/deskhub"))
+        );
+        assert_eq!(
+            user_dir_from(env(&[
+                ("XDG_STATE_HOME", "relative"),
+                ("HOME", "/home/ann")
+            ])),
+            Some(PathBuf::from("/home/ann/.local/state/deskhub"))
+        );
+        assert_eq!(user_dir_from(env(&[])), None);
+    }
+
+    #[test]
+    fn forwards_unformatted_records_at_their_own_level() {
+        let dir = tempfile::tempdir().unwrap();
+        let settings = settings(dir.path().to_path_buf(), LogFormat::Text);
+        let spool = LogSpool::open(&dir.path().join("spool"), 1 << 20).unwrap();
+        let (_, logger) = filtered(&LevelControl::new(settings.levels()))
+            .chain(formatted(&settings, Process::Service).unwrap())
+            .chain(
+                Dispatch::new()
+                    .level(LevelFilter::Warn)
+                    .chain(spool.output(Process::Service)),
+            )
+            .into_log();
+        log(&*logger, "deskhub::hub", Level::Debug, "local only");
+        log(&*logger, "deskhub::hub", Level::Error, "connection refused");
+        spool.seal();
+
+        let batch = spool.oldest().unwrap();
+        assert_eq!(batch.entries.len(), 1);
+        let entry = &batch.entries[0];
+        assert_eq!(
+            (
+                entry.message.as_str(),
+                entry.level.as_str(),
+                entry.process.as_str()
+            ),
+            ("connection refused", "ERROR", "service")
+        );
+        let text = fs::read_to_string(dir.path().join("service.log")).unwrap();
+        assert_eq!(text.lines().count(), 2);
+    }
+
+    #[test]
+    fn parses_module_levels() {
+        let modules = parse_modules(" deskhub::hub=debug, deskhub::capture=TRACE ,").unwrap();
+        assert_eq!(
+            modules,
+            vec![
+                ("deskhub::hub".to_string(), LevelFilter::Debug),
+                ("deskhub::capture".to_string(), LevelFilter::Trace),
+            ]
+        );
+        assert_eq!(
+            format_modules(&modules),
+            "deskhub::hub=debug,deskhub::capture=trace"
+        );
+        assert_eq!(parse_modules(""), Ok(vec![]));
+        for invalid in ["deskhub::hub", "=debug", "deskhub::hub=loud", "a b=info"] {
+            assert!(parse_modules(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn formats_and_sinks_parse() {
+        for format in [LogFormat::Text, LogFormat::Json] {
+            assert_eq!(format.to_string().parse(), Ok(format));
+        }
+        assert_eq!("xml".parse::<LogFormat>(), Err(()));
+        for sink in [LogSink::File, LogSink::Journald, LogSink::Syslog] {
+            assert_eq!(sink.to_string().parse(), Ok(sink));
+        }
+        assert_eq!("eventlog".parse::<LogSink>(), Err(()));
+    }
+}
@@ -0,0 +1,246 @@
+use chrono::{DateTime, Local};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rotation {
+    //Start a new file once the current one has this many bytes.
+    pub max_bytes: u64,
+    //Start a new file once the current one is this old, if set.
+    pub max_age: Option<Duration>,
+    //Rotated files kept next to the current one; older ones are deleted.
+    pub keep: usize,
+}
+
+//Writes `<name>.log` in a directory, moving it aside as `<name>.<timestamp>.log` when it gets
+//too big or too old. Rotation only happens between lines, so no line is split across files.
+pub struct RotatingFile {
+    directory: PathBuf,
+    name: String,
+    rotation: Rotation,
+    file: File,
+    size: u64,
+    opened_at: SystemTime,
+    at_line_start: bool,
+}
+
+impl RotatingFile {
+    pub fn open(directory: &Path, name: &str, rotation: Rotation) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let path = directory.join(format!("{}.log", name));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        Ok(RotatingFile {
+            directory: directory.to_path_buf(),
+            name: name.to_string(),
+            rotation,
+            file,
+            size: metadata.len(),
+            //An existing file is as old as it is; not every filesystem knows when it was created.
+            opened_at: metadata.created().unwrap_or_else(|_| SystemTime::now()),
+            at_line_start: true,
+        })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.directory.join(format!("{}.log", self.name))
+    }
+
+    fn due(&self, now: SystemTime) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_old = self.rotation.max_age.is_some_and(|max_age| {
+            now.duration_since(self.opened_at)
+                .is_ok_and(|age| age >= max_age)
+        });
+        self.size >= self.rotation.max_bytes || too_old
+    }
+
+    fn rotate(&mut self, now: SystemTime) -> io::Result<()> {
+        let stamp = DateTime::<Local>::from(now)
+            .format("%Y%m%d-%H%M%S%.3f")
+            .to_string();
+        //Several rotations within a millisecond get numbered, after any already there.
+        let last = self
+            .rotated_keys()?
+            .into_iter()
+            .filter(|((taken, _), _)| *taken == stamp)
+            .map(|((_, n), _)| n)
+            .max();
+        let rotated = match last {
+            None => self.directory.join(format!("{}.{}.log", self.name, stamp)),
+            Some(n) => self
+                .directory
+                .join(format!("{}.{}.{}.log", self.name, stamp, n + 1)),
+        };
+        self.file.flush()?;
+        fs::rename(self.path(), &rotated)?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path())?;
+        self.size = 0;
+        self.opened_at = now;
+        self.prune()
+    }
+
+    //Rotated files, oldest first.
+    pub fn rotated(&self) -> io::Result<Vec<PathBuf>> {
+        Ok(self
+            .rotated_keys()?
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect())
+    }
+
+    //Rotated files by timestamp and number, oldest first.
+    fn rotated_keys(&self) -> io::Result<Vec<((String, u32), PathBuf)>> {
+        let prefix = format!("{}.", self.name);
+        let mut rotated: Vec<((String, u32), PathBuf)> = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let Some(middle) = file_name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".log"))
+            else {
+                continue;
+            };
+            //"20240102-030405.123", maybe followed by ".<n>".
+            let (stamp, n) = match middle.rsplit_once('.') {
+                Some((stamp, n)) if stamp.contains('.') => (stamp, n.parse().unwrap_or(0)),
+                _ => (middle, 0),
+            };
+            if stamp.is_empty() {
+                continue;
+            }
+            rotated.push(((stamp.to_string(), n), path));
+        }
+        rotated.sort();
+        Ok(rotated)
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        let rotated = self.rotated()?;
+        let excess = rotated.len().saturating_sub(self.rotation.keep);
+        for path in &rotated[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let now = SystemTime::now();
+        if self.at_line_start && self.due(now) {
+            //Better an oversized log than none.
+            if let Err(e) = self.rotate(now) {
+                eprintln!("failed to rotate {}: {}", self.path().display(), e);
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        if written > 0 {
+            self.at_line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rotation(max_bytes: u64, keep: usize) -> Rotation {
+        Rotation {
+            max_bytes,
+            max_age: None,
+            keep,
+        }
+    }
+
+    #[test]
+    fn rotates_by_size_between_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = RotatingFile::open(dir.path(), "service", rotation(20, 10)).unwrap();
+        //A line written in pieces stays whole even though it crosses the limit.
+        write!(file, "first line, ").unwrap();
+        writeln!(file, "long enough").unwrap();
+        writeln!(file, "second").unwrap();
+        writeln!(file, "third").unwrap();
+        let rotated = file.rotated().unwrap();
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(
+            fs::read_to_string(&rotated[0]).unwrap(),
+            "first line, long enough\n"
+        );
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "second\nthird\n");
+    }
+
+    #[test]
+    fn keeps_only_the_newest_files() {
+        let dir = tempfile::tempdir().unwrap();
+        //Another process's logs are left alone.
+        fs::write(dir.path().join("desktop.20200101-000000.000.log"), "x").unwrap();
+        let mut file = RotatingFile::open(dir.path(), "service", rotation(1, 3)).unwrap();
+        for line in 0..10 {
+            writeln!(file, "line {}", line).unwrap();
+        }
+        let rotated = file.rotated().unwrap();
+        let contents: Vec<String> = rotated
+            .iter()
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect();
+        assert_eq!(contents, vec!["line 6\n", "line 7\n", "line 8\n"]);
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "line 9\n");
+        assert!(dir.path().join("desktop.20200101-000000.000.log").exists());
+    }
+
+    #[test]
+    fn rotates_by_age() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = RotatingFile::open(
+            dir.path(),
+            "guide",
+            Rotation {
+                max_bytes: 1 << 20,
+                max_age: Some(Duration::from_secs(3600)),
+                keep: 5,
+            },
+        )
+        .unwrap();
+        writeln!(file, "yesterday").unwrap();
+        writeln!(file, "still fresh").unwrap();
+        assert!(file.rotated().unwrap().is_empty());
+        file.opened_at = SystemTime::now() - Duration::from_secs(7200);
+        writeln!(file, "today").unwrap();
+        let rotated = file.rotated().unwrap();
+        assert_eq!(
+            fs::read_to_string(&rotated[0]).unwrap(),
+            "yesterday\nstill fresh\n"
+        );
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "today\n");
+    }
+
+    #[test]
+    fn appends_to_an_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("service.log"), "before restart\n").unwrap();
+        let mut file = RotatingFile::open(dir.path(), "service", rotation(1 << 20, 3)).unwrap();
+        writeln!(file, "after restart").unwrap();
+        assert_eq!(
+            fs::read_to_string(file.path()).unwrap(),
+            "before restart\nafter restart\n"
+        );
+    }
+}
@@ -1,60 +1,130 @@
-use fern::Dispatch;
+use clap::Parser;
 use iced::{Application, Settings};
-use log;
-use std::env;
+use std::sync::{Arc, Mutex};
 
+mod backoff;
+mod capture;
+mod cli;
+mod clipboard;
+mod config;
+mod consent;
+//Not wired up until frames go out over the network.
+#[allow(dead_code)]
+mod congestion;
 mod desk;
+mod encode;
+mod guide;
+mod hub;
+mod input;
+mod install;
+mod ipc;
+#[cfg(target_os = "linux")]
+mod linux;
+mod logging;
+mod remote;
+mod service;
+mod session;
+mod supervisor;
+mod transfer;
 mod types;
 mod utils;
 #[cfg(target_os = "windows")]
 mod win32;
 
-fn setup_logging(file_path: String) -> Result<(), fern::InitError> {
-    Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{}[{}][{}] {}",
-                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
-                record.target(),
-                record.level(),
-                message
-            ))
-        })
-        .level(log::LevelFilter::Info)
-        .chain(fern::log_file(file_path)?)
-        .chain(std::io::stdout())
-        .apply()?;
-    Ok(())
+//The desktop and guide run as the logged-on user, so they log to a directory of the user's own.
+fn setup_user_logging(config: &config::Config, process: logging::Process) {
+    //Only the service is connected to the hub, and only it logs to the system.
+    let settings = logging::LogSettings {
+        directory: logging::user_dir().unwrap_or_else(|| config.log.directory.clone()),
+        sink: logging::LogSink::File,
+        forward: None,
+        ..config.log_settings()
+    };
+    if let Err(e) = logging::setup(&settings, process) {
+        eprintln!("logging is disabled: {}", e);
+    }
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let settings = Settings {
-        window: iced::window::Settings {
-            size: iced::Size::new(520.0, 360.0),
-            resizable: false,
-            ..Default::default()
-        },
+    let cli = cli::Cli::parse_from(cli::legacy_args(std::env::args_os()));
+    if let Some(cli::Command::Config {
+        command: cli::ConfigCommand::Check,
+    }) = cli.command
+    {
+        std::process::exit(cli::config_check());
+    }
+    let config = match config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(cli::EXIT_FAILURE);
+        }
+    };
+    let window = iced::window::Settings {
+        size: iced::Size::new(config.window.width as f32, config.window.height as f32),
+        resizable: false,
         ..Default::default()
     };
 
-    #[cfg(target_os = "windows")]
-    {
-        if args.iter().any(|arg| arg == "-service") {
-            //This l
-            setup_logging("C:\\deskhub_service_output.log".to_string())
+    let code = match cli.command {
+        None => {
+            setup_user_logging(&config, logging::Process::Guide);
+            let service: service::SharedServiceManager = Arc::new(Mutex::new(
+                service::new_service_manager(&config.service.name),
+            ));
+            let flags = guide::GuideFlags {
+                service,
+                install_dir: install::default_install_dir(),
+                desktop_state_path: config.desktop.state_path.clone(),
+            };
+            let settings = Settings {
+                window,
+                ..Settings::with_flags(flags)
+            };
+            guide::GuideWindow::run(settings)
+                .expect("An error occurred while running the application");
+            return;
+        }
+        Some(cli::Command::RunService) => {
+            logging::setup(&config.log_settings(), logging::Process::Service)
                 .expect("Failed to configure service logging.");
-            let result = win32::service_ctrl::service_dispatch();
+            #[cfg(target_os = "windows")]
+            let result = win32::service_ctrl::service_dispatch(config);
+            #[cfg(target_os = "linux")]
+            let result = linux::service_ctrl::service_dispatch(config);
             log::info!("service dispatch result: {}", result);
             return;
         }
-
-        if args.iter().any(|arg| arg == "-main") {
+        Some(cli::Command::RunDesktop) => {
+            setup_user_logging(&config, logging::Process::Desktop);
+            let flags = desk::DeskFlags {
+                capture: config.capture.clone(),
+                encoder: config.encoder_settings(),
+                input: config.input.clone(),
+                clipboard: config.clipboard_policy(),
+                transfer: config.transfer_policy(),
+                consent: config.consent_settings(),
+                ipc_path: config.desktop.ipc_path.clone(),
+            };
+            let settings = Settings {
+                window,
+                ..Settings::with_flags(flags)
+            };
             desk::DeskWindow::run(settings)
                 .expect("An error occurred while running the application");
             return;
         }
-
-        win32::GuideWindow::run(settings).expect("An error occurred while running the application");
-    }
+        Some(cli::Command::Install {
+            dry_run,
+            install_dir,
+        }) => cli::install(&config, dry_run, install_dir),
+        Some(cli::Command::Uninstall) => cli::uninstall(&config),
+        Some(cli::Command::Start) => cli::start(&config),
+        Some(cli::Command::Stop) => cli::stop(&config),
+        Some(cli::Command::Status { json }) => cli::status(&config, json),
+        Some(cli::Command::Enroll { token }) => cli::enroll(&config, &token),
+        Some(cli::Command::LogLevel { command }) => cli::log_level(&config, command),
+        Some(cli::Command::Config { .. }) => unreachable!("handled before loading the config"),
+    };
+    std::process::exit(code);
 }
@@ -0,0 +1,239 @@
+//Who is connected to this desktop right now, and what they may do.
+use crate::consent::Decision;
+use crate::hub::protocol::HubMessage;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{mpsc, watch};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Permissions {
+    pub view: bool,
+    pub control: bool,
+    pub files: bool,
+    pub clipboard: bool,
+}
+
+impl Permissions {
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.view, "view"),
+            (self.control, "control"),
+            (self.files, "files"),
+            (self.clipboard, "clipboard"),
+        ]
+        .into_iter()
+        .filter_map(|(granted, name)| granted.then_some(name))
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Viewer {
+    //As the hub identifies the viewer's connection.
+    pub id: String,
+    pub name: String,
+    pub permissions: Permissions,
+    pub joined_at: Instant,
+}
+
+struct Connected {
+    viewer: Viewer,
+    //Flipped to true to make the viewer's stream shut down.
+    ended: watch::Sender<bool>,
+}
+
+#[derive(Default)]
+struct State {
+    viewers: Vec<Connected>,
+    //When the first of the current viewers joined.
+    started_at: Option<Instant>,
+}
+
+//The viewers of this desktop. Clones share the same viewers.
+#[derive(Clone)]
+pub struct RemoteSessions {
+    state: Arc<Mutex<State>>,
+    hub: mpsc::UnboundedSender<HubMessage>,
+}
+
+impl RemoteSessions {
+    //Also returns the messages for the hub about sessions ending here.
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<HubMessage>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (
+            RemoteSessions {
+                state: Arc::default(),
+                hub: tx,
+            },
+            rx,
+        )
+    }
+
+    //Add a viewer, given what `Consent::ask` decided about them. Their stream must stop once the
+    //returned receiver turns true.
+    #[allow(dead_code)]
+    pub fn join(
+        &self,
+        viewer: Viewer,
+        consent: Decision,
+    ) -> Result<watch::Receiver<bool>, Decision> {
+        if !consent.allowed() {
+            log::info!("not letting {} in: {:?}", viewer.name, consent);
+            return Err(consent);
+        }
+        let (tx, rx) = watch::channel(false);
+        let mut state = self.state.lock().unwrap();
+        state
+            .viewers
+            .retain(|connected| connected.viewer.id != viewer.id);
+        state.started_at.get_or_insert(viewer.joined_at);
+        log::info!(
+            "{} joined with {}",
+            viewer.name,
+            viewer.permissions.names().join(", ")
+        );
+        state.viewers.push(Connected { viewer, ended: tx });
+        Ok(rx)
+    }
+
+    //The viewer went away on their own.
+    #[allow(dead_code)]
+    pub fn leave(&self, id: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.viewers.retain(|connected| connected.viewer.id != id);
+        if state.viewers.is_empty() {
+            state.started_at = None;
+        }
+    }
+
+    pub fn viewers(&self) -> Vec<Viewer> {
+        let state = self.state.lock().unwrap();
+        state
+            .viewers
+            .iter()
+            .map(|connected| connected.viewer.clone())
+            .collect()
+    }
+
+    pub fn started_at(&self) -> Option<Instant> {
+        self.state.lock().unwrap().started_at
+    }
+
+    //Disconnect every viewer at once and tell the hub why.
+    pub fn end(&self, reason: &str) {
+        let ended = {
+            let mut state = self.state.lock().unwrap();
+            state.started_at = None;
+            std::mem::take(&mut state.viewers)
+        };
+        if ended.is_empty() {
+            return;
+        }
+        for connected in &ended {
+            let _ = connected.ended.send(true);
+        }
+        let viewers: Vec<String> = ended
+            .into_iter()
+            .map(|connected| connected.viewer.id)
+            .collect();
+        log::info!("ended the session of {}: {}", viewers.join(", "), reason);
+        let message = HubMessage::SessionEnded {
+            viewers,
+            reason: reason.to_string(),
+        };
+        if self.hub.send(message).is_err() {
+            log::warn!("the hub was not told that the session ended");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn viewer(id: &str, joined_at: Instant) -> Viewer {
+        Viewer {
+            id: id.to_string(),
+            name: format!("{}@support", id),
+            permissions: Permissions {
+                view: true,
+                clipboard: true,
+                ..Permissions::default()
+            },
+            joined_at,
+        }
+    }
+
+    fn join(sessions: &RemoteSessions, viewer: Viewer) -> watch::Receiver<bool> {
+        sessions.join(viewer, Decision::Accepted).unwrap()
+    }
+
+    #[test]
+    fn tracks_viewers_and_duration() {
+        let (sessions, _hub) = RemoteSessions::new();
+        let start = Instant::now();
+        join(&sessions, viewer("alice", start));
+        join(&sessions, viewer("bob", start + Duration::from_secs(60)));
+        assert_eq!(sessions.started_at(), Some(start));
+        //Reconnecting replaces the viewer rather than listing them twice.
+        join(&sessions, viewer("alice", start + Duration::from_secs(90)));
+        let ids: Vec<String> = sessions.viewers().into_iter().map(|v| v.id).collect();
+        assert_eq!(ids, vec!["bob", "alice"]);
+        assert_eq!(sessions.started_at(), Some(start));
+
+        sessions.leave("alice");
+        sessions.leave("bob");
+        assert!(sessions.viewers().is_empty());
+        assert_eq!(sessions.started_at(), None);
+    }
+
+    #[test]
+    fn ending_stops_streams_and_tells_the_hub() {
+        let (sessions, mut hub) = RemoteSessions::new();
+        let alice = join(&sessions, viewer("alice", Instant::now()));
+        let bob = join(&sessions, viewer("bob", Instant::now()));
+        sessions.end("ended by the local user");
+        assert!(*alice.borrow() && *bob.borrow());
+        assert!(sessions.viewers().is_empty());
+        assert_eq!(
+            hub.try_recv().unwrap(),
+            HubMessage::SessionEnded {
+                viewers: vec!["alice".to_string(), "bob".to_string()],
+                reason: "ended by the local user".to_string(),
+            }
+        );
+        sessions.end("again");
+        assert!(hub.try_recv().is_err());
+    }
+
+    #[test]
+    fn only_consented_viewers_join() {
+        let (sessions, _hub) = RemoteSessions::new();
+        for decision in [Decision::Denied, Decision::TimedOut] {
+            assert_eq!(
+                sessions
+                    .join(viewer("alice", Instant::now()), decision)
+                    .err(),
+                Some(decision)
+            );
+        }
+        assert!(sessions.viewers().is_empty());
+        assert!(sessions
+            .join(viewer("alice", Instant::now()), Decision::NotAsked)
+            .is_ok());
+        assert_eq!(sessions.viewers().len(), 1);
+    }
+
+    #[test]
+    fn names_granted_permissions() {
+        let all = Permissions {
+            view: true,
+            control: true,
+            files: true,
+            clipboard: true,
+        };
+        assert_eq!(all.names(), vec!["view", "control", "files", "clipboard"]);
+        assert!(Permissions::default().names().is_empty());
+    }
+}
@@ -0,0 +1,150 @@
+use super::{ServiceError, ServiceManager, ServiceStatus};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Open,
+    Register,
+    Unregister,
+    SetCommandLine,
+    Start,
+    Stop,
+}
+
+#[derive(Debug)]
+struct FakeState {
+    status: ServiceStatus,
+    command_line: Option<String>,
+    failures: HashMap<Operation, ServiceError>,
+    calls: Vec<Operation>,
+}
+
+//In-memory service manager. Clones share state, so a test can keep one handle
+//to inject failures and inspect calls while the code under test owns another.
+#[derive(Debug, Clone)]
+pub struct FakeServiceManager {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl Default for FakeServiceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeServiceManager {
+    pub fn new() -> Self {
+        Self::with_status(ServiceStatus::DoesNotExist)
+    }
+
+    pub fn with_status(status: ServiceStatus) -> Self {
+        FakeServiceManager {
+            state: Arc::new(Mutex::new(FakeState {
+                status,
+                command_line: None,
+                failures: HashMap::new(),
+                calls: Vec::new(),
+            })),
+        }
+    }
+
+    //Make every later call to `operation` fail with `error` until cleared.
+    pub fn fail(&self, operation: Operation, error: ServiceError) {
+        self.state.lock().unwrap().failures.insert(operation, error);
+    }
+
+    pub fn clear_failure(&self, operation: Operation) {
+        self.state.lock().unwrap().failures.remove(&operation);
+    }
+
+    pub fn status(&self) -> ServiceStatus {
+        self.state.lock().unwrap().status.clone()
+    }
+
+    pub fn command_line(&self) -> Option<String> {
+        self.state.lock().unwrap().command_line.clone()
+    }
+
+    pub fn calls(&self) -> Vec<Operation> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    fn begin(
+        &self,
+        operation: Operation,
+    ) -> Result<std::sync::MutexGuard<'_, FakeState>, ServiceError> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(operation);
+        if let Some(error) = state.failures.get(&operation) {
+            return Err(error.clone());
+        }
+        Ok(state)
+    }
+}
+
+impl ServiceManager for FakeServiceManager {
+    fn open(&mut self) -> Result<(), ServiceError> {
+        let state = self.begin(Operation::Open)?;
+        if state.status == ServiceStatus::DoesNotExist {
+            return Err(ServiceError::DoesNotExist);
+        }
+        Ok(())
+    }
+
+    fn register(&mut self, _display_name: &str, command_line: &str) -> Result<(), ServiceError> {
+        let mut state = self.begin(Operation::Register)?;
+        if state.status != ServiceStatus::DoesNotExist {
+            return Err(ServiceError::AlreadyExists);
+        }
+        state.status = ServiceStatus::Stopped;
+        state.command_line = Some(command_line.to_string());
+        Ok(())
+    }
+
+    fn unregister(&mut self) -> Result<(), ServiceError> {
+        let mut state = self.begin(Operation::Unregister)?;
+        if state.status == ServiceStatus::DoesNotExist {
+            return Err(ServiceError::DoesNotExist);
+        }
+        state.status = ServiceStatus::DoesNotExist;
+        state.command_line = None;
+        Ok(())
+    }
+
+    fn command_line(&self) -> Result<String, ServiceError> {
+        let state = self.state.lock().unwrap();
+        state.command_line.clone().ok_or(ServiceError::DoesNotExist)
+    }
+
+    fn set_command_line(&mut self, command_line: &str) -> Result<(), ServiceError> {
+        let mut state = self.begin(Operation::SetCommandLine)?;
+        if state.status == ServiceStatus::DoesNotExist {
+            return Err(ServiceError::DoesNotExist);
+        }
+        state.command_line = Some(command_line.to_string());
+        Ok(())
+    }
+
+    fn start(&self) -> Result<(), ServiceError> {
+        let mut state = self.begin(Operation::Start)?;
+        if state.status == ServiceStatus::DoesNotExist {
+            return Err(ServiceError::DoesNotExist);
+        }
+        state.status = ServiceStatus::Running;
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), ServiceError> {
+        let mut state = self.begin(Operation::Stop)?;
+        if state.status == ServiceStatus::DoesNotExist {
+            return Err(ServiceError::DoesNotExist);
+        }
+        state.status = ServiceStatus::Stopped;
+        Ok(())
+    }
+
+    fn query_status(&self) -> ServiceStatus {
+        self.status()
+    }
+}
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[cfg(test)]
+pub mod fake;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceStatus {
+    Querying,
+    Running,
+    Stopped,
+    DoesNotExist,
+    #[default]
+    Unknown,
+}
+
+impl fmt::Display for ServiceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceStatus::Querying => write!(f, "Querying"),
+            ServiceStatus::Running => write!(f, "Running"),
+            ServiceStatus::Stopped => write!(f, "Stopped"),
+            ServiceStatus::DoesNotExist => write!(f, "Does Not Exist"),
+            ServiceStatus::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceError {
+    AccessDenied,
+    AlreadyExists,
+    DoesNotExist,
+    Timeout,
+    Os(i64, String),
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceError::AccessDenied => write!(f, "Access denied"),
+            ServiceError::AlreadyExists => write!(f, "The service already exists"),
+            ServiceError::DoesNotExist => write!(f, "The service does not exist"),
+            ServiceError::Timeout => write!(f, "Timed out waiting for the service"),
+            ServiceError::Os(code, message) => write!(f, "{}, {}", code, message),
+        }
+    }
+}
+
+//Operations the agent needs from the platform service manager (SCM on Windows, systemd on Linux).
+pub trait ServiceManager: Send {
+    //Connect to the service manager and look up the service; DoesNotExist if it is not registered.
+    fn open(&mut self) -> Result<(), ServiceError>;
+    //`command_line` is the full command the service manager runs, e.g. `"C:\deskhub.exe" run-service`.
+    fn register(&mut self, display_name: &str, command_line: &str) -> Result<(), ServiceError>;
+    fn unregister(&mut self) -> Result<(), ServiceError>;
+    //The command line an existing service was registered with.
+    fn command_line(&self) -> Result<String, ServiceError>;
+    //Point an existing service at another command line; takes effect on its next start.
+    fn set_command_line(&mut self, command_line: &str) -> Result<(), ServiceError>;
+    fn start(&self) -> Result<(), ServiceError>;
+    fn stop(&self) -> Result<(), ServiceError>;
+    fn query_status(&self) -> ServiceStatus;
+}
+
+pub static SERVICE_DISPLAY_NAME: &str = "DeskHubService";
+
+//Command line the service manager runs: `executable` in service mode.
+pub fn command_line_for(executable: &Path) -> String {
+    format!("\"{}\" run-service", executable.display())
+}
+
+//The GUI hands the manager to background commands, so it is shared behind a mutex.
+pub type SharedServiceManager = Arc<Mutex<Box<dyn ServiceManager>>>;
+
+#[cfg(target_os = "windows")]
+pub fn new_service_manager(service_name: &str) -> Box<dyn ServiceManager> {
+    Box::new(crate::win32::service::Service::new(service_name))
+}
+
+#[cfg(target_os = "linux")]
+pub fn new_service_manager(service_name: &str) -> Box<dyn ServiceManager> {
+    Box::new(crate::linux::service::SystemdService::new(service_name))
+}
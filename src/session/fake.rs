@@ -0,0 +1,51 @@
+use super::{SessionChange, SessionEvent, SessionEventSender, SessionEvents};
+
+//Session source driven by the test: every call emits one event, in order.
+#[derive(Debug, Clone)]
+pub struct ScriptedSessions {
+    events: SessionEventSender,
+}
+
+impl ScriptedSessions {
+    pub fn new() -> (Self, SessionEvents) {
+        let (events, receiver) = super::channel();
+        (ScriptedSessions { events }, receiver)
+    }
+
+    //A receiver that yields `script` and then reports the source as closed.
+    pub fn play(script: Vec<SessionEvent>) -> SessionEvents {
+        let (sessions, receiver) = Self::new();
+        for event in script {
+            sessions.emit(event);
+        }
+        receiver
+    }
+
+    pub fn emit(&self, event: SessionEvent) {
+        let _ = self.events.send(event);
+    }
+
+    pub fn logon(&self, session: &str) {
+        self.emit(SessionEvent::new(session, SessionChange::Logon));
+    }
+
+    pub fn logoff(&self, session: &str) {
+        self.emit(SessionEvent::new(session, SessionChange::Logoff));
+    }
+
+    pub fn lock(&self, session: &str) {
+        self.emit(SessionEvent::new(session, SessionChange::Lock));
+    }
+
+    pub fn unlock(&self, session: &str) {
+        self.emit(SessionEvent::new(session, SessionChange::Unlock));
+    }
+
+    pub fn console_connect(&self, session: &str) {
+        self.emit(SessionEvent::new(session, SessionChange::ConsoleConnect));
+    }
+
+    pub fn console_disconnect(&self, session: &str) {
+        self.emit(SessionEvent::new(session, SessionChange::ConsoleDisconnect));
+    }
+}
@@ -0,0 +1,40 @@
+//Login session changes, reported the same way on every OS so the desktop supervisor can react to them.
+use tokio::sync::mpsc;
+
+#[cfg(test)]
+pub mod fake;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionChange {
+    Logon,
+    Logoff,
+    Lock,
+    Unlock,
+    //The session became (or stopped being) the one shown on the physical console.
+    ConsoleConnect,
+    ConsoleDisconnect,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionEvent {
+    //OS session id: the WTS session id on Windows, the logind session id on Linux.
+    pub session: String,
+    pub change: SessionChange,
+}
+
+impl SessionEvent {
+    pub fn new(session: impl Into<String>, change: SessionChange) -> Self {
+        SessionEvent {
+            session: session.into(),
+            change,
+        }
+    }
+}
+
+//Sources push into the sender from whatever thread they run on; the supervisor owns the receiver.
+pub type SessionEvents = mpsc::UnboundedReceiver<SessionEvent>;
+pub type SessionEventSender = mpsc::UnboundedSender<SessionEvent>;
+
+pub fn channel() -> (SessionEventSender, SessionEvents) {
+    mpsc::unbounded_channel()
+}
@@ -0,0 +1,635 @@
+//Keeps the desktop process alive: launches it into the console session, notices when it exits and
+//launches it again with backoff, and follows the console user through logon, logoff and switching.
+//Launching is platform specific and sits behind `Launcher`; everything else here is OS-independent.
+use crate::backoff::Backoff;
+use crate::session::{SessionChange, SessionEvent, SessionEvents};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+#[cfg(target_os = "windows")]
+static STATE_PATH: &str = "C:\\ProgramData\\DeskHub\\desktop.json";
+#[cfg(target_os = "linux")]
+static STATE_PATH: &str = "/var/lib/deskhub/desktop.json";
+
+pub fn default_state_path() -> PathBuf {
+    PathBuf::from(STATE_PATH)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Exit {
+    //None when the process was killed without an exit code, e.g. by a signal.
+    pub code: Option<i32>,
+}
+
+pub trait ChildProcess: Send {
+    fn id(&self) -> u32;
+    //Some once the process has exited, None while it is still running.
+    fn try_wait(&mut self) -> io::Result<Option<Exit>>;
+    fn kill(&mut self) -> io::Result<()>;
+}
+
+pub struct Launched {
+    pub process: Box<dyn ChildProcess>,
+    //Session the process was started in, as reported by `SessionEvent::session`.
+    pub session: String,
+}
+
+#[derive(Debug)]
+pub enum LaunchError {
+    //Nobody is logged in at the console; the next logon or console connect triggers a launch.
+    NoSession(String),
+    Failed(io::Error),
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LaunchError::NoSession(reason) => write!(f, "no console session: {}", reason),
+            LaunchError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for LaunchError {
+    fn from(e: io::Error) -> Self {
+        LaunchError::Failed(e)
+    }
+}
+
+pub trait Launcher: Send {
+    fn launch(&mut self) -> Result<Launched, LaunchError>;
+}
+
+impl ChildProcess for std::process::Child {
+    fn id(&self) -> u32 {
+        std::process::Child::id(self)
+    }
+
+    fn try_wait(&mut self) -> io::Result<Option<Exit>> {
+        Ok(std::process::Child::try_wait(self)?.map(|status| Exit {
+            code: status.code(),
+        }))
+    }
+
+    fn kill(&mut self) -> io::Result<()> {
+        std::process::Child::kill(self)?;
+        self.wait().map(|_| ())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DesktopPhase {
+    #[default]
+    NotStarted,
+    Running,
+    //Waiting out the backoff before the next launch.
+    Restarting,
+    //Nobody is logged in at the console.
+    WaitingForSession,
+    //Exceeded max_restarts; stays down until the next logon or a service restart.
+    GaveUp,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DesktopState {
+    pub phase: DesktopPhase,
+    pub pid: Option<u32>,
+    pub session: Option<String>,
+    pub locked: bool,
+    //Launches after the first one, since the service started.
+    pub restarts: u32,
+    pub last_exit_code: Option<i32>,
+    pub last_exit_at: Option<String>,
+}
+
+//Reports the supervisor's current view of the desktop process.
+pub type DesktopStateFn = std::sync::Arc<dyn Fn() -> DesktopState + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RestartPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    //Consecutive short-lived runs before giving up; 0 keeps restarting forever.
+    pub max_restarts: u32,
+    //A run at least this long counts as healthy and resets the backoff and the failure count.
+    pub stable_after: Duration,
+    pub poll_interval: Duration,
+}
+
+struct Running {
+    process: Box<dyn ChildProcess>,
+    session: String,
+    started: Instant,
+}
+
+pub struct Supervisor {
+    launcher: Box<dyn Launcher>,
+    policy: RestartPolicy,
+    state: watch::Sender<DesktopState>,
+    backoff: Backoff,
+    failures: u32,
+    running: Option<Running>,
+    //When to launch next; None while waiting for a session or after giving up.
+    next_launch: Option<Instant>,
+}
+
+impl Supervisor {
+    pub fn new(
+        launcher: Box<dyn Launcher>,
+        policy: RestartPolicy,
+    ) -> (Self, watch::Receiver<DesktopState>) {
+        let (state, state_rx) = watch::channel(DesktopState::default());
+        (
+            Supervisor {
+                launcher,
+                backoff: Backoff::new(policy.initial_backoff, policy.max_backoff),
+                policy,
+                state,
+                failures: 0,
+                running: None,
+                next_launch: Some(Instant::now()),
+            },
+            state_rx,
+        )
+    }
+
+    fn update(&self, change: impl FnOnce(&mut DesktopState)) {
+        self.state.send_modify(change);
+    }
+
+    //Supervise until `stop` changes; the running child is killed on the way out.
+    pub async fn run(mut self, mut stop: watch::Receiver<bool>, mut sessions: SessionEvents) {
+        let mut attempted = false;
+        let mut sessions_open = true;
+        while !*stop.borrow() {
+            if self.running.is_none() && self.next_launch.is_some_and(|at| at <= Instant::now()) {
+                if attempted {
+                    self.update(|state| state.restarts += 1);
+                }
+                attempted = true;
+                self.launch(sessions_open);
+            }
+            let next_launch = self.next_launch.unwrap_or_else(Instant::now);
+            tokio::select! {
+                _ = stop.changed() => break,
+                event = sessions.recv(), if sessions_open => match event {
+                    Some(event) => self.on_session_event(event),
+                    None => sessions_open = false,
+                },
+                _ = tokio::time::sleep(self.policy.poll_interval), if self.running.is_some() => {
+                    self.poll();
+                }
+                _ = tokio::time::sleep_until(next_launch),
+                    if self.running.is_none() && self.next_launch.is_some() => {}
+            }
+        }
+        self.kill();
+        self.update(|state| state.phase = DesktopPhase::Stopped);
+    }
+
+    fn launch(&mut self, sessions_open: bool) {
+        self.next_launch = None;
+        match self.launcher.launch() {
+            Ok(Launched { process, session }) => {
+                let pid = process.id();
+                log::info!("desktop process {} started in session {}", pid, session);
+                self.update(|state| {
+                    state.phase = DesktopPhase::Running;
+                    state.pid = Some(pid);
+                    state.session = Some(session.clone());
+                    state.locked = false;
+                });
+                self.running = Some(Running {
+                    process,
+                    session,
+                    started: Instant::now(),
+                });
+            }
+            Err(LaunchError::NoSession(reason)) => {
+                log::info!("waiting for a console session: {}", reason);
+                self.update(|state| state.phase = DesktopPhase::WaitingForSession);
+                //Without session events nothing would wake us, so fall back to polling.
+                if !sessions_open {
+                    self.next_launch = Some(Instant::now() + self.policy.max_backoff);
+                }
+            }
+            Err(LaunchError::Failed(e)) => {
+                log::error!("failed to launch desktop process: {}", e);
+                self.schedule_retry(Duration::ZERO);
+            }
+        }
+    }
+
+    fn poll(&mut self) {
+        let Some(running) = self.running.as_mut() else {
+            return;
+        };
+        let pid = running.process.id();
+        let exit = match running.process.try_wait() {
+            Ok(Some(exit)) => exit,
+            Ok(None) => return,
+            Err(e) => {
+                log::warn!("failed to poll desktop process {}: {}", pid, e);
+                return;
+            }
+        };
+        let ran_for = running.started.elapsed();
+        self.running = None;
+        log::warn!("desktop process {} exited with {:?}", pid, exit.code);
+        self.update(|state| {
+            state.pid = None;
+            state.last_exit_code = exit.code;
+            state.last_exit_at = Some(chrono::Utc::now().to_rfc3339());
+        });
+        self.schedule_retry(ran_for);
+    }
+
+    fn schedule_retry(&mut self, ran_for: Duration) {
+        if ran_for >= self.policy.stable_after {
+            self.backoff.reset();
+            self.failures = 0;
+        }
+        self.failures += 1;
+        if self.policy.max_restarts > 0 && self.failures > self.policy.max_restarts {
+            log::error!(
+                "desktop process failed {} times in a row, not restarting it",
+                self.failures
+            );
+            self.update(|state| state.phase = DesktopPhase::GaveUp);
+            return;
+        }
+        let delay = self.backoff.next_delay();
+        log::info!("restarting desktop process in {:?}", delay);
+        self.update(|state| state.phase = DesktopPhase::Restarting);
+        self.next_launch = Some(Instant::now() + delay);
+    }
+
+    fn on_session_event(&mut self, event: SessionEvent) {
+        log::info!("session {} changed: {:?}", event.session, event.change);
+        let ours = self
+            .running
+            .as_ref()
+            .is_some_and(|running| running.session == event.session);
+        match event.change {
+            //Someone new at the console gets a fresh desktop, even after we gave up.
+            SessionChange::Logon | SessionChange::ConsoleConnect if self.running.is_none() => {
+                self.backoff.reset();
+                self.failures = 0;
+                self.next_launch = Some(Instant::now());
+            }
+            SessionChange::Logoff | SessionChange::ConsoleDisconnect if ours => {
+                self.kill();
+                self.update(|state| state.phase = DesktopPhase::WaitingForSession);
+                self.next_launch = None;
+            }
+            SessionChange::Lock if ours => self.update(|state| state.locked = true),
+            SessionChange::Unlock if ours => self.update(|state| state.locked = false),
+            _ => {}
+        }
+    }
+
+    fn kill(&mut self) {
+        if let Some(mut running) = self.running.take() {
+            if let Err(e) = running.process.kill() {
+                log::warn!(
+                    "failed to kill desktop process {}: {}",
+                    running.process.id(),
+                    e
+                );
+            }
+            self.update(|state| {
+                state.pid = None;
+                state.locked = false;
+            });
+        }
+    }
+}
+
+//Mirror the state into a file, so the guide window (a separate process) can show it.
+pub async fn persist_state(mut state: watch::Receiver<DesktopState>, path: PathBuf) {
+    loop {
+        let snapshot = state.borrow_and_update().clone();
+        if let Err(e) = save_state(&path, &snapshot) {
+            log::warn!("failed to write {}: {}", path.display(), e);
+        }
+        if state.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+fn save_state(path: &Path, state: &DesktopState) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+    fs::rename(&tmp, path)
+}
+
+pub fn load_state(path: &Path) -> Option<DesktopState> {
+    let text = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::fake::ScriptedSessions;
+    use std::process::Command;
+    use std::sync::{Arc, Mutex};
+
+    //Runs `sh -c <script>` in the current console session, one script per launch; the last one
+    //is repeated. With no console session set, launching reports NoSession.
+    struct ScriptLauncher {
+        scripts: Vec<&'static str>,
+        console: Arc<Mutex<Option<&'static str>>>,
+        launches: Arc<Mutex<u32>>,
+    }
+
+    impl Launcher for ScriptLauncher {
+        fn launch(&mut self) -> Result<Launched, LaunchError> {
+            let Some(session) = *self.console.lock().unwrap() else {
+                return Err(LaunchError::NoSession("nobody is logged in".to_string()));
+            };
+            let mut launches = self.launches.lock().unwrap();
+            let script = self.scripts[(*launches as usize).min(self.scripts.len() - 1)];
+            *launches += 1;
+            Ok(Launched {
+                process: Box::new(Command::new("sh").arg("-c").arg(script).spawn()?),
+                session: session.to_string(),
+            })
+        }
+    }
+
+    struct FailingLauncher;
+
+    impl Launcher for FailingLauncher {
+        fn launch(&mut self) -> Result<Launched, LaunchError> {
+            Err(io::Error::new(io::ErrorKind::NotFound, "no such program").into())
+        }
+    }
+
+    fn policy(max_restarts: u32) -> RestartPolicy {
+        RestartPolicy {
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(40),
+            max_restarts,
+            stable_after: Duration::from_secs(60),
+            poll_interval: Duration::from_millis(10),
+        }
+    }
+
+    struct Harness {
+        state: watch::Receiver<DesktopState>,
+        stop: watch::Sender<bool>,
+        task: tokio::task::JoinHandle<()>,
+        sessions: ScriptedSessions,
+        console: Arc<Mutex<Option<&'static str>>>,
+        launches: Arc<Mutex<u32>>,
+    }
+
+    impl Harness {
+        async fn stop(self) -> DesktopState {
+            self.stop.send(true).unwrap();
+            tokio::time::timeout(Duration::from_secs(5), self.task)
+                .await
+                .expect("supervisor did not stop")
+                .unwrap();
+            let state = self.state.borrow().clone();
+            state
+        }
+    }
+
+    fn supervise(
+        scripts: Vec<&'static str>,
+        console: Option<&'static str>,
+        policy: RestartPolicy,
+    ) -> Harness {
+        let console = Arc::new(Mutex::new(console));
+        let launches = Arc::new(Mutex::new(0));
+        let launcher = ScriptLauncher {
+            scripts,
+            console: console.clone(),
+            launches: launches.clone(),
+        };
+        let (supervisor, state) = Supervisor::new(Box::new(launcher), policy);
+        let (sessions, events) = ScriptedSessions::new();
+        let (stop, stop_rx) = watch::channel(false);
+        let task = tokio::spawn(supervisor.run(stop_rx, events));
+        Harness {
+            state,
+            stop,
+            task,
+            sessions,
+            console,
+            launches,
+        }
+    }
+
+    async fn wait_for(
+        state: &mut watch::Receiver<DesktopState>,
+        done: impl Fn(&DesktopState) -> bool,
+    ) -> DesktopState {
+        tokio::time::timeout(Duration::from_secs(10), state.wait_for(|state| done(state)))
+            .await
+            .expect("supervisor did not reach the expected state")
+            .unwrap()
+            .clone()
+    }
+
+    fn process_exists(pid: u32) -> bool {
+        Path::new(&format!("/proc/{}", pid)).exists()
+    }
+
+    #[tokio::test]
+    async fn restarts_crashed_process_and_records_exit_code() {
+        let mut harness = supervise(vec!["exit 3", "exit 4", "sleep 30"], Some("2"), policy(5));
+        let running = wait_for(&mut harness.state, |state| {
+            state.phase == DesktopPhase::Running && state.restarts == 2
+        })
+        .await;
+        assert_eq!(running.last_exit_code, Some(4));
+        assert!(running.last_exit_at.is_some());
+        assert!(running.pid.is_some());
+        assert_eq!(running.session.as_deref(), Some("2"));
+
+        let stopped = harness.stop().await;
+        assert_eq!(stopped.phase, DesktopPhase::Stopped);
+        assert_eq!(stopped.pid, None);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_restarts() {
+        let mut harness = supervise(vec!["exit 1"], Some("2"), policy(2));
+        let gave_up = wait_for(&mut harness.state, |state| {
+            state.phase == DesktopPhase::GaveUp
+        })
+        .await;
+        assert_eq!(gave_up.restarts, 2);
+        assert_eq!(gave_up.last_exit_code, Some(1));
+        assert_eq!(*harness.launches.lock().unwrap(), 3);
+        harness.stop().await;
+    }
+
+    #[tokio::test]
+    async fn stop_kills_running_process() {
+        let mut harness = supervise(vec!["sleep 30"], Some("2"), policy(0));
+        let running = wait_for(&mut harness.state, |state| {
+            state.phase == DesktopPhase::Running
+        })
+        .await;
+        let pid = running.pid.unwrap();
+
+        let launches = harness.launches.clone();
+        assert_eq!(harness.stop().await.phase, DesktopPhase::Stopped);
+        assert_eq!(*launches.lock().unwrap(), 1);
+        //kill() reaps the child, so the pid no longer exists.
+        assert!(!process_exists(pid));
+    }
+
+    #[tokio::test]
+    async fn launch_failures_count_as_restarts() {
+        let (supervisor, mut state) = Supervisor::new(Box::new(FailingLauncher), policy(3));
+        let (_stop_tx, stop_rx) = watch::channel(false);
+        let (_sessions, events) = ScriptedSessions::new();
+        tokio::spawn(supervisor.run(stop_rx, events));
+        let gave_up = wait_for(&mut state, |state| state.phase == DesktopPhase::GaveUp).await;
+        assert_eq!(gave_up.restarts, 3);
+        assert_eq!(gave_up.last_exit_code, None);
+    }
+
+    #[tokio::test]
+    async fn waits_for_logon_before_launching() {
+        let mut harness = supervise(vec!["sleep 30"], None, policy(3));
+        wait_for(&mut harness.state, |state| {
+            state.phase == DesktopPhase::WaitingForSession
+        })
+        .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(*harness.launches.lock().unwrap(), 0);
+
+        *harness.console.lock().unwrap() = Some("5");
+        harness.sessions.logon("5");
+        let running = wait_for(&mut harness.state, |state| {
+            state.phase == DesktopPhase::Running
+        })
+        .await;
+        assert_eq!(running.session.as_deref(), Some("5"));
+        harness.stop().await;
+    }
+
+    #[tokio::test]
+    async fn follows_the_console_user_across_logoff_and_switch() {
+        let mut harness = supervise(vec!["sleep 30"], Some("2"), policy(3));
+        let first = wait_for(&mut harness.state, |state| {
+            state.phase == DesktopPhase::Running
+        })
+        .await;
+
+        //Another session going away does not touch ours.
+        harness.sessions.logoff("7");
+        harness.sessions.lock("2");
+        let locked = wait_for(&mut harness.state, |state| state.locked).await;
+        assert_eq!(locked.pid, first.pid);
+
+        harness.sessions.logoff("2");
+        *harness.console.lock().unwrap() = None;
+        let waiting = wait_for(&mut harness.state, |state| {
+            state.phase == DesktopPhase::WaitingForSession
+        })
+        .await;
+        assert_eq!(waiting.pid, None);
+        assert!(!waiting.locked);
+        assert!(!process_exists(first.pid.unwrap()));
+
+        *harness.console.lock().unwrap() = Some("3");
+        harness.sessions.console_connect("3");
+        let second = wait_for(&mut harness.state, |state| {
+            state.phase == DesktopPhase::Running
+        })
+        .await;
+        assert_eq!(second.session.as_deref(), Some("3"));
+        assert_ne!(second.pid, first.pid);
+
+        harness.sessions.console_disconnect("3");
+        wait_for(&mut harness.state, |state| {
+            state.phase == DesktopPhase::WaitingForSession
+        })
+        .await;
+        harness.stop().await;
+    }
+
+    #[tokio::test]
+    async fn logon_revives_a_desktop_that_gave_up() {
+        let mut harness = supervise(vec!["exit 1", "exit 1", "sleep 30"], Some("2"), policy(1));
+        wait_for(&mut harness.state, |state| {
+            state.phase == DesktopPhase::GaveUp
+        })
+        .await;
+        harness.sessions.unlock("2");
+        harness.sessions.logon("2");
+        wait_for(&mut harness.state, |state| {
+            state.phase == DesktopPhase::Running
+        })
+        .await;
+        harness.stop().await;
+    }
+
+    #[tokio::test]
+    async fn replays_a_scripted_session() {
+        let launches = Arc::new(Mutex::new(0));
+        let launcher = ScriptLauncher {
+            scripts: vec!["sleep 30"],
+            console: Arc::new(Mutex::new(Some("2"))),
+            launches: launches.clone(),
+        };
+        let (supervisor, mut state) = Supervisor::new(Box::new(launcher), policy(3));
+        let events = ScriptedSessions::play(vec![
+            SessionEvent::new("2", SessionChange::Lock),
+            SessionEvent::new("2", SessionChange::Unlock),
+            SessionEvent::new("2", SessionChange::Logoff),
+        ]);
+        let (stop, stop_rx) = watch::channel(false);
+        let task = tokio::spawn(supervisor.run(stop_rx, events));
+        //The launch happens before the first event is read, so the logoff ends the desktop.
+        let waiting = wait_for(&mut state, |state| {
+            state.phase == DesktopPhase::WaitingForSession
+        })
+        .await;
+        assert_eq!(waiting.restarts, 0);
+        assert_eq!(*launches.lock().unwrap(), 1);
+        stop.send(true).unwrap();
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn persists_state_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("desktop.json");
+        let (tx, rx) = watch::channel(DesktopState::default());
+        let task = tokio::spawn(persist_state(rx, path.clone()));
+
+        let state = DesktopState {
+            phase: DesktopPhase::Running,
+            pid: Some(42),
+            session: Some("2".to_string()),
+            restarts: 1,
+            last_exit_code: Some(3),
+            ..Default::default()
+        };
+        tx.send(state.clone()).unwrap();
+        drop(tx);
+        task.await.unwrap();
+        assert_eq!(load_state(&path), Some(state));
+        assert_eq!(load_state(&dir.path().join("missing.json")), None);
+    }
+}
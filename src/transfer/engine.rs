@@ -0,0 +1,735 @@
+use super::protocol::{read_frame, write_frame, TransferMessage, CHUNK_SIZE};
+use super::{check_name, Direction, TransferError, TransferEvent, TransferHistory, TransferPolicy};
+use ring::digest::{Context, SHA256};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+//Hex SHA-256 of a file's contents.
+pub async fn sha256_file(path: &Path) -> Result<String, TransferError> {
+    let mut file = File::open(path).await?;
+    let mut context = Context::new(&SHA256);
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buffer[..read]);
+    }
+    Ok(context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+fn check_sha256(sha256: &str) -> Result<(), TransferError> {
+    if sha256.len() != 64 || !sha256.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return Err(TransferError::Protocol(format!(
+            "invalid sha256 {:?}",
+            sha256
+        )));
+    }
+    Ok(())
+}
+
+//Data received so far sits next to the destination, named for the file and its hash so that
+//only the same file resumes from it.
+fn part_path(directory: &Path, name: &str, sha256: &str) -> PathBuf {
+    directory.join(format!(".{}.{}.part", name, &sha256[..16]))
+}
+
+fn message_id(message: &TransferMessage) -> u64 {
+    match message {
+        TransferMessage::Push { id, .. }
+        | TransferMessage::Pull { id, .. }
+        | TransferMessage::Offer { id, .. }
+        | TransferMessage::Accept { id, .. }
+        | TransferMessage::Reject { id, .. }
+        | TransferMessage::Chunk { id, .. }
+        | TransferMessage::Done { id }
+        | TransferMessage::Verified { id }
+        | TransferMessage::Failed { id, .. } => *id,
+    }
+}
+
+//The next frame, which must belong to transfer `id`.
+async fn reply<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    id: u64,
+) -> Result<(TransferMessage, Vec<u8>), TransferError> {
+    let Some((message, data)) = read_frame(stream).await? else {
+        return Err(TransferError::Closed);
+    };
+    if message_id(&message) != id {
+        return Err(TransferError::Protocol(format!(
+            "expected a message for transfer {}, got {:?}",
+            id, message
+        )));
+    }
+    Ok((message, data))
+}
+
+fn unexpected(message: TransferMessage) -> TransferError {
+    TransferError::Protocol(format!("unexpected {:?}", message))
+}
+
+//One file going one way, from either end.
+struct Transfer<'a> {
+    id: u64,
+    name: &'a str,
+    size: u64,
+    sha256: &'a str,
+    history: &'a TransferHistory,
+}
+
+impl Transfer<'_> {
+    fn started(&self, direction: Direction, resumed_at: u64) {
+        self.history.emit(TransferEvent::Started {
+            id: self.id,
+            name: self.name.to_string(),
+            direction,
+            size: self.size,
+            resumed_at,
+        });
+    }
+
+    fn finished<T>(&self, result: Result<T, TransferError>) -> Result<T, TransferError> {
+        match &result {
+            Ok(_) => self.history.emit(TransferEvent::Completed { id: self.id }),
+            Err(e) => self.history.emit(TransferEvent::Failed {
+                id: self.id,
+                reason: e.to_string(),
+            }),
+        }
+        result
+    }
+
+    //Stream `path` from `offset` on, then wait for the receiver's verdict.
+    async fn send<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        path: &Path,
+        offset: u64,
+    ) -> Result<(), TransferError> {
+        self.started(Direction::Outgoing, offset.min(self.size));
+        let result = self.send_chunks(stream, path, offset).await;
+        self.finished(result)
+    }
+
+    async fn send_chunks<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        path: &Path,
+        mut offset: u64,
+    ) -> Result<(), TransferError> {
+        if offset > self.size {
+            return Err(TransferError::Protocol(format!(
+                "resume offset {} is past the end of the file",
+                offset
+            )));
+        }
+        let mut file = File::open(path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        while offset < self.size {
+            let want = (self.size - offset).min(CHUNK_SIZE as u64) as usize;
+            file.read_exact(&mut buffer[..want]).await.map_err(|_| {
+                TransferError::Io(format!("{} changed while it was sent", path.display()))
+            })?;
+            let chunk = TransferMessage::Chunk {
+                id: self.id,
+                offset,
+            };
+            write_frame(stream, &chunk, &buffer[..want]).await?;
+            offset += want as u64;
+            self.history.emit(TransferEvent::Progress {
+                id: self.id,
+                transferred: offset,
+            });
+        }
+        write_frame(stream, &TransferMessage::Done { id: self.id }, &[]).await?;
+        match reply(stream, self.id).await?.0 {
+            TransferMessage::Verified { .. } => Ok(()),
+            TransferMessage::Failed { reason, .. } => {
+                log::warn!(
+                    "transfer {} failed on the receiving end: {}",
+                    self.id,
+                    reason
+                );
+                Err(TransferError::Corrupt)
+            }
+            other => Err(unexpected(other)),
+        }
+    }
+
+    //Take the file into `directory`, resuming from an earlier attempt's partial file if there is one.
+    async fn receive<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        directory: &Path,
+    ) -> Result<PathBuf, TransferError> {
+        let part = part_path(directory, self.name, self.sha256);
+        let offset = match fs::metadata(&part).await {
+            Ok(metadata) if metadata.len() <= self.size => metadata.len(),
+            _ => 0,
+        };
+        let accept = TransferMessage::Accept {
+            id: self.id,
+            offset,
+        };
+        write_frame(stream, &accept, &[]).await?;
+        self.started(Direction::Incoming, offset);
+        let result = self.receive_chunks(stream, &part, offset).await;
+        let result = match result {
+            Ok(()) => self.verify(stream, &part, directory).await,
+            Err(e) => Err(e),
+        };
+        self.finished(result)
+    }
+
+    async fn receive_chunks<S: AsyncRead + Unpin>(
+        &self,
+        stream: &mut S,
+        part: &Path,
+        mut offset: u64,
+    ) -> Result<(), TransferError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(offset == 0)
+            .open(part)
+            .await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        loop {
+            match reply(stream, self.id).await? {
+                (TransferMessage::Chunk { offset: at, .. }, data) => {
+                    if at != offset || offset + data.len() as u64 > self.size {
+                        return Err(TransferError::Protocol(format!(
+                            "chunk of {} bytes at {} does not follow {} of {}",
+                            data.len(),
+                            at,
+                            offset,
+                            self.size
+                        )));
+                    }
+                    file.write_all(&data).await?;
+                    offset += data.len() as u64;
+                    self.history.emit(TransferEvent::Progress {
+                        id: self.id,
+                        transferred: offset,
+                    });
+                }
+                (TransferMessage::Done { .. }, _) if offset == self.size => break,
+                (TransferMessage::Done { .. }, _) => {
+                    return Err(TransferError::Protocol(format!(
+                        "done after {} of {} bytes",
+                        offset, self.size
+                    )))
+                }
+                (other, _) => return Err(unexpected(other)),
+            }
+        }
+        file.sync_all().await?;
+        Ok(())
+    }
+
+    //Move the complete file into place if it hashes right, else drop it so the next attempt
+    //starts over.
+    async fn verify<S: AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        part: &Path,
+        directory: &Path,
+    ) -> Result<PathBuf, TransferError> {
+        if sha256_file(part).await? != self.sha256.to_ascii_lowercase() {
+            let _ = fs::remove_file(part).await;
+            let failed = TransferMessage::Failed {
+                id: self.id,
+                reason: TransferError::Corrupt.to_string(),
+            };
+            write_frame(stream, &failed, &[]).await?;
+            return Err(TransferError::Corrupt);
+        }
+        let destination = directory.join(self.name);
+        //`check_name` should already guarantee this; never move a file anywhere else.
+        if destination.parent() != Some(directory) {
+            let _ = fs::remove_file(part).await;
+            let error = TransferError::Rejected(format!("invalid file name {:?}", self.name));
+            let failed = TransferMessage::Failed {
+                id: self.id,
+                reason: error.to_string(),
+            };
+            write_frame(stream, &failed, &[]).await?;
+            return Err(error);
+        }
+        fs::rename(part, &destination).await?;
+        write_frame(stream, &TransferMessage::Verified { id: self.id }, &[]).await?;
+        Ok(destination)
+    }
+}
+
+//The agent's end: serves pushes into and pulls out of the allowed directories.
+#[derive(Clone)]
+pub struct TransferService {
+    policy: TransferPolicy,
+    history: TransferHistory,
+}
+
+impl TransferService {
+    pub fn new(policy: TransferPolicy, history: TransferHistory) -> Self {
+        TransferService { policy, history }
+    }
+
+    pub fn history(&self) -> &TransferHistory {
+        &self.history
+    }
+
+    //Handle transfers one after another until the viewer closes the stream.
+    #[allow(dead_code)]
+    pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        mut stream: S,
+    ) -> Result<(), TransferError> {
+        while let Some((message, _)) = read_frame(&mut stream).await? {
+            let id = message_id(&message);
+            let result = match message {
+                TransferMessage::Push {
+                    name,
+                    directory,
+                    size,
+                    sha256,
+                    ..
+                } => {
+                    self.push(&mut stream, id, &name, &directory, size, &sha256)
+                        .await
+                }
+                TransferMessage::Pull { path, .. } => self.pull(&mut stream, id, &path).await,
+                other => Err(unexpected(other)),
+            };
+            match result {
+                Ok(()) => {}
+                Err(TransferError::Rejected(reason)) => {
+                    log::warn!("refused transfer {}: {}", id, reason);
+                    write_frame(&mut stream, &TransferMessage::Reject { id, reason }, &[]).await?;
+                }
+                //The viewer was told and may try again.
+                Err(TransferError::Corrupt) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn push<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        id: u64,
+        name: &str,
+        directory: &str,
+        size: u64,
+        sha256: &str,
+    ) -> Result<(), TransferError> {
+        check_name(name)?;
+        check_sha256(sha256)?;
+        self.check_size(size)?;
+        let directory = self.policy.resolve(Path::new(directory))?;
+        if !directory.is_dir() {
+            return Err(TransferError::Rejected(format!(
+                "{} is not a directory",
+                directory.display()
+            )));
+        }
+        let transfer = Transfer {
+            id,
+            name,
+            size,
+            sha256,
+            history: &self.history,
+        };
+        let path = transfer.receive(stream, &directory).await?;
+        log::info!("received {} ({} bytes)", path.display(), size);
+        Ok(())
+    }
+
+    async fn pull<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        id: u64,
+        path: &str,
+    ) -> Result<(), TransferError> {
+        let path = self.policy.resolve(Path::new(path))?;
+        let metadata = fs::metadata(&path).await?;
+        if !metadata.is_file() {
+            return Err(TransferError::Rejected(format!(
+                "{} is not a file",
+                path.display()
+            )));
+        }
+        self.check_size(metadata.len())?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let sha256 = sha256_file(&path).await?;
+        let offer = TransferMessage::Offer {
+            id,
+            name: name.clone(),
+            size: metadata.len(),
+            sha256: sha256.clone(),
+        };
+        write_frame(stream, &offer, &[]).await?;
+        let offset = match reply(stream, id).await?.0 {
+            TransferMessage::Accept { offset, .. } => offset,
+            TransferMessage::Reject { reason, .. } => {
+                log::info!("viewer declined {}: {}", path.display(), reason);
+                return Ok(());
+            }
+            other => return Err(unexpected(other)),
+        };
+        let transfer = Transfer {
+            id,
+            name: &name,
+            size: metadata.len(),
+            sha256: &sha256,
+            history: &self.history,
+        };
+        transfer.send(stream, &path, offset).await?;
+        log::info!("sent {} ({} bytes)", path.display(), metadata.len());
+        Ok(())
+    }
+
+    fn check_size(&self, size: u64) -> Result<(), TransferError> {
+        if size > self.policy.max_file_bytes {
+            return Err(TransferError::Rejected(format!(
+                "{} bytes is over the {} byte limit",
+                size, self.policy.max_file_bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+//The viewer's end of the protocol; the agent itself only ever serves.
+#[allow(dead_code)]
+pub struct TransferClient<S> {
+    stream: S,
+    history: TransferHistory,
+    next_id: u64,
+}
+
+#[allow(dead_code)]
+impl<S: AsyncRead + AsyncWrite + Unpin> TransferClient<S> {
+    pub fn new(stream: S, history: TransferHistory) -> Self {
+        TransferClient {
+            stream,
+            history,
+            next_id: 1,
+        }
+    }
+
+    fn id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    //Send `local` into `directory` on the agent.
+    pub async fn push(&mut self, local: &Path, directory: &str) -> Result<(), TransferError> {
+        let id = self.id();
+        let name = local
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| TransferError::Io(format!("{} is not a file", local.display())))?;
+        let size = fs::metadata(local).await?.len();
+        let sha256 = sha256_file(local).await?;
+        let push = TransferMessage::Push {
+            id,
+            name: name.clone(),
+            directory: directory.to_string(),
+            size,
+            sha256: sha256.clone(),
+        };
+        write_frame(&mut self.stream, &push, &[]).await?;
+        let offset = match reply(&mut self.stream, id).await?.0 {
+            TransferMessage::Accept { offset, .. } => offset,
+            TransferMessage::Reject { reason, .. } => return Err(TransferError::Rejected(reason)),
+            other => return Err(unexpected(other)),
+        };
+        let transfer = Transfer {
+            id,
+            name: &name,
+            size,
+            sha256: &sha256,
+            history: &self.history,
+        };
+        transfer.send(&mut self.stream, local, offset).await
+    }
+
+    //Fetch `path` from the agent into `directory`, returning where it was stored.
+    pub async fn pull(&mut self, path: &str, directory: &Path) -> Result<PathBuf, TransferError> {
+        let id = self.id();
+        write_frame(
+            &mut self.stream,
+            &TransferMessage::Pull {
+                id,
+                path: path.to_string(),
+            },
+            &[],
+        )
+        .await?;
+        let (name, size, sha256) = match reply(&mut self.stream, id).await?.0 {
+            TransferMessage::Offer {
+                name, size, sha256, ..
+            } => (name, size, sha256),
+            TransferMessage::Reject { reason, .. } => return Err(TransferError::Rejected(reason)),
+            other => return Err(unexpected(other)),
+        };
+        check_name(&name)?;
+        check_sha256(&sha256)?;
+        let transfer = Transfer {
+            id,
+            name: &name,
+            size,
+            sha256: &sha256,
+            history: &self.history,
+        };
+        transfer.receive(&mut self.stream, directory).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::TransferState;
+    use super::*;
+    use tempfile::TempDir;
+    use tokio::io::DuplexStream;
+    use tokio::task::JoinHandle;
+
+    //Not a multiple of the chunk size, so the last chunk is a short one.
+    const FILE_SIZE: usize = CHUNK_SIZE * 3 + 1234;
+
+    fn contents(seed: u8) -> Vec<u8> {
+        (0..FILE_SIZE)
+            .map(|i| (i as u32).wrapping_mul(2654435761).to_be_bytes()[0] ^ seed)
+            .collect()
+    }
+
+    struct Setup {
+        allowed: TempDir,
+        local: TempDir,
+        service: TransferService,
+    }
+
+    fn setup() -> Setup {
+        let allowed = tempfile::tempdir().unwrap();
+        let policy = TransferPolicy {
+            allowed_dirs: vec![allowed.path().to_path_buf()],
+            max_file_bytes: 1 << 20,
+        };
+        Setup {
+            allowed,
+            local: tempfile::tempdir().unwrap(),
+            service: TransferService::new(policy, TransferHistory::new()),
+        }
+    }
+
+    //The service on one end of an in-process pipe; the other end is returned.
+    fn connect(service: &TransferService) -> (DuplexStream, JoinHandle<Result<(), TransferError>>) {
+        let (viewer, agent) = tokio::io::duplex(16 * 1024);
+        let service = service.clone();
+        (
+            viewer,
+            tokio::spawn(async move { service.serve(agent).await }),
+        )
+    }
+
+    fn allowed_dir(setup: &Setup) -> String {
+        setup.allowed.path().to_string_lossy().into_owned()
+    }
+
+    #[tokio::test]
+    async fn pushes_and_pulls_files() {
+        let setup = setup();
+        let (stream, server) = connect(&setup.service);
+        let viewer_history = TransferHistory::new();
+        let mut progress = viewer_history.subscribe();
+        let mut client = TransferClient::new(stream, viewer_history.clone());
+
+        let installer = setup.local.path().join("setup.exe");
+        std::fs::write(&installer, contents(1)).unwrap();
+        client.push(&installer, &allowed_dir(&setup)).await.unwrap();
+        let pushed = setup.allowed.path().join("setup.exe");
+        assert_eq!(std::fs::read(&pushed).unwrap(), contents(1));
+
+        let mut transferred = Vec::new();
+        while let Ok(event) = progress.try_recv() {
+            if let TransferEvent::Progress {
+                transferred: at, ..
+            } = event
+            {
+                transferred.push(at);
+            }
+        }
+        assert_eq!(
+            transferred,
+            vec![
+                CHUNK_SIZE as u64,
+                2 * CHUNK_SIZE as u64,
+                3 * CHUNK_SIZE as u64,
+                FILE_SIZE as u64
+            ]
+        );
+
+        let log = setup.allowed.path().join("agent.log");
+        std::fs::write(&log, contents(2)).unwrap();
+        let pulled = client
+            .pull(&log.to_string_lossy(), setup.local.path())
+            .await
+            .unwrap();
+        assert_eq!(pulled, setup.local.path().join("agent.log"));
+        assert_eq!(std::fs::read(&pulled).unwrap(), contents(2));
+        //The agent hears the verdict on the pull after the viewer has it.
+        drop(client);
+        assert_eq!(server.await.unwrap(), Ok(()));
+
+        for (history, first) in [
+            (&viewer_history, Direction::Outgoing),
+            (setup.service.history(), Direction::Incoming),
+        ] {
+            let records = history.records();
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[1].direction, first);
+            assert_ne!(records[0].direction, first);
+            assert!(records
+                .iter()
+                .all(|record| record.state == TransferState::Completed
+                    && record.transferred == FILE_SIZE as u64));
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_paths_outside_the_policy() {
+        let setup = setup();
+        let (stream, server) = connect(&setup.service);
+        let mut client = TransferClient::new(stream, TransferHistory::new());
+        let file = setup.local.path().join("payload.sh");
+        std::fs::write(&file, b"echo hi").unwrap();
+
+        let outside = setup.local.path().to_string_lossy().into_owned();
+        let escape = format!("{}/..", allowed_dir(&setup));
+        for directory in [outside.as_str(), escape.as_str()] {
+            assert!(matches!(
+                client.push(&file, directory).await,
+                Err(TransferError::Rejected(_))
+            ));
+        }
+        assert!(matches!(
+            client
+                .pull(&file.to_string_lossy(), setup.local.path())
+                .await,
+            Err(TransferError::Rejected(_))
+        ));
+        assert!(matches!(
+            client.pull(&allowed_dir(&setup), setup.local.path()).await,
+            Err(TransferError::Rejected(_))
+        ));
+        std::fs::write(&file, vec![0; 2 << 20]).unwrap();
+        assert!(matches!(
+            client.push(&file, &allowed_dir(&setup)).await,
+            Err(TransferError::Rejected(_))
+        ));
+
+        //A file name with a path in it never comes from our client, but may from others.
+        let mut stream = client.stream;
+        let push = TransferMessage::Push {
+            id: 9,
+            name: "../escaped".to_string(),
+            directory: allowed_dir(&setup),
+            size: 1,
+            sha256: "0".repeat(64),
+        };
+        write_frame(&mut stream, &push, &[]).await.unwrap();
+        assert!(matches!(
+            read_frame(&mut stream).await.unwrap(),
+            Some((TransferMessage::Reject { id: 9, .. }, _))
+        ));
+        assert!(setup.service.history().records().is_empty());
+        drop(stream);
+        assert_eq!(server.await.unwrap(), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn resumes_an_interrupted_push() {
+        let setup = setup();
+        let file = setup.local.path().join("big.iso");
+        std::fs::write(&file, contents(3)).unwrap();
+        let sha256 = sha256_file(&file).await.unwrap();
+
+        //The connection drops after the first chunk.
+        let (mut stream, server) = connect(&setup.service);
+        let push = TransferMessage::Push {
+            id: 1,
+            name: "big.iso".to_string(),
+            directory: allowed_dir(&setup),
+            size: FILE_SIZE as u64,
+            sha256: sha256.clone(),
+        };
+        write_frame(&mut stream, &push, &[]).await.unwrap();
+        assert_eq!(
+            read_frame(&mut stream).await.unwrap(),
+            Some((TransferMessage::Accept { id: 1, offset: 0 }, vec![]))
+        );
+        let chunk = TransferMessage::Chunk { id: 1, offset: 0 };
+        write_frame(&mut stream, &chunk, &contents(3)[..CHUNK_SIZE])
+            .await
+            .unwrap();
+        drop(stream);
+        assert_eq!(server.await.unwrap(), Err(TransferError::Closed));
+        let records = setup.service.history().records();
+        assert!(matches!(records[0].state, TransferState::Failed(_)));
+        assert_eq!(records[0].transferred, CHUNK_SIZE as u64);
+
+        let (stream, server) = connect(&setup.service);
+        let mut client = TransferClient::new(stream, TransferHistory::new());
+        client.push(&file, &allowed_dir(&setup)).await.unwrap();
+        assert_eq!(
+            std::fs::read(setup.allowed.path().join("big.iso")).unwrap(),
+            contents(3)
+        );
+        assert!(!part_path(setup.allowed.path(), "big.iso", &sha256).exists());
+        let events = setup.service.history().records();
+        assert_eq!(events[0].state, TransferState::Completed);
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn discards_corrupt_partial_files() {
+        let setup = setup();
+        let file = setup.local.path().join("setup.msi");
+        std::fs::write(&file, contents(4)).unwrap();
+        let sha256 = sha256_file(&file).await.unwrap();
+        let part = part_path(setup.allowed.path(), "setup.msi", &sha256);
+        std::fs::write(&part, vec![0xee; CHUNK_SIZE]).unwrap();
+
+        let (stream, server) = connect(&setup.service);
+        let mut client = TransferClient::new(stream, TransferHistory::new());
+        assert_eq!(
+            client.push(&file, &allowed_dir(&setup)).await,
+            Err(TransferError::Corrupt)
+        );
+        assert!(!part.exists());
+        assert!(!setup.allowed.path().join("setup.msi").exists());
+
+        client.push(&file, &allowed_dir(&setup)).await.unwrap();
+        assert_eq!(
+            std::fs::read(setup.allowed.path().join("setup.msi")).unwrap(),
+            contents(4)
+        );
+        drop(client);
+        server.await.unwrap().unwrap();
+    }
+}
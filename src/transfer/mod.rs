@@ -0,0 +1,372 @@
+//Chunked, resumable file transfer between a viewer and the agent, over any byte stream.
+use chrono::{DateTime, Local};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+pub mod engine;
+pub mod protocol;
+
+#[cfg(target_os = "windows")]
+static TRANSFER_DIR: &str = "C:\\ProgramData\\DeskHub\\Transfers";
+#[cfg(target_os = "linux")]
+static TRANSFER_DIR: &str = "/var/lib/deskhub/transfers";
+
+//Transfers kept in the history once they are over.
+const HISTORY_LEN: usize = 50;
+
+pub fn default_dir() -> PathBuf {
+    PathBuf::from(TRANSFER_DIR)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferError {
+    Io(String),
+    //The other side broke the protocol.
+    Protocol(String),
+    //The other side refused the transfer.
+    Rejected(String),
+    //The received file does not hash to what the sender announced.
+    Corrupt,
+    //The stream ended mid-transfer; a partial file is kept to resume from.
+    Closed,
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferError::Io(e) => write!(f, "{}", e),
+            TransferError::Protocol(e) => write!(f, "protocol error: {}", e),
+            TransferError::Rejected(reason) => write!(f, "rejected: {}", reason),
+            TransferError::Corrupt => write!(f, "checksum mismatch"),
+            TransferError::Closed => write!(f, "connection closed mid-transfer"),
+        }
+    }
+}
+
+impl From<io::Error> for TransferError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset => TransferError::Closed,
+            _ => TransferError::Io(e.to_string()),
+        }
+    }
+}
+
+//Where the agent may read files from and write them to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferPolicy {
+    pub allowed_dirs: Vec<PathBuf>,
+    pub max_file_bytes: u64,
+}
+
+impl TransferPolicy {
+    //`path` with links and `..` resolved, if it exists inside an allowed directory.
+    pub fn resolve(&self, path: &Path) -> Result<PathBuf, TransferError> {
+        let denied = || {
+            TransferError::Rejected(format!("{} is not in an allowed directory", path.display()))
+        };
+        let resolved = path.canonicalize().map_err(|_| denied())?;
+        let allowed = self
+            .allowed_dirs
+            .iter()
+            .filter_map(|dir| dir.canonicalize().ok())
+            .any(|dir| resolved.starts_with(dir));
+        if !allowed {
+            return Err(denied());
+        }
+        Ok(resolved)
+    }
+}
+
+//Names Windows maps to devices, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+//A file name with no directory part, safe to join onto a destination directory on either platform.
+pub fn check_name(name: &str) -> Result<(), TransferError> {
+    let stem = name.split('.').next().unwrap_or("").trim_end();
+    let plain = !name.is_empty()
+        && name != "."
+        && name != ".."
+        //A colon makes a drive-relative path ("C:x") or an alternate data stream ("a:b").
+        && !name.contains(['/', '\\', '\0', ':'])
+        //Partial files are hidden this way, and must not be overwritten by name.
+        && !name.starts_with('.')
+        //Windows drops these, so the file would land under another name.
+        && !name.ends_with(['.', ' '])
+        && !RESERVED_NAMES
+            .iter()
+            .any(|reserved| stem.eq_ignore_ascii_case(reserved));
+    if !plain {
+        return Err(TransferError::Rejected(format!(
+            "invalid file name {:?}",
+            name
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    //Into this machine.
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferEvent {
+    Started {
+        id: u64,
+        name: String,
+        direction: Direction,
+        size: u64,
+        //Bytes already in place from an earlier, interrupted attempt.
+        resumed_at: u64,
+    },
+    Progress {
+        id: u64,
+        transferred: u64,
+    },
+    Completed {
+        id: u64,
+    },
+    Failed {
+        id: u64,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferState {
+    Running,
+    Completed,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferRecord {
+    pub id: u64,
+    pub name: String,
+    pub direction: Direction,
+    pub size: u64,
+    pub transferred: u64,
+    pub state: TransferState,
+    pub started_at: DateTime<Local>,
+}
+
+#[derive(Default)]
+struct HistoryState {
+    records: VecDeque<TransferRecord>,
+    listeners: Vec<mpsc::UnboundedSender<TransferEvent>>,
+}
+
+//Recent transfers and their progress. Clones share the same history, so the engine can
+//update it while the window shows it.
+#[derive(Clone, Default)]
+pub struct TransferHistory {
+    state: Arc<Mutex<HistoryState>>,
+}
+
+impl TransferHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //Newest first.
+    pub fn records(&self) -> Vec<TransferRecord> {
+        self.state.lock().unwrap().records.iter().cloned().collect()
+    }
+
+    //Every event from now on, for as long as the receiver is kept.
+    #[allow(dead_code)]
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<TransferEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.state.lock().unwrap().listeners.push(tx);
+        rx
+    }
+
+    fn emit(&self, event: TransferEvent) {
+        let mut state = self.state.lock().unwrap();
+        match &event {
+            TransferEvent::Started {
+                id,
+                name,
+                direction,
+                size,
+                resumed_at,
+            } => {
+                state.records.push_front(TransferRecord {
+                    id: *id,
+                    name: name.clone(),
+                    direction: *direction,
+                    size: *size,
+                    transferred: *resumed_at,
+                    state: TransferState::Running,
+                    started_at: Local::now(),
+                });
+                //Running transfers stay however many there are.
+                while state.records.len() > HISTORY_LEN {
+                    match state
+                        .records
+                        .iter()
+                        .rposition(|record| record.state != TransferState::Running)
+                    {
+                        Some(oldest) => state.records.remove(oldest),
+                        None => break,
+                    };
+                }
+            }
+            TransferEvent::Progress { id, transferred } => {
+                if let Some(record) = state.find(*id) {
+                    record.transferred = *transferred;
+                }
+            }
+            TransferEvent::Completed { id } => {
+                if let Some(record) = state.find(*id) {
+                    record.transferred = record.size;
+                    record.state = TransferState::Completed;
+                }
+            }
+            TransferEvent::Failed { id, reason } => {
+                if let Some(record) = state.find(*id) {
+                    record.state = TransferState::Failed(reason.clone());
+                }
+            }
+        }
+        state
+            .listeners
+            .retain(|listener| listener.send(event.clone()).is_ok());
+    }
+}
+
+impl HistoryState {
+    //The latest transfer with this id that is still running.
+    fn find(&mut self, id: u64) -> Option<&mut TransferRecord> {
+        self.records
+            .iter_mut()
+            .find(|record| record.id == id && record.state == TransferState::Running)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started(id: u64) -> TransferEvent {
+        TransferEvent::Started {
+            id,
+            name: format!("file-{}", id),
+            direction: Direction::Incoming,
+            size: 100,
+            resumed_at: 0,
+        }
+    }
+
+    #[test]
+    fn history_follows_events() {
+        let history = TransferHistory::new();
+        let mut events = history.subscribe();
+        history.emit(started(1));
+        history.emit(TransferEvent::Progress {
+            id: 1,
+            transferred: 40,
+        });
+        history.emit(started(2));
+        history.emit(TransferEvent::Failed {
+            id: 2,
+            reason: "disk full".to_string(),
+        });
+        let records = history.records();
+        assert_eq!(
+            records[0].state,
+            TransferState::Failed("disk full".to_string())
+        );
+        assert_eq!(
+            (records[1].transferred, &records[1].state),
+            (40, &TransferState::Running)
+        );
+
+        history.emit(TransferEvent::Completed { id: 1 });
+        assert_eq!(history.records()[1].transferred, 100);
+        assert_eq!(events.try_recv().unwrap(), started(1));
+        assert_eq!(std::iter::from_fn(|| events.try_recv().ok()).count(), 4);
+    }
+
+    #[test]
+    fn history_keeps_running_transfers() {
+        let history = TransferHistory::new();
+        history.emit(started(0));
+        for id in 1..=HISTORY_LEN as u64 + 5 {
+            history.emit(started(id));
+            history.emit(TransferEvent::Completed { id });
+        }
+        let records = history.records();
+        assert_eq!(records.len(), HISTORY_LEN);
+        assert_eq!(records.last().unwrap().id, 0);
+        assert_eq!(records[0].id, HISTORY_LEN as u64 + 5);
+    }
+
+    #[test]
+    fn names_must_be_plain() {
+        for name in [
+            "setup.exe",
+            "agent log 2024.txt",
+            "ünïcode",
+            "console.txt",
+            "COM10",
+        ] {
+            assert_eq!(check_name(name), Ok(()), "{}", name);
+        }
+        for name in [
+            "",
+            ".",
+            "..",
+            "../etc/passwd",
+            "a/b",
+            "a\\b",
+            ".hidden.part",
+            "C:x",
+            "a:b",
+            "NUL",
+            "con.txt",
+            "Com1 .log",
+            "name.",
+            "name ",
+        ] {
+            assert!(check_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn policy_confines_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let allowed = root.path().join("allowed");
+        std::fs::create_dir_all(allowed.join("sub")).unwrap();
+        std::fs::write(root.path().join("secret"), "x").unwrap();
+        let policy = TransferPolicy {
+            allowed_dirs: vec![allowed.clone(), root.path().join("missing")],
+            max_file_bytes: 1 << 20,
+        };
+        assert!(policy.resolve(&allowed.join("sub")).is_ok());
+        assert!(policy.resolve(&allowed).is_ok());
+        for denied in [
+            root.path().join("secret"),
+            allowed.join("..").join("secret"),
+            allowed.join("nothing-here"),
+        ] {
+            assert!(
+                matches!(policy.resolve(&denied), Err(TransferError::Rejected(_))),
+                "{}",
+                denied.display()
+            );
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use super::TransferError;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+//File data travels in chunks of this size.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+const MAX_HEADER: usize = 64 * 1024;
+
+//A push goes Push, Accept, Chunk.., Done, Verified; a pull goes Pull, Offer, Accept, and on
+//as a push with the roles swapped. Either side answers with Reject instead of going on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransferMessage {
+    //Viewer to agent: store a file in `directory` on the agent.
+    Push {
+        id: u64,
+        name: String,
+        directory: String,
+        size: u64,
+        sha256: String,
+    },
+    //Viewer to agent: send the file at `path`.
+    Pull {
+        id: u64,
+        path: String,
+    },
+    //Agent to viewer, in answer to Pull.
+    Offer {
+        id: u64,
+        name: String,
+        size: u64,
+        sha256: String,
+    },
+    //From the receiver: send from `offset` on, what comes before is already here.
+    Accept {
+        id: u64,
+        offset: u64,
+    },
+    Reject {
+        id: u64,
+        reason: String,
+    },
+    //Followed by the chunk's bytes in the same frame.
+    Chunk {
+        id: u64,
+        offset: u64,
+    },
+    Done {
+        id: u64,
+    },
+    //From the receiver, once the whole file hashed to what was announced.
+    Verified {
+        id: u64,
+    },
+    Failed {
+        id: u64,
+        reason: String,
+    },
+}
+
+//Each frame is a length-prefixed JSON header and a length-prefixed blob, lengths big-endian u32.
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &TransferMessage,
+    data: &[u8],
+) -> Result<(), TransferError> {
+    let header = serde_json::to_vec(message).expect("transfer messages always serialize");
+    let mut frame = Vec::with_capacity(8 + header.len() + data.len());
+    frame.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&header);
+    frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    frame.extend_from_slice(data);
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+//The next frame, or None if the stream ended cleanly between frames.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Option<(TransferMessage, Vec<u8>)>, TransferError> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let header = read_blob(reader, u32::from_be_bytes(length) as usize, MAX_HEADER).await?;
+    let message = serde_json::from_slice(&header)
+        .map_err(|e| TransferError::Protocol(format!("bad header: {}", e)))?;
+    reader.read_exact(&mut length).await?;
+    let data = read_blob(reader, u32::from_be_bytes(length) as usize, CHUNK_SIZE).await?;
+    Ok(Some((message, data)))
+}
+
+async fn read_blob<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    length: usize,
+    max: usize,
+) -> Result<Vec<u8>, TransferError> {
+    if length > max {
+        return Err(TransferError::Protocol(format!(
+            "frame of {} bytes exceeds {}",
+            length, max
+        )));
+    }
+    let mut blob = vec![0; length];
+    reader.read_exact(&mut blob).await?;
+    Ok(blob)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn frames_round_trip() {
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        let push = TransferMessage::Push {
+            id: 1,
+            name: "setup.exe".to_string(),
+            directory: "/tmp".to_string(),
+            size: 3,
+            sha256: "ab".to_string(),
+        };
+        let chunk = TransferMessage::Chunk { id: 1, offset: 0 };
+        write_frame(&mut a, &push, &[]).await.unwrap();
+        write_frame(&mut a, &chunk, b"abc").await.unwrap();
+        drop(a);
+        assert_eq!(read_frame(&mut b).await.unwrap(), Some((push, vec![])));
+        assert_eq!(
+            read_frame(&mut b).await.unwrap(),
+            Some((chunk, b"abc".to_vec()))
+        );
+        assert_eq!(read_frame(&mut b).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_frames() {
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        a.write_all(&u32::MAX.to_be_bytes()).await.unwrap();
+        assert!(matches!(
+            read_frame(&mut b).await,
+            Err(TransferError::Protocol(_))
+        ));
+
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        a.write_all(&[0, 0, 0, 2, b'{', b'}', 0, 0, 0, 0])
+            .await
+            .unwrap();
+        assert!(matches!(
+            read_frame(&mut b).await,
+            Err(TransferError::Protocol(_))
+        ));
+
+        //A frame cut short is a dropped connection, not a clean end.
+        let (mut a, mut b) = tokio::io::duplex(1024);
+        a.write_all(&[0, 0, 0, 9, b'{']).await.unwrap();
+        drop(a);
+        assert_eq!(read_frame(&mut b).await, Err(TransferError::Closed));
+    }
+}
@@ -1,5 +1,3 @@
-pub static DESK_SEVICE_NAME: &str = "DeskHubService";
-
 #[derive(Debug, Clone)]
 pub enum AlertType {
     Error,
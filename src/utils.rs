@@ -1,10 +1,14 @@
+#[cfg(target_os = "windows")]
 use std::env;
+#[cfg(target_os = "windows")]
 use std::ptr::null_mut;
+#[cfg(target_os = "windows")]
 use windows_sys::Win32::System::Diagnostics::Debug::{
     FormatMessageW, FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS,
 };
 
 //Get the absolute path of the current executable
+#[cfg(target_os = "windows")]
 pub fn get_executable_path() -> Option<String> {
     if let Ok(exe_path) = env::current_exe() {
         if let Some(path) = exe_path.to_str() {
@@ -14,6 +18,7 @@ pub fn get_executable_path() -> Option<String> {
     None
 }
 
+#[cfg(target_os = "windows")]
 pub fn get_last_error_message(error_code: u32) -> String {
     let mut message_buffer: Vec<u16> = Vec::with_capacity(256);
     unsafe {
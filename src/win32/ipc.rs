@@ -0,0 +1,78 @@
+use crate::ipc::Peer;
+use crate::win32::security::{self, SecurityAttributes};
+use std::ffi::c_void;
+use std::io;
+use std::os::windows::io::AsRawHandle;
+use std::path::Path;
+use std::time::Duration;
+use tokio::net::windows::named_pipe::{
+    ClientOptions, NamedPipeClient, NamedPipeServer, ServerOptions,
+};
+use windows_sys::Win32::Foundation::{ERROR_PIPE_BUSY, FALSE, HANDLE};
+use windows_sys::Win32::System::Pipes::GetNamedPipeClientProcessId;
+
+//Full control for SYSTEM and administrators; interactive users may read and write so the desktop
+//process can connect, but not create instances of their own (0x12019b is FILE_GENERIC_READ and
+//FILE_GENERIC_WRITE without FILE_CREATE_PIPE_INSTANCE). Each connection is then vetted by the
+//client's process id.
+const PIPE_SECURITY: &str = "D:(A;;GA;;;SY)(A;;GA;;;BA)(A;;0x12019b;;;IU)";
+
+//How long a client waits before trying again while every pipe instance is taken.
+const BUSY_RETRY: Duration = Duration::from_millis(50);
+
+//A pipe instance serves one client, so a fresh one is always waiting for the next.
+pub struct Listener {
+    name: String,
+    next: NamedPipeServer,
+}
+
+pub fn bind(path: &Path) -> io::Result<Listener> {
+    let name = path.to_string_lossy().into_owned();
+    //Fails if another process already owns the name, rather than serving alongside it.
+    let next = create(&name, true)?;
+    Ok(Listener { name, next })
+}
+
+fn create(name: &str, first: bool) -> io::Result<NamedPipeServer> {
+    let mut security = SecurityAttributes::from_sddl(PIPE_SECURITY)?;
+    unsafe {
+        ServerOptions::new()
+            .first_pipe_instance(first)
+            .reject_remote_clients(true)
+            .create_with_security_attributes_raw(name, security.as_mut_ptr() as *mut c_void)
+    }
+}
+
+impl Listener {
+    pub async fn accept(&mut self) -> io::Result<(NamedPipeServer, Peer)> {
+        self.next.connect().await?;
+        let connected = std::mem::replace(&mut self.next, create(&self.name, false)?);
+        let mut pid = 0;
+        if unsafe { GetNamedPipeClientProcessId(connected.as_raw_handle() as HANDLE, &mut pid) }
+            == FALSE
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((connected, Peer { pid }))
+    }
+}
+
+pub async fn connect(path: &Path) -> io::Result<NamedPipeClient> {
+    let client = loop {
+        match ClientOptions::new().open(path) {
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {
+                tokio::time::sleep(BUSY_RETRY).await
+            }
+            result => break result?,
+        }
+    };
+    //A process that took the name before the service could otherwise pose as it; the service runs
+    //as SYSTEM, and only SYSTEM or an administrator can own the pipe.
+    if !security::owned_by_admins(&client)? {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} was not created by the service", path.display()),
+        ));
+    }
+    Ok(client)
+}
@@ -1,4 +1,6 @@
-mod guide;
+pub mod ipc;
+pub mod presence;
+pub mod security;
 pub mod service;
 pub mod service_ctrl;
-pub use guide::GuideWindow;
+pub mod session;
@@ -0,0 +1,91 @@
+use std::io;
+use std::os::windows::io::AsRawHandle;
+use std::ptr;
+use windows_sys::Win32::Foundation::{LocalFree, ERROR_SUCCESS, FALSE, HANDLE, PSID};
+use windows_sys::Win32::Security::Authorization::{
+    ConvertStringSecurityDescriptorToSecurityDescriptorW, GetSecurityInfo, SDDL_REVISION_1,
+    SE_FILE_OBJECT,
+};
+use windows_sys::Win32::Security::{
+    IsWellKnownSid, WinBuiltinAdministratorsSid, WinLocalSystemSid, OWNER_SECURITY_INFORMATION,
+    PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES,
+};
+
+//Full control for SYSTEM and Administrators, nothing inherited from the parent and nothing for
+//anyone else. Directories pass it on to what is created in them.
+pub const SYSTEM_AND_ADMINS: &str = "D:P(A;OICI;FA;;;SY)(A;OICI;FA;;;BA)";
+
+//SECURITY_ATTRIBUTES for a security descriptor written in SDDL, to create an object with the
+//right access from the start rather than fixing it up afterwards.
+pub struct SecurityAttributes {
+    attributes: SECURITY_ATTRIBUTES,
+}
+
+impl SecurityAttributes {
+    pub fn from_sddl(sddl: &str) -> io::Result<Self> {
+        let sddl = widestring::U16CString::from_str(sddl)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mut descriptor: PSECURITY_DESCRIPTOR = ptr::null_mut();
+        if unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                ptr::null_mut(),
+            )
+        } == FALSE
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(SecurityAttributes {
+            attributes: SECURITY_ATTRIBUTES {
+                nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+                lpSecurityDescriptor: descriptor,
+                bInheritHandle: FALSE,
+            },
+        })
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut SECURITY_ATTRIBUTES {
+        &mut self.attributes
+    }
+}
+
+//Whether a file or pipe belongs to SYSTEM or the Administrators group, as whatever the service or
+//an elevated administrator creates does.
+pub fn owned_by_admins(object: &impl AsRawHandle) -> io::Result<bool> {
+    let mut owner: PSID = ptr::null_mut();
+    let mut descriptor: PSECURITY_DESCRIPTOR = ptr::null_mut();
+    let status = unsafe {
+        GetSecurityInfo(
+            object.as_raw_handle() as HANDLE,
+            SE_FILE_OBJECT,
+            OWNER_SECURITY_INFORMATION,
+            &mut owner,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut descriptor,
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return Err(io::Error::from_raw_os_error(status as i32));
+    }
+    //`owner` points into `descriptor`, so it is only freed afterwards.
+    let trusted = unsafe {
+        IsWellKnownSid(owner, WinLocalSystemSid) != FALSE
+            || IsWellKnownSid(owner, WinBuiltinAdministratorsSid) != FALSE
+    };
+    unsafe {
+        LocalFree(descriptor as _);
+    }
+    Ok(trusted)
+}
+
+impl Drop for SecurityAttributes {
+    fn drop(&mut self) {
+        unsafe {
+            LocalFree(self.attributes.lpSecurityDescriptor as _);
+        }
+    }
+}
@@ -1,38 +1,16 @@
+use crate::service::{ServiceError, ServiceManager, ServiceStatus};
+use crate::utils;
 use std::ffi::OsString;
-use std::fmt;
 use std::iter::once;
 use std::os::windows::ffi::OsStrExt;
 use std::ptr;
-use windows_sys::Win32::Foundation::{GetLastError, ERROR_SUCCESS, TRUE};
+use windows_sys::Win32::Foundation::{
+    GetLastError, ERROR_ACCESS_DENIED, ERROR_SERVICE_DOES_NOT_EXIST, ERROR_SERVICE_EXISTS,
+    ERROR_SERVICE_REQUEST_TIMEOUT, TRUE,
+};
 use windows_sys::Win32::Security;
 use windows_sys::Win32::System::Services;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ServiceStatus {
-    Querying,
-    Running,
-    Stopped,
-    DoesNotExist,
-    Unknown,
-}
-impl Default for ServiceStatus {
-    fn default() -> Self {
-        ServiceStatus::Unknown
-    }
-}
-
-impl fmt::Display for ServiceStatus {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            ServiceStatus::Querying => write!(f, "Querying"),
-            ServiceStatus::Running => write!(f, "Running"),
-            ServiceStatus::Stopped => write!(f, "Stopped"),
-            ServiceStatus::DoesNotExist => write!(f, "Does Not Exist"),
-            ServiceStatus::Unknown => write!(f, "Unknown"),
-        }
-    }
-}
-
 #[derive(Clone)]
 pub struct Service {
     pub service_name: Vec<u16>,
@@ -40,6 +18,18 @@ pub struct Service {
     service_handle: Security::SC_HANDLE,
 }
 
+//Map the calling thread's last Win32 error to a ServiceError.
+fn last_service_error() -> ServiceError {
+    let err_code = unsafe { GetLastError() };
+    match err_code {
+        ERROR_ACCESS_DENIED => ServiceError::AccessDenied,
+        ERROR_SERVICE_EXISTS => ServiceError::AlreadyExists,
+        ERROR_SERVICE_DOES_NOT_EXIST => ServiceError::DoesNotExist,
+        ERROR_SERVICE_REQUEST_TIMEOUT => ServiceError::Timeout,
+        _ => ServiceError::Os(err_code as i64, utils::get_last_error_message(err_code)),
+    }
+}
+
 impl Service {
     pub fn new(service_name: &str) -> Self {
         Service {
@@ -51,13 +41,15 @@ impl Service {
             service_handle: 0,
         }
     }
+}
 
-    pub fn open(&mut self) -> u32 {
+impl ServiceManager for Service {
+    fn open(&mut self) -> Result<(), ServiceError> {
         unsafe {
             self.service_manager_handle =
                 Services::OpenSCManagerW(ptr::null(), ptr::null(), Services::SC_MANAGER_ALL_ACCESS);
             if self.service_manager_handle == 0 {
-                return GetLastError();
+                return Err(last_service_error());
             }
 
             self.service_handle = Services::OpenServiceW(
@@ -66,19 +58,19 @@ impl Service {
                 Services::SERVICE_ALL_ACCESS,
             );
             if self.service_handle == 0 {
-                return GetLastError();
+                return Err(last_service_error());
             }
 
-            return ERROR_SUCCESS;
+            Ok(())
         }
     }
 
-    pub fn register(&mut self, display_name: &str, binary_path: &str) -> bool {
+    fn register(&mut self, display_name: &str, command_line: &str) -> Result<(), ServiceError> {
         let display_name_wide: Vec<u16> = OsString::from(display_name)
             .encode_wide()
             .chain(once(0))
             .collect();
-        let binary_path_wide: Vec<u16> = OsString::from(binary_path)
+        let binary_path_wide: Vec<u16> = OsString::from(command_line)
             .encode_wide()
             .chain(once(0))
             .collect();
@@ -99,37 +91,101 @@ impl Service {
                 ptr::null_mut(),
                 ptr::null_mut(),
             );
-            !(self.service_handle == 0)
+            if self.service_handle == 0 {
+                return Err(last_service_error());
+            }
+            Ok(())
         }
     }
 
-    pub fn unregister(&mut self) -> bool {
+    fn unregister(&mut self) -> Result<(), ServiceError> {
         unsafe {
             if Services::DeleteService(self.service_handle) != 0 {
                 Services::CloseServiceHandle(self.service_handle);
-                true
+                self.service_handle = 0;
+                Ok(())
+            } else {
+                Err(last_service_error())
+            }
+        }
+    }
+
+    fn command_line(&self) -> Result<String, ServiceError> {
+        unsafe {
+            let mut needed: u32 = 0;
+            Services::QueryServiceConfigW(self.service_handle, ptr::null_mut(), 0, &mut needed);
+            if needed == 0 {
+                return Err(last_service_error());
+            }
+            //u64 storage keeps the QUERY_SERVICE_CONFIGW header aligned.
+            let mut buffer: Vec<u64> = vec![0; (needed as usize + 7) / 8];
+            let config = buffer.as_mut_ptr() as *mut Services::QUERY_SERVICE_CONFIGW;
+            if Services::QueryServiceConfigW(self.service_handle, config, needed, &mut needed)
+                != TRUE
+            {
+                return Err(last_service_error());
+            }
+            Ok(widestring::U16CStr::from_ptr_str((*config).lpBinaryPathName).to_string_lossy())
+        }
+    }
+
+    fn set_command_line(&mut self, command_line: &str) -> Result<(), ServiceError> {
+        let binary_path_wide: Vec<u16> = OsString::from(command_line)
+            .encode_wide()
+            .chain(once(0))
+            .collect();
+        unsafe {
+            if Services::ChangeServiceConfigW(
+                self.service_handle,
+                Services::SERVICE_NO_CHANGE,
+                Services::SERVICE_NO_CHANGE,
+                Services::SERVICE_NO_CHANGE,
+                binary_path_wide.as_ptr(),
+                ptr::null(),
+                ptr::null_mut(),
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+                ptr::null(),
+            ) == TRUE
+            {
+                Ok(())
             } else {
-                false
+                Err(last_service_error())
             }
         }
     }
 
-    pub fn start(&self) -> bool {
-        unsafe { Services::StartServiceW(self.service_handle, 0, ptr::null_mut()) == TRUE }
+    fn start(&self) -> Result<(), ServiceError> {
+        unsafe {
+            if Services::StartServiceW(self.service_handle, 0, ptr::null_mut()) == TRUE {
+                Ok(())
+            } else {
+                Err(last_service_error())
+            }
+        }
     }
 
-    pub fn stop(&self) -> bool {
+    fn stop(&self) -> Result<(), ServiceError> {
         let mut status: Services::SERVICE_STATUS = unsafe { std::mem::zeroed() };
         unsafe {
-            Services::ControlService(
+            if Services::ControlService(
                 self.service_handle,
                 Services::SERVICE_CONTROL_STOP,
                 &mut status,
             ) == TRUE
+            {
+                Ok(())
+            } else {
+                Err(last_service_error())
+            }
         }
     }
 
-    pub fn query_status(&self) -> ServiceStatus {
+    fn query_status(&self) -> ServiceStatus {
+        if self.service_handle == 0 {
+            return ServiceStatus::DoesNotExist;
+        }
         let mut status: Services::SERVICE_STATUS = unsafe { std::mem::zeroed() };
         unsafe {
             Services::QueryServiceStatus(self.service_handle, &mut status);
@@ -1,6 +1,17 @@
-use crate::types;
+use crate::config::Config;
+use crate::hub;
+use crate::ipc;
+use crate::logging::levels::{self, LevelControl};
+use crate::session::{self, SessionChange, SessionEvent, SessionEventSender};
+use crate::supervisor::{
+    self, ChildProcess, DesktopStateFn, Exit, LaunchError, Launched, Launcher, Supervisor,
+};
 use crate::utils;
+use std::ffi::c_void;
+use std::io;
 use std::ptr;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::watch;
 use windows_sys::Win32::Foundation::*;
 use windows_sys::Win32::Security::SecurityImpersonation;
 use windows_sys::Win32::Security::TOKEN_ALL_ACCESS;
@@ -10,34 +21,105 @@ use windows_sys::Win32::System::RemoteDesktop::*;
 use windows_sys::Win32::System::Services::*;
 use windows_sys::Win32::System::Threading::CREATE_UNICODE_ENVIRONMENT;
 use windows_sys::Win32::System::Threading::{
-    CreateProcessAsUserW, TerminateProcess, PROCESS_INFORMATION, STARTUPINFOW,
+    CreateProcessAsUserW, GetExitCodeProcess, GetProcessId, TerminateProcess, PROCESS_INFORMATION,
+    STARTUPINFOW,
 };
 
 static mut C_SERVICE_STATUS_HANDLE: SERVICE_STATUS_HANDLE = 0;
 static mut C_SERVICE_STATUS: SERVICE_STATUS = unsafe { std::mem::zeroed() };
-static mut H_DESKTOP_ROCESS: HANDLE = 0;
+static SERVICE_STOP: Mutex<Option<watch::Sender<bool>>> = Mutex::new(None);
+//SERVICE_CONTROL_SESSIONCHANGE notifications are forwarded to the supervisor through here.
+static SESSION_EVENTS: Mutex<Option<SessionEventSender>> = Mutex::new(None);
+//service_main is called by the SCM without context, so service_dispatch leaves the config here.
+static SERVICE_CONFIG: OnceLock<Config> = OnceLock::new();
+static SERVICE_NAME: OnceLock<widestring::U16CString> = OnceLock::new();
+
+//wParam values of SERVICE_CONTROL_SESSIONCHANGE, from WinUser.h.
+const WTS_CONSOLE_CONNECT: u32 = 0x1;
+const WTS_CONSOLE_DISCONNECT: u32 = 0x2;
+const WTS_SESSION_LOGON: u32 = 0x5;
+const WTS_SESSION_LOGOFF: u32 = 0x6;
+const WTS_SESSION_LOCK: u32 = 0x7;
+const WTS_SESSION_UNLOCK: u32 = 0x8;
+
+//How long the SCM is told to wait for the supervisor and hub client to wind down.
+const STOP_WAIT_HINT_MS: u32 = 30_000;
+
+//Layout of WTSSESSION_NOTIFICATION, the event data of a session change.
+#[repr(C)]
+struct SessionNotification {
+    cb_size: u32,
+    dw_session_id: u32,
+}
+
+fn session_change(event_type: u32) -> Option<SessionChange> {
+    match event_type {
+        WTS_CONSOLE_CONNECT => Some(SessionChange::ConsoleConnect),
+        WTS_CONSOLE_DISCONNECT => Some(SessionChange::ConsoleDisconnect),
+        WTS_SESSION_LOGON => Some(SessionChange::Logon),
+        WTS_SESSION_LOGOFF => Some(SessionChange::Logoff),
+        WTS_SESSION_LOCK => Some(SessionChange::Lock),
+        WTS_SESSION_UNLOCK => Some(SessionChange::Unlock),
+        //Remote connects and the like don't concern the console desktop.
+        _ => None,
+    }
+}
 
-unsafe extern "system" fn service_ctrl_handler(ctrl: u32) {
+unsafe extern "system" fn service_ctrl_handler(
+    ctrl: u32,
+    event_type: u32,
+    event_data: *mut c_void,
+    _: *mut c_void,
+) -> u32 {
     match ctrl {
         SERVICE_CONTROL_PAUSE
         | SERVICE_CONTROL_STOP
         | SERVICE_CONTROL_PRESHUTDOWN
         | SERVICE_CONTROL_SHUTDOWN => {
-            C_SERVICE_STATUS.dwCurrentState = SERVICE_STOPPED;
-            if H_DESKTOP_ROCESS != 0 {
-                TerminateProcess(H_DESKTOP_ROCESS, 1);
-                CloseHandle(H_DESKTOP_ROCESS);
+            //The handler must return promptly; the service thread reports SERVICE_STOPPED once
+            //the supervisor has killed the desktop process and everything has wound down.
+            if let Some(stop) = SERVICE_STOP.lock().unwrap().take() {
+                report_status(SERVICE_STOP_PENDING, STOP_WAIT_HINT_MS);
+                let _ = stop.send(true);
+            }
+        }
+        SERVICE_CONTROL_SESSIONCHANGE if !event_data.is_null() => {
+            let notification = &*(event_data as *const SessionNotification);
+            if let Some(change) = session_change(event_type) {
+                if let Some(events) = SESSION_EVENTS.lock().unwrap().as_ref() {
+                    let _ = events.send(SessionEvent::new(
+                        notification.dw_session_id.to_string(),
+                        change,
+                    ));
+                }
             }
-            SetServiceStatus(C_SERVICE_STATUS_HANDLE, &C_SERVICE_STATUS);
         }
         _ => {}
     }
+    NO_ERROR
+}
+
+unsafe fn report_status(state: u32, wait_hint: u32) {
+    let status = &mut *ptr::addr_of_mut!(C_SERVICE_STATUS);
+    status.dwCurrentState = state;
+    status.dwWaitHint = wait_hint;
+    if state == SERVICE_STOP_PENDING {
+        status.dwControlsAccepted = 0;
+        status.dwCheckPoint += 1;
+    } else {
+        status.dwCheckPoint = 0;
+    }
+    SetServiceStatus(C_SERVICE_STATUS_HANDLE, status);
 }
 
 unsafe extern "system" fn service_main(_: u32, _: *mut *mut u16) {
-    C_SERVICE_STATUS_HANDLE = RegisterServiceCtrlHandlerW(
-        types::DESK_SEVICE_NAME.as_ptr() as *const u16,
+    let Some(config) = SERVICE_CONFIG.get() else {
+        return;
+    };
+    C_SERVICE_STATUS_HANDLE = RegisterServiceCtrlHandlerExW(
+        SERVICE_NAME.get().unwrap().as_ptr(),
         Some(service_ctrl_handler),
+        ptr::null(),
     );
     if C_SERVICE_STATUS_HANDLE == 0 {
         return;
@@ -48,7 +130,8 @@ unsafe extern "system" fn service_main(_: u32, _: *mut *mut u16) {
         dwControlsAccepted: SERVICE_ACCEPT_STOP
             | SERVICE_ACCEPT_PAUSE_CONTINUE
             | SERVICE_ACCEPT_SHUTDOWN
-            | SERVICE_ACCEPT_PRESHUTDOWN,
+            | SERVICE_ACCEPT_PRESHUTDOWN
+            | SERVICE_ACCEPT_SESSIONCHANGE,
         dwWin32ExitCode: 0,
         dwCheckPoint: 0,
         dwServiceSpecificExitCode: 0,
@@ -59,19 +142,157 @@ unsafe extern "system" fn service_main(_: u32, _: *mut *mut u16) {
         //output errors log
         return;
     }
-    if let Some(mut execute_path) = utils::get_executable_path() {
-        execute_path.push_str(" -main");
-        H_DESKTOP_ROCESS = launch_desktop_process(execute_path);
-        log::info!("launched desktop process:{}", H_DESKTOP_ROCESS);
-    } else {
-        //output errors log
+    let Some(execute_path) = utils::get_executable_path() else {
+        log::error!("failed to get the execution path");
+        return;
+    };
+    let launcher = SessionLauncher {
+        //Quoted, or CreateProcessAsUserW would try C:\Program.exe for C:\Program Files\DeskHub.
+        command_line: format!("\"{}\" run-desktop", execute_path),
+        desktop_name: config.desktop.name.clone(),
+    };
+    start_service_thread(config.clone(), launcher);
+}
+
+//service_main runs on a thread owned by the SCM, so the supervisor and hub client get their own runtime.
+fn start_service_thread(config: Config, launcher: SessionLauncher) {
+    let (stop_tx, stop_rx) = watch::channel(false);
+    *SERVICE_STOP.lock().unwrap() = Some(stop_tx);
+    let (events_tx, sessions) = session::channel();
+    *SESSION_EVENTS.lock().unwrap() = Some(events_tx);
+    std::thread::spawn(move || {
+        let runtime = match tokio::runtime::Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                log::error!("failed to create service runtime: {}", e);
+                unsafe { report_status(SERVICE_STOPPED, 0) };
+                return;
+            }
+        };
+        runtime.block_on(async {
+            let (supervisor, desktop_state) =
+                Supervisor::new(Box::new(launcher), config.restart_policy());
+            tokio::spawn(supervisor::persist_state(
+                desktop_state.clone(),
+                config.desktop.state_path.clone(),
+            ));
+            if let Some(control) = LevelControl::installed() {
+                tokio::spawn(control.run(
+                    levels::requests_path(&config.log.directory),
+                    stop_rx.clone(),
+                ));
+            }
+            let state: DesktopStateFn = Arc::new(move || desktop_state.borrow().clone());
+            let (ipc_server, desktop_link) = ipc::server(
+                ipc::desktop_only(state.clone()),
+                ipc::service_status(&config, state.clone()),
+            );
+            //Listening before the desktop process starts, so it finds the pipe.
+            match ipc::bind(&config.desktop.ipc_path) {
+                Ok(listener) => {
+                    tokio::spawn(ipc_server.run(listener, stop_rx.clone()));
+                }
+                Err(e) => log::error!(
+                    "the desktop process cannot reach the service, failed to listen on {}: {}",
+                    config.desktop.ipc_path.display(),
+                    e
+                ),
+            }
+            let hub_task = match config.hub_config() {
+                Some(hub_config) => Some(tokio::spawn(hub::run(
+                    hub_config,
+                    state,
+                    desktop_link,
+                    stop_rx.clone(),
+                ))),
+                None => {
+                    log::info!("no hub configured, running unmanaged");
+                    drop(desktop_link);
+                    None
+                }
+            };
+            supervisor.run(stop_rx, sessions).await;
+            if let Some(hub_task) = hub_task {
+                let _ = hub_task.await;
+            }
+        });
+        drop(runtime);
+        SESSION_EVENTS.lock().unwrap().take();
+        unsafe { report_status(SERVICE_STOPPED, 0) };
+    });
+}
+
+//Starts the desktop process in the active console session.
+struct SessionLauncher {
+    command_line: String,
+    desktop_name: String,
+}
+
+impl Launcher for SessionLauncher {
+    fn launch(&mut self) -> Result<Launched, LaunchError> {
+        let session_id = unsafe { WTSGetActiveConsoleSessionId() };
+        //No session is attached to the console while it is switching between sessions.
+        if session_id == u32::MAX {
+            return Err(LaunchError::NoSession(
+                "no session is attached to the console".to_string(),
+            ));
+        }
+        let handle =
+            launch_desktop_process(self.command_line.clone(), &self.desktop_name, session_id)?;
+        Ok(Launched {
+            process: Box::new(DesktopProcess(handle)),
+            session: session_id.to_string(),
+        })
+    }
+}
+
+struct DesktopProcess(HANDLE);
+
+//The process handle is only used from the supervisor task that owns it.
+unsafe impl Send for DesktopProcess {}
+
+impl ChildProcess for DesktopProcess {
+    fn id(&self) -> u32 {
+        unsafe { GetProcessId(self.0) }
+    }
+
+    fn try_wait(&mut self) -> io::Result<Option<Exit>> {
+        let mut exit_code: u32 = 0;
+        if unsafe { GetExitCodeProcess(self.0, &mut exit_code) } == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+        if exit_code == STILL_ACTIVE as u32 {
+            Ok(None)
+        } else {
+            Ok(Some(Exit {
+                code: Some(exit_code as i32),
+            }))
+        }
+    }
+
+    fn kill(&mut self) -> io::Result<()> {
+        if unsafe { TerminateProcess(self.0, 1) } == FALSE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
     }
 }
 
-pub fn service_dispatch() -> i32 {
+impl Drop for DesktopProcess {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
+
+pub fn service_dispatch(config: Config) -> i32 {
+    let service_name = SERVICE_NAME
+        .get_or_init(|| widestring::U16CString::from_str(&config.service.name).unwrap_or_default());
+    let _ = SERVICE_CONFIG.set(config);
     let service_table: &[SERVICE_TABLE_ENTRYW] = &[
         SERVICE_TABLE_ENTRYW {
-            lpServiceName: types::DESK_SEVICE_NAME.as_ptr() as *mut u16,
+            lpServiceName: service_name.as_ptr() as *mut u16,
             lpServiceProc: Some(service_main),
         },
         SERVICE_TABLE_ENTRYW {
@@ -82,10 +303,13 @@ pub fn service_dispatch() -> i32 {
     unsafe { StartServiceCtrlDispatcherW(service_table.as_ptr()) }
 }
 
-//Launching a process as a specific user in Windows.
-pub fn launch_desktop_process(execute_path: String) -> isize {
+//Launching a process as the user logged on to `session_id` in Windows.
+pub fn launch_desktop_process(
+    execute_path: String,
+    desktop_name: &str,
+    session_id: u32,
+) -> Result<HANDLE, LaunchError> {
     unsafe {
-        let session_id = WTSGetActiveConsoleSessionId();
         let mut h_token: HANDLE = 0;
         let mut h_token_dup: HANDLE = 0;
         let mut lp_environment: *mut std::ffi::c_void = std::ptr::null_mut();
@@ -93,7 +317,6 @@ pub fn launch_desktop_process(execute_path: String) -> isize {
         let mut si: STARTUPINFOW = std::mem::zeroed();
         si.cb = std::mem::size_of::<STARTUPINFOW>() as u32;
         let mut pi: PROCESS_INFORMATION = std::mem::zeroed();
-        let mut h_process: HANDLE = 0;
 
         if WTSQueryUserToken(session_id, &mut h_token) == FALSE {
             let err_code = GetLastError();
@@ -103,7 +326,14 @@ pub fn launch_desktop_process(execute_path: String) -> isize {
                 utils::get_last_error_message(err_code)
             );
             CloseHandle(h_token);
-            return h_process;
+            //The console shows the logon screen; nobody is logged on yet.
+            if err_code == ERROR_NO_TOKEN {
+                return Err(LaunchError::NoSession(format!(
+                    "nobody is logged on to session {}",
+                    session_id
+                )));
+            }
+            return Err(io::Error::from_raw_os_error(err_code as i32).into());
         }
 
         if DuplicateTokenEx(
@@ -123,24 +353,25 @@ pub fn launch_desktop_process(execute_path: String) -> isize {
             );
             CloseHandle(h_token_dup);
             CloseHandle(h_token);
-            return h_process;
+            return Err(io::Error::from_raw_os_error(err_code as i32).into());
         }
 
         if CreateEnvironmentBlock(&mut lp_environment as *mut _ as *mut _, h_token_dup, 0) == FALSE
         {
-            log::error!("CreateEnvironmentBlock fail: {}", GetLastError());
+            let err_code = GetLastError();
+            log::error!("CreateEnvironmentBlock fail: {}", err_code);
             DestroyEnvironmentBlock(lp_environment);
             CloseHandle(h_token_dup);
             CloseHandle(h_token);
-            return h_process;
+            return Err(io::Error::from_raw_os_error(err_code as i32).into());
         }
 
-        let desktop = widestring::U16CString::from_str("winsta0\\default").unwrap();
+        let desktop = widestring::U16CString::from_str(desktop_name).unwrap();
         si.lpDesktop = desktop.as_ptr() as *mut _;
 
         log::info!("CreateProcessAsUserW with execute path: {}", execute_path);
 
-        if CreateProcessAsUserW(
+        let result = if CreateProcessAsUserW(
             h_token_dup,
             std::ptr::null(),
             widestring::U16CString::from_str(execute_path)
@@ -158,7 +389,7 @@ pub fn launch_desktop_process(execute_path: String) -> isize {
         {
             //CloseHandle(pi.hProcess);
             CloseHandle(pi.hThread);
-            h_process = pi.hProcess;
+            Ok(pi.hProcess)
         } else {
             let err_code = GetLastError();
             log::error!(
@@ -166,11 +397,12 @@ pub fn launch_desktop_process(execute_path: String) -> isize {
                 err_code,
                 utils::get_last_error_message(err_code)
             );
-        }
+            Err(io::Error::from_raw_os_error(err_code as i32).into())
+        };
 
         DestroyEnvironmentBlock(lp_environment);
         CloseHandle(h_token_dup);
         CloseHandle(h_token);
-        return h_process;
+        result
     }
 }
@@ -0,0 +1,35 @@
+use windows_sys::Win32::System::RemoteDesktop::{
+    WTSFreeMemory, WTSGetActiveConsoleSessionId, WTSQuerySessionInformationW, WTSUserName,
+    WTS_CURRENT_SERVER_HANDLE,
+};
+
+//User logged on to the physical console session, if any.
+pub fn active_session_user() -> Option<String> {
+    unsafe {
+        let session_id = WTSGetActiveConsoleSessionId();
+        if session_id == u32::MAX {
+            return None;
+        }
+        let mut buffer: *mut u16 = std::ptr::null_mut();
+        let mut bytes: u32 = 0;
+        if WTSQuerySessionInformationW(
+            WTS_CURRENT_SERVER_HANDLE,
+            session_id,
+            WTSUserName,
+            &mut buffer,
+            &mut bytes,
+        ) == 0
+        {
+            return None;
+        }
+        //bytes includes the terminating NUL.
+        let len = (bytes as usize / 2).saturating_sub(1);
+        let name = String::from_utf16_lossy(std::slice::from_raw_parts(buffer, len));
+        WTSFreeMemory(buffer as *mut _);
+        if name.is_empty() {
+            None
+        } else {
+            Some(name)
+        }
+    }
+}